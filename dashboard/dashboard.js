@@ -0,0 +1,314 @@
+'use strict';
+
+// The token is kept per tab, closing it signs out
+const TOKEN = 'roads_token';
+
+const $ = (id) => document.getElementById(id);
+
+let routes = [];
+// The route being edited, unset when creating one
+let editing = null;
+
+class Unauthorized extends Error {
+}
+
+async function api(method, path, body) {
+    const headers = {Authorization: `Bearer ${sessionStorage.getItem(TOKEN)}`};
+    if (body !== undefined) {
+        headers['Content-Type'] = 'application/json';
+    }
+    const res = await fetch(path, {
+        method,
+        headers,
+        body: body === undefined ? undefined : JSON.stringify(body),
+    });
+    if (res.status === 401) {
+        throw new Unauthorized(await res.text());
+    }
+    if (!res.ok) {
+        throw new Error((await res.text()) || res.statusText);
+    }
+
+    return res.status === 204 ? null : res.json();
+}
+
+// `/api/routes/{slug}{suffix}?host=`, slugs keeping their `/`
+function routeUrl(route, suffix = '', params = {}) {
+    const slug = route.slug.split('/').map(encodeURIComponent).join('/');
+    const query = new URLSearchParams(params);
+    if (route.host) {
+        query.set('host', route.host);
+    }
+    const search = query.toString();
+
+    return `/api/routes/${slug}${suffix}${search ? `?${search}` : ''}`;
+}
+
+function show(section) {
+    for (const id of ['sign-in', 'routes', 'stats']) {
+        $(id).hidden = id !== section;
+    }
+    $('sign-out').hidden = section === 'sign-in';
+}
+
+function signOut(message = '') {
+    sessionStorage.removeItem(TOKEN);
+    $('sign-in-error').textContent = message;
+    show('sign-in');
+}
+
+// Runs `action`, showing what went wrong in `errorId`.
+async function attempt(errorId, action) {
+    $(errorId).textContent = '';
+    try {
+        await action();
+        return true;
+    } catch (err) {
+        if (err instanceof Unauthorized) {
+            signOut(err.message);
+        } else {
+            $(errorId).textContent = err.message;
+        }
+        return false;
+    }
+}
+
+async function loadRoutes() {
+    show('routes');
+    await attempt('routes-error', async () => {
+        routes = await api('GET', '/api/routes');
+        routes.sort((a, b) => a.slug.localeCompare(b.slug));
+        renderRoutes();
+    });
+}
+
+function cell(text, className) {
+    const td = document.createElement('td');
+    td.textContent = text;
+    if (className) {
+        td.className = className;
+        td.title = text;
+    }
+    return td;
+}
+
+function button(label, onClick) {
+    const btn = document.createElement('button');
+    btn.type = 'button';
+    btn.textContent = label;
+    btn.addEventListener('click', onClick);
+    return btn;
+}
+
+function renderRoutes() {
+    const search = $('search').value.trim().toLowerCase();
+    const matching = routes.filter((route) =>
+        [route.slug, route.host || '', route.redirect_to, route.owner || '']
+            .some((value) => value.toLowerCase().includes(search)));
+
+    const rows = matching.map((route) => {
+        const tr = document.createElement('tr');
+        const expires = route.expires_at
+            ? new Date(route.expires_at * 1000).toLocaleString()
+            : '';
+        tr.append(
+            cell(route.slug),
+            cell(route.host || ''),
+            cell(route.redirect_to, 'target'),
+            cell(String(route.status_code)),
+            cell(expires),
+            cell(route.owner || ''),
+        );
+        const actions = document.createElement('td');
+        actions.className = 'actions';
+        actions.append(
+            button('Edit', () => openEditor(route)),
+            button('Stats', () => openStats(route)),
+            button('Delete', () => deleteRoute(route)),
+        );
+        tr.append(actions);
+        return tr;
+    });
+    $('route-rows').replaceChildren(...rows);
+    $('no-routes').hidden = matching.length > 0;
+}
+
+// `datetime-local` value of a Unix timestamp, in local time
+function localDateTime(timestamp) {
+    const date = new Date(timestamp * 1000);
+    date.setMinutes(date.getMinutes() - date.getTimezoneOffset());
+    return date.toISOString().slice(0, 16);
+}
+
+function openEditor(route) {
+    editing = route;
+    const form = $('route-form');
+    form.reset();
+    $('editor-title').textContent = route ? `Edit ${route.slug}` : 'New route';
+    $('editor-error').textContent = '';
+    form.slug.disabled = form.host.disabled = Boolean(route);
+    if (route) {
+        form.slug.value = route.slug;
+        form.host.value = route.host || '';
+        form.redirect_to.value = route.redirect_to;
+        form.mode.value = route.mode;
+        form.match_type.value = route.match_type;
+        form.status_code.value = String(route.status_code);
+        form.expires_at.value = route.expires_at ? localDateTime(route.expires_at) : '';
+        form.preserve_query.checked = route.preserve_query;
+        form.preserve_path.checked = route.preserve_path;
+    }
+    $('editor').showModal();
+}
+
+async function saveRoute(event) {
+    event.preventDefault();
+    const form = $('route-form');
+    const fields = {
+        redirect_to: form.redirect_to.value,
+        mode: form.mode.value,
+        match_type: form.match_type.value,
+        status_code: Number(form.status_code.value),
+        expires_at: form.expires_at.value
+            ? Math.floor(new Date(form.expires_at.value).getTime() / 1000)
+            : null,
+        preserve_query: form.preserve_query.checked,
+        preserve_path: form.preserve_path.checked,
+    };
+
+    const saved = await attempt('editor-error', async () => {
+        if (editing) {
+            // everything the form doesn't show is put back as it was
+            await api('PUT', routeUrl(editing), {...editing, ...fields});
+        } else {
+            await api('POST', '/api/routes', {
+                slug: form.slug.value,
+                host: form.host.value || null,
+                ...fields,
+            });
+        }
+    });
+    if (saved) {
+        $('editor').close();
+        await loadRoutes();
+    }
+}
+
+async function deleteRoute(route) {
+    if (!confirm(`Delete ${route.slug}? It can be restored until purged.`)) {
+        return;
+    }
+    if (await attempt('routes-error', () => api('DELETE', routeUrl(route)))) {
+        await loadRoutes();
+    }
+}
+
+let statsRoute = null;
+
+async function openStats(route) {
+    statsRoute = route;
+    $('stats-title').textContent = `Hits of ${route.slug}`;
+    show('stats');
+    await loadStats();
+}
+
+async function loadStats() {
+    const bucket = $('stats-range').value;
+    const seconds = bucket === 'hour' ? 3600 : 24 * 3600;
+    const count = bucket === 'hour' ? 48 : 30;
+    const now = Math.floor(Date.now() / 1000);
+    const from = (Math.floor(now / seconds) - count + 1) * seconds;
+
+    await attempt('stats-error', async () => {
+        const stats = await api('GET', routeUrl(statsRoute, '/stats', {bucket, from}));
+        $('stats-total').textContent = `${stats.total} hits`;
+        renderChart(stats.buckets, from, seconds, count);
+        renderTop('stats-referrers', stats.referrers);
+        renderTop('stats-countries', stats.countries);
+        renderTop('stats-user-agents', stats.user_agents);
+    });
+}
+
+// One bar per bucket, buckets without hits included
+function renderChart(buckets, from, seconds, count) {
+    const hits = new Array(count).fill(0);
+    for (const bucket of buckets) {
+        const i = Math.floor((bucket.start - from) / seconds);
+        if (i >= 0 && i < count) {
+            hits[i] += bucket.hits;
+        }
+    }
+    const max = Math.max(1, ...hits);
+
+    const svg = $('stats-chart');
+    svg.setAttribute('viewBox', `0 0 ${count} 100`);
+    const bars = hits.map((value, i) => {
+        const rect = document.createElementNS('http://www.w3.org/2000/svg', 'rect');
+        const height = (value / max) * 100;
+        rect.setAttribute('x', String(i + 0.1));
+        rect.setAttribute('y', String(100 - height));
+        rect.setAttribute('width', '0.8');
+        rect.setAttribute('height', String(height));
+        const title = document.createElementNS('http://www.w3.org/2000/svg', 'title');
+        title.textContent = `${new Date((from + i * seconds) * 1000).toLocaleString()}: ${value}`;
+        rect.append(title);
+        return rect;
+    });
+    svg.replaceChildren(...bars);
+}
+
+function renderTop(id, counts) {
+    const items = counts.map((count) => {
+        const li = document.createElement('li');
+        li.textContent = `${count.value || '(none)'}: ${count.hits}`;
+        return li;
+    });
+    $(id).replaceChildren(...items);
+}
+
+// Offers single sign-on when the server has it configured.
+async function detectSso() {
+    const res = await fetch('/admin/oidc');
+    $('sso').hidden = res.status !== 204;
+}
+
+// What single sign-on sent back in the URL fragment, `#token=` or `#error=`.
+function takeSsoResult() {
+    const params = new URLSearchParams(location.hash.slice(1));
+    if (!params.has('token') && !params.has('error')) {
+        return '';
+    }
+    history.replaceState(null, '', location.pathname);
+    if (params.has('token')) {
+        sessionStorage.setItem(TOKEN, params.get('token'));
+    }
+
+    return params.get('error') || '';
+}
+
+document.addEventListener('DOMContentLoaded', () => {
+    $('sign-in').addEventListener('submit', async (event) => {
+        event.preventDefault();
+        sessionStorage.setItem(TOKEN, $('token').value.trim());
+        $('token').value = '';
+        await loadRoutes();
+    });
+    $('sso').addEventListener('click', () => location.assign('/admin/oidc/login'));
+    $('sign-out').addEventListener('click', () => signOut());
+    $('search').addEventListener('input', renderRoutes);
+    $('new-route').addEventListener('click', () => openEditor(null));
+    $('route-form').addEventListener('submit', saveRoute);
+    $('cancel-edit').addEventListener('click', () => $('editor').close());
+    $('stats-range').addEventListener('change', loadStats);
+    $('close-stats').addEventListener('click', () => show('routes'));
+
+    detectSso();
+    const ssoError = takeSsoResult();
+    if (ssoError) {
+        signOut(ssoError);
+    } else if (sessionStorage.getItem(TOKEN)) {
+        loadRoutes();
+    } else {
+        show('sign-in');
+    }
+});
@@ -0,0 +1,32 @@
+-- The schema of every SQLite migration up to 0027 at once. Slugs compare
+-- case-sensitively like they do elsewhere, hence the binary collation. An
+-- empty host matches every host.
+CREATE TABLE IF NOT EXISTS routes (
+    host VARCHAR(255) NOT NULL DEFAULT '',
+    slug VARCHAR(512) NOT NULL,
+    redirect_to TEXT NOT NULL,
+    mode VARCHAR(16) NOT NULL DEFAULT 'redirect',
+    proxy TEXT NOT NULL,
+    match_type VARCHAR(16) NOT NULL DEFAULT 'exact',
+    preserve_query BOOLEAN NOT NULL DEFAULT FALSE,
+    preserve_path BOOLEAN NOT NULL DEFAULT FALSE,
+    status_code SMALLINT UNSIGNED NOT NULL DEFAULT 308,
+    expires_at BIGINT,
+    max_hits BIGINT,
+    hits BIGINT NOT NULL DEFAULT 0,
+    geo_targets TEXT NOT NULL,
+    device_targets TEXT NOT NULL,
+    language_targets TEXT NOT NULL,
+    split_targets TEXT NOT NULL,
+    sticky_split BOOLEAN NOT NULL DEFAULT FALSE,
+    utm TEXT NOT NULL,
+    response_headers TEXT NOT NULL,
+    preview BOOLEAN NOT NULL DEFAULT FALSE,
+    password_hash TEXT,
+    signed BOOLEAN NOT NULL DEFAULT FALSE,
+    deleted_at BIGINT,
+    tenant VARCHAR(64),
+    owner VARCHAR(255),
+    PRIMARY KEY (host, slug),
+    INDEX routes_tenant (tenant)
+) DEFAULT CHARSET = utf8mb4 COLLATE = utf8mb4_bin;
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS api_keys (
+    id VARCHAR(64) PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    hash VARCHAR(64) UNIQUE NOT NULL,
+    created_at BIGINT NOT NULL,
+    tenant VARCHAR(64),
+    owner VARCHAR(255)
+) DEFAULT CHARSET = utf8mb4 COLLATE = utf8mb4_bin;
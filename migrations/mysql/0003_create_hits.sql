@@ -0,0 +1,14 @@
+-- Only a prefix of slug is indexed, the whole of it along with host going
+-- over the 3072 bytes InnoDB allows a key in utf8mb4.
+CREATE TABLE IF NOT EXISTS hits (
+    id BIGINT PRIMARY KEY AUTO_INCREMENT,
+    host VARCHAR(255) NOT NULL DEFAULT '',
+    slug VARCHAR(512) NOT NULL,
+    at BIGINT NOT NULL,
+    referrer TEXT,
+    user_agent TEXT,
+    ip_hash VARCHAR(64),
+    country VARCHAR(8),
+    variant INT UNSIGNED,
+    INDEX hits_route_at (host, slug(255), at)
+) DEFAULT CHARSET = utf8mb4 COLLATE = utf8mb4_bin;
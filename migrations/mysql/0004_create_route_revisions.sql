@@ -0,0 +1,14 @@
+-- Only a prefix of slug is indexed, the whole of it along with host going
+-- over the 3072 bytes InnoDB allows a key in utf8mb4.
+CREATE TABLE IF NOT EXISTS route_revisions (
+    id BIGINT PRIMARY KEY AUTO_INCREMENT,
+    host VARCHAR(255) NOT NULL DEFAULT '',
+    slug VARCHAR(512) NOT NULL,
+    at BIGINT NOT NULL,
+    author TEXT NOT NULL,
+    action VARCHAR(16) NOT NULL,
+    old_target TEXT,
+    new_target TEXT,
+    route MEDIUMTEXT,
+    INDEX route_revisions_route (host, slug(255), id)
+) DEFAULT CHARSET = utf8mb4 COLLATE = utf8mb4_bin;
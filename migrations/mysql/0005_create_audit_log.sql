@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGINT PRIMARY KEY AUTO_INCREMENT,
+    at BIGINT NOT NULL,
+    actor TEXT,
+    ip VARCHAR(64),
+    action VARCHAR(64) NOT NULL,
+    target TEXT,
+    diff MEDIUMTEXT,
+    INDEX audit_log_at (at)
+) DEFAULT CHARSET = utf8mb4 COLLATE = utf8mb4_bin;
+
+-- Creating triggers takes SUPER, or log_bin_trust_function_creators = 1,
+-- when the server keeps a binary log.
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log FOR EACH ROW
+    SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'audit_log is append-only';
+
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log FOR EACH ROW
+    SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'audit_log is append-only';
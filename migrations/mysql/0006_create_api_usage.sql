@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS api_usage (
+    subject VARCHAR(255) NOT NULL,
+    kind VARCHAR(64) NOT NULL,
+    window_start BIGINT NOT NULL,
+    count BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (subject, kind, window_start)
+) DEFAULT CHARSET = utf8mb4 COLLATE = utf8mb4_bin;
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS maintenance (
+    id INT PRIMARY KEY NOT NULL CHECK (id = 1),
+    state TEXT NOT NULL
+) DEFAULT CHARSET = utf8mb4 COLLATE = utf8mb4_bin;
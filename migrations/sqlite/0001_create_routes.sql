@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS routes (
+    slug TEXT PRIMARY KEY NOT NULL,
+    redirect_to TEXT NOT NULL
+);
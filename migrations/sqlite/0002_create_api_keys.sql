@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS api_keys (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    hash TEXT UNIQUE NOT NULL,
+    created_at INTEGER NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE routes ADD COLUMN match_type TEXT NOT NULL DEFAULT 'exact';
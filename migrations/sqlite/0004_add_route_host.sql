@@ -0,0 +1,15 @@
+-- SQLite can't change a primary key in place, rebuild the table keyed by
+-- (host, slug). An empty host matches every host.
+CREATE TABLE routes_by_host (
+    host TEXT NOT NULL DEFAULT '',
+    slug TEXT NOT NULL,
+    redirect_to TEXT NOT NULL,
+    match_type TEXT NOT NULL DEFAULT 'exact',
+    PRIMARY KEY (host, slug)
+);
+
+INSERT INTO routes_by_host (slug, redirect_to, match_type)
+SELECT slug, redirect_to, match_type FROM routes;
+
+DROP TABLE routes;
+ALTER TABLE routes_by_host RENAME TO routes;
@@ -0,0 +1,2 @@
+ALTER TABLE routes ADD COLUMN preserve_query BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE routes ADD COLUMN preserve_path BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1 @@
+ALTER TABLE routes ADD COLUMN status_code INTEGER NOT NULL DEFAULT 308;
@@ -0,0 +1,2 @@
+ALTER TABLE routes ADD COLUMN max_hits INTEGER;
+ALTER TABLE routes ADD COLUMN hits INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS hits (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    host TEXT NOT NULL DEFAULT '',
+    slug TEXT NOT NULL,
+    at INTEGER NOT NULL,
+    referrer TEXT,
+    user_agent TEXT,
+    ip_hash TEXT
+);
+
+CREATE INDEX IF NOT EXISTS hits_route_at ON hits (host, slug, at);
@@ -0,0 +1,2 @@
+ALTER TABLE routes ADD COLUMN geo_targets TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE hits ADD COLUMN country TEXT;
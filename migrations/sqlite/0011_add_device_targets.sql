@@ -0,0 +1 @@
+ALTER TABLE routes ADD COLUMN device_targets TEXT NOT NULL DEFAULT '[]';
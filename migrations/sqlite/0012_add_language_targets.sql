@@ -0,0 +1 @@
+ALTER TABLE routes ADD COLUMN language_targets TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,3 @@
+ALTER TABLE routes ADD COLUMN split_targets TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE routes ADD COLUMN sticky_split BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE hits ADD COLUMN variant INTEGER;
@@ -0,0 +1 @@
+ALTER TABLE routes ADD COLUMN utm TEXT NOT NULL DEFAULT '{}';
@@ -0,0 +1 @@
+ALTER TABLE routes ADD COLUMN preview BOOLEAN NOT NULL DEFAULT 0;
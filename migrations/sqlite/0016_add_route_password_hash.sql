@@ -0,0 +1 @@
+ALTER TABLE routes ADD COLUMN password_hash TEXT;
@@ -0,0 +1 @@
+ALTER TABLE routes ADD COLUMN signed BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1 @@
+ALTER TABLE routes ADD COLUMN deleted_at INTEGER;
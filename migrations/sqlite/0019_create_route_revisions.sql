@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS route_revisions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    host TEXT NOT NULL DEFAULT '',
+    slug TEXT NOT NULL,
+    at INTEGER NOT NULL,
+    author TEXT NOT NULL,
+    action TEXT NOT NULL,
+    old_target TEXT,
+    new_target TEXT,
+    route TEXT
+);
+
+CREATE INDEX IF NOT EXISTS route_revisions_route ON route_revisions (host, slug, id);
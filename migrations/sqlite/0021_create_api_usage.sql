@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS api_usage (
+    subject TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    window_start INTEGER NOT NULL,
+    count INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (subject, kind, window_start)
+);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS tenants (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+ALTER TABLE routes ADD COLUMN tenant TEXT;
+ALTER TABLE api_keys ADD COLUMN tenant TEXT;
+CREATE INDEX IF NOT EXISTS routes_tenant ON routes (tenant);
@@ -0,0 +1 @@
+ALTER TABLE routes ADD COLUMN mode TEXT NOT NULL DEFAULT 'redirect';
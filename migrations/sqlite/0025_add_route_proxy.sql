@@ -0,0 +1 @@
+ALTER TABLE routes ADD COLUMN proxy TEXT NOT NULL DEFAULT '{}';
@@ -0,0 +1 @@
+ALTER TABLE routes ADD COLUMN response_headers TEXT NOT NULL DEFAULT '{}';
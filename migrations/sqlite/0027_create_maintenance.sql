@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS maintenance (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+    state TEXT NOT NULL
+);
@@ -0,0 +1,119 @@
+use std::{net::SocketAddr, path::Path, sync::Arc, time::Instant};
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header, HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use chrono::Utc;
+use tokio::{
+    fs::OpenOptions,
+    io::{self, AsyncWrite, AsyncWriteExt},
+    sync::mpsc,
+};
+use tracing::warn;
+
+use crate::config::{AccessLogConfig, AccessLogFormat};
+
+/// Writes one line per request in Common or Combined Log Format, so log
+/// analyzers like GoAccess or awstats can read them.
+pub struct AccessLog {
+    format: AccessLogFormat,
+    latency: bool,
+    lines: mpsc::UnboundedSender<String>,
+}
+
+impl AccessLog {
+    /// Opens the log file (`-` for stdout) and spawns the task writing to it.
+    pub async fn open(config: &AccessLogConfig) -> io::Result<Self> {
+        let out: Box<dyn AsyncWrite + Send + Unpin> = if config.path == Path::new("-") {
+            Box::new(io::stdout())
+        } else {
+            Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&config.path)
+                    .await?,
+            )
+        };
+
+        let (lines, rx) = mpsc::unbounded_channel();
+        tokio::spawn(write_lines(out, rx));
+
+        Ok(Self {
+            format: config.format.clone(),
+            latency: config.latency,
+            lines,
+        })
+    }
+}
+
+async fn write_lines(
+    mut out: Box<dyn AsyncWrite + Send + Unpin>,
+    mut rx: mpsc::UnboundedReceiver<String>,
+) {
+    while let Some(line) = rx.recv().await {
+        let written = out.write_all(line.as_bytes()).await;
+        if let Err(err) = written.and(out.flush().await) {
+            warn!("failed to write access log: {}", err);
+        }
+    }
+}
+
+pub async fn log_requests<B>(
+    State(log): State<Arc<AccessLog>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let start = Instant::now();
+    let client = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map_or("-".into(), |ConnectInfo(addr)| addr.ip().to_string());
+    let request_line = format!(
+        "{} {} {:?}",
+        req.method(),
+        req.uri().path_and_query().map_or("/", |path| path.as_str()),
+        req.version()
+    );
+    let referrer = header_value(req.headers(), header::REFERER);
+    let user_agent = header_value(req.headers(), header::USER_AGENT);
+
+    let response = next.run(req).await;
+
+    let bytes = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok())
+        .unwrap_or("-")
+        .to_owned();
+
+    let mut line = format!(
+        "{} - - [{}] \"{}\" {} {}",
+        client,
+        Utc::now().format("%d/%b/%Y:%H:%M:%S %z"),
+        request_line,
+        response.status().as_u16(),
+        bytes
+    );
+    if log.format == AccessLogFormat::Combined {
+        line.push_str(&format!(" \"{}\" \"{}\"", referrer, user_agent));
+    }
+    if log.latency {
+        // same as Apache's %D, in microseconds
+        line.push_str(&format!(" {}", start.elapsed().as_micros()));
+    }
+    line.push('\n');
+
+    let _ = log.lines.send(line);
+    response
+}
+
+fn header_value(headers: &HeaderMap, name: header::HeaderName) -> String {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map_or("-".into(), |value| value.replace('"', "\\\""))
+}
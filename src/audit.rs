@@ -0,0 +1,149 @@
+use std::net::IpAddr;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+    Router, routing::get,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tracing::warn;
+use utoipa::IntoParams;
+
+use crate::{
+    auth::{self, Principal, Scope},
+    router::{AppState, internal_error},
+    store::{AuditEntry, AuditQuery, Store, StoreError},
+};
+
+const DEFAULT_LIMIT: u32 = 100;
+const MAX_LIMIT: u32 = 1000;
+
+/// Fields whose values never make it into a diff, only the fact they changed
+const REDACTED: [&str; 2] = ["hash", "password_hash"];
+
+/// Fields left out of diffs, counters rather than settings
+const IGNORED: [&str; 1] = ["hits"];
+
+/// Appends `action` by `principal` on `target` to the audit log.
+pub async fn record(
+    store: &Store,
+    principal: &Principal,
+    action: &str,
+    target: Option<String>,
+    diff: Option<Value>,
+) -> Result<(), StoreError> {
+    store
+        .insert_audit(&AuditEntry {
+            id: 0,
+            at: auth::now(),
+            actor: Some(principal.subject.clone()),
+            ip: principal.ip.map(|ip| ip.to_string()),
+            action: action.into(),
+            target,
+            diff,
+        })
+        .await
+}
+
+/// Logs a request from `ip` with credentials that didn't check out. Failing
+/// to record it doesn't change the answer, so errors are only logged.
+pub async fn record_failure(store: &Store, ip: Option<IpAddr>) {
+    let entry = AuditEntry {
+        id: 0,
+        at: auth::now(),
+        actor: None,
+        ip: ip.map(|ip| ip.to_string()),
+        action: "auth.failure".into(),
+        target: None,
+        diff: None,
+    };
+    if let Err(err) = store.insert_audit(&entry).await {
+        warn!("failed to record authentication failure: {}", err);
+    }
+}
+
+/// The top-level fields that differ between `old` and `new`, as
+/// `{"field": {"old": ..., "new": ...}}`. A missing side counts as `null`
+/// fields. `None` when nothing changed.
+pub fn diff<T: Serialize>(old: Option<&T>, new: Option<&T>) -> Option<Value> {
+    let fields = |value: Option<&T>| match value.map(serde_json::to_value) {
+        Some(Ok(Value::Object(fields))) => fields,
+        _ => Map::new(),
+    };
+    let old = fields(old);
+    let new = fields(new);
+
+    let mut changes = Map::new();
+    for name in old.keys().chain(new.keys()) {
+        if changes.contains_key(name) || IGNORED.contains(&name.as_str()) {
+            continue;
+        }
+        let (before, after) = (old.get(name), new.get(name));
+        if before == after {
+            continue;
+        }
+
+        let change = if REDACTED.contains(&name.as_str()) {
+            json!({ "changed": true })
+        } else {
+            json!({ "old": before, "new": after })
+        };
+        changes.insert(name.clone(), change);
+    }
+
+    (!changes.is_empty()).then_some(Value::Object(changes))
+}
+
+/// `?actor=&action=&target=&from=&to=&limit=` of `/api/audit`.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AuditParams {
+    actor: Option<String>,
+    action: Option<String>,
+    target: Option<String>,
+    /// Unix timestamps, `from` inclusive and `to` exclusive
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: Option<u32>,
+}
+
+pub fn audit_routes() -> Router<AppState> {
+    Router::new().route("/api/audit", get(list_audit))
+}
+
+/// The newest entries first, at most `limit` (100 by default, 1000 at
+/// most).
+#[utoipa::path(
+    get,
+    path = "/api/audit",
+    tag = "audit",
+    params(AuditParams),
+    responses((status = 200, description = "Matching entries", body = [AuditEntry]))
+)]
+async fn list_audit(
+    principal: Principal,
+    State(state): State<AppState>,
+    Query(params): Query<AuditParams>,
+) -> Result<Json<Vec<AuditEntry>>, (StatusCode, String)> {
+    principal.require(Scope::AuditRead)?;
+    principal.require_admin()?;
+    principal.require_global()?;
+
+    let query = AuditQuery {
+        actor: params.actor,
+        action: params.action,
+        target: params.target,
+        from: params.from,
+        to: params.to,
+        limit: params.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT),
+    };
+    let entries = state
+        .store
+        .list_audit(&query)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(entries))
+}
@@ -0,0 +1,491 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Path, State},
+    http::{header, request::Parts, StatusCode},
+    Json,
+    response::{IntoResponse, Response},
+    Router, routing::{delete, get},
+};
+use jsonwebtoken::{Algorithm, EncodingKey, Header, Validation};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+use utoipa::ToSchema;
+
+use crate::{
+    audit,
+    quota::QuotaKind,
+    router::{AppState, internal_error},
+    store::{ApiKey, Store, StoreError},
+    tenants, users,
+};
+
+const TOKEN_PREFIX: &str = "roads_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    RoutesRead,
+    RoutesWrite,
+    StatsRead,
+    KeysAdmin,
+    AuditRead,
+    TenantsAdmin,
+    UsersAdmin,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 7] = [
+        Self::RoutesRead,
+        Self::RoutesWrite,
+        Self::StatsRead,
+        Self::KeysAdmin,
+        Self::AuditRead,
+        Self::TenantsAdmin,
+        Self::UsersAdmin,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::RoutesRead => "routes:read",
+            Self::RoutesWrite => "routes:write",
+            Self::StatsRead => "stats:read",
+            Self::KeysAdmin => "keys:admin",
+            Self::AuditRead => "audit:read",
+            Self::TenantsAdmin => "tenants:admin",
+            Self::UsersAdmin => "users:admin",
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "routes:read" => Ok(Self::RoutesRead),
+            "routes:write" => Ok(Self::RoutesWrite),
+            "stats:read" => Ok(Self::StatsRead),
+            "keys:admin" => Ok(Self::KeysAdmin),
+            "audit:read" => Ok(Self::AuditRead),
+            "tenants:admin" => Ok(Self::TenantsAdmin),
+            "users:admin" => Ok(Self::UsersAdmin),
+            _ => Err(format!("unknown scope: {}", s)),
+        }
+    }
+}
+
+/// The caller of an admin endpoint, authenticated either with an API key
+/// (every scope) or a JWT carrying a `scope` claim.
+pub struct Principal {
+    pub subject: String,
+    /// Client address, unset for the command line
+    pub ip: Option<IpAddr>,
+    /// Set for callers confined to one tenant's routes and keys
+    pub tenant: Option<String>,
+    /// Id of the user the caller acts as, owning the routes it creates
+    pub user: Option<String>,
+    /// Unset for users that may only change what they own
+    admin: bool,
+    scopes: Option<Vec<Scope>>,
+}
+
+impl Principal {
+    /// Whoever runs the CLI, which has every scope.
+    pub fn cli() -> Self {
+        Self {
+            subject: "cli".into(),
+            ip: None,
+            tenant: None,
+            user: None,
+            admin: true,
+            scopes: None,
+        }
+    }
+
+    /// Whether the caller may see something belonging to `tenant`. Callers
+    /// without a tenant see everything.
+    pub fn can_access(&self, tenant: Option<&str>) -> bool {
+        self.tenant.is_none() || self.tenant.as_deref() == tenant
+    }
+
+    /// Whether the caller may see and revoke `key`: one of its tenant's, and
+    /// its own for users that aren't admins.
+    pub fn can_see_key(&self, key: &ApiKey) -> bool {
+        self.can_access(key.tenant.as_deref())
+            && (self.admin || key.owner.is_some() && key.owner == self.user)
+    }
+
+    /// Refuses callers confined to a tenant, for what spans every tenant.
+    pub fn require_global(&self) -> Result<(), (StatusCode, String)> {
+        match &self.tenant {
+            Some(_) => Err((
+                StatusCode::FORBIDDEN,
+                "Not available to tenant credentials".into(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the caller may change something owned by `owner`: admins
+    /// everything, users what they own.
+    pub fn can_modify(&self, owner: Option<&str>) -> bool {
+        self.admin || owner.is_some_and(|owner| self.user.as_deref() == Some(owner))
+    }
+
+    pub fn require_owner(&self, owner: Option<&str>) -> Result<(), (StatusCode, String)> {
+        if !self.can_modify(owner) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "Only its owner or an admin can change this".into(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Whether the caller may change every route, credentials of no user
+    /// being an admin's.
+    pub fn is_admin(&self) -> bool {
+        self.admin
+    }
+
+    /// Refuses users that aren't admins.
+    pub fn require_admin(&self) -> Result<(), (StatusCode, String)> {
+        if !self.admin {
+            return Err((StatusCode::FORBIDDEN, "Only admins can do this".into()));
+        }
+
+        Ok(())
+    }
+
+    pub fn require(&self, scope: Scope) -> Result<(), (StatusCode, String)> {
+        match &self.scopes {
+            Some(scopes) if !scopes.contains(&scope) => Err((
+                StatusCode::FORBIDDEN,
+                format!("Missing scope {}", scope.as_str()),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Refuses callers missing any scope, for what grants every scope.
+    pub fn require_all(&self) -> Result<(), (StatusCode, String)> {
+        Scope::ALL.into_iter().try_for_each(|scope| self.require(scope))
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct Claims {
+    sub: String,
+    /// Space separated, as in OAuth 2.0
+    #[serde(default)]
+    scope: String,
+    /// Confines the token to one tenant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+    /// Id of the user the token acts as
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    exp: u64,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Principal {
+    type Rejection = Response;
+
+    /// Also counts the request against the caller's and its tenant's quotas,
+    /// when enabled.
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let principal = authenticate(parts, state).await?;
+        if let Some(quotas) = &state.quotas {
+            let exceeded = quotas
+                .consume(&state.store, &principal, QuotaKind::Requests, 1)
+                .await
+                .map_err(|err| internal_error(err).into_response())?;
+            if let Some(subject) = exceeded {
+                return Err(quotas.exceeded_response(&subject, QuotaKind::Requests));
+            }
+        }
+
+        Ok(principal)
+    }
+}
+
+async fn authenticate(parts: &Parts, state: &AppState) -> Result<Principal, Response> {
+    let ip = parts
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let token = parts
+        .headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(unauthorized)?;
+
+    if token.starts_with(TOKEN_PREFIX) {
+        let key = state
+            .store
+            .find_key(&hash_token(token))
+            .await
+            .map_err(|err| internal_error(err).into_response())?;
+        let Some(key) = key else {
+            audit::record_failure(&state.store, ip).await;
+            return Err(unauthorized());
+        };
+
+        let admin = is_admin(state, ip, key.owner.as_deref()).await?;
+        return Ok(Principal {
+            subject: key.id,
+            ip,
+            tenant: key.tenant,
+            user: key.owner,
+            admin,
+            scopes: None,
+        });
+    }
+
+    let jwt_key = state.jwt_key.as_ref().ok_or_else(unauthorized)?;
+    let validation = Validation::new(Algorithm::HS256);
+    let claims = match jsonwebtoken::decode::<Claims>(token, jwt_key, &validation) {
+        Ok(decoded) => decoded.claims,
+        Err(err) => {
+            debug!("rejected JWT: {}", err);
+            audit::record_failure(&state.store, ip).await;
+            return Err(unauthorized());
+        }
+    };
+
+    let admin = is_admin(state, ip, claims.user.as_deref()).await?;
+    Ok(Principal {
+        subject: claims.sub,
+        ip,
+        tenant: claims.tenant,
+        user: claims.user,
+        admin,
+        scopes: Some(
+            claims
+                .scope
+                .split_whitespace()
+                .filter_map(|scope| scope.parse().ok())
+                .collect(),
+        ),
+    })
+}
+
+/// Whether credentials acting as `user` are an admin's, those of no user
+/// being. Users deleted since lose their credentials.
+async fn is_admin(
+    state: &AppState,
+    ip: Option<IpAddr>,
+    user: Option<&str>,
+) -> Result<bool, Response> {
+    let Some(id) = user else {
+        return Ok(true);
+    };
+
+    let user = state
+        .store
+        .get_user(id)
+        .await
+        .map_err(|err| internal_error(err).into_response())?;
+    match user {
+        Some(user) => Ok(user.admin),
+        None => {
+            audit::record_failure(&state.store, ip).await;
+            Err(unauthorized())
+        }
+    }
+}
+
+fn unauthorized() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        "Missing or invalid credentials",
+    )
+        .into_response()
+}
+
+/// Signs an HS256 token for `subject` granting `scopes` for `ttl`, confined
+/// to `tenant` and acting as `user` when set.
+pub fn mint_token(
+    secret: &str,
+    subject: &str,
+    scopes: &[Scope],
+    tenant: Option<&str>,
+    user: Option<&str>,
+    ttl: Duration,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let claims = Claims {
+        sub: subject.into(),
+        scope: scopes
+            .iter()
+            .map(|scope| scope.as_str())
+            .collect::<Vec<_>>()
+            .join(" "),
+        tenant: tenant.map(Into::into),
+        user: user.map(Into::into),
+        exp: (now() as u64).saturating_add(ttl.as_secs()),
+    };
+
+    jsonwebtoken::encode(
+        &Header::new(Algorithm::HS256),
+        &claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )
+}
+
+/// Seconds since the Unix epoch.
+pub(crate) fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Generates a new key, acting for `tenant` and as `owner` when set, and
+/// stores its hash. The returned token is the only copy of the secret.
+pub async fn mint_key(
+    store: &Store,
+    name: &str,
+    tenant: Option<&str>,
+    owner: Option<&str>,
+) -> Result<(ApiKey, String), StoreError> {
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    let token = format!("{}{}", TOKEN_PREFIX, hex::encode(secret));
+
+    let hash = hash_token(&token);
+    let key = ApiKey {
+        id: hash[..12].into(),
+        name: name.into(),
+        hash,
+        created_at: now(),
+        tenant: tenant.map(Into::into),
+        owner: owner.map(Into::into),
+    };
+    store.insert_key(&key).await?;
+
+    Ok((key, token))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct NewKey {
+    name: String,
+    /// Tenant the key acts for, the caller's own for tenant callers
+    tenant: Option<String>,
+    /// User the key acts as, the caller itself for users that aren't admins
+    owner: Option<String>,
+}
+
+/// A new key, the only time its `token` is shown.
+#[derive(Serialize, ToSchema)]
+pub(crate) struct MintedKey {
+    #[serde(flatten)]
+    key: ApiKey,
+    token: String,
+}
+
+pub fn key_routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/keys", get(list_keys).post(create_key))
+        .route("/api/keys/:id", delete(revoke_key))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/keys",
+    tag = "keys",
+    responses((status = 200, description = "Every API key, oldest first", body = [ApiKey]))
+)]
+async fn list_keys(
+    principal: Principal,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ApiKey>>, (StatusCode, String)> {
+    principal.require(Scope::KeysAdmin)?;
+
+    let mut keys = state.store.list_keys().await.map_err(internal_error)?;
+    keys.retain(|key| principal.can_see_key(key));
+
+    Ok(Json(keys))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/keys",
+    tag = "keys",
+    request_body = NewKey,
+    responses((status = 201, description = "The new key and its token", body = MintedKey))
+)]
+async fn create_key(
+    principal: Principal,
+    State(state): State<AppState>,
+    Json(req): Json<NewKey>,
+) -> Result<(StatusCode, Json<MintedKey>), (StatusCode, String)> {
+    principal.require(Scope::KeysAdmin)?;
+    // keys have every scope, which a scoped token can't hand out
+    principal.require_all()?;
+    let tenant = tenants::assign(&state.store, &principal, req.tenant).await?;
+    let owner = users::assign(&state.store, &principal, req.owner).await?;
+
+    let (key, token) = mint_key(&state.store, &req.name, tenant.as_deref(), owner.as_deref())
+        .await
+        .map_err(internal_error)?;
+    audit::record(
+        &state.store,
+        &principal,
+        "key.create",
+        Some(key.id.clone()),
+        audit::diff(None, Some(&key)),
+    )
+    .await
+    .map_err(internal_error)?;
+
+    debug!("minted API key: {} by {}", &key.id, &principal.subject);
+    Ok((StatusCode::CREATED, Json(MintedKey { key, token })))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/keys/{id}",
+    tag = "keys",
+    params(("id" = String, Path, description = "Id of the key")),
+    responses(
+        (status = 204, description = "Revoked"),
+        (status = 404, description = "API key not found", body = String),
+    )
+)]
+async fn revoke_key(
+    principal: Principal,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    principal.require(Scope::KeysAdmin)?;
+
+    let not_found = || (StatusCode::NOT_FOUND, "API key not found".into());
+    let keys = state.store.list_keys().await.map_err(internal_error)?;
+    let key = keys.iter().find(|key| key.id == id).ok_or_else(not_found)?;
+    if !principal.can_see_key(key) {
+        return Err(not_found());
+    }
+    if !state.store.delete_key(&id).await.map_err(internal_error)? {
+        return Err(not_found());
+    }
+    audit::record(&state.store, &principal, "key.revoke", Some(id.clone()), None)
+        .await
+        .map_err(internal_error)?;
+
+    debug!("revoked API key: {} by {}", &id, &principal.subject);
+    Ok(StatusCode::NO_CONTENT)
+}
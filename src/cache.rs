@@ -0,0 +1,232 @@
+use std::{
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use moka::future::Cache;
+use redis::AsyncCommands;
+use tracing::{info, warn};
+
+use crate::{
+    auth,
+    config::{CacheBackend, CacheConfig, DatabaseConfig},
+    replica::ReadReplica,
+    store::{RedisConnection, Route, Store, StoreError},
+};
+
+const REDIS_KEY_PREFIX: &str = "cache:route:";
+
+/// Lookup cache in front of the store, keyed by host and slug. Misses are cached as
+/// well so unknown slugs don't reach the database on every request.
+pub struct RouteCache {
+    backend: Backend,
+    /// Set when `database_replica_url` is, misses being read from it
+    replica: Option<Arc<ReadReplica>>,
+    /// What the store last answered, for lookups while it can't be reached.
+    /// Unset when `database.stale_ttl` is 0
+    stale: Option<Cache<String, Option<Route>>>,
+    outage: Outage,
+}
+
+enum Backend {
+    Disabled,
+    Memory(Cache<String, Option<Route>>),
+    /// Shared between instances, entries expire after `ttl` seconds
+    Redis { con: RedisConnection, ttl: u64 },
+}
+
+/// Spacing of the attempts to reach the store while it's down, lookups
+/// being answered from the stale routes meanwhile.
+struct Outage {
+    /// Unix timestamp before which the store isn't tried, 0 while it's up
+    retry_at: AtomicI64,
+    /// Seconds waited since the last failure
+    backoff: AtomicU64,
+    max_backoff: u64,
+}
+
+impl Outage {
+    /// Seconds the store is left alone for.
+    fn failed(&self) -> u64 {
+        let backoff = (self.backoff.load(Ordering::Relaxed) * 2).clamp(1, self.max_backoff.max(1));
+        self.backoff.store(backoff, Ordering::Relaxed);
+        self.retry_at.store(auth::now() + backoff as i64, Ordering::Relaxed);
+
+        backoff
+    }
+
+    /// Whether the store was down until now.
+    fn recovered(&self) -> bool {
+        self.backoff.store(0, Ordering::Relaxed);
+        self.retry_at.swap(0, Ordering::Relaxed) != 0
+    }
+}
+
+impl RouteCache {
+    pub async fn new(
+        config: &CacheConfig,
+        database: &DatabaseConfig,
+        replica: Option<Arc<ReadReplica>>,
+    ) -> Result<Self, StoreError> {
+        let backend = match (config.enabled, &config.backend) {
+            (false, _) => Backend::Disabled,
+            (true, CacheBackend::Memory) => Backend::Memory(
+                Cache::builder()
+                    .max_capacity(config.capacity)
+                    .time_to_live(Duration::from_secs(config.ttl))
+                    .build(),
+            ),
+            (true, CacheBackend::Redis) => Backend::Redis {
+                con: RedisConnection::open(&config.redis_url).await?,
+                ttl: config.ttl,
+            },
+        };
+        let stale = (database.stale_ttl > 0).then(|| {
+            Cache::builder()
+                .max_capacity(config.capacity)
+                .time_to_live(Duration::from_secs(database.stale_ttl))
+                .build()
+        });
+
+        Ok(Self {
+            backend,
+            replica,
+            stale,
+            outage: Outage {
+                retry_at: AtomicI64::new(0),
+                backoff: AtomicU64::new(0),
+                max_backoff: database.max_backoff,
+            },
+        })
+    }
+
+    #[tracing::instrument(name = "route_lookup", skip(self, store))]
+    pub async fn get(
+        &self,
+        host: Option<&str>,
+        slug: &str,
+        store: &Store,
+    ) -> Result<Option<Route>, StoreError> {
+        let key = cache_key(host, slug);
+        match &self.backend {
+            Backend::Disabled => self.load(&key, host, slug, store).await,
+            Backend::Memory(routes) => {
+                if let Some(route) = routes.get(&key).await {
+                    record_lookup("hit");
+                    return Ok(route);
+                }
+                record_lookup("miss");
+
+                let route = self.load(&key, host, slug, store).await?;
+                routes.insert(key, route.clone()).await;
+
+                Ok(route)
+            }
+            Backend::Redis { con, ttl } => {
+                let redis_key = format!("{}{}", REDIS_KEY_PREFIX, key);
+                let cached: Option<String> = con.get().await?.get(&redis_key).await?;
+                if let Some(route) = cached.and_then(|raw| serde_json::from_str(&raw).ok()) {
+                    record_lookup("hit");
+                    return Ok(route);
+                }
+                record_lookup("miss");
+
+                let route = self.load(&key, host, slug, store).await?;
+                let raw = serde_json::to_string(&route).expect("routes serialize to JSON");
+                con.get()
+                    .await?
+                    .set_ex::<_, _, ()>(&redis_key, raw, *ttl as usize)
+                    .await?;
+
+                Ok(route)
+            }
+        }
+    }
+
+    /// Reads the route from the store, or from the stale routes while it
+    /// can't be reached.
+    async fn load(
+        &self,
+        key: &str,
+        host: Option<&str>,
+        slug: &str,
+        store: &Store,
+    ) -> Result<Option<Route>, StoreError> {
+        let retry_at = self.outage.retry_at.load(Ordering::Relaxed);
+        let now = auth::now();
+        if now < retry_at {
+            return self
+                .stale(key)
+                .await
+                .ok_or(StoreError::Unavailable((retry_at - now) as u64));
+        }
+
+        let loaded = match &self.replica {
+            Some(replica) => replica.get(host, slug, store).await,
+            None => store.get(host, slug).await,
+        };
+        match loaded {
+            Ok(route) => {
+                if self.outage.recovered() {
+                    info!("storage is reachable again");
+                }
+                if let Some(stale) = &self.stale {
+                    stale.insert(key.to_owned(), route.clone()).await;
+                }
+
+                Ok(route)
+            }
+            Err(err) => {
+                let backoff = self.outage.failed();
+                warn!("route lookup failed, next attempt in {}s: {}", backoff, err);
+                metrics::counter!("roads_store_failures_total").increment(1);
+                self.stale(key).await.ok_or(err)
+            }
+        }
+    }
+
+    async fn stale(&self, key: &str) -> Option<Option<Route>> {
+        let route = self.stale.as_ref()?.get(key).await?;
+        record_lookup("stale");
+
+        Some(route)
+    }
+
+    /// Checks the shared backend is reachable, local caches always are.
+    pub async fn ping(&self) -> Result<(), StoreError> {
+        if let Backend::Redis { con, .. } = &self.backend {
+            redis::cmd("PING").query_async::<_, ()>(&mut con.get().await?).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn invalidate(&self, host: Option<&str>, slug: &str) -> Result<(), StoreError> {
+        let key = cache_key(host, slug);
+        if let Some(stale) = &self.stale {
+            stale.invalidate(&key).await;
+        }
+        match &self.backend {
+            Backend::Disabled => {}
+            Backend::Memory(routes) => routes.invalidate(&key).await,
+            Backend::Redis { con, .. } => {
+                let key = format!("{}{}", REDIS_KEY_PREFIX, key);
+                con.get().await?.del::<_, ()>(&key).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Hostnames can't contain `/`, so keys of different hosts never collide.
+fn cache_key(host: Option<&str>, slug: &str) -> String {
+    format!("{}/{}", host.unwrap_or_default(), slug)
+}
+
+fn record_lookup(result: &'static str) {
+    metrics::counter!("roads_cache_lookups_total", "result" => result).increment(1);
+}
@@ -0,0 +1,98 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tracing::{info, warn};
+
+use crate::store::CircuitBreaker;
+
+/// Circuits of the upstreams, closed until their route's `CircuitBreaker`
+/// sees too many failures.
+#[derive(Default)]
+pub struct Circuits {
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+struct Circuit {
+    /// Set while open, a single request being let through once it's past
+    open_until: Option<Instant>,
+    window_start: Instant,
+    requests: u32,
+    failures: u32,
+}
+
+impl Circuits {
+    /// Whether requests may go to `upstream`, the one trying an open circuit
+    /// again included.
+    pub fn allows(&self, upstream: &str) -> bool {
+        let circuits = self.circuits.lock().expect("circuits aren't poisoned");
+
+        circuits
+            .get(upstream)
+            .and_then(|circuit| circuit.open_until)
+            .is_none_or(|until| Instant::now() >= until)
+    }
+
+    /// Whether the circuit of `upstream` is open.
+    pub fn is_open(&self, upstream: &str) -> bool {
+        let circuits = self.circuits.lock().expect("circuits aren't poisoned");
+
+        circuits.get(upstream).is_some_and(|circuit| circuit.open_until.is_some())
+    }
+
+    /// Notes a request is about to go to `upstream`. On an open circuit
+    /// past its cooldown that's the one trying it again, the others waiting
+    /// another cooldown for its outcome.
+    pub fn begin(&self, upstream: &str, breaker: &CircuitBreaker) {
+        let mut circuits = self.circuits.lock().expect("circuits aren't poisoned");
+        if let Some(until) = circuits.get_mut(upstream).and_then(|c| c.open_until.as_mut()) {
+            *until = Instant::now() + Duration::from_secs(breaker.cooldown);
+        }
+    }
+
+    /// Counts the outcome of a request to `upstream`, opening its circuit
+    /// past the error rate of `breaker`, or closing it again on a success.
+    pub fn record(&self, upstream: &str, breaker: &CircuitBreaker, failed: bool) {
+        let now = Instant::now();
+        let mut circuits = self.circuits.lock().expect("circuits aren't poisoned");
+        let circuit = circuits.entry(upstream.to_owned()).or_insert_with(|| Circuit {
+            open_until: None,
+            window_start: now,
+            requests: 0,
+            failures: 0,
+        });
+
+        if circuit.open_until.is_some() {
+            if failed {
+                circuit.open_until = Some(now + Duration::from_secs(breaker.cooldown));
+            } else {
+                info!("circuit closed again: {}", upstream);
+                circuit.open_until = None;
+                circuit.window_start = now;
+                circuit.requests = 0;
+                circuit.failures = 0;
+            }
+            return;
+        }
+
+        if now.duration_since(circuit.window_start) >= Duration::from_secs(breaker.window) {
+            circuit.window_start = now;
+            circuit.requests = 0;
+            circuit.failures = 0;
+        }
+        circuit.requests += 1;
+        circuit.failures += u32::from(failed);
+
+        let rate = f64::from(circuit.failures) / f64::from(circuit.requests);
+        if circuit.requests >= breaker.min_requests && rate >= breaker.error_rate {
+            warn!(
+                "circuit opened, {} of {} requests failed: {}",
+                circuit.failures, circuit.requests, upstream
+            );
+            metrics::counter!("roads_circuits_opened_total").increment(1);
+            circuit.open_until = Some(now + Duration::from_secs(breaker.cooldown));
+        }
+    }
+}
@@ -0,0 +1,924 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    pin::pin,
+    time::Duration,
+};
+
+use clap::{Args, Parser, Subcommand};
+use futures::StreamExt;
+
+use crate::{
+    audit,
+    auth::{self, Principal, Scope},
+    config::Config,
+    export, history,
+    import::{self, Conflict, Format},
+    password, router,
+    signing::{self, Signer},
+    slug,
+    ServerError,
+    store::{
+        self, Balance, Canary, CircuitBreaker, DeviceTarget, GeoTarget, HeaderRules,
+        LanguageTarget, Maintenance, MatchType, Mirror, PathRewrite, ProxyOptions, Retries,
+        RevisionAction, Route, RouteMode, SplitTarget, Store, Tenant, Timeouts, User,
+    },
+    tenants, users,
+};
+
+#[derive(Parser)]
+#[command(version, about = "Roads redirect service")]
+pub struct Cli {
+    /// Config file to load instead of `ROADS_CONFIG` or `./roads.toml`
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Start the HTTP server (default)
+    Serve,
+
+    /// Apply pending database migrations
+    Migrate,
+
+    /// Manage redirect routes
+    #[command(subcommand)]
+    Route(RouteCommand),
+
+    /// Manage admin API keys
+    #[command(subcommand)]
+    Key(KeyCommand),
+
+    /// Issue scoped JWTs
+    #[command(subcommand)]
+    Token(TokenCommand),
+
+    /// Manage tenants, isolated sets of routes and keys
+    #[command(subcommand)]
+    Tenant(TenantCommand),
+
+    /// Manage users, owning the routes their keys create
+    #[command(subcommand)]
+    User(UserCommand),
+
+    /// Take routes down for planned downtime, answering 503 until it ends
+    #[command(subcommand)]
+    Maintenance(MaintenanceCommand),
+
+    /// Write every route as CSV or JSON, for backups and migrations
+    Export {
+        /// `csv` or `json`, which `route import` reads back
+        #[arg(long, default_value = "json")]
+        format: Format,
+
+        /// Add each route's all-time click count
+        #[arg(long)]
+        stats: bool,
+
+        /// File to write instead of stdout
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum RouteCommand {
+    /// Create a route redirecting `slug` to `target`
+    Add(Box<RouteAdd>),
+
+    /// List every route
+    List {
+        /// List the deleted routes instead
+        #[arg(long)]
+        deleted: bool,
+    },
+
+    /// Delete a route, it can be restored until purged
+    Rm {
+        slug: String,
+
+        /// Hostname the route is scoped to
+        #[arg(long)]
+        host: Option<String>,
+    },
+
+    /// Bring back a deleted route
+    Restore {
+        slug: String,
+
+        /// Hostname the route is scoped to
+        #[arg(long)]
+        host: Option<String>,
+    },
+
+    /// Drop deleted routes for good
+    Purge {
+        /// Only the ones deleted at least this many seconds ago
+        #[arg(long, default_value_t = 0)]
+        older_than: i64,
+    },
+
+    /// Create routes from a CSV or JSON file, told apart by extension
+    Import {
+        file: PathBuf,
+
+        /// `skip` routes that already exist or `overwrite` them
+        #[arg(long, default_value = "skip")]
+        on_conflict: Conflict,
+    },
+
+    /// Print a signed link to a route marked `--signed`
+    Sign {
+        slug: String,
+
+        /// Seconds the link stays valid
+        #[arg(long, default_value_t = 86400)]
+        expires_in: i64,
+    },
+}
+
+/// Options of `route add`.
+#[derive(Args)]
+pub struct RouteAdd {
+    pub slug: String,
+    pub target: String,
+
+    /// `exact`, `pattern` for slugs like `u/:name` with `{name}` in the
+    /// target, or `regex` with `$1` in the target
+    #[arg(long = "match", default_value = "exact")]
+    pub match_type: MatchType,
+
+    /// `redirect` to the target, or `proxy` requests to it keeping the
+    /// route's URL in the address bar
+    #[arg(long, default_value = "redirect")]
+    pub mode: RouteMode,
+
+    /// Base URL requests of a proxy route are spread over instead of the
+    /// target's origin, repeatable
+    #[arg(long = "upstream")]
+    pub upstreams: Vec<String>,
+
+    /// `round_robin` over the upstreams, or `least_connections`
+    #[arg(long, default_value = "round_robin")]
+    pub balance: Balance,
+
+    /// Keep each client on the upstream it first got, through a cookie
+    #[arg(long)]
+    pub sticky_sessions: bool,
+
+    /// Base URL of an upstream getting `--canary-percent` of the requests,
+    /// those with `x-roads-canary: true` always
+    #[arg(long, requires = "canary_percent")]
+    pub canary: Option<String>,
+
+    /// Percent of the requests going to the `--canary`
+    #[arg(long, requires = "canary")]
+    pub canary_percent: Option<f64>,
+
+    /// Base URL of an upstream getting copies of proxied requests, its
+    /// responses dropped
+    #[arg(long)]
+    pub mirror: Option<String>,
+
+    /// Percent of the requests copied to the `--mirror`
+    #[arg(long, requires = "mirror", default_value_t = store::default_mirror_percent())]
+    pub mirror_percent: f64,
+
+    /// Times a proxied idempotent request is retried when the upstream is
+    /// unreachable or answers 502/503
+    #[arg(long, default_value_t = 0)]
+    pub retries: u32,
+
+    /// Share of failed proxied requests, between 0 and 1, that stops
+    /// requests to an upstream for a while
+    #[arg(long)]
+    pub error_rate: Option<f64>,
+
+    /// Base URL proxied requests go to while every upstream's circuit is open
+    #[arg(long, requires = "error_rate")]
+    pub fallback: Option<String>,
+
+    /// Header replacing any of the same name on proxied requests, as
+    /// `host: example.com`, repeatable
+    #[arg(long = "set-header", value_parser = parse_header)]
+    pub set_headers: Vec<(String, String)>,
+
+    /// Header left out of proxied requests, like `cookie`, repeatable
+    #[arg(long = "remove-header")]
+    pub remove_headers: Vec<String>,
+
+    /// Regex over the request path and its replacement, making the path
+    /// proxied requests get, e.g. `--rewrite '^/svc/foo' ''`
+    #[arg(long, num_args = 2, value_names = ["PATTERN", "REPLACEMENT"])]
+    pub rewrite: Option<Vec<String>>,
+
+    /// Gzip proxied responses (`true`) or not (`false`) whatever
+    /// `compression.enabled` says
+    #[arg(long)]
+    pub compression: Option<bool>,
+
+    /// Seconds proxied responses stay in the `[proxy.cache]` whatever the
+    /// upstream says, 0 keeping them out of it
+    #[arg(long)]
+    pub cache_ttl: Option<u64>,
+
+    /// Seconds without hearing from the upstream before a proxied request
+    /// is answered 504, 0 for no limit
+    #[arg(long)]
+    pub read_timeout: Option<u64>,
+
+    /// Seconds a proxied request may take in all, 0 for no limit
+    #[arg(long)]
+    pub total_timeout: Option<u64>,
+
+    /// Only redirect requests for this hostname
+    #[arg(long)]
+    pub host: Option<String>,
+
+    /// Append the request's query string to the target
+    #[arg(long)]
+    pub preserve_query: bool,
+
+    /// Also redirect paths below the slug, appending the rest to the target
+    #[arg(long)]
+    pub preserve_path: bool,
+
+    /// Redirect status, 301 or 308 for permanent moves, 302 or 307 for
+    /// temporary ones
+    #[arg(long = "status", default_value_t = 308)]
+    pub status_code: u16,
+
+    /// Seconds until the route expires, it never does when left out
+    #[arg(long)]
+    pub expires_in: Option<i64>,
+
+    /// Redirects allowed before the route expires, e.g. 1 for one-time links
+    #[arg(long)]
+    pub max_hits: Option<i64>,
+
+    /// Target for visitors from some countries, as `DE,FR=https://...`,
+    /// repeatable. `EU` stands for every member state
+    #[arg(long = "geo", value_parser = parse_geo_target)]
+    pub geo_targets: Vec<GeoTarget>,
+
+    /// Target for some device classes, as `ios=https://...`, repeatable.
+    /// Classes are ios, android, mobile, tablet and desktop
+    #[arg(long = "device", value_parser = parse_device_target)]
+    pub device_targets: Vec<DeviceTarget>,
+
+    /// Target for some languages, as `pt-BR,pt=https://...`, repeatable
+    #[arg(long = "lang", value_parser = parse_language_target)]
+    pub language_targets: Vec<LanguageTarget>,
+
+    /// Weighted variant replacing the target, as `70=https://...`,
+    /// repeatable
+    #[arg(long = "split", value_parser = parse_split_target)]
+    pub split_targets: Vec<SplitTarget>,
+
+    /// Keep serving a client the variant it got first
+    #[arg(long)]
+    pub sticky_split: bool,
+
+    /// UTM parameter added to the target, as `utm_campaign=spring`,
+    /// repeatable
+    #[arg(long = "utm", value_parser = parse_utm_param)]
+    pub utm: Vec<(String, String)>,
+
+    /// Header of the response, as `x-frame-options: DENY`, repeatable. An
+    /// empty value drops a global `[response_headers]` one
+    #[arg(long = "response-header", value_parser = parse_header)]
+    pub response_headers: Vec<(String, String)>,
+
+    /// Show a page with the target and a continue link instead of
+    /// redirecting
+    #[arg(long)]
+    pub preview: bool,
+
+    /// Ask for this password before redirecting
+    #[arg(long)]
+    pub password: Option<String>,
+
+    /// Only redirect through links minted by `route sign`
+    #[arg(long)]
+    pub signed: bool,
+
+    /// Tenant the route belongs to
+    #[arg(long)]
+    pub tenant: Option<String>,
+
+    /// User owning the route, who may change it besides admins
+    #[arg(long)]
+    pub owner: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum KeyCommand {
+    /// Mint a new key and print its token
+    Create {
+        name: String,
+
+        /// Confine the key to this tenant's routes and keys
+        #[arg(long)]
+        tenant: Option<String>,
+
+        /// Act as this user, limited to its own routes unless it's an admin
+        #[arg(long)]
+        user: Option<String>,
+    },
+
+    /// List every key
+    List,
+
+    /// Revoke a key by id
+    Revoke { id: String },
+}
+
+#[derive(Subcommand)]
+pub enum TokenCommand {
+    /// Sign a token for `subject`
+    Create {
+        subject: String,
+
+        /// Granted scope, repeatable (routes:read, routes:write, stats:read, keys:admin,
+        /// audit:read, tenants:admin, users:admin)
+        #[arg(long = "scope", required = true)]
+        scopes: Vec<Scope>,
+
+        /// Lifetime in seconds
+        #[arg(long, default_value_t = 3600)]
+        ttl: u64,
+
+        /// Confine the token to this tenant's routes and keys
+        #[arg(long)]
+        tenant: Option<String>,
+
+        /// Act as this user, limited to its own routes unless it's an admin
+        #[arg(long)]
+        user: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TenantCommand {
+    /// Create a tenant, its id made of lowercase letters, digits and `-`
+    Create {
+        id: String,
+
+        /// Display name, the id when left out
+        #[arg(long)]
+        name: Option<String>,
+    },
+
+    /// List every tenant
+    List,
+
+    /// Delete a tenant no routes or keys belong to anymore
+    Rm { id: String },
+}
+
+#[derive(Subcommand)]
+pub enum MaintenanceCommand {
+    /// Start maintenance of the given routes, or of every route
+    On {
+        /// Labels of the routes, like `docs` or `example.com/docs`
+        routes: Vec<String>,
+
+        /// Seconds clients are told to come back after, those of the
+        /// `[maintenance]` config when left out
+        #[arg(long)]
+        retry_after: Option<u64>,
+    },
+
+    /// End the maintenance
+    Off,
+
+    /// Print what's under maintenance
+    Status,
+}
+
+#[derive(Subcommand)]
+pub enum UserCommand {
+    /// Create a user, identified by a username or email address
+    Create {
+        id: String,
+
+        /// Display name, the id when left out
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Let the user change every route, not only its own
+        #[arg(long)]
+        admin: bool,
+    },
+
+    /// List every user
+    List,
+
+    /// Delete a user no keys act as anymore
+    Rm { id: String },
+}
+
+fn parse_geo_target(s: &str) -> Result<GeoTarget, String> {
+    let (countries, target) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected COUNTRIES=TARGET, got {}", s))?;
+
+    Ok(GeoTarget {
+        countries: countries
+            .split(',')
+            .map(|code| code.trim().to_ascii_uppercase())
+            .collect(),
+        redirect_to: target.into(),
+    })
+}
+
+fn parse_device_target(s: &str) -> Result<DeviceTarget, String> {
+    let (devices, target) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected DEVICES=TARGET, got {}", s))?;
+
+    Ok(DeviceTarget {
+        devices: devices
+            .split(',')
+            .map(|device| device.trim().parse())
+            .collect::<Result<_, _>>()?,
+        redirect_to: target.into(),
+    })
+}
+
+fn parse_language_target(s: &str) -> Result<LanguageTarget, String> {
+    let (languages, target) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected LANGUAGES=TARGET, got {}", s))?;
+
+    Ok(LanguageTarget {
+        languages: languages.split(',').map(|tag| tag.trim().into()).collect(),
+        redirect_to: target.into(),
+    })
+}
+
+fn parse_split_target(s: &str) -> Result<SplitTarget, String> {
+    let (weight, target) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected WEIGHT=TARGET, got {}", s))?;
+
+    Ok(SplitTarget {
+        weight: weight.trim().parse().map_err(|err| format!("invalid weight: {}", err))?,
+        redirect_to: target.into(),
+    })
+}
+
+fn parse_utm_param(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE, got {}", s))?;
+
+    Ok((name.into(), value.into()))
+}
+
+fn parse_header(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected NAME: VALUE, got {}", s))?;
+
+    Ok((name.trim().into(), value.trim().into()))
+}
+
+pub async fn route(cmd: RouteCommand, store: &Store, config: &Config) -> Result<(), ServerError> {
+    let principal = Principal::cli();
+    match cmd {
+        RouteCommand::Add(add) => {
+            let RouteAdd {
+                slug,
+                target,
+                match_type,
+                mode,
+                upstreams,
+                balance,
+                sticky_sessions,
+                canary,
+                canary_percent,
+                mirror,
+                mirror_percent,
+                retries,
+                error_rate,
+                fallback,
+                set_headers,
+                remove_headers,
+                rewrite,
+                compression,
+                cache_ttl,
+                read_timeout,
+                total_timeout,
+                host,
+                preserve_query,
+                preserve_path,
+                status_code,
+                expires_in,
+                max_hits,
+                geo_targets,
+                device_targets,
+                language_targets,
+                split_targets,
+                sticky_split,
+                utm,
+                response_headers,
+                preview,
+                password,
+                signed,
+                tenant,
+                owner,
+            } = *add;
+            check_tenant(store, tenant.as_deref()).await?;
+            check_user(store, owner.as_deref()).await?;
+            let mut route = Route {
+                host: host.as_deref().map(router::normalize_host),
+                slug,
+                redirect_to: target,
+                mode,
+                proxy: ProxyOptions {
+                    upstreams,
+                    balance,
+                    sticky_sessions,
+                    canary: canary.zip(canary_percent).map(|(upstream, percent)| Canary {
+                        upstream,
+                        percent,
+                        header: store::default_canary_header(),
+                    }),
+                    mirror: mirror.map(|upstream| Mirror {
+                        upstream,
+                        percent: mirror_percent,
+                    }),
+                    retries: Retries {
+                        attempts: retries,
+                        ..Retries::default()
+                    },
+                    circuit_breaker: CircuitBreaker {
+                        error_rate: error_rate.unwrap_or_default(),
+                        fallback,
+                        ..CircuitBreaker::default()
+                    },
+                    request_headers: HeaderRules {
+                        set: set_headers.into_iter().collect(),
+                        remove: remove_headers,
+                        ..HeaderRules::default()
+                    },
+                    rewrite: rewrite.map(|args| PathRewrite {
+                        pattern: args[0].clone(),
+                        replacement: args[1].clone(),
+                    }),
+                    compression,
+                    cache_ttl,
+                    timeouts: Timeouts {
+                        read: read_timeout,
+                        total: total_timeout,
+                    },
+                },
+                match_type,
+                preserve_query,
+                preserve_path,
+                status_code,
+                expires_at: expires_in.map(|secs| auth::now() + secs),
+                max_hits,
+                hits: 0,
+                geo_targets,
+                device_targets,
+                language_targets,
+                split_targets,
+                sticky_split,
+                utm: utm.into_iter().collect(),
+                response_headers: response_headers.into_iter().collect(),
+                preview,
+                password_hash: password
+                    .as_deref()
+                    .map(password::hash)
+                    .transpose()
+                    .map_err(|err| ServerError::InvalidRoute(err.to_string()))?,
+                signed,
+                deleted_at: None,
+                tenant,
+                owner,
+            };
+            slug::normalize(&config.slugs, &mut route);
+            slug::validate(&config.slugs, &route).map_err(ServerError::InvalidRoute)?;
+            router::check_route(&route).map_err(ServerError::InvalidRoute)?;
+            if !store.insert(&route).await? {
+                return Err(ServerError::RouteExists(route.label()));
+            }
+            history::record(store, &principal, RevisionAction::Create, None, Some(&route)).await?;
+            println!("{} -> {}", route.label(), route.redirect_to);
+        }
+        RouteCommand::List { deleted } => {
+            let routes = if deleted {
+                store.list_deleted().await?
+            } else {
+                store.list().await?
+            };
+            for route in routes {
+                let notes: Vec<&str> = [
+                    (route.match_type != MatchType::Exact).then_some(route.match_type.as_str()),
+                    (route.mode != RouteMode::Redirect).then_some(route.mode.as_str()),
+                ]
+                .into_iter()
+                .flatten()
+                .collect();
+                if notes.is_empty() {
+                    println!("{} -> {}", route.label(), route.redirect_to);
+                } else {
+                    println!("{} -> {} ({})", route.label(), route.redirect_to, notes.join(", "));
+                }
+            }
+        }
+        RouteCommand::Rm { slug, host } => {
+            let host = host.as_deref().map(router::normalize_host);
+            let Some(old) = store.get(host.as_deref(), &slug).await? else {
+                return Err(ServerError::RouteNotFound(slug));
+            };
+            if !store.delete(host.as_deref(), &slug).await? {
+                return Err(ServerError::RouteNotFound(slug));
+            }
+            history::record(store, &principal, RevisionAction::Delete, Some(&old), None).await?;
+            println!("removed {}", slug);
+        }
+        RouteCommand::Restore { slug, host } => {
+            let host = host.as_deref().map(router::normalize_host);
+            if !store.restore(host.as_deref(), &slug).await? {
+                return Err(ServerError::RouteNotFound(slug));
+            }
+            if let Some(route) = store.get(host.as_deref(), &slug).await? {
+                history::record(store, &principal, RevisionAction::Restore, None, Some(&route))
+                    .await?;
+            }
+            println!("restored {}", slug);
+        }
+        RouteCommand::Purge { older_than } => {
+            let purged = store.purge(auth::now() - older_than + 1).await?;
+            println!("purged {} routes", purged);
+        }
+        RouteCommand::Import { file, on_conflict } => {
+            let body = std::fs::read_to_string(&file).map_err(ServerError::ImportFile)?;
+            let csv = file
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+            let format = if csv { Format::Csv } else { Format::Json };
+            let rows = import::parse(&body, format).map_err(ServerError::InvalidRoute)?;
+
+            let report =
+                import::import(store, &config.slugs, &principal, rows, on_conflict, None).await?;
+            for failure in &report.failed {
+                let slug = failure.slug.as_deref().unwrap_or("-");
+                eprintln!("row {} ({}): {}", failure.row, slug, failure.error);
+            }
+            println!(
+                "{} created, {} updated, {} skipped, {} failed",
+                report.created,
+                report.updated,
+                report.skipped,
+                report.failed.len()
+            );
+        }
+        RouteCommand::Sign { slug, expires_in } => {
+            let secret = config
+                .signing
+                .secret
+                .as_deref()
+                .ok_or(ServerError::SigningNotConfigured)?;
+            let query = Signer::new(secret).sign(&slug, auth::now() + expires_in);
+            let base_url = config.shorten.base_url.as_deref().unwrap_or_default();
+            println!(
+                "{}/{}{}?{}",
+                base_url.trim_end_matches('/'),
+                signing::PREFIX,
+                slug,
+                query
+            );
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn key(cmd: KeyCommand, store: &Store) -> Result<(), ServerError> {
+    let principal = Principal::cli();
+    match cmd {
+        KeyCommand::Create { name, tenant, user } => {
+            check_tenant(store, tenant.as_deref()).await?;
+            check_user(store, user.as_deref()).await?;
+            let (key, token) =
+                auth::mint_key(store, &name, tenant.as_deref(), user.as_deref()).await?;
+            let diff = audit::diff(None, Some(&key));
+            audit::record(store, &principal, "key.create", Some(key.id.clone()), diff).await?;
+            println!("{} {}", key.id, token);
+        }
+        KeyCommand::List => {
+            for key in store.list_keys().await? {
+                match key.tenant {
+                    Some(tenant) => println!("{} {} ({})", key.id, key.name, tenant),
+                    None => println!("{} {}", key.id, key.name),
+                }
+            }
+        }
+        KeyCommand::Revoke { id } => {
+            if !store.delete_key(&id).await? {
+                return Err(ServerError::KeyNotFound(id));
+            }
+            audit::record(store, &principal, "key.revoke", Some(id.clone()), None).await?;
+            println!("revoked {}", id);
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn export(
+    format: Format,
+    stats: bool,
+    output: Option<PathBuf>,
+    store: &Store,
+) -> Result<(), ServerError> {
+    let mut out: Box<dyn Write> = match &output {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).map_err(ServerError::ExportFile)?,
+        )),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+
+    let routes = store.list().await?;
+    let mut chunks = pin!(export::chunks(store.clone(), routes, format, stats));
+    while let Some(chunk) = chunks.next().await {
+        out.write_all(chunk?.as_bytes())
+            .map_err(ServerError::ExportFile)?;
+    }
+
+    out.flush().map_err(ServerError::ExportFile)
+}
+
+pub fn token(cmd: TokenCommand, config: &Config) -> Result<(), ServerError> {
+    let TokenCommand::Create {
+        subject,
+        scopes,
+        ttl,
+        tenant,
+        user,
+    } = cmd;
+    let secret = config
+        .auth
+        .jwt_secret
+        .as_deref()
+        .ok_or(ServerError::JwtNotConfigured)?;
+
+    println!(
+        "{}",
+        auth::mint_token(
+            secret,
+            &subject,
+            &scopes,
+            tenant.as_deref(),
+            user.as_deref(),
+            Duration::from_secs(ttl),
+        )?
+    );
+    Ok(())
+}
+
+pub async fn tenant(cmd: TenantCommand, store: &Store) -> Result<(), ServerError> {
+    let principal = Principal::cli();
+    match cmd {
+        TenantCommand::Create { id, name } => {
+            tenants::check_id(&id).map_err(ServerError::InvalidTenant)?;
+            let tenant = Tenant {
+                name: name.unwrap_or_else(|| id.clone()),
+                id,
+                created_at: auth::now(),
+            };
+            if !store.insert_tenant(&tenant).await? {
+                return Err(ServerError::TenantExists(tenant.id));
+            }
+            let diff = audit::diff(None, Some(&tenant));
+            audit::record(store, &principal, "tenant.create", Some(tenant.id.clone()), diff)
+                .await?;
+            println!("{} {}", tenant.id, tenant.name);
+        }
+        TenantCommand::List => {
+            for tenant in store.list_tenants().await? {
+                println!("{} {}", tenant.id, tenant.name);
+            }
+        }
+        TenantCommand::Rm { id } => {
+            if tenants::in_use(store, &id).await? {
+                return Err(ServerError::TenantInUse(id));
+            }
+            if !store.delete_tenant(&id).await? {
+                return Err(ServerError::TenantNotFound(id));
+            }
+            audit::record(store, &principal, "tenant.delete", Some(id.clone()), None).await?;
+            println!("removed {}", id);
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn user(cmd: UserCommand, store: &Store) -> Result<(), ServerError> {
+    let principal = Principal::cli();
+    match cmd {
+        UserCommand::Create { id, name, admin } => {
+            users::check_id(&id).map_err(ServerError::InvalidUser)?;
+            let user = User {
+                name: name.unwrap_or_else(|| id.clone()),
+                id,
+                admin,
+                created_at: auth::now(),
+            };
+            if !store.insert_user(&user).await? {
+                return Err(ServerError::UserExists(user.id));
+            }
+            let diff = audit::diff(None, Some(&user));
+            audit::record(store, &principal, "user.create", Some(user.id.clone()), diff).await?;
+            println!("{} {}", user.id, user.name);
+        }
+        UserCommand::List => {
+            for user in store.list_users().await? {
+                let role = if user.admin { "admin" } else { "user" };
+                println!("{} {} ({})", user.id, user.name, role);
+            }
+        }
+        UserCommand::Rm { id } => {
+            if users::has_keys(store, &id).await? {
+                return Err(ServerError::UserHasKeys(id));
+            }
+            if !store.delete_user(&id).await? {
+                return Err(ServerError::UserNotFound(id));
+            }
+            audit::record(store, &principal, "user.delete", Some(id.clone()), None).await?;
+            println!("removed {}", id);
+        }
+    }
+
+    Ok(())
+}
+
+/// Running servers notice within their `maintenance.refresh_interval`.
+pub async fn maintenance(cmd: MaintenanceCommand, store: &Store) -> Result<(), ServerError> {
+    let principal = Principal::cli();
+    let old = store.maintenance().await?;
+    let maintenance = match cmd {
+        MaintenanceCommand::On {
+            routes,
+            retry_after,
+        } => Maintenance {
+            all: routes.is_empty(),
+            routes,
+            retry_after,
+        },
+        MaintenanceCommand::Off => Maintenance::default(),
+        MaintenanceCommand::Status => {
+            if old.all {
+                println!("every route is under maintenance");
+            }
+            for route in &old.routes {
+                println!("{}", route);
+            }
+            if !old.is_on() {
+                println!("no maintenance");
+            }
+            return Ok(());
+        }
+    };
+
+    store.set_maintenance(&maintenance).await?;
+    let diff = audit::diff(Some(&old), Some(&maintenance));
+    audit::record(store, &principal, "maintenance.update", None, diff).await?;
+    if maintenance.is_on() {
+        println!("maintenance started");
+    } else {
+        println!("maintenance ended");
+    }
+
+    Ok(())
+}
+
+/// Refuses tenants that weren't created.
+async fn check_tenant(store: &Store, tenant: Option<&str>) -> Result<(), ServerError> {
+    match tenant {
+        Some(id) if store.get_tenant(id).await?.is_none() => {
+            Err(ServerError::TenantNotFound(id.into()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Refuses users that weren't created.
+async fn check_user(store: &Store, user: Option<&str>) -> Result<(), ServerError> {
+    match user {
+        Some(id) if store.get_user(id).await?.is_none() => {
+            Err(ServerError::UserNotFound(id.into()))
+        }
+        _ => Ok(()),
+    }
+}
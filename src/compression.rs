@@ -0,0 +1,127 @@
+use std::{
+    io::{self, Write},
+    sync::Arc,
+};
+
+use axum::{
+    body::{self, Bytes, HttpBody},
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use flate2::{write::GzEncoder, Compression};
+use futures::stream;
+use hyper::Body;
+
+use crate::config::CompressionConfig;
+
+/// Put on a response by its route, overriding `compression.enabled`.
+#[derive(Debug, Clone, Copy)]
+pub struct RouteCompression(pub bool);
+
+/// Gzips the responses `config` picks for clients accepting it, chunk by
+/// chunk so streamed bodies keep streaming.
+pub async fn compress_responses<B>(
+    State(config): State<Arc<CompressionConfig>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let accepts_gzip = req.method() != Method::HEAD && accepts_gzip(req.headers());
+    let mut response = next.run(req).await;
+    if !compresses(&config, &response) {
+        return response;
+    }
+
+    let headers = response.headers_mut();
+    headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    if !accepts_gzip {
+        return response;
+    }
+    headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    headers.remove(header::CONTENT_LENGTH);
+
+    let encoder = GzEncoder::new(Vec::new(), Compression::new(config.level));
+    response.map(|body| {
+        let chunks = stream::unfold(Some((body, encoder)), |state| async move {
+            let (mut body, mut encoder) = state?;
+            match body.data().await {
+                Some(Ok(chunk)) => {
+                    // flushed so what's there so far reaches the client
+                    match encoder.write_all(&chunk).and_then(|()| encoder.flush()) {
+                        Ok(()) => {
+                            let compressed = Bytes::from(std::mem::take(encoder.get_mut()));
+                            Some((Ok(compressed), Some((body, encoder))))
+                        }
+                        Err(err) => Some((Err(err), None)),
+                    }
+                }
+                Some(Err(err)) => Some((Err(io::Error::other(err)), None)),
+                None => Some((encoder.finish().map(Bytes::from), None)),
+            }
+        });
+        body::boxed(Body::wrap_stream(chunks))
+    })
+}
+
+/// Whether `response` is to be compressed: enabled for its route, of one of
+/// the content types, big enough and not encoded yet.
+fn compresses(config: &CompressionConfig, response: &Response) -> bool {
+    let enabled = response
+        .extensions()
+        .get::<RouteCompression>()
+        .map_or(config.enabled, |RouteCompression(enabled)| *enabled);
+    let status = response.status();
+    if !enabled
+        || status.is_informational()
+        || matches!(status, StatusCode::NO_CONTENT | StatusCode::PARTIAL_CONTENT)
+        || status == StatusCode::NOT_MODIFIED
+    {
+        return false;
+    }
+
+    let headers = response.headers();
+    if headers.contains_key(header::CONTENT_ENCODING) {
+        return false;
+    }
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+    let listed = config.content_types.iter().any(|listed| {
+        if listed.ends_with('/') {
+            content_type.starts_with(listed.as_str())
+        } else {
+            content_type == *listed
+        }
+    });
+    let size = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+
+    listed && size.is_none_or(|size| size >= config.min_size)
+}
+
+/// Whether `Accept-Encoding` takes gzip, `*` included.
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut params = coding.split(';');
+            let name = params.next().unwrap_or_default().trim();
+            let refused = params.any(|param| {
+                param
+                    .trim()
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+        })
+}
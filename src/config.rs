@@ -0,0 +1,1639 @@
+use std::{
+    collections::BTreeMap,
+    env, fmt, fs, io,
+    net::{AddrParseError, IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Deserializer};
+use thiserror::Error;
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+
+use crate::{auth, error_pages, fallback, qr, response_headers, slug, store::HeaderRules};
+
+const DEFAULT_CONFIG_FILE: &str = "roads.toml";
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// `0.0.0.0` for every IPv4 interface, `::` (or `[::]`) for every IPv4
+    /// and IPv6 one
+    #[serde(deserialize_with = "deserialize_host")]
+    pub host: IpAddr,
+    pub port: u16,
+    /// Replaces `host`/`port` and the `[tls]` port when set
+    pub listeners: Vec<ListenerConfig>,
+    /// Expect PROXY protocol headers on the `host`/`port` and `[tls]`
+    /// listeners
+    pub proxy_protocol: bool,
+    /// Seconds open connections get to finish after SIGTERM/Ctrl+C
+    pub drain_timeout: u64,
+    /// Accept HTTP/2, with prior knowledge on plain HTTP and through ALPN
+    /// on TLS
+    pub http2: bool,
+    pub database_url: String,
+    /// Copy of the database route lookups read from, falling back to
+    /// `database_url` while it fails
+    pub database_replica_url: Option<String>,
+    pub database: DatabaseConfig,
+    /// Run pending migrations when the server starts
+    pub auto_migrate: bool,
+    pub log_level: String,
+    pub log_format: LogFormat,
+    /// Serve Prometheus metrics on `/metrics`
+    pub metrics: bool,
+    /// Serve the OpenAPI spec and Swagger UI on `/api/docs`
+    pub api_docs: bool,
+    /// Serve the web dashboard on `/admin`
+    pub dashboard: bool,
+    pub cache: CacheConfig,
+    pub auth: AuthConfig,
+    pub tracing: TracingConfig,
+    pub access_log: AccessLogConfig,
+    pub tls: TlsConfig,
+    pub expired: ExpiredConfig,
+    pub maintenance: MaintenanceConfig,
+    pub tracking: TrackingConfig,
+    pub events: EventsConfig,
+    pub geoip: GeoIpConfig,
+    /// UTM parameters added to every redirect target, see `Route::utm`
+    pub utm: BTreeMap<String, String>,
+    /// Headers of every redirect and proxied response, see
+    /// `Route::response_headers`
+    pub response_headers: BTreeMap<String, String>,
+    /// HTML templates of the errors routes answer with, by status
+    pub error_pages: BTreeMap<String, PathBuf>,
+    pub fallback: FallbackConfig,
+    pub shorten: ShortenConfig,
+    pub slugs: SlugConfig,
+    pub qr: QrConfig,
+    pub passwords: PasswordConfig,
+    pub signing: SigningConfig,
+    pub trash: TrashConfig,
+    pub webhooks: WebhooksConfig,
+    pub rate_limit: RateLimitConfig,
+    pub quotas: QuotaConfig,
+    pub cors: CorsConfig,
+    pub oidc: OidcConfig,
+    pub grpc: GrpcConfig,
+    pub health_checks: HealthCheckConfig,
+    pub proxy: ProxyConfig,
+    pub compression: CompressionConfig,
+    pub body_limits: BodyLimitConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            host: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 3000,
+            listeners: Vec::new(),
+            proxy_protocol: false,
+            drain_timeout: 30,
+            http2: true,
+            database_url: "redis://0.0.0.0:6379/".into(),
+            database_replica_url: None,
+            database: DatabaseConfig::default(),
+            auto_migrate: true,
+            log_level: "roads=trace,tower_http=debug".into(),
+            log_format: LogFormat::Text,
+            metrics: true,
+            api_docs: true,
+            dashboard: true,
+            cache: CacheConfig::default(),
+            auth: AuthConfig::default(),
+            tracing: TracingConfig::default(),
+            access_log: AccessLogConfig::default(),
+            tls: TlsConfig::default(),
+            expired: ExpiredConfig::default(),
+            maintenance: MaintenanceConfig::default(),
+            tracking: TrackingConfig::default(),
+            events: EventsConfig::default(),
+            geoip: GeoIpConfig::default(),
+            utm: BTreeMap::new(),
+            response_headers: BTreeMap::new(),
+            error_pages: BTreeMap::new(),
+            fallback: FallbackConfig::default(),
+            shorten: ShortenConfig::default(),
+            slugs: SlugConfig::default(),
+            qr: QrConfig::default(),
+            passwords: PasswordConfig::default(),
+            signing: SigningConfig::default(),
+            trash: TrashConfig::default(),
+            webhooks: WebhooksConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            quotas: QuotaConfig::default(),
+            cors: CorsConfig::default(),
+            oidc: OidcConfig::default(),
+            grpc: GrpcConfig::default(),
+            health_checks: HealthCheckConfig::default(),
+            proxy: ProxyConfig::default(),
+            compression: CompressionConfig::default(),
+            body_limits: BodyLimitConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ListenerConfig {
+    pub addr: ListenAddr,
+    /// Terminate TLS with the `[tls]` certificate settings, TCP only
+    #[serde(default)]
+    pub tls: bool,
+    /// Permissions of a Unix socket, e.g. `0o660`
+    #[serde(default)]
+    pub mode: Option<u32>,
+    /// Expect a PROXY protocol v1/v2 header on every connection and take
+    /// the client address from it, TCP only
+    #[serde(default)]
+    pub proxy_protocol: bool,
+    /// What this listener answers, everything when left out
+    #[serde(default = "Service::all")]
+    pub serve: Vec<Service>,
+}
+
+/// `host:port`, or `unix:/path/to.sock` for a Unix domain socket.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some(path) => Ok(Self::Unix(path.into())),
+            None => s.parse().map(Self::Tcp),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ListenAddr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => addr.fmt(f),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Service {
+    /// The slug lookups
+    Redirects,
+    /// `/api/...` and the `/admin` dashboard
+    Admin,
+    /// `/ping`, `/healthz` and `/readyz`
+    Health,
+    /// `/metrics`, when `metrics` is on
+    Metrics,
+}
+
+impl Service {
+    pub fn all() -> Vec<Self> {
+        vec![Self::Redirects, Self::Admin, Self::Health, Self::Metrics]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// One JSON object per line, span and event fields flattened
+    Json,
+}
+
+/// Connections to the database, and how lookups carry on while it's down.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    /// Connections of the SQLite or MySQL pool
+    pub max_connections: u32,
+    pub min_connections: u32,
+    /// Seconds a query waits for a free connection
+    pub acquire_timeout: u64,
+    /// Seconds unused connections are kept open
+    pub idle_timeout: u64,
+    /// Seconds a statement waits for a locked SQLite database or MySQL row
+    pub statement_timeout: u64,
+    /// Seconds routes looked up are kept to answer with while the database
+    /// can't be reached, 0 failing those lookups instead
+    pub stale_ttl: u64,
+    /// Most seconds between attempts to reach the database while it's down
+    pub max_backoff: u64,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout: 5,
+            idle_timeout: 600,
+            statement_timeout: 5,
+            stale_ttl: 86_400,
+            max_backoff: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    pub backend: CacheBackend,
+    /// Seconds a lookup stays cached
+    pub ttl: u64,
+    /// Entries kept by the memory backend
+    pub capacity: u64,
+    /// Server used by the redis backend and pub/sub
+    pub redis_url: String,
+    /// Broadcast route changes through Redis pub/sub on `redis_url`, so
+    /// every instance drops them from its local caches right away
+    pub pubsub: bool,
+    pub pubsub_channel: String,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            backend: CacheBackend::Memory,
+            ttl: 60,
+            capacity: 10_000,
+            redis_url: "redis://0.0.0.0:6379/".into(),
+            pubsub: false,
+            pubsub_channel: "roads:invalidations".into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheBackend {
+    /// Per-instance cache
+    Memory,
+    /// Shared by every instance pointing at the same server
+    Redis,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// HS256 secret for JWT bearer tokens, JWTs are rejected when unset
+    pub jwt_secret: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TracingConfig {
+    /// OTLP/gRPC collector, spans are only exported when set
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: "roads".into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AccessLogConfig {
+    pub enabled: bool,
+    /// File the log is appended to, `-` for stdout
+    pub path: PathBuf,
+    pub format: AccessLogFormat,
+    /// Append the request duration in microseconds, like Apache's `%D`
+    pub latency: bool,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: "access.log".into(),
+            format: AccessLogFormat::Combined,
+            latency: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    Common,
+    /// Common plus referrer and user agent
+    Combined,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// Serve HTTPS on `port` next to the plain HTTP listener. Explicit
+    /// `listeners` opt into TLS on their own
+    pub enabled: bool,
+    pub port: u16,
+    /// PEM certificate chain
+    pub cert: PathBuf,
+    /// PEM private key
+    pub key: PathBuf,
+    /// Also serve HTTP/3 on the same port over UDP, needs the `http3`
+    /// build feature
+    pub http3: bool,
+    pub acme: AcmeConfig,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 3443,
+            cert: "cert.pem".into(),
+            key: "key.pem".into(),
+            http3: false,
+            acme: AcmeConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AcmeConfig {
+    /// Get certificates from Let's Encrypt instead of `cert`/`key`
+    pub enabled: bool,
+    pub domains: Vec<String>,
+    /// Contact emails for the ACME account
+    pub contact: Vec<String>,
+    /// Where the account key and certificates are kept between restarts
+    pub cache_dir: PathBuf,
+    /// Use the production directory, staging otherwise
+    pub production: bool,
+}
+
+impl Default for AcmeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            domains: Vec::new(),
+            contact: Vec::new(),
+            cache_dir: "acme".into(),
+            production: false,
+        }
+    }
+}
+
+/// Response for routes past their `expires_at`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ExpiredConfig {
+    /// 410 Gone, or 404 to not reveal the link ever existed
+    pub status: u16,
+    /// HTML page sent as the body, a plain text message when unset
+    pub page: Option<PathBuf>,
+}
+
+impl Default for ExpiredConfig {
+    fn default() -> Self {
+        Self {
+            status: 410,
+            page: None,
+        }
+    }
+}
+
+/// Response for routes under maintenance, turned on through the API or
+/// `roads maintenance`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct MaintenanceConfig {
+    /// Seconds in `Retry-After`, unless the maintenance sets its own
+    pub retry_after: u64,
+    /// HTML page sent as the body, a plain text message when unset
+    pub page: Option<PathBuf>,
+    /// Seconds between looks at the store for maintenance started elsewhere,
+    /// like by the CLI or another instance
+    pub refresh_interval: u64,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            retry_after: 300,
+            page: None,
+            refresh_interval: 5,
+        }
+    }
+}
+
+/// What `/` and slugs without a route answer with, for every host but those
+/// of `hosts`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct FallbackConfig {
+    #[serde(flatten)]
+    pub default: FallbackRule,
+    /// Overrides by hostname
+    pub hosts: BTreeMap<String, FallbackRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct FallbackRule {
+    pub action: FallbackAction,
+    /// Where `redirect` sends to
+    pub url: Option<String>,
+    /// Status of `redirect`, one of `REDIRECT_STATUSES`
+    pub status: u16,
+    /// HTML of `page`
+    pub page: Option<PathBuf>,
+}
+
+impl Default for FallbackRule {
+    fn default() -> Self {
+        Self {
+            action: FallbackAction::NotFound,
+            url: None,
+            status: 302,
+            page: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FallbackAction {
+    /// 404, with the page of `[error_pages]` if there's one
+    NotFound,
+    /// Redirect to `url`
+    Redirect,
+    /// A landing page, 200 on `/` and 404 on unknown slugs
+    Page,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TrackingConfig {
+    /// Record every redirect in the `hits` table
+    pub enabled: bool,
+    /// Hits written to the store at once
+    pub batch_size: usize,
+    /// Seconds between writes of a partial batch
+    pub flush_interval: u64,
+    /// Hits waiting to be written before new ones are dropped
+    pub queue_size: usize,
+    /// Mixed into client address hashes so they can't be reversed by
+    /// hashing every address
+    pub ip_salt: String,
+}
+
+impl Default for TrackingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            batch_size: 500,
+            flush_interval: 1,
+            queue_size: 10_000,
+            ip_salt: String::new(),
+        }
+    }
+}
+
+/// Hits and closed proxy tunnels published to NATS as JSON.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct EventsConfig {
+    /// `nats://[user:pass@]host[:port]`, or `tls://` over TLS, nothing is
+    /// published when unset
+    pub nats_url: Option<String>,
+    /// Prefix of the `.hits` and `.tunnels` subjects
+    pub subject: String,
+    /// Events waiting to be published before new ones are dropped
+    pub queue_size: usize,
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        Self {
+            nats_url: None,
+            subject: "roads".into(),
+            queue_size: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct GeoIpConfig {
+    /// MaxMind GeoLite2/GeoIP2 Country or City database (`.mmdb`), routes'
+    /// `geo_targets` are ignored when unset
+    pub database: Option<PathBuf>,
+}
+
+/// Slugs generated by `POST /api/shorten`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ShortenConfig {
+    pub length: usize,
+    /// Characters slugs are drawn from, base62 by default
+    pub alphabet: String,
+    /// Prefix of the returned short URLs, the request's host when unset
+    pub base_url: Option<String>,
+}
+
+impl Default for ShortenConfig {
+    fn default() -> Self {
+        Self {
+            length: 7,
+            alphabet: "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz".into(),
+            base_url: None,
+        }
+    }
+}
+
+/// Rules for the slugs of new routes.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SlugConfig {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// Store exact slugs lowercased and look them up case-insensitively
+    pub lowercase: bool,
+    /// First segments no route may use, compared case-insensitively
+    pub reserved: Vec<String>,
+}
+
+impl Default for SlugConfig {
+    fn default() -> Self {
+        Self {
+            min_length: 1,
+            max_length: 128,
+            lowercase: false,
+            reserved: ["api", "ping", "healthz", "metrics", "admin", "s"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+/// Defaults of `GET /api/routes/{slug}/qr`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct QrConfig {
+    /// Image side in pixels
+    pub size: u32,
+    pub error_correction: ErrorCorrection,
+}
+
+impl Default for QrConfig {
+    fn default() -> Self {
+        Self {
+            size: 256,
+            error_correction: ErrorCorrection::M,
+        }
+    }
+}
+
+/// QR code error correction, the share of the code that can be damaged:
+/// 7%, 15%, 25% or 30%.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, ToSchema)]
+pub enum ErrorCorrection {
+    L,
+    M,
+    Q,
+    H,
+}
+
+/// Cookies remembering who entered the password of a protected route.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PasswordConfig {
+    /// Seconds a correct password is remembered for
+    pub cookie_ttl: u64,
+    /// Signs the cookies, shared by every instance behind a load balancer.
+    /// A random one is used when unset, forgetting passwords on restart
+    pub cookie_secret: Option<String>,
+}
+
+impl Default for PasswordConfig {
+    fn default() -> Self {
+        Self {
+            cookie_ttl: 3600,
+            cookie_secret: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SigningConfig {
+    /// HMAC key of `/s/{slug}` links, which are only served when set
+    pub secret: Option<String>,
+}
+
+/// Deleted routes, kept around to be restored.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TrashConfig {
+    /// Seconds a deleted route can be restored for
+    pub retention: u64,
+    /// Seconds between purges of the routes past `retention`
+    pub purge_interval: u64,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self {
+            retention: 30 * 24 * 3600,
+            purge_interval: 3600,
+        }
+    }
+}
+
+/// HTTP endpoints told about route changes.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct WebhooksConfig {
+    pub endpoints: Vec<WebhookEndpoint>,
+    /// Seconds a delivery attempt may take
+    pub timeout: u64,
+    /// Deliveries tried before giving up, backing off between them
+    pub attempts: u32,
+    /// Seconds between the checks for newly expired routes
+    pub expiry_interval: u64,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            endpoints: Vec::new(),
+            timeout: 10,
+            attempts: 3,
+            expiry_interval: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct WebhookEndpoint {
+    /// `http` or `https` URL the events are posted to
+    pub url: String,
+    /// Events sent to this endpoint, every one when left out
+    #[serde(default = "WebhookEvent::all")]
+    pub events: Vec<WebhookEvent>,
+    /// Signs bodies with HMAC-SHA256, sent in `X-Roads-Signature`
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookEvent {
+    Created,
+    Updated,
+    Deleted,
+    Restored,
+    Expired,
+}
+
+impl WebhookEvent {
+    pub fn all() -> Vec<Self> {
+        vec![
+            Self::Created,
+            Self::Updated,
+            Self::Deleted,
+            Self::Restored,
+            Self::Expired,
+        ]
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Created => "route.created",
+            Self::Updated => "route.updated",
+            Self::Deleted => "route.deleted",
+            Self::Restored => "route.restored",
+            Self::Expired => "route.expired",
+        }
+    }
+}
+
+/// Token buckets per client IP, in front of every route but the health and
+/// metrics ones.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// Redirects and every other path outside `/api`
+    pub redirects: RateConfig,
+    /// The admin API
+    pub api: RateConfig,
+    /// Peers whose `X-Forwarded-For` is believed, addresses or CIDR ranges
+    pub trusted_proxies: Vec<IpRange>,
+    /// Clients tracked at once, the least recently seen are forgotten first
+    pub capacity: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            redirects: RateConfig {
+                rate: 20.0,
+                burst: 40,
+            },
+            api: RateConfig {
+                rate: 5.0,
+                burst: 20,
+            },
+            trusted_proxies: Vec::new(),
+            capacity: 100_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RateConfig {
+    /// Requests per second sustained
+    pub rate: f64,
+    /// Requests a client can make at once
+    pub burst: u32,
+}
+
+/// An address, or a CIDR range like `10.0.0.0/8`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            IpAddr::V4(_) => ip,
+        };
+        let (addr, ip, bits): (u128, u128, u32) = match (self.addr, ip) {
+            (IpAddr::V4(addr), IpAddr::V4(ip)) => {
+                (u32::from(addr).into(), u32::from(ip).into(), 32)
+            }
+            (IpAddr::V6(addr), IpAddr::V6(ip)) => (addr.into(), ip.into(), 128),
+            _ => return false,
+        };
+        let mask = u128::MAX
+            .checked_shl(bits - u32::from(self.prefix))
+            .unwrap_or_default();
+
+        (addr ^ ip) & mask == 0
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| format!("invalid address: {}", s))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|&prefix| prefix <= max)
+                .ok_or_else(|| format!("invalid prefix length: {}", s))?,
+            None => max,
+        };
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl<'de> Deserialize<'de> for IpRange {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Daily allowances of each API key or JWT subject, 0 meaning unlimited.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct QuotaConfig {
+    pub enabled: bool,
+    /// Admin and shorten API requests
+    pub requests_per_day: u64,
+    /// Routes created, through `POST /api/routes`, `/api/shorten` or imports
+    pub creates_per_day: u64,
+    /// Overrides by API key id or JWT subject
+    pub keys: BTreeMap<String, QuotaLimits>,
+    /// Limits shared by every caller of a tenant, by tenant id
+    pub tenants: BTreeMap<String, QuotaLimits>,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            requests_per_day: 0,
+            creates_per_day: 1000,
+            keys: BTreeMap::new(),
+            tenants: BTreeMap::new(),
+        }
+    }
+}
+
+/// Replaces the global limits it sets for one caller.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct QuotaLimits {
+    pub requests_per_day: Option<u64>,
+    pub creates_per_day: Option<u64>,
+}
+
+/// Cross-origin access to the admin API, for dashboards served from
+/// another origin. Off while `allowed_origins` is empty.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// Origins like `https://dash.example.com`, or `*` for any
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    /// Let browsers send cookies and `Authorization`, not with `*` anywhere
+    pub allow_credentials: bool,
+    /// Seconds browsers may cache a preflight answer
+    pub max_age: u64,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: ["GET", "POST", "PUT", "DELETE"].map(String::from).into(),
+            allowed_headers: ["authorization", "content-type"].map(String::from).into(),
+            allow_credentials: false,
+            max_age: 600,
+        }
+    }
+}
+
+/// OpenID Connect sign-in for the dashboard, and exchange of ID tokens for
+/// Roads JWTs. Off while `issuer_url` is unset, and needs `auth.jwt_secret`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct OidcConfig {
+    /// Like `https://accounts.google.com`, where discovery starts
+    pub issuer_url: Option<String>,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    /// `/admin/oidc/callback` on the public address of the admin listener
+    pub redirect_url: Option<String>,
+    pub scopes: Vec<String>,
+    /// Claim naming the user, a dotted path for nested ones
+    pub user_claim: String,
+    /// Claim listing the user's roles or groups, a dotted path for nested
+    /// ones like `realm_access.roles`
+    pub roles_claim: String,
+    /// Roles that make users admins
+    pub admin_roles: Vec<String>,
+    /// Roles allowed to sign in, anyone when empty
+    pub allowed_roles: Vec<String>,
+    /// Scopes granted to users that aren't admins, admins get every scope
+    pub user_scopes: Vec<String>,
+    /// Audiences accepted in exchanged ID tokens besides `client_id`
+    pub audiences: Vec<String>,
+    /// Seconds the JWTs handed out stay valid
+    pub token_ttl: u64,
+}
+
+impl Default for OidcConfig {
+    fn default() -> Self {
+        Self {
+            issuer_url: None,
+            client_id: String::new(),
+            client_secret: None,
+            redirect_url: None,
+            scopes: ["openid", "email", "profile"].map(String::from).into(),
+            user_claim: "email".into(),
+            roles_claim: "groups".into(),
+            admin_roles: Vec::new(),
+            allowed_roles: Vec::new(),
+            user_scopes: ["routes:read", "routes:write", "stats:read"]
+                .map(String::from)
+                .into(),
+            audiences: Vec::new(),
+            token_ttl: 8 * 3600,
+        }
+    }
+}
+
+/// The route admin API over gRPC, see `proto/admin.proto`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct GrpcConfig {
+    /// Serve it on `port` of `host`, in plaintext
+    pub enabled: bool,
+    pub port: u16,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 50051,
+        }
+    }
+}
+
+/// Active checks of proxy upstreams, failing ones being taken out of
+/// rotation until they pass again.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct HealthCheckConfig {
+    pub enabled: bool,
+    pub kind: HealthCheckKind,
+    /// Path `http` checks get, passing on a 2xx or 3xx status
+    pub path: String,
+    /// Seconds between two rounds of checks
+    pub interval: u64,
+    /// Seconds a check may take before it fails
+    pub timeout: u64,
+    /// Passed checks in a row bringing a down upstream back
+    pub healthy_threshold: u32,
+    /// Failed checks in a row taking an upstream down
+    pub unhealthy_threshold: u32,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            kind: HealthCheckKind::Http,
+            path: "/".into(),
+            interval: 10,
+            timeout: 2,
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthCheckKind {
+    /// GET `path`
+    Http,
+    /// Only open a connection
+    Tcp,
+}
+
+/// Defaults of every proxy-mode route.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ProxyConfig {
+    /// Seconds to connect to an upstream
+    pub connect_timeout: u64,
+    /// Seconds without hearing from the upstream, 0 for no limit
+    pub read_timeout: u64,
+    /// Seconds for the whole exchange, 0 for no limit
+    pub total_timeout: u64,
+    /// Whether upstreams may be loopback, link-local or private addresses,
+    /// or hostnames resolving to them. Off, routes can't reach into the
+    /// network of the proxy
+    pub allow_private_upstreams: bool,
+    /// Applied before the route's own
+    pub request_headers: HeaderRules,
+    pub cache: ResponseCacheConfig,
+    pub dns: DnsConfig,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: 5,
+            read_timeout: 60,
+            total_timeout: 0,
+            allow_private_upstreams: false,
+            request_headers: HeaderRules::default(),
+            cache: ResponseCacheConfig::default(),
+            dns: DnsConfig::default(),
+        }
+    }
+}
+
+/// Caching of the GET responses of proxy routes, for as long as their
+/// `Cache-Control` or the route's `cache_ttl` says.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ResponseCacheConfig {
+    pub enabled: bool,
+    /// Megabytes of response bodies kept
+    pub capacity: u64,
+    /// Bytes of the biggest body kept, those without a length never are
+    pub max_size: u64,
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            capacity: 64,
+            max_size: 1024 * 1024,
+        }
+    }
+}
+
+/// Resolution of upstream hostnames, cached in process.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DnsConfig {
+    /// `https://` URL of a DoH server or `tls://host[:port]` of a DoT one,
+    /// the system resolver when unset
+    pub upstream: Option<String>,
+    /// Seconds the addresses of a host are kept, 0 resolving it for every
+    /// connection
+    pub ttl: u64,
+    /// Seconds a host found not to exist, or to have no addresses, is kept,
+    /// 0 for none. Other failures, timeouts included, never are
+    pub negative_ttl: u64,
+    /// Hostnames kept
+    pub capacity: u64,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            upstream: None,
+            ttl: 30,
+            negative_ttl: 5,
+            capacity: 10000,
+        }
+    }
+}
+
+/// Gzip of proxied responses and the dashboard, for clients accepting it.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    /// Routes can turn it on or off for themselves
+    pub enabled: bool,
+    /// Bytes a body needs to be compressed, those of unknown size always are
+    pub min_size: u64,
+    /// Compressed content types, `text/` matching every text type
+    pub content_types: Vec<String>,
+    /// From 1, fastest, to 9, smallest
+    pub level: u32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_size: 1024,
+            content_types: vec![
+                "text/".into(),
+                "application/json".into(),
+                "application/javascript".into(),
+                "application/xml".into(),
+                "image/svg+xml".into(),
+            ],
+            level: 6,
+        }
+    }
+}
+
+/// Largest request bodies taken, bigger ones being answered 413.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct BodyLimitConfig {
+    /// Bytes of admin API bodies, route imports included
+    pub api: usize,
+    /// Bytes of bodies sent to proxy routes, 0 for no limit
+    pub proxy: u64,
+}
+
+impl Default for BodyLimitConfig {
+    fn default() -> Self {
+        Self {
+            api: 2 * 1024 * 1024,
+            proxy: 10 * 1024 * 1024,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file at `path` (if any) and applies environment
+    /// overrides on top of it.
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let mut config = match path {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// The configured listeners, or the plain `host:port` one plus the TLS
+    /// one when `[tls]` is enabled.
+    pub fn listeners(&self) -> Vec<ListenerConfig> {
+        if !self.listeners.is_empty() {
+            return self.listeners.clone();
+        }
+
+        let mut listeners = vec![ListenerConfig {
+            addr: ListenAddr::Tcp(SocketAddr::from((self.host, self.port))),
+            tls: false,
+            mode: None,
+            proxy_protocol: self.proxy_protocol,
+            serve: Service::all(),
+        }];
+        if self.tls.enabled {
+            listeners.push(ListenerConfig {
+                addr: ListenAddr::Tcp(SocketAddr::from((self.host, self.tls.port))),
+                tls: true,
+                mode: None,
+                proxy_protocol: self.proxy_protocol,
+                serve: Service::all(),
+            });
+        }
+
+        listeners
+    }
+
+    /// Whether `new` differs in anything but the hot-reloadable settings.
+    pub fn needs_restart(&self, new: &Config) -> bool {
+        let reloaded = Config {
+            log_level: new.log_level.clone(),
+            ..self.clone()
+        };
+
+        reloaded != *new
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let listeners = self.listeners();
+        for (i, listener) in listeners.iter().enumerate() {
+            let taken = listeners[..i]
+                .iter()
+                .find(|other| overlaps(&other.addr, &listener.addr));
+            if let Some(other) = taken {
+                return Err(ConfigError::Invalid(format!(
+                    "listeners {} and {} bind the same address",
+                    other.addr, listener.addr
+                )));
+            }
+
+            let unix = matches!(listener.addr, ListenAddr::Unix(_));
+            if unix && listener.tls {
+                return Err(ConfigError::Invalid(format!(
+                    "TLS listeners need a TCP address, got {}",
+                    listener.addr
+                )));
+            }
+            if unix && listener.proxy_protocol {
+                return Err(ConfigError::Invalid(format!(
+                    "PROXY protocol needs a TCP address, got {}",
+                    listener.addr
+                )));
+            }
+        }
+
+        if self.database.max_connections == 0
+            || self.database.min_connections > self.database.max_connections
+        {
+            return Err(ConfigError::Invalid(
+                "database.max_connections must be positive and at least min_connections".into(),
+            ));
+        }
+
+        if self.tracking.batch_size == 0 || self.tracking.queue_size == 0 {
+            return Err(ConfigError::Invalid(
+                "tracking.batch_size and tracking.queue_size must be positive".into(),
+            ));
+        }
+        if let Some(url) = &self.events.nats_url {
+            if !url.starts_with("nats://") && !url.starts_with("tls://") {
+                return Err(ConfigError::Invalid(format!(
+                    "events.nats_url {} is not a nats:// or tls:// URL",
+                    url
+                )));
+            }
+        }
+        if self.events.queue_size == 0 || self.events.subject.is_empty() {
+            return Err(ConfigError::Invalid(
+                "events.queue_size must be positive and events.subject set".into(),
+            ));
+        }
+
+        if let Some(name) = self.utm.keys().find(|name| !name.starts_with("utm_")) {
+            return Err(ConfigError::Invalid(format!(
+                "utm.{} is not a UTM parameter",
+                name
+            )));
+        }
+        response_headers::check(&self.response_headers).map_err(ConfigError::Invalid)?;
+        error_pages::check(&self.error_pages).map_err(ConfigError::Invalid)?;
+        fallback::check(&self.fallback).map_err(ConfigError::Invalid)?;
+
+        let mut alphabet: Vec<char> = self.shorten.alphabet.chars().collect();
+        alphabet.sort_unstable();
+        alphabet.dedup();
+        if self.shorten.length == 0 || alphabet.len() < 2 {
+            return Err(ConfigError::Invalid(
+                "shorten needs a positive length and an alphabet of 2 characters or more".into(),
+            ));
+        }
+        if alphabet.iter().any(|&c| c == '/' || !slug::is_slug_char(c)) {
+            return Err(ConfigError::Invalid(
+                "shorten.alphabet can only contain ASCII letters, digits and `-_.~`".into(),
+            ));
+        }
+
+        if self.slugs.min_length == 0 || self.slugs.min_length > self.slugs.max_length {
+            return Err(ConfigError::Invalid(
+                "slugs.min_length must be positive and at most slugs.max_length".into(),
+            ));
+        }
+        if !(self.slugs.min_length..=self.slugs.max_length).contains(&self.shorten.length) {
+            return Err(ConfigError::Invalid(
+                "shorten.length must be within slugs.min_length and slugs.max_length".into(),
+            ));
+        }
+
+        if self.qr.size == 0 || self.qr.size > qr::MAX_SIZE {
+            return Err(ConfigError::Invalid(format!(
+                "qr.size must be 1 to {} pixels",
+                qr::MAX_SIZE
+            )));
+        }
+
+        if self.maintenance.refresh_interval == 0 {
+            return Err(ConfigError::Invalid(
+                "maintenance.refresh_interval must be positive".into(),
+            ));
+        }
+
+        if self.passwords.cookie_ttl == 0 {
+            return Err(ConfigError::Invalid(
+                "passwords.cookie_ttl must be positive".into(),
+            ));
+        }
+
+        if self.signing.secret.as_deref().is_some_and(str::is_empty) {
+            return Err(ConfigError::Invalid("signing.secret can't be empty".into()));
+        }
+
+        if self.trash.purge_interval == 0 {
+            return Err(ConfigError::Invalid(
+                "trash.purge_interval must be positive".into(),
+            ));
+        }
+
+        let endpoints = &self.webhooks.endpoints;
+        if let Some(endpoint) = endpoints.iter().find(|endpoint| !is_http_url(&endpoint.url)) {
+            return Err(ConfigError::Invalid(format!(
+                "webhook URL {} must be an http or https URL",
+                endpoint.url
+            )));
+        }
+        if self.webhooks.timeout == 0
+            || self.webhooks.attempts == 0
+            || self.webhooks.expiry_interval == 0
+        {
+            return Err(ConfigError::Invalid(
+                "webhooks.timeout, webhooks.attempts and webhooks.expiry_interval must be \
+                 positive"
+                    .into(),
+            ));
+        }
+
+        let rates = [&self.rate_limit.redirects, &self.rate_limit.api];
+        if rates
+            .iter()
+            .any(|rate| !rate.rate.is_finite() || rate.rate <= 0.0 || rate.burst == 0) {
+            return Err(ConfigError::Invalid(
+                "rate_limit rates and bursts must be positive".into(),
+            ));
+        }
+
+        self.validate_cors()?;
+        self.validate_oidc()?;
+
+        let checks = &self.health_checks;
+        if checks.interval == 0
+            || checks.timeout == 0
+            || checks.healthy_threshold == 0
+            || checks.unhealthy_threshold == 0
+        {
+            return Err(ConfigError::Invalid(
+                "health_checks.interval, timeout and thresholds must be positive".into(),
+            ));
+        }
+        if !checks.path.starts_with('/') {
+            return Err(ConfigError::Invalid(format!(
+                "health_checks.path {} must start with /",
+                checks.path
+            )));
+        }
+
+        if !(1..=9).contains(&self.compression.level) {
+            return Err(ConfigError::Invalid(format!(
+                "compression.level must be between 1 and 9, got {}",
+                self.compression.level
+            )));
+        }
+
+        self.proxy
+            .request_headers
+            .check()
+            .map_err(|err| ConfigError::Invalid(format!("proxy.request_headers: {}", err)))?;
+        if let Some(upstream) = &self.proxy.dns.upstream {
+            let url = match upstream.strip_prefix("tls://") {
+                Some(addr) => crate::dns::dot_addr(addr),
+                None => upstream.clone(),
+            };
+            let valid = url.parse::<hyper::Uri>().is_ok_and(|uri| {
+                uri.scheme_str() == Some("https") && uri.host().is_some_and(|host| !host.is_empty())
+            });
+            if !valid {
+                return Err(ConfigError::Invalid(format!(
+                    "proxy.dns.upstream {} is not an https:// DoH URL or a tls:// DoT server",
+                    upstream
+                )));
+            }
+        }
+
+        if ![404, 410].contains(&self.expired.status) {
+            return Err(ConfigError::Invalid(format!(
+                "expired.status must be 404 or 410, got {}",
+                self.expired.status
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn validate_cors(&self) -> Result<(), ConfigError> {
+        let cors = &self.cors;
+        let origin = cors
+            .allowed_origins
+            .iter()
+            .find(|origin| *origin != "*" && !is_origin(origin));
+        if let Some(origin) = origin {
+            return Err(ConfigError::Invalid(format!(
+                "CORS origin {} must be a scheme and host, like https://example.com",
+                origin
+            )));
+        }
+        let method = cors
+            .allowed_methods
+            .iter()
+            .find(|method| *method != "*" && method.parse::<hyper::Method>().is_err());
+        if let Some(method) = method {
+            return Err(ConfigError::Invalid(format!("Invalid CORS method: {}", method)));
+        }
+        let name = cors
+            .allowed_headers
+            .iter()
+            .find(|name| *name != "*" && name.parse::<hyper::header::HeaderName>().is_err());
+        if let Some(name) = name {
+            return Err(ConfigError::Invalid(format!("Invalid CORS header: {}", name)));
+        }
+
+        let wildcard = [&cors.allowed_origins, &cors.allowed_methods, &cors.allowed_headers]
+            .iter()
+            .any(|values| values.iter().any(|value| value == "*"));
+        if cors.allow_credentials && wildcard {
+            return Err(ConfigError::Invalid(
+                "cors.allow_credentials can't be used with a `*` origin, method or header"
+                    .into(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn validate_oidc(&self) -> Result<(), ConfigError> {
+        let oidc = &self.oidc;
+        let Some(issuer) = &oidc.issuer_url else {
+            return Ok(());
+        };
+
+        if !issuer.starts_with("https://") && !issuer.starts_with("http://") {
+            return Err(ConfigError::Invalid(format!(
+                "oidc.issuer_url {} must be an http or https URL",
+                issuer
+            )));
+        }
+        if oidc.client_id.is_empty() || oidc.redirect_url.is_none() {
+            return Err(ConfigError::Invalid(
+                "oidc needs a client_id and a redirect_url".into(),
+            ));
+        }
+        if self.auth.jwt_secret.is_none() {
+            return Err(ConfigError::Invalid(
+                "oidc needs auth.jwt_secret to sign the tokens it hands out".into(),
+            ));
+        }
+        if let Some(scope) = oidc
+            .user_scopes
+            .iter()
+            .find(|scope| scope.parse::<auth::Scope>().is_err())
+        {
+            return Err(ConfigError::Invalid(format!(
+                "Invalid scope in oidc.user_scopes: {}",
+                scope
+            )));
+        }
+        if oidc.token_ttl == 0 {
+            return Err(ConfigError::Invalid("oidc.token_ttl must be positive".into()));
+        }
+
+        Ok(())
+    }
+
+    fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let raw = fs::read_to_string(path)?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => Ok(serde_yaml::from_str(&raw)?),
+            _ => Ok(toml::from_str(&raw)?),
+        }
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        if let Ok(host) = env::var("HOST") {
+            self.host = parse_host(&host).ok_or(ConfigError::Env("HOST"))?;
+        }
+        if let Ok(port) = env::var("PORT") {
+            self.port = port.parse().map_err(|_| ConfigError::Env("PORT"))?;
+        }
+        if let Ok(url) = env::var("DATABASE_URL") {
+            self.database_url = url;
+        }
+        if let Ok(url) = env::var("DATABASE_REPLICA_URL") {
+            self.database_replica_url = Some(url);
+        }
+        if let Ok(secret) = env::var("JWT_SECRET") {
+            self.auth.jwt_secret = Some(secret);
+        }
+        if let Ok(secret) = env::var("OIDC_CLIENT_SECRET") {
+            self.oidc.client_secret = Some(secret);
+        }
+        if let Ok(endpoint) = env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+            self.tracing.otlp_endpoint = Some(endpoint);
+        }
+        if let Ok(name) = env::var("OTEL_SERVICE_NAME") {
+            self.tracing.service_name = name;
+        }
+        if let Ok(level) = env::var("RUST_LOG") {
+            self.log_level = level;
+        }
+        if let Ok(format) = env::var("LOG_FORMAT") {
+            self.log_format = match format.as_str() {
+                "text" => LogFormat::Text,
+                "json" => LogFormat::Json,
+                _ => return Err(ConfigError::Env("LOG_FORMAT")),
+            };
+        }
+
+        Ok(())
+    }
+}
+
+/// Accepts IPv6 addresses with or without brackets.
+fn parse_host(host: &str) -> Option<IpAddr> {
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+
+    host.parse().ok()
+}
+
+fn deserialize_host<'de, D: Deserializer<'de>>(deserializer: D) -> Result<IpAddr, D::Error> {
+    let host = String::deserialize(deserializer)?;
+
+    parse_host(&host).ok_or_else(|| serde::de::Error::custom(format!("invalid host: {}", host)))
+}
+
+/// Whether binding both would fail, `[::]` also covers IPv4.
+fn overlaps(a: &ListenAddr, b: &ListenAddr) -> bool {
+    match (a, b) {
+        (ListenAddr::Unix(a), ListenAddr::Unix(b)) => a == b,
+        (ListenAddr::Tcp(a), ListenAddr::Tcp(b)) if a.port() == b.port() => {
+            let covers = |a: &SocketAddr, b: &SocketAddr| {
+                a.ip() == b.ip()
+                    || (a.ip().is_unspecified() && (a.is_ipv6() || b.is_ipv4()))
+            };
+            covers(a, b) || covers(b, a)
+        }
+        _ => false,
+    }
+}
+
+fn is_http_url(url: &str) -> bool {
+    url.parse::<hyper::Uri>().is_ok_and(|uri| {
+        matches!(uri.scheme_str(), Some("http" | "https")) && uri.host().is_some()
+    })
+}
+
+/// Whether `origin` is a bare `scheme://host[:port]`, as browsers send in
+/// `Origin`.
+fn is_origin(origin: &str) -> bool {
+    is_http_url(origin)
+        && !origin.ends_with('/')
+        && origin
+            .parse::<hyper::Uri>()
+            .is_ok_and(|uri| uri.path() == "/" && uri.query().is_none())
+}
+
+/// Resolves the config file from `ROADS_CONFIG`, falling back to
+/// `roads.toml` in the working directory when it exists.
+pub fn path() -> Option<PathBuf> {
+    if let Ok(path) = env::var("ROADS_CONFIG") {
+        return Some(path.into());
+    }
+
+    let default = PathBuf::from(DEFAULT_CONFIG_FILE);
+    default.exists().then_some(default)
+}
+
+/// Polls `path` for changes and hands every successfully reloaded config to
+/// `on_change`. Invalid files are logged and skipped.
+pub fn watch<F>(path: PathBuf, mut current: Config, on_change: F)
+    where
+        F: Fn(&Config, &Config) + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut last_modified = modified(&path);
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let modified = modified(&path);
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            match Config::load(Some(&path)) {
+                Ok(config) if config != current => {
+                    debug!("reloaded config from {}", path.display());
+                    on_change(&current, &config);
+                    current = config;
+                }
+                Ok(_) => {}
+                Err(err) => warn!("ignoring invalid config {}: {}", path.display(), err),
+            }
+        }
+    });
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Error while reading config file: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Invalid TOML config: {0}")]
+    Toml(#[from] toml::de::Error),
+
+    #[error("Invalid YAML config: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+
+    #[error("Invalid value for environment variable {0}")]
+    Env(&'static str),
+
+    #[error("Invalid config: {0}")]
+    Invalid(String),
+}
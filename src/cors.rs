@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use axum::http::{HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
+
+use crate::config::CorsConfig;
+
+/// The CORS layer of the admin API, `None` without allowed origins.
+pub fn layer(config: &CorsConfig) -> Option<CorsLayer> {
+    if config.allowed_origins.is_empty() {
+        return None;
+    }
+
+    let wildcard = |values: &[String]| values.iter().any(|value| value == "*");
+    let origins = if wildcard(&config.allowed_origins) {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(config.allowed_origins.iter().map(|origin| {
+            HeaderValue::from_str(origin).expect("CORS origins are validated with the config")
+        }))
+    };
+    let methods = if wildcard(&config.allowed_methods) {
+        AllowMethods::any()
+    } else {
+        AllowMethods::list(config.allowed_methods.iter().map(|method| {
+            method
+                .parse::<Method>()
+                .expect("CORS methods are validated with the config")
+        }))
+    };
+    let headers = if wildcard(&config.allowed_headers) {
+        AllowHeaders::any()
+    } else {
+        AllowHeaders::list(config.allowed_headers.iter().map(|name| {
+            name.parse::<HeaderName>()
+                .expect("CORS headers are validated with the config")
+        }))
+    };
+
+    Some(
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(methods)
+            .allow_headers(headers)
+            .allow_credentials(config.allow_credentials)
+            .max_age(Duration::from_secs(config.max_age)),
+    )
+}
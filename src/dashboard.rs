@@ -0,0 +1,49 @@
+use axum::{
+    http::header,
+    response::IntoResponse,
+    Router, routing::get,
+};
+
+use crate::router::AppState;
+
+const INDEX: &str = include_str!("../dashboard/index.html");
+const SCRIPT: &str = include_str!("../dashboard/dashboard.js");
+const STYLE: &str = include_str!("../dashboard/dashboard.css");
+
+/// Only the dashboard's own files, and calls to the API it's served with
+const CONTENT_SECURITY_POLICY: &str =
+    "default-src 'none'; script-src 'self'; style-src 'self'; connect-src 'self'; \
+     img-src 'self'; form-action 'self'; frame-ancestors 'none'; base-uri 'none'";
+
+/// The web dashboard on `/admin`. The page itself is public, it signs in
+/// with an API key or JWT and does everything through `/api`, so it can
+/// only do what its credentials allow.
+pub fn dashboard_routes() -> Router<AppState> {
+    Router::new()
+        .route("/admin", get(index))
+        .route("/admin/", get(index))
+        .route(
+            "/admin/dashboard.js",
+            get(|| asset("text/javascript; charset=utf-8", SCRIPT)),
+        )
+        .route(
+            "/admin/dashboard.css",
+            get(|| asset("text/css; charset=utf-8", STYLE)),
+        )
+}
+
+async fn index() -> impl IntoResponse {
+    asset("text/html; charset=utf-8", INDEX).await
+}
+
+async fn asset(content_type: &'static str, body: &'static str) -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CONTENT_SECURITY_POLICY, CONTENT_SECURITY_POLICY),
+            (header::CACHE_CONTROL, "no-cache"),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        ],
+        body,
+    )
+}
@@ -0,0 +1,70 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// What a client is, as far as its User-Agent tells. A client is both an
+/// OS and a form factor, e.g. an iPad is `ios` and `tablet`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Device {
+    Ios,
+    Android,
+    Mobile,
+    Tablet,
+    Desktop,
+}
+
+impl Device {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Ios => "ios",
+            Self::Android => "android",
+            Self::Mobile => "mobile",
+            Self::Tablet => "tablet",
+            Self::Desktop => "desktop",
+        }
+    }
+}
+
+impl FromStr for Device {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ios" => Ok(Self::Ios),
+            "android" => Ok(Self::Android),
+            "mobile" => Ok(Self::Mobile),
+            "tablet" => Ok(Self::Tablet),
+            "desktop" => Ok(Self::Desktop),
+            _ => Err(format!("unknown device: {}", s)),
+        }
+    }
+}
+
+/// Every class `user_agent` belongs to. Anything that isn't recognizably
+/// a phone or tablet is a desktop.
+pub fn classify(user_agent: &str) -> Vec<Device> {
+    let mut devices = Vec::with_capacity(2);
+    if user_agent.contains("iPhone") || user_agent.contains("iPod") {
+        devices.extend([Device::Ios, Device::Mobile]);
+    } else if user_agent.contains("iPad") {
+        devices.extend([Device::Ios, Device::Tablet]);
+    } else if user_agent.contains("Android") {
+        // Android tablets leave `Mobile` out of their User-Agent
+        let form = if user_agent.contains("Mobile") {
+            Device::Mobile
+        } else {
+            Device::Tablet
+        };
+        devices.extend([Device::Android, form]);
+    } else if user_agent.contains("Tablet") || user_agent.contains("Kindle") {
+        devices.push(Device::Tablet);
+    } else if user_agent.contains("Mobi") || user_agent.contains("Opera Mini") {
+        devices.push(Device::Mobile);
+    } else {
+        devices.push(Device::Desktop);
+    }
+
+    devices
+}
@@ -0,0 +1,667 @@
+use std::{
+    future::Future,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+    vec,
+};
+
+use hyper::{
+    body::HttpBody,
+    client::{connect::dns::Name, HttpConnector},
+    header,
+    service::Service,
+    Body, Client, Method, Request, StatusCode, Uri,
+};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use moka::{future::Cache, Expiry};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tower::ServiceExt;
+use tracing::{debug, warn};
+
+use crate::config::{DnsConfig, ProxyConfig};
+
+/// Longest a lookup waits on a DoH or DoT server.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+/// Port of DoT servers given without one.
+const DOT_PORT: u16 = 853;
+/// Largest answer read from a DoH server.
+const MAX_ANSWER: usize = 64 * 1024;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+
+/// Upstream hostnames resolved by the system resolver, or by the DoH or DoT
+/// server of `upstream`, what it answered kept in process so that
+/// connections, tunnels' above all, don't wait on it each time. Addresses
+/// are kept for the TTL of their records up to `ttl`, and hosts found not
+/// to exist for `negative_ttl`, other failures not being kept. The system
+/// resolver doesn't tell TTLs, what it answers is kept for `ttl`. Unless
+/// `allow_private` is set, private addresses are dropped from what hosts
+/// resolve to, so that a hostname can't be pointed at the network of the
+/// proxy, be it when created or later on.
+#[derive(Clone)]
+pub struct CachingResolver {
+    /// Unset for the system resolver
+    server: Option<Arc<Server>>,
+    /// Unset when both TTLs are 0
+    lookups: Option<Cache<String, Lookup>>,
+    ttls: Ttls,
+    allow_private: bool,
+}
+
+/// Connects to upstreams through a [`CachingResolver`], refusing private
+/// addresses given as such too unless `[proxy] allow_private_upstreams` is
+/// set.
+#[derive(Clone)]
+pub struct UpstreamConnector {
+    http: HttpConnector<CachingResolver>,
+    allow_private: bool,
+}
+
+/// A resolver queried over an encrypted connection, so that lookups can't
+/// be read or tampered with on the way.
+enum Server {
+    /// DNS over HTTPS, RFC 8484
+    Https {
+        client: Client<HttpsConnector<HttpConnector>>,
+        url: Uri,
+    },
+    /// DNS over TLS, RFC 7858, a connection per lookup
+    Tls {
+        connector: HttpsConnector<HttpConnector>,
+        /// `https://` with the host and port, which the connector wants
+        addr: Uri,
+    },
+}
+
+#[derive(Clone)]
+enum Lookup {
+    /// With the lowest TTL of the records, when known
+    Found(Arc<Vec<SocketAddr>>, Option<Duration>),
+    Failed(io::ErrorKind, Arc<str>),
+}
+
+#[derive(Clone, Copy)]
+struct Ttls {
+    found: Duration,
+    failed: Duration,
+}
+
+impl Ttls {
+    fn of(&self, lookup: &Lookup) -> Duration {
+        match lookup {
+            Lookup::Found(_, ttl) => ttl.map_or(self.found, |ttl| ttl.min(self.found)),
+            // NXDOMAIN or no addresses, anything else might not happen again
+            Lookup::Failed(io::ErrorKind::NotFound, _) => self.failed,
+            Lookup::Failed(..) => Duration::ZERO,
+        }
+    }
+}
+
+impl Expiry<String, Lookup> for Ttls {
+    fn expire_after_create(
+        &self,
+        _host: &String,
+        lookup: &Lookup,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Some(self.of(lookup))
+    }
+}
+
+impl CachingResolver {
+    pub fn new(config: &DnsConfig, allow_private: bool) -> Self {
+        let ttls = Ttls {
+            found: Duration::from_secs(config.ttl),
+            failed: Duration::from_secs(config.negative_ttl),
+        };
+        let lookups = (config.ttl > 0 || config.negative_ttl > 0).then(|| {
+            Cache::builder()
+                .max_capacity(config.capacity)
+                .expire_after(ttls)
+                .build()
+        });
+
+        Self {
+            server: config.upstream.as_deref().map(|url| Arc::new(Server::new(url))),
+            lookups,
+            ttls,
+            allow_private,
+        }
+    }
+
+    /// The addresses of `host`, with port 0 for the connector to set,
+    /// public ones only unless `allow_private` is set.
+    async fn resolve(self, host: String) -> io::Result<vec::IntoIter<SocketAddr>> {
+        let addrs = self.cached_lookup(&host).await.into_addrs()?;
+        if self.allow_private {
+            return Ok(addrs);
+        }
+
+        let public: Vec<_> = addrs.filter(|addr| is_public(addr.ip())).collect();
+        if public.is_empty() {
+            return Err(refused(&format!("{} only resolves to private addresses", host)));
+        }
+
+        Ok(public.into_iter())
+    }
+
+    async fn cached_lookup(&self, host: &str) -> Lookup {
+        let Some(lookups) = &self.lookups else {
+            record_lookup("uncached");
+            return self.lookup(host).await;
+        };
+        if let Some(lookup) = lookups.get(host).await {
+            record_lookup(match lookup {
+                Lookup::Found(..) => "hit",
+                Lookup::Failed(..) => "negative_hit",
+            });
+            return lookup;
+        }
+        record_lookup("miss");
+
+        let lookup = self.lookup(host).await;
+        if !self.ttls.of(&lookup).is_zero() {
+            lookups.insert(host.to_owned(), lookup.clone()).await;
+        }
+
+        lookup
+    }
+
+    async fn lookup(&self, host: &str) -> Lookup {
+        let started = Instant::now();
+        let looked_up = match &self.server {
+            Some(server) => match tokio::time::timeout(LOOKUP_TIMEOUT, server.lookup(host)).await {
+                Ok(looked_up) => looked_up,
+                // never kept, see `Ttls::of`
+                Err(_) => Err(io::ErrorKind::TimedOut.into()),
+            },
+            None => tokio::net::lookup_host((host, 0))
+                .await
+                .map(|addrs| (addrs.map(|addr| addr.ip()).collect(), None)),
+        };
+        metrics::histogram!("roads_dns_lookup_duration_seconds")
+            .record(started.elapsed().as_secs_f64());
+
+        match looked_up {
+            Ok((ips, ttl)) if !ips.is_empty() => {
+                let addrs = ips.into_iter().map(|ip| SocketAddr::new(ip, 0)).collect();
+                Lookup::Found(Arc::new(addrs), ttl)
+            }
+            Ok(_) => Lookup::Failed(io::ErrorKind::NotFound, "no addresses found".into()),
+            Err(err) => {
+                debug!("resolving {} failed: {}", host, err);
+                metrics::counter!("roads_dns_failures_total").increment(1);
+                Lookup::Failed(err.kind(), err.to_string().into())
+            }
+        }
+    }
+}
+
+impl UpstreamConnector {
+    pub fn new(config: &ProxyConfig) -> Self {
+        let resolver = CachingResolver::new(&config.dns, config.allow_private_upstreams);
+        let mut http = HttpConnector::new_with_resolver(resolver);
+        http.enforce_http(false);
+        let connect_timeout = Duration::from_secs(config.connect_timeout);
+        http.set_connect_timeout(Some(connect_timeout).filter(|timeout| !timeout.is_zero()));
+
+        Self {
+            http,
+            allow_private: config.allow_private_upstreams,
+        }
+    }
+}
+
+impl Service<Uri> for UpstreamConnector {
+    type Response = TcpStream;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<TcpStream, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        // the HTTP connector doesn't resolve addresses, they're checked here
+        let host = uri.host().unwrap_or_default();
+        let literal = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>();
+        if let Some(ip) = literal.ok().filter(|ip| !self.allow_private && !is_public(*ip)) {
+            let err = refused(&format!("{} is a private address", ip));
+            return Box::pin(async move { Err(err.into()) });
+        }
+
+        let connecting = self.http.call(uri);
+        Box::pin(async move { connecting.await.map_err(Into::into) })
+    }
+}
+
+/// Whether `ip` is reachable from anywhere, as opposed to loopback,
+/// link-local, private (RFC 1918, RFC 4193), shared (RFC 6598), multicast
+/// and reserved addresses, and those of "this network". IPv6 addresses
+/// carrying an IPv4 one are judged by it.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            // multicast, reserved and broadcast from 224.0.0.0 on
+            !(a == 0
+                || a >= 224
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || (a == 100 && b & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match embedded_ipv4(ip) {
+            Some(ip) => is_public(ip.into()),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_multicast() || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+/// The IPv4 address of IPv4-mapped and -compatible (`::a.b.c.d`, `::` and
+/// `::1` included), NAT64 (`64:ff9b::/96`) and 6to4 (`2002::/16`) addresses.
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let ipv4 = |high: u16, low: u16| Ipv4Addr::from(u32::from(high) << 16 | u32::from(low));
+    match ip.segments() {
+        [0x64, 0xff9b, 0, 0, 0, 0, high, low] => Some(ipv4(high, low)),
+        [0x2002, high, low, ..] => Some(ipv4(high, low)),
+        _ => ip.to_ipv4(),
+    }
+}
+
+fn refused(message: &str) -> io::Error {
+    warn!("refused upstream: {}", message);
+    metrics::counter!("roads_proxy_blocked_upstreams_total").increment(1);
+    io::Error::new(io::ErrorKind::PermissionDenied, message.to_owned())
+}
+
+impl Server {
+    /// `url` is `https://` for DoH or `tls://` for DoT, as checked by the
+    /// config.
+    fn new(url: &str) -> Self {
+        let connector = HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_only()
+            .enable_http1()
+            .build();
+
+        match url.strip_prefix("tls://") {
+            Some(addr) => Self::Tls {
+                connector,
+                addr: dot_addr(addr).parse().expect("DoT servers are checked by the config"),
+            },
+            None => Self::Https {
+                client: Client::builder().build(connector),
+                url: url.parse().expect("DoH URLs are checked by the config"),
+            },
+        }
+    }
+
+    /// The A and AAAA records of `host`, with their lowest TTL.
+    async fn lookup(&self, host: &str) -> io::Result<(Vec<IpAddr>, Option<Duration>)> {
+        let queries = [encode_query(0, host, TYPE_A)?, encode_query(1, host, TYPE_AAAA)?];
+        let answers = match self {
+            Self::Https { client, url } => {
+                let [a, aaaa] = queries;
+                let (a, aaaa) = tokio::try_join!(post(client, url, a), post(client, url, aaaa))?;
+                vec![a, aaaa]
+            }
+            Self::Tls { connector, addr } => exchange(connector, addr, &queries).await?,
+        };
+
+        let mut ips = Vec::new();
+        let mut min_ttl: Option<u32> = None;
+        for answer in answers {
+            let (found, ttl) = decode_answer(&answer)?;
+            ips.extend(found);
+            min_ttl = match (min_ttl, ttl) {
+                (Some(min_ttl), Some(ttl)) => Some(min_ttl.min(ttl)),
+                (min_ttl, ttl) => min_ttl.or(ttl),
+            };
+        }
+
+        Ok((ips, min_ttl.map(|ttl| Duration::from_secs(ttl.into()))))
+    }
+}
+
+/// `https://` with the host and port of a DoT server given as `host`,
+/// `host:port`, `[v6]` or `[v6]:port`.
+pub fn dot_addr(addr: &str) -> String {
+    let addr = addr.trim_end_matches('/');
+    // the `]` of IPv6 addresses keeps them from passing for a port
+    let has_port = addr
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+    if has_port {
+        format!("https://{}", addr)
+    } else {
+        format!("https://{}:{}", addr, DOT_PORT)
+    }
+}
+
+/// Sends `query` to a DoH server, returning its answer.
+async fn post(
+    client: &Client<HttpsConnector<HttpConnector>>,
+    url: &Uri,
+    query: Vec<u8>,
+) -> io::Result<Vec<u8>> {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header(header::CONTENT_TYPE, "application/dns-message")
+        .header(header::ACCEPT, "application/dns-message")
+        .body(Body::from(query))
+        .expect("DoH requests are valid");
+    let response = client.request(request).await.map_err(io::Error::other)?;
+    if response.status() != StatusCode::OK {
+        return Err(io::Error::other(format!("DoH server answered {}", response.status())));
+    }
+
+    let mut body = response.into_body();
+    let mut answer = Vec::new();
+    while let Some(chunk) = body.data().await {
+        answer.extend_from_slice(&chunk.map_err(io::Error::other)?);
+        if answer.len() > MAX_ANSWER {
+            return Err(invalid("DoH answer too large"));
+        }
+    }
+
+    Ok(answer)
+}
+
+/// Sends `queries` to a DoT server over one connection, returning the
+/// answers in the same order.
+async fn exchange(
+    connector: &HttpsConnector<HttpConnector>,
+    addr: &Uri,
+    queries: &[Vec<u8>],
+) -> io::Result<Vec<Vec<u8>>> {
+    let mut stream = connector
+        .clone()
+        .oneshot(addr.clone())
+        .await
+        .map_err(io::Error::other)?;
+    for query in queries {
+        let len = u16::try_from(query.len()).expect("queries fit in a DNS message");
+        stream.write_all(&len.to_be_bytes()).await?;
+        stream.write_all(query).await?;
+    }
+    stream.flush().await?;
+
+    // answers may come in any order, told apart by the id of their query
+    let mut answers = vec![Vec::new(); queries.len()];
+    for _ in queries {
+        let len = stream.read_u16().await?;
+        let mut answer = vec![0; len.into()];
+        stream.read_exact(&mut answer).await?;
+        let id = answer.get(..2).map_or(u16::MAX, |id| u16::from_be_bytes([id[0], id[1]]));
+        match answers.get_mut(usize::from(id)) {
+            Some(slot) if slot.is_empty() => *slot = answer,
+            _ => return Err(invalid("DoT answer to an unknown query")),
+        }
+    }
+
+    Ok(answers)
+}
+
+/// A recursive query for the `qtype` records of `host`.
+fn encode_query(id: u16, host: &str, qtype: u16) -> io::Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(host.len() + 18);
+    query.extend_from_slice(&id.to_be_bytes());
+    // recursion desired, one question
+    query.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid hostname"));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&qtype.to_be_bytes());
+    // class IN
+    query.extend_from_slice(&1u16.to_be_bytes());
+
+    Ok(query)
+}
+
+/// The addresses of the A and AAAA records of `answer`, with their lowest
+/// TTL. Other records, like the CNAMEs leading to them, are skipped.
+fn decode_answer(answer: &[u8]) -> io::Result<(Vec<IpAddr>, Option<u32>)> {
+    let header = answer.get(..12).ok_or_else(|| invalid("truncated DNS header"))?;
+    let flags = u16::from_be_bytes([header[2], header[3]]);
+    if flags & 0x8000 == 0 {
+        return Err(invalid("DNS message is not an answer"));
+    }
+    // the records left out might have been the addresses, lower TTLs too
+    if flags & 0x0200 != 0 {
+        return Err(invalid("DNS answer is truncated"));
+    }
+    match flags & 0x000f {
+        0 => {}
+        3 => return Err(io::Error::new(io::ErrorKind::NotFound, "no such host")),
+        rcode => return Err(io::Error::other(format!("DNS server failed with rcode {}", rcode))),
+    }
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    let records = u16::from_be_bytes([header[6], header[7]]);
+
+    let mut at = 12;
+    for _ in 0..questions {
+        // type and class
+        at = skip_name(answer, at)? + 4;
+    }
+    let mut ips = Vec::new();
+    let mut min_ttl: Option<u32> = None;
+    for _ in 0..records {
+        at = skip_name(answer, at)?;
+        let fixed = answer.get(at..at + 10).ok_or_else(|| invalid("truncated DNS record"))?;
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+        let len = usize::from(u16::from_be_bytes([fixed[8], fixed[9]]));
+        at += 10;
+        let data = answer.get(at..at + len).ok_or_else(|| invalid("truncated DNS record"))?;
+        at += len;
+
+        let ip = match (rtype, data) {
+            (TYPE_A, &[a, b, c, d]) => IpAddr::from(Ipv4Addr::new(a, b, c, d)),
+            (TYPE_AAAA, data) if data.len() == 16 => {
+                let octets: [u8; 16] = data.try_into().expect("AAAA records hold 16 bytes");
+                IpAddr::from(Ipv6Addr::from(octets))
+            }
+            _ => continue,
+        };
+        ips.push(ip);
+        min_ttl = Some(min_ttl.map_or(ttl, |min_ttl| min_ttl.min(ttl)));
+    }
+
+    Ok((ips, min_ttl))
+}
+
+/// Where the name at `at` ends, compressed or not.
+fn skip_name(message: &[u8], mut at: usize) -> io::Result<usize> {
+    loop {
+        let len = *message.get(at).ok_or_else(|| invalid("truncated DNS name"))?;
+        match len {
+            0 => return Ok(at + 1),
+            // a pointer to the rest of the name
+            len if len & 0xc0 == 0xc0 => return Ok(at + 2),
+            len => at += 1 + usize::from(len),
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+impl Lookup {
+    fn into_addrs(self) -> io::Result<vec::IntoIter<SocketAddr>> {
+        match self {
+            Self::Found(addrs, _) => Ok(Vec::clone(&addrs).into_iter()),
+            Self::Failed(kind, message) => Err(io::Error::new(kind, message.to_string())),
+        }
+    }
+}
+
+impl Service<Name> for CachingResolver {
+    type Response = vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        Box::pin(self.clone().resolve(name.as_str().to_owned()))
+    }
+}
+
+fn record_lookup(result: &'static str) {
+    metrics::counter!("roads_dns_lookups_total", "result" => result).increment(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn proxy_config(allow_private_upstreams: bool) -> ProxyConfig {
+        ProxyConfig {
+            allow_private_upstreams,
+            ..ProxyConfig::default()
+        }
+    }
+
+    #[test]
+    fn tells_public_addresses() {
+        let public = [
+            "1.1.1.1",
+            "100.128.0.1",
+            "2606:4700::1111",
+            "::ffff:8.8.8.8",
+            "::8.8.8.8",
+            "64:ff9b::808:808",
+            "2002:808:808::1",
+        ];
+        for ip in public {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        let private = [
+            "0.0.0.0",
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ];
+        for ip in private {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn refuses_ipv4_carried_by_ipv6() {
+        for ip in ["::127.0.0.1", "::10.0.0.1", "::a9fe:a9fe"] {
+            assert!(!is_public(ip.parse().unwrap()), "compatible {}", ip);
+        }
+        for ip in ["64:ff9b::7f00:1", "64:ff9b::10.0.0.1", "64:ff9b::192.168.1.1"] {
+            assert!(!is_public(ip.parse().unwrap()), "NAT64 {}", ip);
+        }
+        for ip in ["2002:7f00:1::1", "2002:a00:1::", "2002:a9fe:a9fe::1"] {
+            assert!(!is_public(ip.parse().unwrap()), "6to4 {}", ip);
+        }
+    }
+
+    #[test]
+    fn refuses_multicast_and_reserved_addresses() {
+        for ip in ["224.0.0.1", "239.255.255.250", "240.0.0.1", "254.1.2.3"] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["ff02::1", "ff05::1:3", "ff0e::1", "::ffff:224.0.0.1"] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn keeps_only_missing_hosts_among_failures() {
+        let ttls = Ttls {
+            found: Duration::from_secs(30),
+            failed: Duration::from_secs(5),
+        };
+        let failed = |kind| Lookup::Failed(kind, "failed".into());
+        assert_eq!(ttls.of(&failed(io::ErrorKind::NotFound)), ttls.failed);
+        assert!(ttls.of(&failed(io::ErrorKind::TimedOut)).is_zero());
+        assert!(ttls.of(&failed(io::ErrorKind::ConnectionRefused)).is_zero());
+        assert!(ttls.of(&failed(io::ErrorKind::Other)).is_zero());
+    }
+
+    #[test]
+    fn refuses_truncated_answers() {
+        let mut answer = encode_query(0, "example.com", TYPE_A).unwrap();
+        // an answer, one record
+        answer[2] |= 0x80;
+        answer[7] = 1;
+        answer.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 93, 184, 215, 14]);
+        let (ips, ttl) = decode_answer(&answer).unwrap();
+        assert_eq!(ips, [IpAddr::from([93, 184, 215, 14])]);
+        assert_eq!(ttl, Some(60));
+
+        answer[2] |= 0x02;
+        let err = decode_answer(&answer).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn refuses_hostnames_resolving_to_private_addresses() {
+        let resolver = CachingResolver::new(&DnsConfig::default(), false);
+        let err = resolver.resolve("localhost".to_owned()).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+        let resolver = CachingResolver::new(&DnsConfig::default(), true);
+        let addrs: Vec<_> = resolver.resolve("localhost".to_owned()).await.unwrap().collect();
+        assert!(addrs.iter().all(|addr| addr.ip().is_loopback()));
+    }
+
+    #[tokio::test]
+    async fn refuses_private_upstreams_unless_allowed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        for host in ["127.0.0.1", "localhost"] {
+            let uri: Uri = format!("http://{}:{}", host, port).parse().unwrap();
+
+            let err = UpstreamConnector::new(&proxy_config(false))
+                .oneshot(uri.clone())
+                .await
+                .unwrap_err();
+            let err = err.downcast_ref::<io::Error>().map(io::Error::kind).or_else(|| {
+                let source = std::error::Error::source(&*err)?;
+                source.downcast_ref::<io::Error>().map(io::Error::kind)
+            });
+            assert_eq!(err, Some(io::ErrorKind::PermissionDenied), "{}", host);
+
+            let connector = UpstreamConnector::new(&proxy_config(true));
+            connector.oneshot(uri).await.unwrap();
+            listener.accept().await.unwrap();
+        }
+    }
+}
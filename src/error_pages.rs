@@ -0,0 +1,74 @@
+use std::{collections::BTreeMap, io, path::PathBuf};
+
+use axum::{
+    body::Body,
+    http::{header, Response, StatusCode},
+};
+
+use crate::preview;
+
+/// HTML pages of the errors routes answer with, by status, instead of a
+/// plain text message. Templates can use `{{status}}`, `{{message}}`,
+/// `{{slug}}`, `{{host}}` and `{{path}}`, HTML-escaped.
+#[derive(Default)]
+pub struct ErrorPages {
+    templates: BTreeMap<u16, String>,
+}
+
+/// What an error page is about.
+pub struct ErrorContext<'a> {
+    /// The path asked for without its leading `/`, the slug and what's
+    /// past it
+    pub slug: &'a str,
+    pub host: Option<&'a str>,
+    pub path: &'a str,
+}
+
+impl ErrorPages {
+    /// Reads the templates of `[error_pages]`, whose keys `check` let
+    /// through.
+    pub async fn load(config: &BTreeMap<String, PathBuf>) -> io::Result<Self> {
+        let mut templates = BTreeMap::new();
+        for (status, path) in config {
+            let Ok(status) = status.parse() else {
+                continue;
+            };
+            templates.insert(status, tokio::fs::read_to_string(path).await?);
+        }
+
+        Ok(Self { templates })
+    }
+
+    /// The page for `status`, when there's a template for it.
+    pub fn render(
+        &self,
+        status: StatusCode,
+        message: &str,
+        context: &ErrorContext,
+    ) -> Option<Response<Body>> {
+        let template = self.templates.get(&status.as_u16())?;
+        let html = template
+            .replace("{{status}}", status.as_str())
+            .replace("{{message}}", &preview::escape(message))
+            .replace("{{slug}}", &preview::escape(context.slug))
+            .replace("{{host}}", &preview::escape(context.host.unwrap_or_default()))
+            .replace("{{path}}", &preview::escape(context.path));
+
+        Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(Body::from(html))
+            .ok()
+    }
+}
+
+/// Whether every key of `[error_pages]` is an error status.
+pub fn check(config: &BTreeMap<String, PathBuf>) -> Result<(), String> {
+    for status in config.keys() {
+        if !status.parse::<u16>().is_ok_and(|status| (400..600).contains(&status)) {
+            return Err(format!("error_pages.{} is not an error status", status));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use futures::{stream, Stream, StreamExt};
+use serde::Serialize;
+
+use crate::{
+    auth,
+    import::Format,
+    store::{Route, StatsQuery, Store, StoreError},
+};
+
+/// Columns of CSV exports, the ones `import` reads plus the counters.
+const CSV_COLUMNS: [&str; 9] = [
+    "host",
+    "slug",
+    "redirect_to",
+    "mode",
+    "match_type",
+    "status_code",
+    "preserve_query",
+    "preserve_path",
+    "hits",
+];
+
+#[derive(Serialize)]
+struct Exported<'a> {
+    #[serde(flatten)]
+    route: &'a Route,
+    /// Tracked hits of all time, when asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clicks: Option<i64>,
+}
+
+/// `routes` in `format`, one chunk per route so large exports never sit in
+/// memory as a whole. CSV keeps to the columns `import` reads, JSON has
+/// every field and can be imported back as is. With `stats`, routes also
+/// get a `clicks` count, looked up as the chunks are produced.
+pub fn chunks(
+    store: Store,
+    routes: Vec<Route>,
+    format: Format,
+    stats: bool,
+) -> impl Stream<Item = Result<String, StoreError>> {
+    let head = match format {
+        Format::Csv => {
+            let mut columns = CSV_COLUMNS.join(",");
+            if stats {
+                columns.push_str(",clicks");
+            }
+            columns + "\n"
+        }
+        Format::Json => "[".to_owned(),
+    };
+    let tail = match format {
+        Format::Csv => String::new(),
+        Format::Json => "\n]\n".to_owned(),
+    };
+
+    let rows = stream::iter(routes.into_iter().enumerate()).then(move |(i, route)| {
+        let store = store.clone();
+        async move {
+            let clicks = if stats {
+                Some(clicks(&store, &route).await?)
+            } else {
+                None
+            };
+
+            Ok(match format {
+                Format::Csv => csv_row(&route, clicks),
+                Format::Json => {
+                    let separator = if i == 0 { "\n" } else { ",\n" };
+                    let json = serde_json::to_string(&Exported {
+                        route: &route,
+                        clicks,
+                    })
+                    .expect("routes serialize to JSON");
+                    format!("{}{}", separator, json)
+                }
+            })
+        }
+    });
+
+    stream::once(async move { Ok(head) })
+        .chain(rows)
+        .chain(stream::once(async move { Ok(tail) }))
+}
+
+async fn clicks(store: &Store, route: &Route) -> Result<i64, StoreError> {
+    let to = auth::now() + 1;
+    let query = StatsQuery {
+        from: 0,
+        to,
+        bucket: to,
+        top: 0,
+    };
+    let stats = store
+        .hit_stats(route.host.as_deref(), &route.slug, &query)
+        .await?;
+
+    Ok(stats.total)
+}
+
+fn csv_row(route: &Route, clicks: Option<i64>) -> String {
+    let mut record = vec![
+        route.host.clone().unwrap_or_default(),
+        route.slug.clone(),
+        route.redirect_to.clone(),
+        route.mode.as_str().to_owned(),
+        route.match_type.as_str().to_owned(),
+        route.status_code.to_string(),
+        route.preserve_query.to_string(),
+        route.preserve_path.to_string(),
+        route.hits.to_string(),
+    ];
+    record.extend(clicks.map(|clicks| clicks.to_string()));
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record(&record)
+        .expect("CSV records write to memory");
+    let row = writer.into_inner().expect("CSV records write to memory");
+
+    String::from_utf8(row).expect("CSV records of strings are UTF-8")
+}
@@ -0,0 +1,122 @@
+use std::{collections::HashMap, io};
+
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Response, StatusCode},
+};
+
+use crate::{
+    config::{FallbackAction, FallbackConfig, FallbackRule},
+    router::{internal_error, normalize_host, route_not_found},
+    store::REDIRECT_STATUSES,
+};
+
+/// What `/` and slugs without a route answer with, by `[fallback]`.
+pub struct Fallback {
+    default: Answer,
+    /// By normalized hostname
+    hosts: HashMap<String, Answer>,
+}
+
+enum Answer {
+    NotFound,
+    Redirect { status: StatusCode, location: HeaderValue },
+    /// HTML from `page`
+    Page(String),
+}
+
+impl Answer {
+    async fn load(rule: &FallbackRule) -> io::Result<Self> {
+        Ok(match rule.action {
+            FallbackAction::NotFound => Self::NotFound,
+            FallbackAction::Redirect => Self::Redirect {
+                status: StatusCode::from_u16(rule.status).unwrap_or(StatusCode::FOUND),
+                location: rule
+                    .url
+                    .as_deref()
+                    .and_then(|url| HeaderValue::from_str(url).ok())
+                    .unwrap_or(HeaderValue::from_static("/")),
+            },
+            FallbackAction::Page => match &rule.page {
+                Some(path) => Self::Page(tokio::fs::read_to_string(path).await?),
+                None => Self::NotFound,
+            },
+        })
+    }
+}
+
+impl Fallback {
+    /// Reads the pages of the rules, which `check` let through.
+    pub async fn load(config: &FallbackConfig) -> io::Result<Self> {
+        let mut hosts = HashMap::new();
+        for (host, rule) in &config.hosts {
+            hosts.insert(normalize_host(host), Answer::load(rule).await?);
+        }
+
+        Ok(Self {
+            default: Answer::load(&config.default).await?,
+            hosts,
+        })
+    }
+
+    /// The answer for `host`, on `/` when `root`, else on an unknown slug.
+    /// `not_found` is an error for `[error_pages]` to fill in.
+    pub fn response(
+        &self,
+        host: Option<&str>,
+        root: bool,
+    ) -> Result<Response<Body>, (StatusCode, String)> {
+        let answer = host
+            .and_then(|host| self.hosts.get(host))
+            .unwrap_or(&self.default);
+
+        match answer {
+            Answer::NotFound => Err(route_not_found()),
+            Answer::Redirect { status, location } => Response::builder()
+                .status(*status)
+                .header(header::LOCATION, location.clone())
+                .body(Body::empty())
+                .map_err(internal_error),
+            Answer::Page(html) => {
+                let status = if root { StatusCode::OK } else { StatusCode::NOT_FOUND };
+                Response::builder()
+                    .status(status)
+                    .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+                    .body(Body::from(html.clone()))
+                    .map_err(internal_error)
+            }
+        }
+    }
+}
+
+/// Whether every rule of `[fallback]` has what its action needs.
+pub fn check(config: &FallbackConfig) -> Result<(), String> {
+    let hosts = config
+        .hosts
+        .iter()
+        .map(|(host, rule)| (format!("fallback.hosts.\"{}\"", host), rule));
+    for (name, rule) in [("fallback".to_owned(), &config.default)].into_iter().chain(hosts) {
+        match rule.action {
+            FallbackAction::NotFound => {}
+            FallbackAction::Redirect => {
+                let url = rule.url.as_deref().unwrap_or_default();
+                if url.is_empty() || HeaderValue::from_str(url).is_err() {
+                    return Err(format!("{} redirects without a valid url", name));
+                }
+                if !REDIRECT_STATUSES.contains(&rule.status) {
+                    return Err(format!(
+                        "{}.status {} is not one of 301, 302, 307 or 308",
+                        name, rule.status
+                    ));
+                }
+            }
+            FallbackAction::Page => {
+                if rule.page.is_none() {
+                    return Err(format!("{} serves a page without a page", name));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,34 @@
+use std::{net::IpAddr, path::Path};
+
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+
+/// Country lookups in a MaxMind GeoLite2/GeoIP2 Country or City database,
+/// loaded in memory once at startup.
+pub struct GeoIp {
+    reader: Reader<Vec<u8>>,
+}
+
+pub struct Location {
+    /// ISO 3166-1 alpha-2 code, e.g. `DE`
+    pub country: String,
+    pub in_eu: bool,
+}
+
+impl GeoIp {
+    pub fn open(path: &Path) -> Result<Self, MaxMindDBError> {
+        Ok(Self {
+            reader: Reader::open_readfile(path)?,
+        })
+    }
+
+    /// `None` for private addresses and the ones missing from the database.
+    pub fn lookup(&self, ip: IpAddr) -> Option<Location> {
+        let record: geoip2::Country = self.reader.lookup(ip).ok()?;
+        let country = record.country?;
+
+        Some(Location {
+            country: country.iso_code?.to_owned(),
+            in_eu: country.is_in_european_union.unwrap_or(false),
+        })
+    }
+}
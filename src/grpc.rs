@@ -0,0 +1,895 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    str::FromStr,
+    task::{Context, Poll},
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Path, Query, State},
+    http::{self, Uri},
+    Json,
+};
+use futures::future::BoxFuture;
+use hyper::{Body, StatusCode};
+use tonic::{
+    body::BoxBody,
+    codec::ProstCodec,
+    server::{Grpc, NamedService, UnaryService},
+    transport::Server,
+    Code, Request, Response, Status,
+};
+use tower::Service;
+use tracing::{debug, info};
+
+use crate::{
+    auth::{Principal, Scope},
+    router::{self, AppState, HostQuery, ListQuery, NewRoute, RouteUpdate},
+    stats,
+    store::{
+        self, Canary, CircuitBreaker, DeviceTarget, GeoTarget, HeaderRules, HitStats,
+        LanguageTarget, Mirror, PathRewrite, Retries, SplitTarget, Timeouts,
+    },
+};
+
+/// `Routes` of `proto/admin.proto`, kept in sync by hand.
+pub mod proto {
+    use std::collections::BTreeMap;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct RouteKey {
+        #[prost(string, optional, tag = "1")]
+        pub host: Option<String>,
+        #[prost(string, tag = "2")]
+        pub slug: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Route {
+        #[prost(string, optional, tag = "1")]
+        pub host: Option<String>,
+        #[prost(string, tag = "2")]
+        pub slug: String,
+        #[prost(string, tag = "3")]
+        pub redirect_to: String,
+        #[prost(string, tag = "4")]
+        pub mode: String,
+        #[prost(uint32, tag = "5")]
+        pub status_code: u32,
+        #[prost(int64, tag = "6")]
+        pub hits: i64,
+        #[prost(string, optional, tag = "7")]
+        pub tenant: Option<String>,
+        #[prost(string, optional, tag = "8")]
+        pub owner: Option<String>,
+        #[prost(string, tag = "9")]
+        pub match_type: String,
+        #[prost(bool, tag = "10")]
+        pub preserve_query: bool,
+        #[prost(bool, tag = "11")]
+        pub preserve_path: bool,
+        #[prost(int64, optional, tag = "12")]
+        pub expires_at: Option<i64>,
+        #[prost(int64, optional, tag = "13")]
+        pub max_hits: Option<i64>,
+        #[prost(message, repeated, tag = "14")]
+        pub geo_targets: Vec<GeoTarget>,
+        #[prost(message, repeated, tag = "15")]
+        pub device_targets: Vec<DeviceTarget>,
+        #[prost(message, repeated, tag = "16")]
+        pub language_targets: Vec<LanguageTarget>,
+        #[prost(message, repeated, tag = "17")]
+        pub split_targets: Vec<SplitTarget>,
+        #[prost(bool, tag = "18")]
+        pub sticky_split: bool,
+        #[prost(btree_map = "string, string", tag = "19")]
+        pub utm: BTreeMap<String, String>,
+        #[prost(btree_map = "string, string", tag = "20")]
+        pub response_headers: BTreeMap<String, String>,
+        #[prost(bool, tag = "21")]
+        pub preview: bool,
+        #[prost(string, optional, tag = "22")]
+        pub password_hash: Option<String>,
+        #[prost(bool, tag = "23")]
+        pub signed: bool,
+        #[prost(int64, optional, tag = "24")]
+        pub deleted_at: Option<i64>,
+        #[prost(message, optional, tag = "25")]
+        pub proxy: Option<ProxyOptions>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GeoTarget {
+        #[prost(string, repeated, tag = "1")]
+        pub countries: Vec<String>,
+        #[prost(string, tag = "2")]
+        pub redirect_to: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct DeviceTarget {
+        #[prost(string, repeated, tag = "1")]
+        pub devices: Vec<String>,
+        #[prost(string, tag = "2")]
+        pub redirect_to: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct LanguageTarget {
+        #[prost(string, repeated, tag = "1")]
+        pub languages: Vec<String>,
+        #[prost(string, tag = "2")]
+        pub redirect_to: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SplitTarget {
+        #[prost(uint32, tag = "1")]
+        pub weight: u32,
+        #[prost(string, tag = "2")]
+        pub redirect_to: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ProxyOptions {
+        #[prost(string, repeated, tag = "1")]
+        pub upstreams: Vec<String>,
+        #[prost(string, tag = "2")]
+        pub balance: String,
+        #[prost(bool, tag = "3")]
+        pub sticky_sessions: bool,
+        #[prost(message, optional, tag = "4")]
+        pub canary: Option<Canary>,
+        #[prost(message, optional, tag = "5")]
+        pub mirror: Option<Mirror>,
+        #[prost(message, optional, tag = "6")]
+        pub retries: Option<Retries>,
+        #[prost(message, optional, tag = "7")]
+        pub circuit_breaker: Option<CircuitBreaker>,
+        #[prost(message, optional, tag = "8")]
+        pub request_headers: Option<HeaderRules>,
+        #[prost(message, optional, tag = "9")]
+        pub rewrite: Option<PathRewrite>,
+        #[prost(bool, optional, tag = "10")]
+        pub compression: Option<bool>,
+        #[prost(uint64, optional, tag = "11")]
+        pub cache_ttl: Option<u64>,
+        #[prost(message, optional, tag = "12")]
+        pub timeouts: Option<Timeouts>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Canary {
+        #[prost(string, tag = "1")]
+        pub upstream: String,
+        #[prost(double, tag = "2")]
+        pub percent: f64,
+        #[prost(string, optional, tag = "3")]
+        pub header: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Mirror {
+        #[prost(string, tag = "1")]
+        pub upstream: String,
+        #[prost(double, optional, tag = "2")]
+        pub percent: Option<f64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Retries {
+        #[prost(uint32, tag = "1")]
+        pub attempts: u32,
+        #[prost(uint64, optional, tag = "2")]
+        pub backoff: Option<u64>,
+        #[prost(uint64, optional, tag = "3")]
+        pub max_backoff: Option<u64>,
+        #[prost(double, optional, tag = "4")]
+        pub budget: Option<f64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CircuitBreaker {
+        #[prost(double, tag = "1")]
+        pub error_rate: f64,
+        #[prost(uint32, optional, tag = "2")]
+        pub min_requests: Option<u32>,
+        #[prost(uint64, optional, tag = "3")]
+        pub window: Option<u64>,
+        #[prost(uint64, optional, tag = "4")]
+        pub cooldown: Option<u64>,
+        #[prost(string, optional, tag = "5")]
+        pub fallback: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HeaderRules {
+        #[prost(btree_map = "string, string", tag = "1")]
+        pub set: BTreeMap<String, String>,
+        #[prost(btree_map = "string, string", tag = "2")]
+        pub add: BTreeMap<String, String>,
+        #[prost(string, repeated, tag = "3")]
+        pub remove: Vec<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PathRewrite {
+        #[prost(string, tag = "1")]
+        pub pattern: String,
+        #[prost(string, tag = "2")]
+        pub replacement: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Timeouts {
+        #[prost(uint64, optional, tag = "1")]
+        pub read: Option<u64>,
+        #[prost(uint64, optional, tag = "2")]
+        pub total: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListRoutesRequest {
+        #[prost(bool, tag = "1")]
+        pub deleted: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListRoutesResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub routes: Vec<Route>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CreateRouteRequest {
+        #[prost(message, optional, tag = "1")]
+        pub route: Option<Route>,
+        #[prost(string, optional, tag = "2")]
+        pub password: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct UpdateRouteRequest {
+        #[prost(message, optional, tag = "1")]
+        pub key: Option<RouteKey>,
+        #[prost(message, optional, tag = "2")]
+        pub route: Option<Route>,
+        #[prost(string, optional, tag = "3")]
+        pub password: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct DeleteRouteResponse {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StatsRequest {
+        #[prost(message, optional, tag = "1")]
+        pub key: Option<RouteKey>,
+        #[prost(int64, optional, tag = "2")]
+        pub from: Option<i64>,
+        #[prost(int64, optional, tag = "3")]
+        pub to: Option<i64>,
+        #[prost(string, optional, tag = "4")]
+        pub bucket: Option<String>,
+        #[prost(uint32, optional, tag = "5")]
+        pub top: Option<u32>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Stats {
+        #[prost(int64, tag = "1")]
+        pub total: i64,
+        #[prost(message, repeated, tag = "2")]
+        pub buckets: Vec<Bucket>,
+        #[prost(message, repeated, tag = "3")]
+        pub referrers: Vec<Count>,
+        #[prost(message, repeated, tag = "4")]
+        pub user_agents: Vec<Count>,
+        #[prost(message, repeated, tag = "5")]
+        pub countries: Vec<Count>,
+        #[prost(message, repeated, tag = "6")]
+        pub variants: Vec<Count>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Bucket {
+        #[prost(int64, tag = "1")]
+        pub start: i64,
+        #[prost(int64, tag = "2")]
+        pub hits: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Count {
+        #[prost(string, tag = "1")]
+        pub value: String,
+        #[prost(int64, tag = "2")]
+        pub hits: i64,
+    }
+}
+
+/// Serves the `Routes` service on `addr` until `stop` resolves.
+pub async fn serve(
+    state: AppState,
+    addr: SocketAddr,
+    stop: impl Future<Output = ()>,
+) -> Result<(), tonic::transport::Error> {
+    info!("gRPC admin API listening on {}", addr);
+    Server::builder()
+        .add_service(RoutesService { state })
+        .serve_with_shutdown(addr, stop)
+        .await
+}
+
+/// `roads.admin.v1.Routes`, answering with the REST handlers.
+#[derive(Clone)]
+struct RoutesService {
+    state: AppState,
+}
+
+impl NamedService for RoutesService {
+    const NAME: &'static str = "roads.admin.v1.Routes";
+}
+
+impl Service<http::Request<Body>> for RoutesService {
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        let state = self.state.clone();
+        let path = request.uri().path().to_owned();
+        let method = path.strip_prefix("/roads.admin.v1.Routes/").unwrap_or_default();
+        match method {
+            "ListRoutes" => unary(request, state, list_routes),
+            "GetRoute" => unary(request, state, get_route),
+            "CreateRoute" => unary(request, state, create_route),
+            "UpdateRoute" => unary(request, state, update_route),
+            "DeleteRoute" => unary(request, state, delete_route),
+            "GetStats" => unary(request, state, get_stats),
+            _ => {
+                let unimplemented = Status::unimplemented(format!("no method {}", path)).to_http();
+                Box::pin(async move { Ok(unimplemented) })
+            }
+        }
+    }
+}
+
+/// A unary method answered by `handler`.
+struct Unary<F> {
+    state: AppState,
+    handler: F,
+}
+
+impl<F, Fut, Req, Res> UnaryService<Req> for Unary<F>
+where
+    F: Fn(AppState, Principal, Req) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Result<Res, Status>> + Send + 'static,
+    Req: Send + 'static,
+{
+    type Response = Res;
+    type Future = BoxFuture<'static, Result<Response<Res>, Status>>;
+
+    fn call(&mut self, request: Request<Req>) -> Self::Future {
+        let state = self.state.clone();
+        let handler = self.handler.clone();
+        let addr = request.remote_addr();
+        let (metadata, _, message) = request.into_parts();
+        let (mut parts, ()) = http::Request::new(()).into_parts();
+        parts.headers = metadata.into_headers();
+        if let Some(addr) = addr {
+            parts.extensions.insert(ConnectInfo(addr));
+        }
+
+        Box::pin(async move {
+            // counted against the quotas like REST requests
+            let principal = match Principal::from_request_parts(&mut parts, &state).await {
+                Ok(principal) => principal,
+                Err(rejection) => {
+                    let code = rejection.status();
+                    let body = hyper::body::to_bytes(rejection.into_body())
+                        .await
+                        .unwrap_or_default();
+                    return Err(status(code, &String::from_utf8_lossy(&body)));
+                }
+            };
+            handler(state, principal, message).await.map(Response::new)
+        })
+    }
+}
+
+fn unary<F, Fut, Req, Res>(
+    request: http::Request<Body>,
+    state: AppState,
+    handler: F,
+) -> BoxFuture<'static, Result<http::Response<BoxBody>, Infallible>>
+where
+    F: Fn(AppState, Principal, Req) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Result<Res, Status>> + Send + 'static,
+    Req: prost::Message + Default + Send + 'static,
+    Res: prost::Message + Send + 'static,
+{
+    Box::pin(async move {
+        let mut grpc = Grpc::new(ProstCodec::<Res, Req>::default());
+        Ok(grpc.unary(Unary { state, handler }, request).await)
+    })
+}
+
+async fn list_routes(
+    state: AppState,
+    principal: Principal,
+    request: proto::ListRoutesRequest,
+) -> Result<proto::ListRoutesResponse, Status> {
+    let query = ListQuery {
+        deleted: request.deleted,
+    };
+    let Json(routes) = router::list_routes(principal, State(state), Query(query))
+        .await
+        .map_err(into_status)?;
+
+    Ok(proto::ListRoutesResponse {
+        routes: routes.iter().map(to_proto).collect(),
+    })
+}
+
+async fn get_route(
+    state: AppState,
+    principal: Principal,
+    key: proto::RouteKey,
+) -> Result<proto::Route, Status> {
+    principal.require(Scope::RoutesRead).map_err(into_status)?;
+
+    let host = key.host.as_deref().map(router::normalize_host);
+    let route = router::get_accessible(&state, &principal, host.as_deref(), &key.slug)
+        .await
+        .map_err(into_status)?;
+    Ok(to_proto(&route))
+}
+
+async fn create_route(
+    state: AppState,
+    principal: Principal,
+    request: proto::CreateRouteRequest,
+) -> Result<proto::Route, Status> {
+    let route = request.route.ok_or_else(|| Status::invalid_argument("Missing route"))?;
+    let new = NewRoute {
+        route: store::Route {
+            hits: 0,
+            deleted_at: None,
+            ..from_proto(route).map_err(invalid_route)?
+        },
+        password: request.password,
+    };
+    let (_, Json(route)) = router::add_route(principal, State(state), Json(new))
+        .await
+        .map_err(into_status)?;
+
+    debug!("route created over gRPC: {}", route.label());
+    Ok(to_proto(&route))
+}
+
+async fn update_route(
+    state: AppState,
+    principal: Principal,
+    request: proto::UpdateRouteRequest,
+) -> Result<proto::Route, Status> {
+    let key = request.key.unwrap_or_default();
+    let route = request.route.ok_or_else(|| Status::invalid_argument("Missing route"))?;
+    let route = from_proto(route).map_err(invalid_route)?;
+    let update = RouteUpdate::new(route, request.password);
+    let query = HostQuery { host: key.host };
+    let Json(route) =
+        router::update_route(principal, State(state), Path(key.slug), Query(query), Json(update))
+            .await
+            .map_err(into_status)?;
+
+    Ok(to_proto(&route))
+}
+
+async fn delete_route(
+    state: AppState,
+    principal: Principal,
+    key: proto::RouteKey,
+) -> Result<proto::DeleteRouteResponse, Status> {
+    let host = key.host.as_deref().map(router::normalize_host);
+    router::remove_route(&principal, &state, host.as_deref(), &key.slug)
+        .await
+        .map_err(into_status)?;
+
+    Ok(proto::DeleteRouteResponse {})
+}
+
+async fn get_stats(
+    state: AppState,
+    principal: Principal,
+    request: proto::StatsRequest,
+) -> Result<proto::Stats, Status> {
+    let key = request.key.unwrap_or_default();
+    // the REST handler reads its parameters off the query
+    let params = [
+        ("host", key.host),
+        ("from", request.from.map(|from| from.to_string())),
+        ("to", request.to.map(|to| to.to_string())),
+        ("bucket", request.bucket),
+        ("top", request.top.map(|top| top.to_string())),
+    ];
+    let params: Vec<_> = params
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value?)))
+        .collect();
+    let query = serde_urlencoded::to_string(params)
+        .map_err(|_| Status::invalid_argument("Invalid stats parameters"))?;
+    let uri: Uri = format!("/?{}", query)
+        .parse()
+        .map_err(|_| Status::invalid_argument("Invalid stats parameters"))?;
+    let Json(stats) = stats::route_stats(principal, state, &key.slug, &uri)
+        .await
+        .map_err(into_status)?;
+
+    Ok(stats_to_proto(stats))
+}
+
+fn to_proto(route: &store::Route) -> proto::Route {
+    proto::Route {
+        host: route.host.clone(),
+        slug: route.slug.clone(),
+        redirect_to: route.redirect_to.clone(),
+        mode: route.mode.as_str().to_owned(),
+        status_code: route.status_code.into(),
+        hits: route.hits,
+        tenant: route.tenant.clone(),
+        owner: route.owner.clone(),
+        match_type: route.match_type.as_str().to_owned(),
+        preserve_query: route.preserve_query,
+        preserve_path: route.preserve_path,
+        expires_at: route.expires_at,
+        max_hits: route.max_hits,
+        geo_targets: route
+            .geo_targets
+            .iter()
+            .map(|target| proto::GeoTarget {
+                countries: target.countries.clone(),
+                redirect_to: target.redirect_to.clone(),
+            })
+            .collect(),
+        device_targets: route
+            .device_targets
+            .iter()
+            .map(|target| proto::DeviceTarget {
+                devices: target.devices.iter().map(|device| device.as_str().into()).collect(),
+                redirect_to: target.redirect_to.clone(),
+            })
+            .collect(),
+        language_targets: route
+            .language_targets
+            .iter()
+            .map(|target| proto::LanguageTarget {
+                languages: target.languages.clone(),
+                redirect_to: target.redirect_to.clone(),
+            })
+            .collect(),
+        split_targets: route
+            .split_targets
+            .iter()
+            .map(|target| proto::SplitTarget {
+                weight: target.weight,
+                redirect_to: target.redirect_to.clone(),
+            })
+            .collect(),
+        sticky_split: route.sticky_split,
+        utm: route.utm.clone(),
+        response_headers: route.response_headers.clone(),
+        preview: route.preview,
+        password_hash: route.password_hash.clone(),
+        signed: route.signed,
+        deleted_at: route.deleted_at,
+        proxy: (!route.proxy.is_default()).then(|| proxy_to_proto(&route.proxy)),
+    }
+}
+
+fn proxy_to_proto(proxy: &store::ProxyOptions) -> proto::ProxyOptions {
+    let retries = &proxy.retries;
+    let breaker = &proxy.circuit_breaker;
+    let headers = &proxy.request_headers;
+
+    proto::ProxyOptions {
+        upstreams: proxy.upstreams.clone(),
+        balance: proxy.balance.as_str().to_owned(),
+        sticky_sessions: proxy.sticky_sessions,
+        canary: proxy.canary.as_ref().map(|canary| proto::Canary {
+            upstream: canary.upstream.clone(),
+            percent: canary.percent,
+            header: Some(canary.header.clone()),
+        }),
+        mirror: proxy.mirror.as_ref().map(|mirror| proto::Mirror {
+            upstream: mirror.upstream.clone(),
+            percent: Some(mirror.percent),
+        }),
+        retries: (!retries.is_off()).then_some(proto::Retries {
+            attempts: retries.attempts,
+            backoff: Some(retries.backoff),
+            max_backoff: Some(retries.max_backoff),
+            budget: Some(retries.budget),
+        }),
+        circuit_breaker: (!breaker.is_off()).then(|| proto::CircuitBreaker {
+            error_rate: breaker.error_rate,
+            min_requests: Some(breaker.min_requests),
+            window: Some(breaker.window),
+            cooldown: Some(breaker.cooldown),
+            fallback: breaker.fallback.clone(),
+        }),
+        request_headers: (!headers.is_empty()).then(|| proto::HeaderRules {
+            set: headers.set.clone(),
+            add: headers.add.clone(),
+            remove: headers.remove.clone(),
+        }),
+        rewrite: proxy.rewrite.as_ref().map(|rewrite| proto::PathRewrite {
+            pattern: rewrite.pattern.clone(),
+            replacement: rewrite.replacement.clone(),
+        }),
+        compression: proxy.compression,
+        cache_ttl: proxy.cache_ttl,
+        timeouts: (!proxy.timeouts.is_unset()).then_some(proto::Timeouts {
+            read: proxy.timeouts.read,
+            total: proxy.timeouts.total,
+        }),
+    }
+}
+
+/// The route of a request, unset fields taking the defaults of the REST API.
+fn from_proto(route: proto::Route) -> Result<store::Route, String> {
+    let device_targets = route
+        .device_targets
+        .into_iter()
+        .map(|target| {
+            Ok(DeviceTarget {
+                devices: target
+                    .devices
+                    .iter()
+                    .map(|device| device.parse())
+                    .collect::<Result<_, _>>()?,
+                redirect_to: target.redirect_to,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+    let status_code = match route.status_code {
+        0 => store::default_status_code(),
+        code => u16::try_from(code).map_err(|_| format!("invalid status {}", code))?,
+    };
+
+    Ok(store::Route {
+        host: route.host,
+        slug: route.slug,
+        redirect_to: route.redirect_to,
+        mode: parse_or_default(&route.mode)?,
+        proxy: route.proxy.map(proxy_from_proto).transpose()?.unwrap_or_default(),
+        match_type: parse_or_default(&route.match_type)?,
+        preserve_query: route.preserve_query,
+        preserve_path: route.preserve_path,
+        status_code,
+        expires_at: route.expires_at,
+        max_hits: route.max_hits,
+        hits: route.hits,
+        geo_targets: route
+            .geo_targets
+            .into_iter()
+            .map(|target| GeoTarget {
+                countries: target.countries,
+                redirect_to: target.redirect_to,
+            })
+            .collect(),
+        device_targets,
+        language_targets: route
+            .language_targets
+            .into_iter()
+            .map(|target| LanguageTarget {
+                languages: target.languages,
+                redirect_to: target.redirect_to,
+            })
+            .collect(),
+        split_targets: route
+            .split_targets
+            .into_iter()
+            .map(|target| SplitTarget {
+                weight: target.weight,
+                redirect_to: target.redirect_to,
+            })
+            .collect(),
+        sticky_split: route.sticky_split,
+        utm: route.utm,
+        response_headers: route.response_headers,
+        preview: route.preview,
+        password_hash: route.password_hash,
+        signed: route.signed,
+        deleted_at: route.deleted_at,
+        tenant: route.tenant,
+        owner: route.owner,
+    })
+}
+
+fn proxy_from_proto(proxy: proto::ProxyOptions) -> Result<store::ProxyOptions, String> {
+    let retries = proxy.retries.map(|retries| {
+        let default = Retries::default();
+        Retries {
+            attempts: retries.attempts,
+            backoff: retries.backoff.unwrap_or(default.backoff),
+            max_backoff: retries.max_backoff.unwrap_or(default.max_backoff),
+            budget: retries.budget.unwrap_or(default.budget),
+        }
+    });
+    let circuit_breaker = proxy.circuit_breaker.map(|breaker| {
+        let default = CircuitBreaker::default();
+        CircuitBreaker {
+            error_rate: breaker.error_rate,
+            min_requests: breaker.min_requests.unwrap_or(default.min_requests),
+            window: breaker.window.unwrap_or(default.window),
+            cooldown: breaker.cooldown.unwrap_or(default.cooldown),
+            fallback: breaker.fallback,
+        }
+    });
+
+    Ok(store::ProxyOptions {
+        upstreams: proxy.upstreams,
+        balance: parse_or_default(&proxy.balance)?,
+        sticky_sessions: proxy.sticky_sessions,
+        canary: proxy.canary.map(|canary| Canary {
+            upstream: canary.upstream,
+            percent: canary.percent,
+            header: canary.header.unwrap_or_else(store::default_canary_header),
+        }),
+        mirror: proxy.mirror.map(|mirror| Mirror {
+            upstream: mirror.upstream,
+            percent: mirror.percent.unwrap_or_else(store::default_mirror_percent),
+        }),
+        retries: retries.unwrap_or_default(),
+        circuit_breaker: circuit_breaker.unwrap_or_default(),
+        request_headers: proxy
+            .request_headers
+            .map(|headers| HeaderRules {
+                set: headers.set,
+                add: headers.add,
+                remove: headers.remove,
+            })
+            .unwrap_or_default(),
+        rewrite: proxy.rewrite.map(|rewrite| PathRewrite {
+            pattern: rewrite.pattern,
+            replacement: rewrite.replacement,
+        }),
+        compression: proxy.compression,
+        cache_ttl: proxy.cache_ttl,
+        timeouts: proxy
+            .timeouts
+            .map(|timeouts| Timeouts {
+                read: timeouts.read,
+                total: timeouts.total,
+            })
+            .unwrap_or_default(),
+    })
+}
+
+/// `value` parsed, the default when empty as proto3 leaves unset strings.
+fn parse_or_default<T>(value: &str) -> Result<T, String>
+where
+    T: FromStr<Err = String> + Default,
+{
+    if value.is_empty() {
+        return Ok(T::default());
+    }
+
+    value.parse()
+}
+
+fn invalid_route(err: String) -> Status {
+    Status::invalid_argument(format!("Invalid route: {}", err))
+}
+
+fn stats_to_proto(stats: HitStats) -> proto::Stats {
+    let counts = |counts: Vec<store::Count>| {
+        counts
+            .into_iter()
+            .map(|count| proto::Count {
+                value: count.value,
+                hits: count.hits,
+            })
+            .collect()
+    };
+
+    proto::Stats {
+        total: stats.total,
+        buckets: stats
+            .buckets
+            .into_iter()
+            .map(|bucket| proto::Bucket {
+                start: bucket.start,
+                hits: bucket.hits,
+            })
+            .collect(),
+        referrers: counts(stats.referrers),
+        user_agents: counts(stats.user_agents),
+        countries: counts(stats.countries),
+        variants: counts(stats.variants),
+    }
+}
+
+/// The gRPC status of an error of the REST handlers.
+fn into_status((code, message): (StatusCode, String)) -> Status {
+    status(code, &message)
+}
+
+fn status(code: StatusCode, message: &str) -> Status {
+    let code = match code {
+        StatusCode::BAD_REQUEST => Code::InvalidArgument,
+        StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+        StatusCode::FORBIDDEN => Code::PermissionDenied,
+        StatusCode::NOT_FOUND => Code::NotFound,
+        StatusCode::CONFLICT => Code::AlreadyExists,
+        StatusCode::PAYLOAD_TOO_LARGE | StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+        StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+        _ => Code::Internal,
+    };
+    Status::new(code, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_survive_the_proto() {
+        let route: store::Route = serde_json::from_value(serde_json::json!({
+            "host": "example.com",
+            "slug": "shop",
+            "redirect_to": "https://shop.example.com",
+            "mode": "proxy",
+            "match_type": "pattern",
+            "status_code": 307,
+            "device_targets": [
+                {"devices": ["ios", "tablet"], "redirect_to": "https://m.example.com"},
+            ],
+            "split_targets": [{"weight": 3, "redirect_to": "https://b.example.com"}],
+            "utm": {"utm_source": "grpc"},
+            "proxy": {
+                "upstreams": ["https://a.internal", "https://b.internal"],
+                "balance": "least_connections",
+                "canary": {"upstream": "https://c.internal", "percent": 5.0},
+                "retries": {"attempts": 2},
+                "request_headers": {"set": {"x-env": "prod"}, "remove": ["cookie"]},
+                "timeouts": {"read": 10},
+            },
+        }))
+        .unwrap();
+
+        let back = from_proto(to_proto(&route)).unwrap();
+        assert_eq!(serde_json::to_value(back).unwrap(), serde_json::to_value(route).unwrap());
+    }
+
+    #[test]
+    fn unset_fields_take_the_rest_defaults() {
+        let route = from_proto(proto::Route {
+            slug: "docs".into(),
+            redirect_to: "https://docs.example.com".into(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(route.status_code, 308);
+        assert_eq!(route.mode, store::RouteMode::Redirect);
+        assert!(route.proxy.is_default());
+
+        let route = proto::Route {
+            mode: "tunnel".into(),
+            ..Default::default()
+        };
+        assert!(from_proto(route).is_err());
+    }
+
+    #[test]
+    fn maps_unavailable_and_too_large() {
+        assert_eq!(status(StatusCode::SERVICE_UNAVAILABLE, "").code(), Code::Unavailable);
+        assert_eq!(status(StatusCode::PAYLOAD_TOO_LARGE, "").code(), Code::ResourceExhausted);
+    }
+}
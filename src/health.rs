@@ -0,0 +1,67 @@
+use std::{future::Future, time::Duration};
+
+use axum::{extract::State, http::StatusCode, Json, Router, routing::get};
+use serde::Serialize;
+
+use crate::{router::AppState, store::StoreError};
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Ok,
+    /// Serving, but a non-critical dependency is down
+    Degraded,
+    Unavailable,
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    status: Status,
+    database: String,
+    cache: String,
+}
+
+pub fn health_routes() -> Router<AppState> {
+    Router::new()
+        .route("/ping", get(|| async { "Pong" }))
+        .route("/healthz", get(|| async { "ok" }))
+        .route("/readyz", get(readyz))
+}
+
+/// Ready as long as the database answers, the cache is only reported as
+/// degraded since lookups still work without it.
+async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+    let database = check(state.store.ping()).await;
+    let cache = check(state.cache.ping()).await;
+
+    let (code, status) = match (&database, &cache) {
+        (Err(_), _) => (StatusCode::SERVICE_UNAVAILABLE, Status::Unavailable),
+        (Ok(()), Err(_)) => (StatusCode::OK, Status::Degraded),
+        (Ok(()), Ok(())) => (StatusCode::OK, Status::Ok),
+    };
+
+    (
+        code,
+        Json(Readiness {
+            status,
+            database: describe(database),
+            cache: describe(cache),
+        }),
+    )
+}
+
+async fn check<F>(probe: F) -> Result<(), String>
+    where
+        F: Future<Output = Result<(), StoreError>>,
+{
+    match tokio::time::timeout(CHECK_TIMEOUT, probe).await {
+        Ok(result) => result.map_err(|err| err.to_string()),
+        Err(_) => Err("timed out".into()),
+    }
+}
+
+fn describe(result: Result<(), String>) -> String {
+    result.map_or_else(|err| err, |()| "ok".into())
+}
@@ -0,0 +1,181 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use futures::future;
+use hyper::{Body, Client, Request, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use serde::Serialize;
+use tower::ServiceExt;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    auth,
+    config::{HealthCheckConfig, HealthCheckKind},
+    dns::UpstreamConnector,
+    store::{RouteMode, Store},
+};
+
+/// The last checks of an upstream.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct UpstreamHealth {
+    /// Whether requests are sent to it
+    pub healthy: bool,
+    /// Passed checks in a row
+    pub passes: u32,
+    /// Failed checks in a row
+    pub failures: u32,
+    /// Unix timestamp of the last check
+    pub checked_at: i64,
+    /// Why the last check failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Checks the upstreams of proxy routes every `interval` seconds. Upstreams
+/// are healthy until they fail `unhealthy_threshold` checks in a row, then
+/// down until they pass `healthy_threshold` in a row.
+pub struct HealthChecks {
+    config: HealthCheckConfig,
+    /// Connects like the proxy does, private addresses refused alike
+    upstreams: UpstreamConnector,
+    client: Client<HttpsConnector<UpstreamConnector>>,
+    statuses: RwLock<HashMap<String, UpstreamHealth>>,
+}
+
+impl HealthChecks {
+    /// Spawns the checks, running for as long as the server does.
+    pub fn start(
+        config: &HealthCheckConfig,
+        store: Store,
+        upstreams: UpstreamConnector,
+    ) -> Arc<Self> {
+        let connector = HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .wrap_connector(upstreams.clone());
+        let checks = Arc::new(Self {
+            config: config.clone(),
+            upstreams,
+            client: Client::builder().build(connector),
+            statuses: RwLock::default(),
+        });
+
+        let mut ticks = tokio::time::interval(Duration::from_secs(config.interval));
+        let task = checks.clone();
+        tokio::spawn(async move {
+            loop {
+                ticks.tick().await;
+                task.check_all(&store).await;
+            }
+        });
+
+        checks
+    }
+
+    /// Whether requests may go to `upstream`, the unchecked ones included.
+    pub fn is_healthy(&self, upstream: &str) -> bool {
+        self.status(upstream).is_none_or(|status| status.healthy)
+    }
+
+    pub fn status(&self, upstream: &str) -> Option<UpstreamHealth> {
+        let statuses = self.statuses.read().expect("statuses aren't poisoned");
+
+        statuses.get(upstream).cloned()
+    }
+
+    /// One round of checks over the upstreams of every proxy route,
+    /// forgetting those no route uses anymore.
+    async fn check_all(&self, store: &Store) {
+        let routes = match store.list().await {
+            Ok(routes) => routes,
+            Err(err) => {
+                warn!("failed to list the routes to health check: {}", err);
+                return;
+            }
+        };
+        let upstreams: BTreeSet<String> = routes
+            .into_iter()
+            .filter(|route| route.mode == RouteMode::Proxy)
+            .flat_map(|route| route.proxy.all_upstreams().cloned().collect::<Vec<_>>())
+            .collect();
+
+        let checks = upstreams.iter().map(|upstream| self.check(upstream));
+        let results = future::join_all(checks).await;
+
+        let mut statuses = self.statuses.write().expect("statuses aren't poisoned");
+        statuses.retain(|upstream, _| upstreams.contains(upstream));
+        for (upstream, result) in upstreams.into_iter().zip(results) {
+            let status = statuses.entry(upstream.clone()).or_insert(UpstreamHealth {
+                healthy: true,
+                passes: 0,
+                failures: 0,
+                checked_at: 0,
+                error: None,
+            });
+            status.checked_at = auth::now();
+            match result {
+                Ok(()) => {
+                    status.passes += 1;
+                    status.failures = 0;
+                    status.error = None;
+                    if !status.healthy && status.passes >= self.config.healthy_threshold {
+                        status.healthy = true;
+                        info!("upstream is back up: {}", upstream);
+                    }
+                }
+                Err(err) => {
+                    status.passes = 0;
+                    status.failures += 1;
+                    status.error = Some(err);
+                    if status.healthy && status.failures >= self.config.unhealthy_threshold {
+                        status.healthy = false;
+                        warn!("upstream is down: {}", upstream);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn check(&self, upstream: &str) -> Result<(), String> {
+        let uri: Uri = upstream.parse().map_err(|err| format!("invalid URL: {}", err))?;
+        let timeout = Duration::from_secs(self.config.timeout);
+
+        match self.config.kind {
+            HealthCheckKind::Http => {
+                let url = format!("{}{}", upstream.trim_end_matches('/'), self.config.path);
+                let request = Request::get(url)
+                    .body(Body::empty())
+                    .map_err(|err| err.to_string())?;
+                let response = tokio::time::timeout(timeout, self.client.request(request))
+                    .await
+                    .map_err(|_| "timed out".to_owned())?
+                    .map_err(|err| err.to_string())?;
+                let status = response.status();
+                if status.is_success() || status.is_redirection() {
+                    Ok(())
+                } else {
+                    Err(format!("answered {}", status))
+                }
+            }
+            HealthCheckKind::Tcp => {
+                let host = uri.host().ok_or("no host")?;
+                let port = uri
+                    .port_u16()
+                    .unwrap_or(if uri.scheme_str() == Some("https") { 443 } else { 80 });
+                let addr: Uri = format!("http://{}:{}", host, port)
+                    .parse()
+                    .map_err(|err| format!("invalid URL: {}", err))?;
+                tokio::time::timeout(timeout, self.upstreams.clone().oneshot(addr))
+                    .await
+                    .map_err(|_| "timed out".to_owned())?
+                    .map(drop)
+                    .map_err(|err| err.to_string())
+            }
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use axum::{extract::Query, http::Uri, Json};
+use hyper::StatusCode;
+use serde::Deserialize;
+use utoipa::IntoParams;
+use tracing::debug;
+
+use crate::{
+    audit,
+    auth::{Principal, Scope},
+    router::{self, AppState, internal_error},
+    store::{Revision, RevisionAction, Route, Store, StoreError},
+};
+
+/// Records a change to a route by `principal` in its history and the
+/// audit log, `old` and `new` being the route before and after it. `None`
+/// for a route that didn't exist or is deleted.
+pub async fn record(
+    store: &Store,
+    principal: &Principal,
+    action: RevisionAction,
+    old: Option<&Route>,
+    new: Option<&Route>,
+) -> Result<(), StoreError> {
+    store
+        .insert_revision(&Revision::new(&principal.subject, action, old, new))
+        .await?;
+
+    audit::record(
+        store,
+        principal,
+        &format!("route.{}", action.as_str()),
+        new.or(old).map(Route::label),
+        audit::diff(old, new),
+    )
+    .await
+}
+
+/// `?host=` of `/api/routes/{slug}/history`.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct HistoryParams {
+    /// Hostname the route is scoped to
+    host: Option<String>,
+}
+
+/// Every recorded change to a route, oldest first.
+#[utoipa::path(
+    get,
+    path = "/api/routes/{slug}/history",
+    tag = "routes",
+    params(("slug" = String, Path, description = "Slug of the route, `/` included"), HistoryParams),
+    responses(
+        (status = 200, description = "Revisions of the route", body = [Revision]),
+        (status = 404, description = "Route not found", body = String),
+    )
+)]
+pub async fn route_history(
+    principal: Principal,
+    state: AppState,
+    slug: &str,
+    uri: &Uri,
+) -> Result<Json<Vec<Revision>>, (StatusCode, String)> {
+    principal.require(Scope::RoutesRead)?;
+
+    let Query(params) = Query::<HistoryParams>::try_from_uri(uri)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+    let host = params.host.as_deref().map(router::normalize_host);
+    router::check_access(&state, &principal, host.as_deref(), slug).await?;
+    let revisions = state
+        .store
+        .list_revisions(host.as_deref(), slug)
+        .await
+        .map_err(internal_error)?;
+    // routes created before history was kept have none
+    if revisions.is_empty()
+        && state
+            .store
+            .get(host.as_deref(), slug)
+            .await
+            .map_err(internal_error)?
+            .is_none()
+    {
+        return Err(router::route_not_found());
+    }
+
+    Ok(Json(revisions))
+}
+
+/// `?host=&revision=` of `/api/routes/{slug}/rollback`.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RollbackParams {
+    /// Hostname the route is scoped to
+    host: Option<String>,
+    /// Id of the revision to go back to
+    revision: i64,
+}
+
+/// Puts a route back the way `revision` left it, keeping its hit count.
+/// The rollback is itself recorded, so it can be rolled back in turn.
+#[utoipa::path(
+    post,
+    path = "/api/routes/{slug}/rollback",
+    tag = "routes",
+    params(
+        ("slug" = String, Path, description = "Slug of the route, `/` included"),
+        RollbackParams,
+    ),
+    responses(
+        (status = 200, description = "The route as rolled back", body = Route),
+        (status = 400, description = "The revision deleted the route", body = String),
+        (status = 404, description = "Route or revision not found", body = String),
+    )
+)]
+pub async fn rollback(
+    principal: Principal,
+    state: AppState,
+    slug: &str,
+    uri: &Uri,
+) -> Result<Json<Route>, (StatusCode, String)> {
+    principal.require(Scope::RoutesWrite)?;
+
+    let Query(params) = Query::<RollbackParams>::try_from_uri(uri)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+    let host = params.host.as_deref().map(router::normalize_host);
+    let current = router::get_accessible(&state, &principal, host.as_deref(), slug).await?;
+    principal.require_owner(current.owner.as_deref())?;
+    let revision = state
+        .store
+        .get_revision(host.as_deref(), slug, params.revision)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Revision not found".into()))?;
+    let Some(route) = revision.route else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Revision {} deleted the route, restore it instead", revision.id),
+        ));
+    };
+
+    let route = Route {
+        hits: current.hits,
+        deleted_at: None,
+        tenant: current.tenant.clone(),
+        owner: current.owner.clone(),
+        ..route
+    };
+    if !state.store.update(&route).await.map_err(internal_error)? {
+        return Err(router::route_not_found());
+    }
+    router::invalidate(&state, host.as_deref(), slug).await?;
+    let action = RevisionAction::Rollback;
+    router::record_change(&state, &principal, action, Some(&current), Some(&route)).await?;
+
+    debug!(
+        "rolled back route: {} to revision {} by {}",
+        route.label(),
+        revision.id,
+        &principal.subject
+    );
+    Ok(Json(route))
+}
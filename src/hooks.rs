@@ -0,0 +1,79 @@
+use std::{net::IpAddr, ops::ControlFlow};
+
+use async_trait::async_trait;
+use axum::http::request::Parts;
+use hyper::{Body, Response};
+
+use crate::store::Route;
+
+/// What middleware sees of a request to a route.
+pub struct RouteRequest<'a> {
+    /// `Host`, lowercased and without its port
+    pub host: Option<&'a str>,
+    pub client: Option<IpAddr>,
+    /// Method, URI and headers, the headers reaching the route as left here
+    pub parts: &'a mut Parts,
+}
+
+/// Hooks into the requests of routes, registered through
+/// `RoadsServerBuilder::middleware` and run in that order. Every hook does
+/// nothing by default.
+#[async_trait]
+pub trait RoadsMiddleware: Send + Sync {
+    /// Before the route is looked up. `Break` answers with its response,
+    /// skipping the middleware after.
+    async fn before_lookup(&self, _request: &mut RouteRequest<'_>) -> ControlFlow<Response<Body>> {
+        ControlFlow::Continue(())
+    }
+
+    /// Once `route` resolved to `target`, the redirect location or proxied
+    /// URL with its query, which can be changed. `Break` answers with its
+    /// response instead.
+    async fn after_resolve(
+        &self,
+        _route: &Route,
+        _target: &mut String,
+        _request: &mut RouteRequest<'_>,
+    ) -> ControlFlow<Response<Body>> {
+        ControlFlow::Continue(())
+    }
+
+    /// On every answer to a route path, errors and error pages included.
+    async fn on_response(&self, _host: Option<&str>, _path: &str, _response: &mut Response<Body>) {
+    }
+}
+
+pub(crate) async fn before_lookup(
+    middleware: &[Box<dyn RoadsMiddleware>],
+    request: &mut RouteRequest<'_>,
+) -> ControlFlow<Response<Body>> {
+    for middleware in middleware {
+        middleware.before_lookup(request).await?;
+    }
+
+    ControlFlow::Continue(())
+}
+
+pub(crate) async fn after_resolve(
+    middleware: &[Box<dyn RoadsMiddleware>],
+    route: &Route,
+    target: &mut String,
+    request: &mut RouteRequest<'_>,
+) -> ControlFlow<Response<Body>> {
+    for middleware in middleware {
+        middleware.after_resolve(route, target, request).await?;
+    }
+
+    ControlFlow::Continue(())
+}
+
+pub(crate) async fn on_response(
+    middleware: &[Box<dyn RoadsMiddleware>],
+    host: Option<&str>,
+    path: &str,
+    response: &mut Response<Body>,
+) {
+    for middleware in middleware {
+        middleware.on_response(host, path, response).await;
+    }
+}
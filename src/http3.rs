@@ -0,0 +1,94 @@
+use std::{io, net::SocketAddr, sync::Arc};
+
+use axum::{body::Body, extract::ConnectInfo, http::Request, Router};
+use h3::{error::Code, server::RequestStream};
+use hyper::body::{Buf, Bytes};
+use tower::ServiceExt;
+use tracing::debug;
+
+type Stream = RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>;
+
+/// Serves `app` over HTTP/3 on the UDP side of `addr`, with the same
+/// certificates as the TLS listener.
+pub fn spawn(
+    addr: SocketAddr,
+    mut tls_config: rustls::ServerConfig,
+    app: Router,
+) -> io::Result<()> {
+    tls_config.alpn_protocols = vec![b"h3".to_vec()];
+    let endpoint = quinn::Endpoint::server(
+        quinn::ServerConfig::with_crypto(Arc::new(tls_config)),
+        addr,
+    )?;
+    debug!("listening on {} (HTTP/3)", addr);
+
+    tokio::spawn(async move {
+        while let Some(connecting) = endpoint.accept().await {
+            tokio::spawn(serve_connection(connecting, app.clone()));
+        }
+    });
+
+    Ok(())
+}
+
+async fn serve_connection(connecting: quinn::Connecting, app: Router) {
+    let conn = match connecting.await {
+        Ok(conn) => conn,
+        Err(err) => return debug!("QUIC handshake failed: {}", err),
+    };
+    let remote = conn.remote_address();
+
+    let mut conn = match h3::server::Connection::new(h3_quinn::Connection::new(conn)).await {
+        Ok(conn) => conn,
+        Err(err) => return debug!("HTTP/3 setup failed: {}", err),
+    };
+
+    loop {
+        match conn.accept().await {
+            Ok(Some((req, stream))) => {
+                let app = app.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = serve_request(req, stream, remote, app).await {
+                        debug!("HTTP/3 request failed: {}", err);
+                    }
+                });
+            }
+            Ok(None) => break,
+            Err(err) => return debug!("HTTP/3 connection closed: {}", err),
+        }
+    }
+}
+
+async fn serve_request(
+    req: Request<()>,
+    mut stream: Stream,
+    remote: SocketAddr,
+    app: Router,
+) -> Result<(), h3::Error> {
+    let mut body = Vec::new();
+    while let Some(mut chunk) = stream.recv_data().await? {
+        body.extend_from_slice(&chunk.copy_to_bytes(chunk.remaining()));
+    }
+
+    let (parts, ()) = req.into_parts();
+    let mut req = Request::from_parts(parts, Body::from(body));
+    req.extensions_mut().insert(ConnectInfo(remote));
+
+    let response = match app.oneshot(req).await {
+        Ok(response) => response,
+        Err(err) => match err {},
+    };
+    let (parts, body) = response.into_parts();
+    let Ok(body) = hyper::body::to_bytes(body).await else {
+        stream.stop_stream(Code::H3_INTERNAL_ERROR);
+        return Ok(());
+    };
+
+    stream
+        .send_response(axum::http::Response::from_parts(parts, ()))
+        .await?;
+    if !body.is_empty() {
+        stream.send_data(body).await?;
+    }
+    stream.finish().await
+}
@@ -0,0 +1,279 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::Principal,
+    config::SlugConfig,
+    history,
+    quota::QuotaKind,
+    router::{self, AppState},
+    slug,
+    store::{MatchType, RevisionAction, Route, RouteMode, Store, StoreError},
+    tenants,
+};
+
+/// What to do with rows whose host and slug already have a route.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Conflict {
+    /// Keep the existing route
+    #[default]
+    Skip,
+    /// Replace it, keeping its hit count
+    Overwrite,
+}
+
+impl FromStr for Conflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "overwrite" => Ok(Self::Overwrite),
+            _ => Err(format!("unknown conflict policy: {}", s)),
+        }
+    }
+}
+
+/// Layout of imports and exports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// A header naming the columns, `slug` and `redirect_to` (or `target`)
+    /// required, then one route per line
+    Csv,
+    /// An array of routes as `POST /api/routes` takes them
+    #[default]
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown format: {}", s)),
+        }
+    }
+}
+
+/// A CSV line, only the simple route options have a column.
+#[derive(Deserialize)]
+struct CsvRow {
+    slug: String,
+    #[serde(alias = "target")]
+    redirect_to: String,
+    #[serde(default)]
+    host: Option<String>,
+    #[serde(default)]
+    mode: Option<RouteMode>,
+    #[serde(default)]
+    match_type: Option<MatchType>,
+    #[serde(default)]
+    status_code: Option<u16>,
+    #[serde(default)]
+    preserve_query: Option<bool>,
+    #[serde(default)]
+    preserve_path: Option<bool>,
+}
+
+impl From<CsvRow> for Route {
+    fn from(row: CsvRow) -> Self {
+        let mut route = Route::new(row.slug, row.redirect_to);
+        route.host = row.host.filter(|host| !host.is_empty());
+        route.mode = row.mode.unwrap_or_default();
+        route.match_type = row.match_type.unwrap_or_default();
+        route.status_code = row.status_code.unwrap_or(route.status_code);
+        route.preserve_query = row.preserve_query.unwrap_or_default();
+        route.preserve_path = row.preserve_path.unwrap_or_default();
+        route
+    }
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct ImportReport {
+    pub created: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub failed: Vec<ImportFailure>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImportFailure {
+    /// 1-based, the CSV header not counting
+    pub row: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slug: Option<String>,
+    pub error: String,
+}
+
+/// The routes of `body`, each row parsed on its own so a bad one only fails
+/// itself. Fails as a whole when `body` isn't CSV with the required columns
+/// or a JSON array.
+pub fn parse(body: &str, format: Format) -> Result<Vec<Result<Route, String>>, String> {
+    match format {
+        Format::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(body.as_bytes());
+            let headers = reader.headers().map_err(|err| err.to_string())?;
+            let has = |name| headers.iter().any(|header| header == name);
+            if !has("slug") || !(has("redirect_to") || has("target")) {
+                return Err("CSV needs `slug` and `redirect_to` columns".into());
+            }
+
+            Ok(reader
+                .deserialize::<CsvRow>()
+                .map(|row| row.map(Route::from).map_err(|err| err.to_string()))
+                .collect())
+        }
+        Format::Json => {
+            let rows: Vec<serde_json::Value> =
+                serde_json::from_str(body).map_err(|err| err.to_string())?;
+
+            Ok(rows
+                .into_iter()
+                .map(|row| serde_json::from_value(row).map_err(|err| err.to_string()))
+                .collect())
+        }
+    }
+}
+
+/// Validates and stores `rows` one by one, recording each change as made
+/// by `principal`. `server` is the running server's state, whose lookups
+/// forget every written route and whose webhooks hear about it.
+pub async fn import(
+    store: &Store,
+    slugs: &SlugConfig,
+    principal: &Principal,
+    rows: Vec<Result<Route, String>>,
+    conflict: Conflict,
+    server: Option<&AppState>,
+) -> Result<ImportReport, StoreError> {
+    let mut report = ImportReport::default();
+    let known = store.list_tenants().await?;
+    for (i, row) in rows.into_iter().enumerate() {
+        let mut route = match row {
+            Ok(route) => route,
+            Err(error) => {
+                report.failed.push(ImportFailure {
+                    row: i + 1,
+                    slug: None,
+                    error,
+                });
+                continue;
+            }
+        };
+        route.host = route.host.as_deref().map(router::normalize_host);
+        route.hits = 0;
+        route.deleted_at = None;
+        route.owner = principal.user.clone();
+        slug::normalize(slugs, &mut route);
+        let exists = |id: &str| known.iter().any(|tenant| tenant.id == id);
+        let valid = tenants::choose(principal, route.tenant.take(), exists)
+            .map_err(|(_, err)| err)
+            .and_then(|tenant| {
+                route.tenant = tenant;
+                slug::validate(slugs, &route).map_err(|err| format!("Invalid slug: {}", err))
+            })
+            .and_then(|()| router::check_route(&route));
+        if let Err(error) = valid {
+            report.failed.push(ImportFailure {
+                row: i + 1,
+                slug: Some(route.slug),
+                error,
+            });
+            continue;
+        }
+
+        let quotas = server.and_then(|state| state.quotas.as_ref());
+        if let Some(quotas) = quotas {
+            if let Some(subject) = quotas.consume(store, principal, QuotaKind::Creates, 1).await? {
+                report.failed.push(ImportFailure {
+                    row: i + 1,
+                    slug: Some(route.slug),
+                    error: quotas.exceeded(&subject, QuotaKind::Creates).1,
+                });
+                continue;
+            }
+        }
+
+        let inserted = store.insert(&route).await?;
+        if let Some(quotas) = quotas.filter(|_| !inserted) {
+            quotas
+                .release(store, principal, QuotaKind::Creates, 1)
+                .await?;
+        }
+
+        if inserted {
+            record(store, server, principal, RevisionAction::Create, None, Some(&route)).await?;
+            report.created += 1;
+        } else if conflict == Conflict::Skip {
+            report.skipped += 1;
+            continue;
+        } else {
+            let Some(old) = store.get(route.host.as_deref(), &route.slug).await? else {
+                report.skipped += 1;
+                continue;
+            };
+            let refused = if !principal.can_access(old.tenant.as_deref()) {
+                Some("Route belongs to another tenant")
+            } else if !principal.can_modify(old.owner.as_deref()) {
+                Some("Only its owner or an admin can change this route")
+            } else {
+                None
+            };
+            if let Some(error) = refused {
+                report.failed.push(ImportFailure {
+                    row: i + 1,
+                    slug: Some(route.slug),
+                    error: error.into(),
+                });
+                continue;
+            }
+            route.owner = old.owner.clone();
+            // rows without a tenant, like CSV ones, leave it as it was
+            if route.tenant.is_none() {
+                route.tenant = old.tenant.clone();
+            }
+            if !store.update(&route).await? {
+                report.skipped += 1;
+                continue;
+            }
+            let action = RevisionAction::Update;
+            record(store, server, principal, action, Some(&old), Some(&route)).await?;
+            report.updated += 1;
+        }
+        if let Some(state) = server {
+            state.cache.invalidate(route.host.as_deref(), &route.slug).await?;
+            if let Some(invalidations) = &state.invalidations {
+                invalidations.publish(route.host.as_deref(), &route.slug).await;
+            }
+        }
+    }
+    if let Some(state) = server {
+        state.patterns.invalidate().await;
+    }
+
+    Ok(report)
+}
+
+async fn record(
+    store: &Store,
+    server: Option<&AppState>,
+    principal: &Principal,
+    action: RevisionAction,
+    old: Option<&Route>,
+    new: Option<&Route>,
+) -> Result<(), StoreError> {
+    history::record(store, principal, action, old, new).await?;
+    if let Some(webhooks) = server.and_then(|state| state.webhooks.as_ref()) {
+        webhooks.changed(&principal.subject, action, old, new);
+    }
+
+    Ok(())
+}
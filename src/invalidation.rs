@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use rand::RngCore;
+use redis::{AsyncCommands, RedisResult};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::CacheConfig,
+    router::{self, AppState},
+    store::{RedisConnection, StoreError},
+};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Route changes broadcast through Redis pub/sub, so every instance drops
+/// what its local caches hold of a route as soon as one of them changes it.
+/// Changes made while an instance isn't subscribed reach it by the TTLs.
+pub struct Invalidations {
+    con: RedisConnection,
+    /// Of the subscription, which has a connection of its own
+    client: redis::Client,
+    channel: String,
+    /// Tells the changes of this instance apart
+    origin: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Message {
+    origin: String,
+    host: Option<String>,
+    slug: String,
+}
+
+impl Invalidations {
+    /// `None` unless `cache.pubsub` is enabled.
+    pub async fn new(config: &CacheConfig) -> Result<Option<Self>, StoreError> {
+        if !config.enabled || !config.pubsub {
+            return Ok(None);
+        }
+
+        let client = redis::Client::open(config.redis_url.as_str())?;
+        let mut origin = [0u8; 8];
+        rand::thread_rng().fill_bytes(&mut origin);
+
+        Ok(Some(Self {
+            con: RedisConnection::open(&config.redis_url).await?,
+            client,
+            channel: config.pubsub_channel.clone(),
+            origin: hex::encode(origin),
+        }))
+    }
+
+    /// Tells the other instances the route of `host` and `slug` changed.
+    /// Failures are only logged, their caches catching up by the TTLs.
+    pub async fn publish(&self, host: Option<&str>, slug: &str) {
+        let message = Message {
+            origin: self.origin.clone(),
+            host: host.map(String::from),
+            slug: slug.to_owned(),
+        };
+        let message = serde_json::to_string(&message).expect("messages serialize to JSON");
+        let mut published = self.send(&message).await;
+        if published.as_ref().is_err_and(|err| err.is_io_error()) {
+            // on a new connection, the last one having dropped
+            published = self.send(&message).await;
+        }
+        match published {
+            Ok(()) => metrics::counter!("roads_cache_invalidations_sent_total").increment(1),
+            Err(err) => warn!("failed to publish a cache invalidation: {}", err),
+        }
+    }
+
+    async fn send(&self, message: &str) -> RedisResult<()> {
+        self.con.get().await?.publish(&self.channel, message).await
+    }
+
+    /// Drops the routes other instances change from the caches of `state`,
+    /// resubscribing with a growing backoff while Redis can't be reached.
+    pub fn subscribe(&self, state: AppState) {
+        let (tx, mut rx) = mpsc::channel::<Message>(1024);
+        let (client, channel, origin) =
+            (self.client.clone(), self.channel.clone(), self.origin.clone());
+        // the pub/sub connection blocks while waiting for messages
+        std::thread::spawn(move || {
+            let mut backoff = MIN_BACKOFF;
+            loop {
+                match listen(&client, &channel, &origin, &tx, &mut backoff) {
+                    Ok(()) => return,
+                    Err(err) => warn!("lost the cache invalidation subscription: {}", err),
+                }
+                std::thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        });
+
+        tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                debug!("invalidating {:?} {} for another instance", message.host, message.slug);
+                metrics::counter!("roads_cache_invalidations_received_total").increment(1);
+                let host = message.host.as_deref();
+                if let Err((_, err)) = router::invalidate_local(&state, host, &message.slug).await {
+                    warn!("failed to apply a cache invalidation: {}", err);
+                }
+            }
+        });
+    }
+}
+
+/// Forwards the messages of other instances until `tx` closes.
+fn listen(
+    client: &redis::Client,
+    channel: &str,
+    origin: &str,
+    tx: &mpsc::Sender<Message>,
+    backoff: &mut Duration,
+) -> redis::RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let mut pubsub = con.as_pubsub();
+    pubsub.subscribe(channel)?;
+    info!("subscribed to cache invalidations on {}", channel);
+    *backoff = MIN_BACKOFF;
+
+    loop {
+        let payload: String = pubsub.get_message()?.get_payload()?;
+        let message = match serde_json::from_str::<Message>(&payload) {
+            Ok(message) => message,
+            Err(err) => {
+                warn!("ignoring a malformed cache invalidation: {}", err);
+                continue;
+            }
+        };
+        if message.origin != origin && tx.blocking_send(message).is_err() {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use crate::store::LanguageTarget;
+
+/// Language tags of an `Accept-Language` header, lowercased and most
+/// preferred first. Tags with `q=0` and the `*` wildcard are left out, ties
+/// keep the header order.
+pub fn preferred(header: &str) -> Vec<String> {
+    let mut tags: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let tag = parts.next()?.trim().to_ascii_lowercase();
+            let q = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+
+            (!tag.is_empty() && tag != "*" && q > 0.0).then_some((tag, q))
+        })
+        .collect();
+    tags.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    tags.into_iter().map(|(tag, _)| tag).collect()
+}
+
+/// The target for the most preferred language that has one. A target for
+/// `pt` also serves `pt-BR` when no target is that specific.
+pub fn pick<'a>(targets: &'a [LanguageTarget], header: &str) -> Option<&'a LanguageTarget> {
+    preferred(header).iter().find_map(|tag| {
+        let exact = targets.iter().find(|target| {
+            target
+                .languages
+                .iter()
+                .any(|language| language.eq_ignore_ascii_case(tag))
+        });
+
+        exact.or_else(|| {
+            targets.iter().find(|target| {
+                target.languages.iter().any(|language| {
+                    tag.len() > language.len()
+                        && tag.as_bytes()[language.len()] == b'-'
+                        && tag[..language.len()].eq_ignore_ascii_case(language)
+                })
+            })
+        })
+    })
+}
@@ -0,0 +1,179 @@
+//! Roads' redirect and proxy engine, served on its own by the `roads` binary
+//! or built with `RoadsServer::builder()` to nest into another axum app.
+
+use std::env;
+
+use thiserror::Error;
+use tracing_subscriber::filter;
+
+pub use crate::{
+    config::{Config, ListenAddr, ListenerConfig, Service},
+    hooks::{RoadsMiddleware, RouteRequest},
+    router::{AppState, path_routes},
+    server::{RoadsServer, RoadsServerBuilder},
+    store::{Route, RouteMode, Store},
+};
+
+mod access_log;
+mod audit;
+mod auth;
+mod cache;
+mod circuit_breaker;
+mod compression;
+pub mod cli;
+pub mod config;
+mod cors;
+mod dashboard;
+mod device;
+mod dns;
+mod error_pages;
+mod events;
+mod fallback;
+mod export;
+mod geoip;
+mod grpc;
+mod health;
+mod health_checks;
+mod history;
+pub mod hooks;
+mod language;
+mod maintenance;
+#[cfg(feature = "http3")]
+mod http3;
+mod import;
+mod invalidation;
+mod openapi;
+mod password;
+mod patterns;
+mod oidc;
+mod plain;
+mod preview;
+pub mod proxy;
+mod proxy_protocol;
+mod qr;
+mod quota;
+mod rate_limit;
+mod replica;
+mod response_cache;
+mod response_headers;
+pub mod router;
+mod server;
+mod shorten;
+mod signing;
+mod slug;
+mod stats;
+pub mod store;
+pub mod telemetry;
+mod tenants;
+mod tls;
+mod tracking;
+mod trash;
+mod users;
+mod utm;
+mod webhooks;
+
+#[derive(Debug, Error)]
+pub enum ServerError {
+    #[error(transparent)]
+    Store(#[from] store::StoreError),
+
+    #[error("Error while getting environment variables")]
+    Var(#[from] env::VarError),
+
+    #[error(transparent)]
+    Config(#[from] config::ConfigError),
+
+    #[error("Invalid log level: {0}")]
+    LogLevel(#[from] filter::ParseError),
+
+    #[error(transparent)]
+    HttpError(#[from] hyper::Error),
+
+    #[error("Error while opening the access log: {0}")]
+    AccessLog(#[from] std::io::Error),
+
+    #[error("Error while reading the import file: {0}")]
+    ImportFile(std::io::Error),
+
+    #[error("Error while writing the export: {0}")]
+    ExportFile(std::io::Error),
+
+    #[error("Error while reading the expired page: {0}")]
+    ExpiredPage(std::io::Error),
+
+    #[error("Error while reading the maintenance page: {0}")]
+    MaintenancePage(std::io::Error),
+
+    #[error("Error while reading an error page: {0}")]
+    ErrorPage(std::io::Error),
+
+    #[error("Error while reading a fallback page: {0}")]
+    FallbackPage(std::io::Error),
+
+    #[error("Error while opening the GeoIP database: {0}")]
+    GeoIp(#[from] maxminddb::MaxMindDBError),
+
+    #[error("TLS listener error: {0}")]
+    Tls(std::io::Error),
+
+    #[error("Error while binding the listener: {0}")]
+    Listen(std::io::Error),
+
+    #[error("gRPC listener error: {0}")]
+    Grpc(tonic::transport::Error),
+
+    #[cfg(not(unix))]
+    #[error("Unix sockets are not supported on this platform: {0}")]
+    UnixUnsupported(std::path::PathBuf),
+
+    #[error("Route already exists: {0}")]
+    RouteExists(String),
+
+    #[error("Route not found: {0}")]
+    RouteNotFound(String),
+
+    #[error("Invalid route: {0}")]
+    InvalidRoute(String),
+
+    #[error("API key not found: {0}")]
+    KeyNotFound(String),
+
+    #[error("Tenant already exists: {0}")]
+    TenantExists(String),
+
+    #[error("Tenant not found: {0}")]
+    TenantNotFound(String),
+
+    #[error("Routes or keys still belong to tenant {0}")]
+    TenantInUse(String),
+
+    #[error("Invalid tenant: {0}")]
+    InvalidTenant(String),
+
+    #[error("User already exists: {0}")]
+    UserExists(String),
+
+    #[error("User not found: {0}")]
+    UserNotFound(String),
+
+    #[error("API keys still act as user {0}")]
+    UserHasKeys(String),
+
+    #[error("Invalid user: {0}")]
+    InvalidUser(String),
+
+    #[error("JWT support needs `auth.jwt_secret` to be configured")]
+    JwtNotConfigured,
+
+    #[error("Signed links need `signing.secret` to be configured")]
+    SigningNotConfigured,
+
+    #[error(transparent)]
+    Jwt(#[from] jsonwebtoken::errors::Error),
+
+    #[error("Error while setting up the OTLP exporter: {0}")]
+    Tracing(#[from] opentelemetry::trace::TraceError),
+
+    #[error("Error while installing the metrics recorder: {0}")]
+    Metrics(#[from] metrics_exporter_prometheus::BuildError),
+}
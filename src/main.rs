@@ -1,62 +1,105 @@
-use std::{env, net::SocketAddr, num};
+use std::path::PathBuf;
 
-use axum::{http::StatusCode, Router, routing::get};
-use thiserror::Error;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use clap::Parser;
+use tracing_subscriber::{
+    EnvFilter, layer::SubscriberExt, registry::Registry, reload, util::SubscriberInitExt,
+};
 
-use crate::router::path_routes;
+use roads::{
+    cli::{self, Cli, Command},
+    config::{self, Config, LogFormat},
+    RoadsServer, ServerError, store::{self, Store}, telemetry,
+};
 
-mod router;
+type LogHandle = reload::Handle<EnvFilter, Registry>;
 
 #[tokio::main]
 async fn main() -> Result<(), ServerError> {
+    let cli = Cli::parse();
+    let config_path = cli.config.or_else(config::path);
+    let config = Config::load(config_path.as_deref())?;
+
+    let (filter, log_handle) = reload::Layer::new(EnvFilter::try_new(&config.log_level)?);
+    let tracer = config
+        .tracing
+        .otlp_endpoint
+        .as_deref()
+        .map(|endpoint| telemetry::otlp_tracer(endpoint, &config.tracing.service_name))
+        .transpose()?;
+    let json = config.log_format == LogFormat::Json;
     tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "roads=trace, tower_http=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
+        .with(filter)
+        .with((!json).then(tracing_subscriber::fmt::layer))
+        .with(json.then(|| {
+            tracing_subscriber::fmt::layer()
+                .json()
+                .flatten_event(true)
+                .with_span_list(false)
+        }))
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)))
         .init();
+    // `log` records are capped at init time, let the reloadable filter decide
+    tracing::log::set_max_level(tracing::log::LevelFilter::Trace);
 
-    let client = redis::Client::open("redis://0.0.0.0:6379/")?;
-    let con = client.get_connection()?;
-
-    let router_param = path_routes(con);
-
-    let router_svc = Router::new()
-        .route("/ping", get(|| async { "Pong" }))
-        .merge(router_param)
-        .fallback(route_not_found);
+    let store = store::connect(&config.database_url, &config.database).await?;
 
-    let port: u16 = env::var("PORT").unwrap_or_else(|_| "3000".into()).parse()?;
-    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let command = cli.command.unwrap_or(Command::Serve);
+    if config.auto_migrate || matches!(command, Command::Migrate) {
+        store::migrate(&store).await?;
+    }
 
-    axum::Server::bind(&addr)
-        .serve(router_svc.into_make_service())
-        .await?;
+    let result = match command {
+        Command::Serve => serve(config, config_path, store, log_handle).await,
+        Command::Migrate => {
+            println!("migrations applied");
+            Ok(())
+        }
+        Command::Route(cmd) => cli::route(cmd, &store, &config).await,
+        Command::Key(cmd) => cli::key(cmd, &store).await,
+        Command::Token(cmd) => cli::token(cmd, &config),
+        Command::Tenant(cmd) => cli::tenant(cmd, &store).await,
+        Command::User(cmd) => cli::user(cmd, &store).await,
+        Command::Maintenance(cmd) => cli::maintenance(cmd, &store).await,
+        Command::Export {
+            format,
+            stats,
+            output,
+        } => cli::export(format, stats, output, &store).await,
+    };
 
-    tracing::debug!("listening on {}", addr);
-    Ok(())
+    // flush spans still waiting in the batch exporter
+    opentelemetry::global::shutdown_tracer_provider();
+    result
 }
 
-async fn route_not_found() -> (StatusCode, String) {
-    (
-        StatusCode::NOT_FOUND,
-        "Ops, this route doesn't exist!".to_string(),
-    )
-}
-
-#[derive(Debug, Error)]
-enum ServerError {
-    #[error("Redis error: {0}")]
-    Redis(#[from] redis::RedisError),
-
-    #[error("Error while getting environment variables")]
-    Var(#[from] env::VarError),
+async fn serve(
+    config: Config,
+    config_path: Option<PathBuf>,
+    store: Store,
+    log_handle: LogHandle,
+) -> Result<(), ServerError> {
+    if let Some(path) = config_path {
+        config::watch(path, config.clone(), move |old, new| {
+            if old.log_level != new.log_level {
+                match EnvFilter::try_new(&new.log_level) {
+                    Ok(filter) => {
+                        let _ = log_handle.reload(filter);
+                    }
+                    Err(err) => tracing::warn!("ignoring invalid log level: {}", err),
+                }
+            }
 
-    #[error(transparent)]
-    ParseError(#[from] num::ParseIntError),
+            if old.needs_restart(new) {
+                tracing::warn!("only log_level is reloaded, other changes require a restart");
+            }
+        });
+    }
 
-    #[error(transparent)]
-    HttpError(#[from] hyper::Error),
+    RoadsServer::builder()
+        .config(config)
+        .store(store)
+        .build()
+        .await?
+        .serve()
+        .await
 }
@@ -0,0 +1,151 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use axum::{
+    extract::State,
+    http::{header, HeaderValue, StatusCode},
+    Json,
+    response::Response,
+    Router, routing::get,
+};
+use hyper::Body;
+use tracing::{debug, info, warn};
+
+use crate::{
+    audit,
+    auth::{Principal, Scope},
+    config::MaintenanceConfig,
+    router::{AppState, internal_error},
+    store::{Maintenance, Route, Store},
+};
+
+/// The maintenance in effect, as set through the API or read back from the
+/// store every `refresh_interval` for the one set elsewhere.
+pub struct MaintenanceMode {
+    current: RwLock<Maintenance>,
+    retry_after: u64,
+    /// HTML from `maintenance.page`
+    html: Option<String>,
+}
+
+impl MaintenanceMode {
+    /// Loads the page and the maintenance in effect, then spawns the task
+    /// keeping up with the store.
+    pub async fn start(config: &MaintenanceConfig, store: Store) -> std::io::Result<Arc<Self>> {
+        let html = match &config.page {
+            Some(path) => Some(tokio::fs::read_to_string(path).await?),
+            None => None,
+        };
+        let mode = Arc::new(Self {
+            current: RwLock::default(),
+            retry_after: config.retry_after,
+            html,
+        });
+
+        let mut ticks = tokio::time::interval(Duration::from_secs(config.refresh_interval));
+        let task = mode.clone();
+        tokio::spawn(async move {
+            loop {
+                ticks.tick().await;
+                match store.maintenance().await {
+                    Ok(maintenance) => task.set(maintenance),
+                    Err(err) => warn!("failed to read the maintenance: {}", err),
+                }
+            }
+        });
+
+        Ok(mode)
+    }
+
+    pub fn current(&self) -> Maintenance {
+        self.current.read().expect("maintenance isn't poisoned").clone()
+    }
+
+    fn set(&self, maintenance: Maintenance) {
+        let mut current = self.current.write().expect("maintenance isn't poisoned");
+        if *current != maintenance {
+            if maintenance.is_on() {
+                info!("maintenance started: {:?}", maintenance);
+            } else {
+                info!("maintenance ended");
+            }
+            *current = maintenance;
+        }
+    }
+
+    /// Whether `route` is down, or any route at all when `None`.
+    pub fn covers(&self, route: Option<&Route>) -> bool {
+        self.current.read().expect("maintenance isn't poisoned").covers(route)
+    }
+
+    /// The 503 routes under maintenance answer with.
+    pub fn response(&self) -> Result<Response<Body>, (StatusCode, String)> {
+        let retry_after = self.current().retry_after.unwrap_or(self.retry_after);
+        let response = Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(header::RETRY_AFTER, HeaderValue::from(retry_after))
+            .header(header::CACHE_CONTROL, "no-store");
+        match &self.html {
+            Some(html) => response
+                .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+                .body(Body::from(html.clone())),
+            None => response.body(Body::from("Down for maintenance")),
+        }
+        .map_err(internal_error)
+    }
+}
+
+pub fn maintenance_routes() -> Router<AppState> {
+    Router::new().route("/api/maintenance", get(read_maintenance).put(update_maintenance))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/maintenance",
+    tag = "maintenance",
+    responses((status = 200, description = "The maintenance in effect", body = Maintenance))
+)]
+async fn read_maintenance(
+    principal: Principal,
+    State(state): State<AppState>,
+) -> Result<Json<Maintenance>, (StatusCode, String)> {
+    principal.require(Scope::RoutesRead)?;
+
+    Ok(Json(state.maintenance.current()))
+}
+
+/// Starts, changes or ends the maintenance, `{}` ending it. Only admins
+/// outside any tenant can, since it can take every route down.
+#[utoipa::path(
+    put,
+    path = "/api/maintenance",
+    tag = "maintenance",
+    request_body = Maintenance,
+    responses((status = 200, description = "The maintenance now in effect", body = Maintenance))
+)]
+async fn update_maintenance(
+    principal: Principal,
+    State(state): State<AppState>,
+    Json(maintenance): Json<Maintenance>,
+) -> Result<Json<Maintenance>, (StatusCode, String)> {
+    principal.require(Scope::RoutesWrite)?;
+    principal.require_admin()?;
+    principal.require_global()?;
+
+    let old = state.maintenance.current();
+    state
+        .store
+        .set_maintenance(&maintenance)
+        .await
+        .map_err(internal_error)?;
+    state.maintenance.set(maintenance.clone());
+    let diff = audit::diff(Some(&old), Some(&maintenance));
+    audit::record(&state.store, &principal, "maintenance.update", None, diff)
+        .await
+        .map_err(internal_error)?;
+
+    debug!("maintenance set by {}", &principal.subject);
+    Ok(Json(maintenance))
+}
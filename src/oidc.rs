@@ -0,0 +1,518 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+    response::{IntoResponse, Redirect, Response},
+    Router, routing::{get, post},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hyper::{client::HttpConnector, header, Body, Client, Request};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use jsonwebtoken::{jwk::JwkSet, DecodingKey, Validation};
+use moka::future::Cache;
+use rand::RngCore;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{self, Scope},
+    config::OidcConfig,
+    router::AppState,
+    store::{Store, StoreError, User},
+    users,
+};
+
+/// Discovery documents and signing keys are fetched again after this
+const PROVIDER_TTL: Duration = Duration::from_secs(3600);
+/// Time users get to sign in at the provider
+const LOGIN_TTL: Duration = Duration::from_secs(600);
+const MAX_PENDING: u64 = 10_000;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+pub enum OidcError {
+    #[error("Error while calling the OpenID provider: {0}")]
+    Provider(String),
+
+    #[error("Invalid ID token: {0}")]
+    Token(#[from] jsonwebtoken::errors::Error),
+
+    #[error("Invalid ID token: {0}")]
+    Claims(String),
+
+    #[error("Sign-in expired or unknown, try again")]
+    UnknownLogin,
+
+    #[error("Not allowed to sign in")]
+    Forbidden,
+
+    #[error(transparent)]
+    Store(#[from] StoreError),
+
+    #[error("Error while signing the token: {0}")]
+    Sign(jsonwebtoken::errors::Error),
+}
+
+impl OidcError {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::Provider(_) => StatusCode::BAD_GATEWAY,
+            Self::Token(_) | Self::Claims(_) | Self::UnknownLogin => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::Store(_) | Self::Sign(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// What the provider's discovery document says, and its signing keys.
+struct Provider {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    keys: JwkSet,
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+/// A sign-in sent to the provider, by its `state`.
+#[derive(Clone)]
+struct Pending {
+    nonce: String,
+    /// PKCE code verifier
+    verifier: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Who an ID token names, with the role its claims map to.
+pub struct Identity {
+    pub user: String,
+    pub name: Option<String>,
+    pub admin: bool,
+}
+
+/// Signs users in with an OpenID Connect provider (authorization code flow
+/// with PKCE) and hands them Roads JWTs acting as their user, created on
+/// first sign-in.
+pub struct Oidc {
+    config: OidcConfig,
+    issuer: String,
+    jwt_secret: String,
+    user_scopes: Vec<Scope>,
+    client: Client<HttpsConnector<HttpConnector>>,
+    provider: Cache<(), Arc<Provider>>,
+    pending: Cache<String, Pending>,
+}
+
+impl Oidc {
+    /// `None` when no issuer is configured.
+    pub fn new(config: &OidcConfig, jwt_secret: Option<&str>) -> Option<Self> {
+        let issuer = config.issuer_url.as_deref()?;
+        let jwt_secret = jwt_secret?;
+
+        let connector = HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+
+        Some(Self {
+            config: config.clone(),
+            issuer: issuer.trim_end_matches('/').into(),
+            jwt_secret: jwt_secret.into(),
+            user_scopes: config
+                .user_scopes
+                .iter()
+                .filter_map(|scope| scope.parse().ok())
+                .collect(),
+            client: Client::builder().build(connector),
+            provider: Cache::builder().time_to_live(PROVIDER_TTL).build(),
+            pending: Cache::builder()
+                .max_capacity(MAX_PENDING)
+                .time_to_live(LOGIN_TTL)
+                .build(),
+        })
+    }
+
+    /// Where to send users to sign in.
+    pub async fn login_url(&self) -> Result<String, OidcError> {
+        let provider = self.provider().await?;
+        let state = random_token();
+        let pending = Pending {
+            nonce: random_token(),
+            verifier: random_token(),
+        };
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(pending.verifier.as_bytes()));
+
+        let query = serde_urlencoded::to_string([
+            ("response_type", "code"),
+            ("client_id", &self.config.client_id),
+            ("redirect_uri", self.redirect_url()),
+            ("scope", &self.config.scopes.join(" ")),
+            ("state", &state),
+            ("nonce", &pending.nonce),
+            ("code_challenge", &challenge),
+            ("code_challenge_method", "S256"),
+        ])
+        .expect("authorization requests encode as a query");
+        self.pending.insert(state, pending).await;
+
+        let endpoint = &provider.authorization_endpoint;
+        let separator = if endpoint.contains('?') { '&' } else { '?' };
+        Ok(format!("{}{}{}", endpoint, separator, query))
+    }
+
+    /// Trades the `code` the provider sent back with `state` for the
+    /// identity of whoever signed in.
+    pub async fn finish_login(&self, code: &str, state: &str) -> Result<Identity, OidcError> {
+        let pending = self
+            .pending
+            .remove(state)
+            .await
+            .ok_or(OidcError::UnknownLogin)?;
+        let provider = self.provider().await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.redirect_url()),
+            ("client_id", &self.config.client_id),
+            ("code_verifier", &pending.verifier),
+        ];
+        if let Some(secret) = &self.config.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let body = serde_urlencoded::to_string(form).expect("token requests encode as a form");
+        let request = Request::post(&provider.token_endpoint)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(header::ACCEPT, "application/json")
+            .body(Body::from(body));
+        let tokens: TokenResponse = self.fetch(request).await?;
+
+        self.verify(&tokens.id_token, Some(&pending.nonce)).await
+    }
+
+    /// Checks the signature, issuer, audience, expiry and, when given, the
+    /// nonce of `id_token`, and maps its claims to an identity.
+    pub async fn verify(&self, id_token: &str, nonce: Option<&str>) -> Result<Identity, OidcError> {
+        let header = jsonwebtoken::decode_header(id_token)?;
+        let kid = header
+            .kid
+            .ok_or_else(|| OidcError::Claims("no key id in the header".into()))?;
+        let mut provider = self.provider().await?;
+        if provider.keys.find(&kid).is_none() {
+            // the provider may have rotated its keys since they were fetched
+            self.provider.invalidate(&()).await;
+            provider = self.provider().await?;
+        }
+        let jwk = provider
+            .keys
+            .find(&kid)
+            .ok_or_else(|| OidcError::Claims(format!("unknown key {}", kid)))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&provider.issuer]);
+        let mut audiences = vec![self.config.client_id.as_str()];
+        audiences.extend(self.config.audiences.iter().map(String::as_str));
+        validation.set_audience(&audiences);
+        let claims = jsonwebtoken::decode::<HashMap<String, Value>>(
+            id_token,
+            &DecodingKey::from_jwk(jwk)?,
+            &validation,
+        )?
+        .claims;
+
+        if let Some(nonce) = nonce {
+            if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+                return Err(OidcError::Claims("nonce mismatch".into()));
+            }
+        }
+        self.identity(&claims)
+    }
+
+    fn identity(&self, claims: &HashMap<String, Value>) -> Result<Identity, OidcError> {
+        let user = claim(claims, &self.config.user_claim)
+            .and_then(Value::as_str)
+            .filter(|user| users::check_id(user).is_ok())
+            .ok_or_else(|| {
+                OidcError::Claims(format!("no usable {} claim", self.config.user_claim))
+            })?;
+        if self.config.user_claim == "email"
+            && claims.get("email_verified").and_then(Value::as_bool) == Some(false)
+        {
+            return Err(OidcError::Claims("email not verified".into()));
+        }
+
+        let roles: Vec<&str> = match claim(claims, &self.config.roles_claim) {
+            Some(Value::Array(roles)) => roles.iter().filter_map(Value::as_str).collect(),
+            Some(Value::String(role)) => vec![role.as_str()],
+            _ => Vec::new(),
+        };
+        let has_any = |wanted: &[String]| roles.iter().any(|role| wanted.iter().any(|w| w == role));
+        if !self.config.allowed_roles.is_empty() && !has_any(&self.config.allowed_roles) {
+            return Err(OidcError::Forbidden);
+        }
+
+        Ok(Identity {
+            user: user.into(),
+            name: claims.get("name").and_then(Value::as_str).map(Into::into),
+            admin: has_any(&self.config.admin_roles),
+        })
+    }
+
+    /// Creates or updates the user of `identity`, its role following the
+    /// provider's, and signs a token acting as it. Returns the token and
+    /// its expiry.
+    pub async fn sign_in(
+        &self,
+        store: &Store,
+        identity: Identity,
+    ) -> Result<(String, i64), OidcError> {
+        let existing = store.get_user(&identity.user).await?;
+        let user = User {
+            name: identity
+                .name
+                .or_else(|| existing.as_ref().map(|user| user.name.clone()))
+                .unwrap_or_else(|| identity.user.clone()),
+            id: identity.user,
+            admin: identity.admin,
+            created_at: auth::now(),
+        };
+        match &existing {
+            None => {
+                store.insert_user(&user).await?;
+            }
+            Some(old) if old.name != user.name || old.admin != user.admin => {
+                store.update_user(&user).await?;
+            }
+            Some(_) => {}
+        }
+
+        let scopes = if user.admin {
+            &Scope::ALL[..]
+        } else {
+            &self.user_scopes[..]
+        };
+        let ttl = Duration::from_secs(self.config.token_ttl);
+        let token = auth::mint_token(&self.jwt_secret, &user.id, scopes, None, Some(&user.id), ttl)
+            .map_err(OidcError::Sign)?;
+
+        debug!("signed in with OpenID Connect: {}", &user.id);
+        Ok((token, auth::now() + ttl.as_secs() as i64))
+    }
+
+    fn redirect_url(&self) -> &str {
+        self.config
+            .redirect_url
+            .as_deref()
+            .expect("redirect_url is validated with the config")
+    }
+
+    async fn provider(&self) -> Result<Arc<Provider>, OidcError> {
+        self.provider
+            .try_get_with((), self.discover())
+            .await
+            .map_err(|err| OidcError::Provider(err.to_string()))
+    }
+
+    async fn discover(&self) -> Result<Arc<Provider>, OidcError> {
+        let url = format!("{}/.well-known/openid-configuration", self.issuer);
+        let discovery: Discovery = self.get(&url).await?;
+        if discovery.issuer.trim_end_matches('/') != self.issuer {
+            return Err(OidcError::Provider(format!(
+                "discovery names issuer {}, not {}",
+                discovery.issuer, self.issuer
+            )));
+        }
+        let keys: JwkSet = self.get(&discovery.jwks_uri).await?;
+
+        Ok(Arc::new(Provider {
+            issuer: discovery.issuer,
+            authorization_endpoint: discovery.authorization_endpoint,
+            token_endpoint: discovery.token_endpoint,
+            keys,
+        }))
+    }
+
+    async fn get<T: DeserializeOwned>(&self, url: &str) -> Result<T, OidcError> {
+        let request = Request::get(url)
+            .header(header::ACCEPT, "application/json")
+            .body(Body::empty());
+        self.fetch(request).await
+    }
+
+    async fn fetch<T: DeserializeOwned>(
+        &self,
+        request: Result<Request<Body>, hyper::http::Error>,
+    ) -> Result<T, OidcError> {
+        let request = request.map_err(|err| OidcError::Provider(err.to_string()))?;
+        let uri = request.uri().clone();
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, self.client.request(request))
+            .await
+            .map_err(|_| OidcError::Provider(format!("{} timed out", uri)))?
+            .map_err(|err| OidcError::Provider(err.to_string()))?;
+
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|err| OidcError::Provider(err.to_string()))?;
+        if !status.is_success() {
+            return Err(OidcError::Provider(format!(
+                "{} answered {}: {}",
+                uri,
+                status,
+                String::from_utf8_lossy(&body)
+            )));
+        }
+
+        serde_json::from_slice(&body).map_err(|err| OidcError::Provider(err.to_string()))
+    }
+}
+
+/// The claim at `path`, dots going into nested objects.
+fn claim<'a>(claims: &'a HashMap<String, Value>, path: &str) -> Option<&'a Value> {
+    let mut parts = path.split('.');
+    let first = claims.get(parts.next()?)?;
+
+    parts.try_fold(first, |value, part| value.get(part))
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// The dashboard's sign-in through the provider. Tokens are handed to the
+/// dashboard in the URL fragment, which browsers don't send anywhere.
+pub fn login_routes() -> Router<AppState> {
+    Router::new()
+        .route("/admin/oidc", get(|| async { StatusCode::NO_CONTENT }))
+        .route("/admin/oidc/login", get(login))
+        .route("/admin/oidc/callback", get(callback))
+}
+
+/// Exchange of ID tokens, for scripts already signed in with the provider.
+pub fn exchange_routes() -> Router<AppState> {
+    Router::new().route("/api/oidc/token", post(exchange))
+}
+
+fn oidc(state: &AppState) -> Result<&Oidc, (StatusCode, String)> {
+    state.oidc.as_deref().ok_or((
+        StatusCode::NOT_FOUND,
+        "OpenID Connect isn't configured".into(),
+    ))
+}
+
+async fn login(State(state): State<AppState>) -> Result<Redirect, (StatusCode, String)> {
+    let url = oidc(&state)?
+        .login_url()
+        .await
+        .map_err(|err| (err.status(), err.to_string()))?;
+
+    Ok(Redirect::to(&url))
+}
+
+/// `?code=&state=`, or `?error=` when the provider refused.
+#[derive(Deserialize)]
+struct CallbackParams {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+async fn callback(
+    State(state): State<AppState>,
+    Query(params): Query<CallbackParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let oidc = oidc(&state)?;
+    let signed_in = match (params.code, params.state, params.error) {
+        (_, _, Some(error)) => Err(params.error_description.unwrap_or(error)),
+        (Some(code), Some(login), None) => {
+            let signed_in = async {
+                let identity = oidc.finish_login(&code, &login).await?;
+                oidc.sign_in(&state.store, identity).await
+            };
+            signed_in.await.map_err(|err| {
+                warn!("OpenID Connect sign-in failed: {}", err);
+                err.to_string()
+            })
+        }
+        _ => Err("Missing code or state".into()),
+    };
+
+    let fragment = match signed_in {
+        Ok((token, _)) => serde_urlencoded::to_string([("token", token)]),
+        Err(error) => serde_urlencoded::to_string([("error", error)]),
+    }
+    .expect("fragments encode as a query");
+    Ok(Redirect::to(&format!("/admin/#{}", fragment)).into_response())
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct Exchange {
+    /// ID token issued by the provider to `oidc.client_id` or one of
+    /// `oidc.audiences`
+    id_token: String,
+}
+
+/// A Roads JWT acting as the user the ID token named.
+#[derive(Serialize, ToSchema)]
+pub(crate) struct Exchanged {
+    token: String,
+    /// Unix timestamp, in seconds
+    expires_at: i64,
+    user: String,
+    admin: bool,
+}
+
+/// Signs in without a browser, the user and role mapped as for the
+/// dashboard.
+#[utoipa::path(
+    post,
+    path = "/api/oidc/token",
+    tag = "auth",
+    request_body = Exchange,
+    security(()),
+    responses(
+        (status = 200, description = "A token acting as the user", body = Exchanged),
+        (status = 401, description = "Invalid ID token", body = String),
+        (status = 403, description = "Not allowed to sign in", body = String),
+        (status = 404, description = "OpenID Connect isn't configured", body = String),
+    )
+)]
+async fn exchange(
+    State(state): State<AppState>,
+    Json(req): Json<Exchange>,
+) -> Result<Json<Exchanged>, (StatusCode, String)> {
+    let oidc = oidc(&state)?;
+    let error = |err: OidcError| (err.status(), err.to_string());
+
+    let identity = oidc.verify(&req.id_token, None).await.map_err(error)?;
+    let (user, admin) = (identity.user.clone(), identity.admin);
+    let (token, expires_at) = oidc.sign_in(&state.store, identity).await.map_err(error)?;
+
+    Ok(Json(Exchanged {
+        token,
+        expires_at,
+        user,
+        admin,
+    }))
+}
@@ -0,0 +1,151 @@
+use axum::Router;
+use utoipa::{
+    Modify, OpenApi,
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::{
+    audit, auth,
+    config::ErrorCorrection,
+    device::Device,
+    health_checks::UpstreamHealth,
+    history,
+    import::{Conflict, Format, ImportFailure, ImportReport},
+    maintenance, oidc, proxy, qr, quota, response_cache,
+    router::{self, AppState, NewRoute, RouteUpdate},
+    shorten::{self, Shortened, ShortenRequest},
+    stats,
+    store::{
+        ApiKey, AuditEntry, Balance, Bucket, Canary, CircuitBreaker, Count, DeviceTarget,
+        GeoTarget, HeaderRules, HitStats, LanguageTarget, Maintenance, MatchType, Mirror,
+        PathRewrite, ProxyOptions, Retries, Revision, RevisionAction, Route, RouteMode,
+        SplitTarget, Tenant, Timeouts, User,
+    },
+    tenants, users,
+};
+
+/// The admin and analytics API, generated from the handlers.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Roads",
+        description = "Admin API of Roads. Every endpoint takes an API key or JWT as a \
+                       bearer token, answering 401 without one and 403 when it lacks the \
+                       scope needed, or changes a route of another user without being an \
+                       admin. Errors have a plain text body."
+    ),
+    paths(
+        router::list_routes,
+        router::add_route,
+        router::read_route,
+        router::update_route,
+        router::delete_route,
+        router::route_action,
+        router::import_routes,
+        router::export_routes,
+        history::route_history,
+        history::rollback,
+        stats::route_stats,
+        qr::route_qr,
+        response_cache::purge_route,
+        shorten::shorten,
+        auth::list_keys,
+        auth::create_key,
+        auth::revoke_key,
+        tenants::list_tenants,
+        tenants::create_tenant,
+        tenants::delete_tenant,
+        users::list_users,
+        users::create_user,
+        users::delete_user,
+        oidc::exchange,
+        audit::list_audit,
+        quota::read_usage,
+        proxy::list_upstreams,
+        maintenance::read_maintenance,
+        maintenance::update_maintenance,
+    ),
+    components(schemas(
+        Route,
+        NewRoute,
+        RouteUpdate,
+        RouteMode,
+        ProxyOptions,
+        Balance,
+        Canary,
+        Mirror,
+        Retries,
+        CircuitBreaker,
+        HeaderRules,
+        PathRewrite,
+        Timeouts,
+        MatchType,
+        SplitTarget,
+        LanguageTarget,
+        DeviceTarget,
+        GeoTarget,
+        Device,
+        Revision,
+        RevisionAction,
+        HitStats,
+        Bucket,
+        Count,
+        ImportReport,
+        ImportFailure,
+        Conflict,
+        Format,
+        ErrorCorrection,
+        ShortenRequest,
+        Shortened,
+        ApiKey,
+        auth::NewKey,
+        auth::MintedKey,
+        Tenant,
+        tenants::NewTenant,
+        User,
+        users::NewUser,
+        oidc::Exchange,
+        oidc::Exchanged,
+        AuditEntry,
+        quota::Usage,
+        quota::KindUsage,
+        proxy::Upstream,
+        UpstreamHealth,
+        Maintenance,
+    )),
+    modifiers(&BearerAuth),
+    security(("bearer" = [])),
+    tags(
+        (name = "routes", description = "Managing routes"),
+        (name = "stats", description = "Click statistics"),
+        (name = "keys", description = "API keys"),
+        (name = "tenants", description = "Tenants, isolated sets of routes and keys"),
+        (name = "users", description = "Users, owning the routes they create"),
+        (name = "auth", description = "Signing in with OpenID Connect"),
+        (name = "audit", description = "The audit log"),
+        (name = "usage", description = "Daily quotas"),
+        (name = "upstreams", description = "Upstreams of proxy routes and their health"),
+        (name = "maintenance", description = "Planned downtime of routes"),
+    )
+)]
+struct ApiDoc;
+
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+/// Swagger UI on `/api/docs`, over the spec at `/api/docs/openapi.json`.
+pub fn docs_routes() -> Router<AppState> {
+    SwaggerUi::new("/api/docs")
+        .url("/api/docs/openapi.json", ApiDoc::openapi())
+        .into()
+}
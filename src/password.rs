@@ -0,0 +1,129 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, SaltString},
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+};
+use axum::{
+    body::Body,
+    http::{header, Response, StatusCode},
+};
+use sha2::{Digest, Sha256, Sha512};
+use tower_cookies::{cookie::time::Duration, Cookie, Cookies, Key};
+
+use crate::{
+    auth,
+    config::PasswordConfig,
+    router::internal_error,
+    store::Route,
+};
+
+/// Checks and grants access to password-protected routes through signed
+/// cookies, one per route.
+pub struct Unlocker {
+    key: Key,
+    ttl: i64,
+}
+
+impl Unlocker {
+    /// Signs with a key derived from `passwords.cookie_secret`, or a random
+    /// one that logs everyone out on restart.
+    pub fn new(config: &PasswordConfig) -> Self {
+        let key = match &config.cookie_secret {
+            Some(secret) => Key::from(&Sha512::digest(secret.as_bytes())),
+            None => Key::generate(),
+        };
+
+        Self {
+            key,
+            ttl: config.cookie_ttl as i64,
+        }
+    }
+
+    /// Whether the client entered the current password of `route` recently.
+    pub fn is_unlocked(&self, route: &Route, cookies: &Cookies) -> bool {
+        let Some(hash) = &route.password_hash else {
+            return true;
+        };
+        let Some(cookie) = cookies.signed(&self.key).get(&cookie_name(route)) else {
+            return false;
+        };
+
+        // the fingerprint locks clients out again when the password changes
+        let Some((expires_at, fingerprint)) = cookie.value().split_once(':') else {
+            return false;
+        };
+        expires_at.parse::<i64>().is_ok_and(|at| at > auth::now())
+            && fingerprint == self::fingerprint(hash)
+    }
+
+    pub fn unlock(&self, route: &Route, cookies: &Cookies) {
+        let Some(hash) = &route.password_hash else {
+            return;
+        };
+
+        let value = format!("{}:{}", auth::now() + self.ttl, fingerprint(hash));
+        let mut cookie = Cookie::new(cookie_name(route), value);
+        cookie.set_path("/");
+        cookie.set_http_only(true);
+        cookie.set_max_age(Duration::seconds(self.ttl));
+        cookies.signed(&self.key).add(cookie);
+    }
+}
+
+fn cookie_name(route: &Route) -> String {
+    format!("roads_unlock_{}", &auth::hash_token(&route.label())[..12])
+}
+
+fn fingerprint(hash: &str) -> String {
+    hex::encode(&Sha256::digest(hash.as_bytes())[..6])
+}
+
+/// Argon2id PHC string of `password`, what routes store.
+pub fn hash(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+
+    Ok(hash.to_string())
+}
+
+/// Whether `password` matches `hash`, a malformed hash matching nothing.
+pub fn verify(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash)
+        .is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+}
+
+/// The form asking for the password, posting back to the same URL.
+pub fn form(status: StatusCode, wrong: bool) -> Result<Response<Body>, (StatusCode, String)> {
+    let error = if wrong {
+        "<p role=\"alert\">Wrong password, try again.</p>\n"
+    } else {
+        ""
+    };
+    let html = format!(
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         <head>\n\
+         <meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <meta name=\"robots\" content=\"noindex\">\n\
+         <title>Password required</title>\n\
+         </head>\n\
+         <body>\n\
+         <p>This link is password protected.</p>\n\
+         {}\
+         <form method=\"post\">\n\
+         <input type=\"password\" name=\"password\" autocomplete=\"current-password\" \
+         autofocus required>\n\
+         <button type=\"submit\">Continue</button>\n\
+         </form>\n\
+         </body>\n\
+         </html>\n",
+        error
+    );
+
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::from(html))
+        .map_err(internal_error)
+}
@@ -0,0 +1,235 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use regex::{Regex, RegexSet};
+use tokio::sync::RwLock;
+use tracing::warn;
+
+use crate::{
+    replica::ReadReplica,
+    store::{MatchType, Route, Store, StoreError},
+};
+
+/// Routes matched by pattern or regex instead of exact slug, compiled once
+/// and reloaded from the store after `ttl` or when a route changes. The
+/// slugs of exact routes with `preserve_path` are kept along, so paths are
+/// only looked up by their parents when one of them is such a route.
+pub struct PatternRoutes {
+    ttl: Duration,
+    /// Set when `database_replica_url` is, routes being loaded from it
+    replica: Option<Arc<ReadReplica>>,
+    compiled: RwLock<Option<Compiled>>,
+}
+
+struct Compiled {
+    loaded_at: Instant,
+    /// Matchers per route host, `None` holding the host-agnostic routes
+    hosts: HashMap<Option<String>, Matchers>,
+}
+
+#[derive(Default)]
+struct Matchers {
+    patterns: matchit::Router<Route>,
+    /// One entry per regex route, tested in a single pass
+    regex_set: RegexSet,
+    regexes: Vec<(Regex, Route)>,
+    /// Slugs of the exact routes with `preserve_path`
+    prefixes: HashSet<String>,
+    /// Length of the longest of `prefixes`
+    longest_prefix: usize,
+}
+
+impl PatternRoutes {
+    pub fn new(ttl: Duration, replica: Option<Arc<ReadReplica>>) -> Self {
+        Self {
+            ttl,
+            replica,
+            compiled: RwLock::new(None),
+        }
+    }
+
+    /// The first route matching `path`, with its captures substituted into
+    /// `redirect_to`. Only routes scoped to `host` are considered, `None`
+    /// being the host-agnostic ones.
+    pub async fn find(
+        &self,
+        host: Option<&str>,
+        path: &str,
+        store: &Store,
+    ) -> Result<Option<Route>, StoreError> {
+        self.with_compiled(store, |compiled| compiled.find(host, path))
+            .await
+    }
+
+    /// The parents of `path` that are the slug of an exact `preserve_path`
+    /// route of `host`, longest first.
+    pub async fn prefixes<'p>(
+        &self,
+        host: Option<&str>,
+        path: &'p str,
+        store: &Store,
+    ) -> Result<Vec<&'p str>, StoreError> {
+        self.with_compiled(store, |compiled| compiled.prefixes(host, path))
+            .await
+    }
+
+    async fn with_compiled<T>(
+        &self,
+        store: &Store,
+        f: impl Fn(&Compiled) -> T,
+    ) -> Result<T, StoreError> {
+        if let Some(compiled) = self.compiled.read().await.as_ref() {
+            if compiled.loaded_at.elapsed() < self.ttl {
+                return Ok(f(compiled));
+            }
+        }
+
+        let mut compiled = self.compiled.write().await;
+        // another request may have reloaded while we waited for the lock
+        if let Some(compiled) = compiled.as_ref() {
+            if compiled.loaded_at.elapsed() < self.ttl {
+                return Ok(f(compiled));
+            }
+        }
+
+        let routes = match &self.replica {
+            Some(replica) => replica.list_patterns(store).await,
+            None => store.list_patterns().await,
+        };
+        let reloaded = match (routes, compiled.as_mut()) {
+            (Ok(routes), _) => Compiled::load(routes),
+            // keep matching the routes loaded last until the store is back
+            (Err(err), Some(stale)) => {
+                warn!("failed to reload pattern routes, keeping the loaded ones: {}", err);
+                stale.loaded_at = Instant::now();
+                return Ok(f(stale));
+            }
+            (Err(err), None) => return Err(err),
+        };
+        let found = f(&reloaded);
+        *compiled = Some(reloaded);
+
+        Ok(found)
+    }
+
+    pub async fn invalidate(&self) {
+        *self.compiled.write().await = None;
+    }
+}
+
+impl Compiled {
+    fn load(routes: Vec<Route>) -> Self {
+        let mut hosts: HashMap<Option<String>, Matchers> = HashMap::new();
+        for route in routes {
+            let slug = route.slug.clone();
+            let matchers = hosts.entry(route.host.clone()).or_default();
+            let loaded = match route.match_type {
+                MatchType::Exact => {
+                    if route.preserve_path {
+                        matchers.longest_prefix = matchers.longest_prefix.max(slug.len());
+                        matchers.prefixes.insert(slug.clone());
+                    }
+                    Ok(())
+                }
+                MatchType::Pattern => matchers
+                    .patterns
+                    .insert(format!("/{}", slug), route)
+                    .map_err(|err| err.to_string()),
+                MatchType::Regex => {
+                    compile_regex(&slug).map(|regex| matchers.regexes.push((regex, route)))
+                }
+            };
+
+            if let Err(err) = loaded {
+                warn!("skipping {} route: {}", slug, err);
+            }
+        }
+
+        for matchers in hosts.values_mut() {
+            matchers.regex_set =
+                RegexSet::new(matchers.regexes.iter().map(|(regex, _)| regex.as_str()))
+                    .expect("each regex compiled on its own");
+        }
+
+        Self {
+            loaded_at: Instant::now(),
+            hosts,
+        }
+    }
+
+    fn find(&self, host: Option<&str>, path: &str) -> Option<Route> {
+        self.hosts.get(&host.map(str::to_owned))?.find(path)
+    }
+
+    fn prefixes<'p>(&self, host: Option<&str>, path: &'p str) -> Vec<&'p str> {
+        let Some(matchers) = self.hosts.get(&host.map(str::to_owned)) else {
+            return Vec::new();
+        };
+
+        let mut prefixes = Vec::new();
+        let mut prefix = path;
+        while let Some((parent, _)) = prefix.rsplit_once('/') {
+            prefix = parent;
+            if prefix.len() <= matchers.longest_prefix && matchers.prefixes.contains(prefix) {
+                prefixes.push(prefix);
+            }
+        }
+
+        prefixes
+    }
+}
+
+impl Matchers {
+    fn find(&self, path: &str) -> Option<Route> {
+        self.find_pattern(path).or_else(|| self.find_regex(path))
+    }
+
+    fn find_pattern(&self, path: &str) -> Option<Route> {
+        let path = format!("/{}", path);
+        let matched = self.patterns.at(&path).ok()?;
+
+        let mut redirect_to = matched.value.redirect_to.clone();
+        for (name, value) in matched.params.iter() {
+            redirect_to = redirect_to.replace(&format!("{{{}}}", name), value);
+        }
+
+        Some(Route {
+            redirect_to,
+            ..matched.value.clone()
+        })
+    }
+
+    /// Routes are listed by slug, the first one matching wins.
+    fn find_regex(&self, path: &str) -> Option<Route> {
+        let index = self.regex_set.matches(path).into_iter().next()?;
+        let (regex, route) = &self.regexes[index];
+        let captures = regex.captures(path)?;
+
+        let mut redirect_to = String::new();
+        captures.expand(&route.redirect_to, &mut redirect_to);
+
+        Some(Route {
+            redirect_to,
+            ..route.clone()
+        })
+    }
+}
+
+/// The slug has to match the whole path, like a RewriteRule with `^...$`.
+fn compile_regex(slug: &str) -> Result<Regex, String> {
+    Regex::new(&format!("^(?:{})$", slug)).map_err(|err| err.to_string())
+}
+
+/// Checks the slug of a pattern or regex route compiles.
+pub fn validate(route: &Route) -> Result<(), String> {
+    match route.match_type {
+        MatchType::Exact => Ok(()),
+        MatchType::Pattern => matchit::Router::new()
+            .insert(format!("/{}", route.slug), ())
+            .map_err(|err| err.to_string()),
+        MatchType::Regex => compile_regex(&route.slug).map(|_| ()),
+    }
+}
@@ -0,0 +1,109 @@
+use std::{
+    future::Future,
+    io,
+    net::{SocketAddr, TcpListener},
+};
+#[cfg(unix)]
+use std::{
+    fs::{self, Permissions},
+    os::unix::fs::PermissionsExt,
+    path::Path,
+};
+
+use axum::Router;
+use axum_server::{accept::DefaultAcceptor, Handle, HttpConfig};
+use socket2::{Domain, Socket, Type};
+#[cfg(unix)]
+use hyper::server::accept;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tracing::debug;
+
+use crate::{proxy_protocol, ServerError};
+
+/// Serves `app` over plain HTTP until `stop` resolves.
+pub async fn serve_tcp(
+    addr: SocketAddr,
+    http2: bool,
+    proxy_protocol: bool,
+    app: Router,
+    stop: impl Future<Output = ()> + Send + 'static,
+) -> Result<(), ServerError> {
+    if proxy_protocol {
+        let handle = Handle::new();
+        let stopping = handle.clone();
+        tokio::spawn(async move {
+            stop.await;
+            stopping.graceful_shutdown(None);
+        });
+
+        let listener = bind(addr).map_err(ServerError::Listen)?;
+        debug!("listening on {} (PROXY protocol)", addr);
+        return axum_server::from_tcp(listener)
+            .handle(handle)
+            .http_config(HttpConfig::new().http1_only(!http2).build())
+            .acceptor(proxy_protocol::Acceptor::new(DefaultAcceptor, true))
+            .serve(app.into_make_service())
+            .await
+            .map_err(ServerError::Listen);
+    }
+
+    let listener = bind(addr).map_err(ServerError::Listen)?;
+    let server = axum::Server::from_tcp(listener)?
+        .http1_only(!http2)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(stop);
+    debug!("listening on {}", addr);
+
+    Ok(server.await?)
+}
+
+/// Binds a TCP listener. `[::]` always accepts IPv4 clients as well,
+/// whatever the OS default for dual-stack sockets is.
+pub fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        socket.set_only_v6(false)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+
+    Ok(socket.into())
+}
+
+/// Serves `app` on a Unix socket at `path` until `stop` resolves. A stale
+/// socket file is replaced, and removed again once the server stops.
+#[cfg(unix)]
+pub async fn serve_unix(
+    path: &Path,
+    mode: Option<u32>,
+    http2: bool,
+    app: Router,
+    stop: impl Future<Output = ()>,
+) -> Result<(), ServerError> {
+    if path.exists() {
+        fs::remove_file(path).map_err(ServerError::Listen)?;
+    }
+    let listener = UnixListener::bind(path).map_err(ServerError::Listen)?;
+    if let Some(mode) = mode {
+        fs::set_permissions(path, Permissions::from_mode(mode)).map_err(ServerError::Listen)?;
+    }
+    debug!("listening on unix:{}", path.display());
+
+    let incoming = accept::poll_fn(move |cx| {
+        listener
+            .poll_accept(cx)
+            .map(|accepted| Some(accepted.map(|(stream, _)| stream)))
+    });
+    let result = axum::Server::builder(incoming)
+        .http1_only(!http2)
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(stop)
+        .await;
+
+    let _ = fs::remove_file(path);
+    Ok(result?)
+}
@@ -0,0 +1,71 @@
+use axum::{
+    body::Body,
+    http::{header, Response, StatusCode},
+};
+
+use crate::router::internal_error;
+
+/// Query parameter asking any route for its preview page
+const PARAM: &str = "preview";
+
+/// Whether `query` has a `preview` parameter, with or without a value.
+pub fn requested(query: &str) -> bool {
+    query
+        .split('&')
+        .any(|param| param.split('=').next() == Some(PARAM))
+}
+
+/// `query` without its `preview` parameters, so they aren't passed on to
+/// the target.
+pub fn strip(query: &str) -> String {
+    query
+        .split('&')
+        .filter(|param| param.split('=').next() != Some(PARAM))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// A page showing `target` with a link to continue there.
+pub fn page(target: &str) -> Result<Response<Body>, (StatusCode, String)> {
+    let target = escape(target);
+    let html = format!(
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         <head>\n\
+         <meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <meta name=\"robots\" content=\"noindex\">\n\
+         <title>Leaving for {0}</title>\n\
+         </head>\n\
+         <body>\n\
+         <p>This link leads to:</p>\n\
+         <p><code>{0}</code></p>\n\
+         <p><a href=\"{0}\" rel=\"noreferrer\">Continue</a></p>\n\
+         </body>\n\
+         </html>\n",
+        target
+    );
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::from(html))
+        .map_err(internal_error)
+}
+
+/// `value` safe to put in HTML text and attributes.
+pub fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
@@ -0,0 +1,919 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    io,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{extract::State, Json, Router, routing::get};
+use futures::{stream, StreamExt};
+use hyper::{
+    body::HttpBody,
+    header::{self, HeaderMap, HeaderValue},
+    upgrade::OnUpgrade,
+    Body, Client, Method, Request, Response, StatusCode, Uri, Version,
+};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use moka::future::Cache;
+use rand::Rng;
+use regex::Regex;
+use serde::Serialize;
+use tower_cookies::Cookie;
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{self, Principal, Scope},
+    circuit_breaker::Circuits,
+    config::ProxyConfig,
+    dns::UpstreamConnector,
+    events::{Event, EventSink, TunnelSession},
+    health_checks::{HealthChecks, UpstreamHealth},
+    response_cache::ResponseCache,
+    router::{AppState, internal_error},
+    store::{Balance, Canary, HeaderRules, Mirror, PathRewrite, Route, RouteMode},
+};
+
+/// Headers about a single connection, never passed on. Headers named in
+/// `Connection` are dropped too.
+const HOP_BY_HOP: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Retries a route can save up, and starts with.
+const BUDGET_CAP: f64 = 10.0;
+/// Bodies of retried and mirrored requests are kept in memory, up to this
+/// size.
+const MAX_REPLAY_BODY: u64 = 64 * 1024;
+/// Mirrored requests in flight past which requests aren't copied anymore.
+const MAX_MIRRORS: usize = 256;
+
+/// Forwards requests of proxy-mode routes to their target and streams the
+/// response back, neither body being buffered. Routes with upstreams have
+/// them take turns, or the least busy one picked, among the healthy ones
+/// whose circuit is closed.
+pub struct Proxy {
+    client: Client<HttpsConnector<UpstreamConnector>>,
+    /// Set when health checks are enabled
+    checks: Option<Arc<HealthChecks>>,
+    /// Next upstream of round-robin routes, by route label
+    turns: Mutex<HashMap<String, usize>>,
+    /// Requests in flight by upstream, their response bodies included
+    in_flight: Arc<Mutex<HashMap<String, usize>>>,
+    /// Retries left by route label, each request adding its route's
+    /// `retries.budget`
+    budgets: Mutex<HashMap<String, f64>>,
+    circuits: Circuits,
+    /// Rules of the `[proxy]` config
+    request_headers: HeaderRules,
+    /// Compiled `rewrite` patterns
+    rewrites: Cache<String, Regex>,
+    /// Set when `proxy.cache` is enabled
+    cache: Option<ResponseCache>,
+    /// Bytes of request bodies, 0 for no limit
+    max_body: u64,
+    /// Seconds of the `[proxy]` config, 0 for no limit
+    read_timeout: u64,
+    total_timeout: u64,
+    /// Mirrored requests in flight
+    mirrors: Arc<AtomicUsize>,
+    /// Set when events are published, for closed tunnels
+    events: Option<Arc<EventSink>>,
+}
+
+impl Proxy {
+    pub fn new(
+        config: &ProxyConfig,
+        upstreams: UpstreamConnector,
+        max_body: u64,
+        checks: Option<Arc<HealthChecks>>,
+        events: Option<Arc<EventSink>>,
+    ) -> Self {
+        let connector = HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .wrap_connector(upstreams);
+
+        Self {
+            client: Client::builder().build(connector),
+            checks,
+            turns: Mutex::default(),
+            in_flight: Arc::default(),
+            budgets: Mutex::default(),
+            circuits: Circuits::default(),
+            request_headers: config.request_headers.clone(),
+            rewrites: Cache::new(1024),
+            cache: config.cache.enabled.then(|| ResponseCache::new(&config.cache)),
+            max_body,
+            read_timeout: config.read_timeout,
+            total_timeout: config.total_timeout,
+            mirrors: Arc::default(),
+            events,
+        }
+    }
+
+    /// Sends `request` to `target` of `route`, a full URL whose origin is
+    /// swapped for one of the route's upstreams, telling the upstream who
+    /// `client` is and which host was asked for through the `X-Forwarded-*`
+    /// headers, then changing them as the header rules say. Idempotent
+    /// requests failing to connect or answered 502/503 are retried on another
+    /// upstream as `route.proxy.retries` allows. Upstreams too slow for the
+    /// timeouts get the request answered 504. With sticky sessions, clients
+    /// are told which upstream to come back to through a cookie. Requests
+    /// going to the route's canary, or not, are answered `x-roads-upstream:
+    /// canary` or `stable`. A copy goes to the route's mirror, if it has one.
+    pub async fn forward(
+        &self,
+        route: &Route,
+        target: &str,
+        request: Request<Body>,
+        client: Option<IpAddr>,
+    ) -> Result<Response<Body>, (StatusCode, String)> {
+        let (mut parts, body) = request.into_parts();
+        let upgrade = is_websocket(&parts.headers)
+            .then(|| parts.extensions.remove::<OnUpgrade>())
+            .flatten();
+        let mut headers = parts.headers;
+        let affinity = route.proxy.sticky_sessions.then(|| affinity_cookie(route));
+        let sticky = affinity
+            .as_deref()
+            .and_then(|name| cookie(&headers, name))
+            .map(str::to_owned);
+        let canary = route
+            .proxy
+            .canary
+            .as_ref()
+            .is_some_and(|canary| to_canary(canary, &headers, sticky.as_deref()));
+        remove_hop_by_hop(&mut headers);
+        if upgrade.is_some() {
+            headers.insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+            headers.insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+        }
+        // set again from the target by the client
+        if let Some(host) = headers.remove(header::HOST) {
+            headers.insert("x-forwarded-host", host);
+        }
+        if let Some(ip) = client {
+            let forwarded_for = match headers.get("x-forwarded-for").map(HeaderValue::to_str) {
+                Some(Ok(chain)) => format!("{}, {}", chain, ip),
+                _ => ip.to_string(),
+            };
+            if let Ok(value) = HeaderValue::from_str(&forwarded_for) {
+                headers.insert("x-forwarded-for", value);
+            }
+        }
+        self.request_headers.apply(&mut headers);
+        route.proxy.request_headers.apply(&mut headers);
+
+        // cached by the target asked for, whichever upstream answers
+        let requested = target;
+        let cache = self.cache.as_ref().filter(|_| {
+            upgrade.is_none() && ResponseCache::accepts(route, &parts.method, &headers)
+        });
+        if let Some(cache) = cache {
+            if let Some(response) = cache.lookup(route, target, &parts.method, &headers).await {
+                return Ok(response);
+            }
+        }
+
+        let limited = self.max_body > 0;
+        if limited && body.size_hint().lower() > self.max_body {
+            return Err(payload_too_large());
+        }
+        let retries = &route.proxy.retries;
+        let retried = retries.attempts > 0 && is_replayable(&parts.method, &body);
+        let mirror = route.proxy.mirror.as_ref().filter(|mirror| {
+            upgrade.is_none()
+                && body.size_hint().upper().is_some_and(|size| size <= MAX_REPLAY_BODY)
+                && rand::thread_rng().gen_bool(mirror.percent / 100.0)
+        });
+        let overflowed = Arc::new(AtomicBool::new(false));
+        // retried and mirrored requests are sent again, so their body is kept
+        let (replay, mut body) = if retried || mirror.is_some() {
+            let bytes = hyper::body::to_bytes(body).await.map_err(|err| {
+                (StatusCode::BAD_REQUEST, format!("Failed to read the body: {}", err))
+            })?;
+            if retried {
+                self.deposit(route);
+            }
+            (Some(bytes), None)
+        } else if limited {
+            (None, Some(limit(body, self.max_body, overflowed.clone())))
+        } else {
+            (None, Some(body))
+        };
+
+        let timeouts = &route.proxy.timeouts;
+        let read = seconds(timeouts.read.unwrap_or(self.read_timeout));
+        let deadline = seconds(timeouts.total.unwrap_or(self.total_timeout))
+            .map(|total| Instant::now() + total);
+        if let Some((mirror, bytes)) = mirror.zip(replay.clone()) {
+            let request = Request::builder()
+                .method(parts.method.clone())
+                .version(Version::HTTP_11)
+                .body(Body::from(bytes))
+                .map_err(internal_error)?;
+            self.mirror(mirror, target, request, &headers, wait(read, deadline));
+        }
+
+        let mut tried = Vec::new();
+        let mut attempt = 0;
+        loop {
+            let (base, in_flight) = self.pick(route, target, &tried, sticky.as_deref(), canary)?;
+            let target = rebase(target, &base);
+            let uri: Uri = target.parse().map_err(|err| {
+                warn!("invalid proxy target {}: {}", target, err);
+                bad_gateway()
+            })?;
+            let body = match &replay {
+                Some(bytes) => Body::from(bytes.clone()),
+                None => body.take().unwrap_or_default(),
+            };
+            // the client speaks HTTP/1.1 whatever the listener got
+            let mut request = Request::builder()
+                .method(parts.method.clone())
+                .uri(uri)
+                .version(Version::HTTP_11)
+                .body(body)
+                .map_err(internal_error)?;
+            *request.headers_mut() = headers.clone();
+
+            debug!("proxying {} {}", parts.method, target);
+            let result = self.send(request, read, deadline).await;
+            if result.is_err() && overflowed.load(Ordering::Relaxed) {
+                return Err(payload_too_large());
+            }
+            let status = result.as_ref().ok().map(Response::status);
+            let breaker = &route.proxy.circuit_breaker;
+            if breaker.is_enabled() {
+                let failed = status.is_none_or(|status| status.is_server_error());
+                self.circuits.record(&base, breaker, failed);
+            }
+            let failed = status.is_none_or(|status| {
+                matches!(status, StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE)
+            });
+            if failed && retried && attempt < retries.attempts && self.withdraw(route) {
+                attempt += 1;
+                let delay = retries.backoff(attempt);
+                debug!("retrying {} in {:?}", route.label(), delay);
+                metrics::counter!("roads_proxy_retries_total").increment(1);
+                drop(in_flight);
+                tried.push(base);
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+
+            let mut response = result?;
+            *response.version_mut() = Version::default();
+            let status = response.status().as_u16().to_string();
+            metrics::counter!("roads_proxied_total", "status" => status).increment(1);
+            if let Some(canary) = &route.proxy.canary {
+                let served = if base == canary.upstream { "canary" } else { "stable" };
+                debug!("{} served by the {} upstream", route.label(), served);
+                metrics::counter!(
+                    "roads_proxy_canary_requests_total",
+                    "route" => route.label(),
+                    "upstream" => served,
+                )
+                .increment(1);
+                let served = HeaderValue::from_static(served);
+                response.headers_mut().insert("x-roads-upstream", served);
+            }
+            if response.status() == StatusCode::SWITCHING_PROTOCOLS {
+                let Some(upgrade) = upgrade else {
+                    warn!("{} switched protocols unasked", target);
+                    return Err(bad_gateway());
+                };
+                let session = self
+                    .events
+                    .clone()
+                    .map(|events| (events, TunnelSession::open(route, &base)));
+                tunnel(upgrade, &mut response, in_flight, session);
+                return Ok(response);
+            }
+            remove_hop_by_hop(response.headers_mut());
+            // the upstream stays busy until the body is through
+            let response = response.map(|body| guard(body, read, deadline, in_flight));
+            let ttl = cache
+                .filter(|_| parts.method == Method::GET)
+                .zip(ResponseCache::ttl(route, &response));
+            let mut response = match ttl {
+                Some((cache, ttl)) => cache.store(route, requested, &headers, response, ttl).await?,
+                None => response,
+            };
+            let token = affinity_token(&base);
+            let moved = sticky.as_deref() != Some(token.as_str())
+                && route.proxy.all_upstreams().any(|upstream| *upstream == base);
+            if let Some(name) = affinity.filter(|_| moved) {
+                let mut cookie = Cookie::new(name, token);
+                cookie.set_path("/");
+                cookie.set_http_only(true);
+                if let Ok(value) = HeaderValue::from_str(&cookie.to_string()) {
+                    response.headers_mut().append(header::SET_COOKIE, value);
+                }
+            }
+            return Ok(response);
+        }
+    }
+
+    /// Sends a copy of a request for `target` to `mirror` in the background,
+    /// with `headers` and giving up after `timeout`, its response dropped.
+    /// Skipped while `MAX_MIRRORS` copies are in flight.
+    fn mirror(
+        &self,
+        mirror: &Mirror,
+        target: &str,
+        mut request: Request<Body>,
+        headers: &HeaderMap,
+        timeout: Option<Duration>,
+    ) {
+        if self.mirrors.fetch_add(1, Ordering::Relaxed) >= MAX_MIRRORS {
+            self.mirrors.fetch_sub(1, Ordering::Relaxed);
+            debug!("too many mirrored requests in flight, not copying {}", target);
+            metrics::counter!("roads_proxy_mirrored_total", "result" => "skipped").increment(1);
+            return;
+        }
+        let target = rebase(target, &mirror.upstream);
+        match target.parse() {
+            Ok(uri) => *request.uri_mut() = uri,
+            Err(err) => {
+                self.mirrors.fetch_sub(1, Ordering::Relaxed);
+                warn!("invalid mirror target {}: {}", target, err);
+                return;
+            }
+        }
+        *request.headers_mut() = headers.clone();
+
+        let client = self.client.clone();
+        let mirrors = self.mirrors.clone();
+        tokio::spawn(async move {
+            let exchange = async {
+                let response = client.request(request).await?;
+                let status = response.status();
+                hyper::body::to_bytes(response.into_body()).await?;
+                Ok::<_, hyper::Error>(status)
+            };
+            let result = match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, exchange).await.ok(),
+                None => Some(exchange.await),
+            };
+            mirrors.fetch_sub(1, Ordering::Relaxed);
+
+            let result = match result {
+                Some(Ok(status)) => status.as_u16().to_string(),
+                Some(Err(err)) => {
+                    debug!("mirroring to {} failed: {}", target, err);
+                    "failed".into()
+                }
+                None => {
+                    debug!("mirroring to {} timed out", target);
+                    "timed_out".into()
+                }
+            };
+            metrics::counter!("roads_proxy_mirrored_total", "result" => result).increment(1);
+        });
+    }
+
+    /// Sends `request`, giving up when its response headers take longer than
+    /// `read`, or come past `deadline`.
+    async fn send(
+        &self,
+        request: Request<Body>,
+        read: Option<Duration>,
+        deadline: Option<Instant>,
+    ) -> Result<Response<Body>, (StatusCode, String)> {
+        let target = request.uri().to_string();
+        let sent = self.client.request(request);
+        let result = match wait(read, deadline) {
+            Some(wait) => match tokio::time::timeout(wait, sent).await {
+                Ok(result) => result,
+                Err(_) => {
+                    warn!("proxying to {} timed out", target);
+                    metrics::counter!("roads_proxy_timeouts_total").increment(1);
+                    return Err(gateway_timeout());
+                }
+            },
+            None => sent.await,
+        };
+
+        result.map_err(|err| {
+            warn!("proxying to {} failed: {}", target, err);
+            if timed_out(&err) {
+                metrics::counter!("roads_proxy_timeouts_total").increment(1);
+            }
+            upstream_error(&err)
+        })
+    }
+
+    /// `path` of a request as `rewrite` makes it, starting with a `/` unless
+    /// it's empty.
+    pub async fn rewrite_path(
+        &self,
+        rewrite: &PathRewrite,
+        path: &str,
+    ) -> Result<String, (StatusCode, String)> {
+        let regex = match self.rewrites.get(&rewrite.pattern).await {
+            Some(regex) => regex,
+            None => {
+                let regex = Regex::new(&rewrite.pattern).map_err(internal_error)?;
+                self.rewrites.insert(rewrite.pattern.clone(), regex.clone()).await;
+                regex
+            }
+        };
+
+        let path = regex.replace(path, rewrite.replacement.as_str());
+        if path.is_empty() || path.starts_with('/') {
+            Ok(path.into_owned())
+        } else {
+            Ok(format!("/{}", path))
+        }
+    }
+
+    /// Adds a request of `route` to its retry budget.
+    fn deposit(&self, route: &Route) {
+        let mut budgets = self.budgets.lock().expect("retry budgets aren't poisoned");
+        let budget = budgets.entry(route.label()).or_insert(BUDGET_CAP);
+        *budget = (*budget + route.proxy.retries.budget).min(BUDGET_CAP);
+    }
+
+    /// Whether the retry budget of `route` has room for another retry,
+    /// taking it.
+    fn withdraw(&self, route: &Route) -> bool {
+        let mut budgets = self.budgets.lock().expect("retry budgets aren't poisoned");
+        let budget = budgets.entry(route.label()).or_insert(BUDGET_CAP);
+        if *budget < 1.0 {
+            debug!("retry budget of {} is used up", route.label());
+            return false;
+        }
+
+        *budget -= 1.0;
+        true
+    }
+
+    /// Requests in flight to `upstream`.
+    pub fn in_flight(&self, upstream: &str) -> usize {
+        let in_flight = self.in_flight.lock().expect("in-flight counts aren't poisoned");
+
+        in_flight.get(upstream).copied().unwrap_or_default()
+    }
+
+    /// What health checks last found out about `upstream`, `None` when they
+    /// are off or haven't got to it yet.
+    pub fn health(&self, upstream: &str) -> Option<UpstreamHealth> {
+        self.checks.as_ref()?.status(upstream)
+    }
+
+    /// Drops the cached responses of the route of `host` and `slug`.
+    pub fn purge_cache(&self, host: Option<&str>, slug: &str) {
+        if let Some(cache) = &self.cache {
+            cache.purge(host, slug);
+        }
+    }
+
+    /// Whether the circuit of `upstream` is open.
+    pub fn is_circuit_open(&self, upstream: &str) -> bool {
+        self.circuits.is_open(upstream)
+    }
+
+    /// The base URL `target` of `route` is sent to next: one of its
+    /// upstreams, counted as in flight until the guard is dropped, or the
+    /// target's own origin when there are none. Upstreams already `tried`
+    /// are avoided while there are others, the one whose affinity token is
+    /// `sticky` taken over the others. The route's canary is taken instead
+    /// when `canary` is set and it's up. 503 when every upstream is down, or
+    /// their circuits open without a fallback.
+    fn pick(
+        &self,
+        route: &Route,
+        target: &str,
+        tried: &[String],
+        sticky: Option<&str>,
+        canary: bool,
+    ) -> Result<(String, Option<InFlight>), (StatusCode, String)> {
+        let breaker = &route.proxy.circuit_breaker;
+        let canary = route.proxy.canary.as_ref().filter(|_| canary).filter(|canary| {
+            !tried.contains(&canary.upstream)
+                && self
+                    .checks
+                    .as_ref()
+                    .is_none_or(|checks| checks.is_healthy(&canary.upstream))
+                && (!breaker.is_enabled() || self.circuits.allows(&canary.upstream))
+        });
+        if let Some(canary) = canary {
+            return Ok(self.take(route, &canary.upstream));
+        }
+        if route.proxy.upstreams.is_empty() {
+            let origin = origin(target);
+            if !breaker.is_enabled() || self.circuits.allows(origin) {
+                self.circuits.begin(origin, breaker);
+                return Ok((origin.to_owned(), None));
+            }
+            return match &breaker.fallback {
+                Some(fallback) => Ok((fallback.clone(), None)),
+                None => Err(circuit_open(route)),
+            };
+        }
+        let mut upstreams: Vec<&String> = route
+            .proxy
+            .upstreams
+            .iter()
+            .filter(|upstream| {
+                self.checks
+                    .as_ref()
+                    .is_none_or(|checks| checks.is_healthy(upstream))
+            })
+            .collect();
+        if upstreams.iter().any(|upstream| !tried.contains(upstream)) {
+            upstreams.retain(|upstream| !tried.contains(upstream));
+        }
+        if upstreams.is_empty() {
+            warn!("no healthy upstream for: {}", route.label());
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                "No healthy upstream".into(),
+            ));
+        }
+        if breaker.is_enabled() {
+            upstreams.retain(|upstream| self.circuits.allows(upstream));
+            if upstreams.is_empty() {
+                return match &breaker.fallback {
+                    Some(fallback) => Ok((fallback.clone(), None)),
+                    None => Err(circuit_open(route)),
+                };
+            }
+        }
+
+        let in_flight = self.in_flight.lock().expect("in-flight counts aren't poisoned");
+        let sticky = sticky.and_then(|token| {
+            upstreams
+                .iter()
+                .copied()
+                .find(|upstream| affinity_token(upstream) == token)
+        });
+        let upstream = sticky.unwrap_or_else(|| match route.proxy.balance {
+            Balance::RoundRobin => {
+                let mut turns = self.turns.lock().expect("turns aren't poisoned");
+                let turn = turns.entry(route.label()).or_default();
+                let current = *turn;
+                *turn = current.wrapping_add(1);
+                upstreams[current % upstreams.len()]
+            }
+            Balance::LeastConnections => upstreams
+                .iter()
+                .copied()
+                .min_by_key(|upstream| in_flight.get(*upstream).copied().unwrap_or_default())
+                .expect("upstreams aren't empty"),
+        });
+        drop(in_flight);
+
+        Ok(self.take(route, upstream))
+    }
+
+    /// `upstream` as the one a request of `route` goes to, counted as in
+    /// flight until the guard is dropped.
+    fn take(&self, route: &Route, upstream: &str) -> (String, Option<InFlight>) {
+        let mut in_flight = self.in_flight.lock().expect("in-flight counts aren't poisoned");
+        *in_flight.entry(upstream.to_owned()).or_default() += 1;
+        let breaker = &route.proxy.circuit_breaker;
+        if breaker.is_enabled() {
+            self.circuits.begin(upstream, breaker);
+        }
+
+        let guard = InFlight {
+            counts: self.in_flight.clone(),
+            upstream: upstream.to_owned(),
+        };
+        (upstream.to_owned(), Some(guard))
+    }
+}
+
+/// A request counted against an upstream.
+struct InFlight {
+    counts: Arc<Mutex<HashMap<String, usize>>>,
+    upstream: String,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().expect("in-flight counts aren't poisoned");
+        if let Some(count) = counts.get_mut(&self.upstream) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.upstream);
+            }
+        }
+    }
+}
+
+/// `target` with its scheme and authority replaced by `upstream`, a base URL
+/// whose path is put before the target's.
+fn rebase(target: &str, upstream: &str) -> String {
+    let rest = target.split_once("://").map_or(target, |(_, rest)| rest);
+    let path = rest.find(['/', '?']).map_or("", |at| &rest[at..]);
+
+    format!("{}{}", upstream.trim_end_matches('/'), path)
+}
+
+/// Pipes the connection of the client, once upgraded, to the one `response`
+/// of the upstream upgraded, both ways until either closes. The upstream
+/// counts as busy all along. `session` is published once closed.
+fn tunnel(
+    client: OnUpgrade,
+    response: &mut Response<Body>,
+    in_flight: Option<InFlight>,
+    session: Option<(Arc<EventSink>, TunnelSession)>,
+) {
+    let upstream = hyper::upgrade::on(response);
+    tokio::spawn(async move {
+        let _in_flight = in_flight;
+        let (mut client, mut upstream) = match tokio::try_join!(client, upstream) {
+            Ok(connections) => connections,
+            Err(err) => {
+                warn!("upgrading the proxied connection failed: {}", err);
+                return;
+            }
+        };
+
+        metrics::gauge!("roads_proxy_tunnels").increment(1.0);
+        let opened = Instant::now();
+        let (sent, received) = match tokio::io::copy_bidirectional(&mut client, &mut upstream).await
+        {
+            Ok((sent, received)) => {
+                debug!("tunnel closed, {} bytes up, {} down", sent, received);
+                (sent, received)
+            }
+            Err(err) => {
+                debug!("tunnel closed: {}", err);
+                (0, 0)
+            }
+        };
+        metrics::gauge!("roads_proxy_tunnels").decrement(1.0);
+        if let Some((events, mut session)) = session {
+            session.duration_ms = opened.elapsed().as_millis() as u64;
+            session.bytes_up = sent;
+            session.bytes_down = received;
+            events.publish(Event::Tunnel(session));
+        }
+    });
+}
+
+/// `body` failing once more than `max` bytes went through, setting
+/// `overflowed`.
+fn limit(body: Body, max: u64, overflowed: Arc<AtomicBool>) -> Body {
+    let mut size = 0;
+    Body::wrap_stream(body.map(move |chunk| {
+        let chunk = chunk?;
+        size += chunk.len() as u64;
+        if size > max {
+            overflowed.store(true, Ordering::Relaxed);
+            return Err(io::Error::other("request body too large").into());
+        }
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(chunk)
+    }))
+}
+
+/// `body` of a response failing once the upstream sends nothing for `read`,
+/// or past `deadline`, and keeping `in_flight` until it's through.
+fn guard(
+    body: Body,
+    read: Option<Duration>,
+    deadline: Option<Instant>,
+    in_flight: Option<InFlight>,
+) -> Body {
+    if read.is_none() && deadline.is_none() && in_flight.is_none() {
+        return body;
+    }
+
+    let chunks = stream::unfold(Some((body, in_flight)), move |state| async move {
+        let (mut body, in_flight) = state?;
+        let next = match wait(read, deadline) {
+            Some(wait) => match tokio::time::timeout(wait, body.data()).await {
+                Ok(next) => next,
+                Err(_) => {
+                    debug!("upstream timed out sending the body");
+                    metrics::counter!("roads_proxy_timeouts_total").increment(1);
+                    let err = io::Error::from(io::ErrorKind::TimedOut);
+                    return Some((Err(err.into()), None));
+                }
+            },
+            None => body.data().await,
+        };
+        match next? {
+            Ok(chunk) => Some((Ok(chunk), Some((body, in_flight)))),
+            Err(err) => Some((Err(Box::<dyn Error + Send + Sync>::from(err)), None)),
+        }
+    });
+    Body::wrap_stream(chunks)
+}
+
+/// How long to wait on the upstream: `read`, cut short by `deadline`.
+fn wait(read: Option<Duration>, deadline: Option<Instant>) -> Option<Duration> {
+    let left = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+    match (read, left) {
+        (Some(read), Some(left)) => Some(read.min(left)),
+        (read, left) => read.or(left),
+    }
+}
+
+/// `secs` as a timeout, `None` for 0.
+fn seconds(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// Whether `err`, or one of its causes, is a timeout.
+fn timed_out(err: &hyper::Error) -> bool {
+    let mut source = err.source();
+    while let Some(err) = source {
+        if err
+            .downcast_ref::<io::Error>()
+            .is_some_and(|err| err.kind() == io::ErrorKind::TimedOut)
+        {
+            return true;
+        }
+        source = err.source();
+    }
+
+    false
+}
+
+/// What a request failing with `err` is answered: 504 when the upstream
+/// took too long, 502 otherwise.
+pub fn upstream_error(err: &hyper::Error) -> (StatusCode, String) {
+    if timed_out(err) {
+        gateway_timeout()
+    } else {
+        bad_gateway()
+    }
+}
+
+/// Whether a request with `headers` goes to `canary`: as its header says,
+/// else where the `sticky` cookie points, else drawn by its percent.
+fn to_canary(canary: &Canary, headers: &HeaderMap, sticky: Option<&str>) -> bool {
+    let forced = headers
+        .get(canary.header.as_str())
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| match value.trim() {
+            "true" | "1" => Some(true),
+            "false" | "0" => Some(false),
+            _ => None,
+        });
+
+    forced
+        .or_else(|| sticky.map(|token| token == affinity_token(&canary.upstream)))
+        .unwrap_or_else(|| rand::thread_rng().gen_bool(canary.percent / 100.0))
+}
+
+/// Name of the cookie keeping the clients of `route` on an upstream.
+fn affinity_cookie(route: &Route) -> String {
+    format!("roads_upstream_{}", &auth::hash_token(&route.label())[..12])
+}
+
+/// What the affinity cookie holds for `upstream`, not giving its URL away.
+fn affinity_token(upstream: &str) -> String {
+    auth::hash_token(upstream)[..12].to_owned()
+}
+
+/// Value of the cookie `name` sent along with `headers`.
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| {
+            let (key, value) = pair.split_once('=')?;
+            (key.trim() == name).then(|| value.trim())
+        })
+}
+
+/// Whether `headers` ask for a WebSocket upgrade.
+fn is_websocket(headers: &HeaderMap) -> bool {
+    let has = |name, token: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    };
+
+    has(header::CONNECTION, "upgrade") && has(header::UPGRADE, "websocket")
+}
+
+/// The scheme and authority of `target`.
+fn origin(target: &str) -> &str {
+    let start = target.find("://").map_or(0, |at| at + 3);
+    let end = target[start..].find(['/', '?']).map_or(target.len(), |at| start + at);
+
+    &target[..end]
+}
+
+/// Whether a request can be sent again: idempotent, with a body of known,
+/// small size.
+fn is_replayable(method: &Method, body: &Body) -> bool {
+    let idempotent = matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE | Method::TRACE
+    );
+
+    idempotent && body.size_hint().upper().is_some_and(|size| size <= MAX_REPLAY_BODY)
+}
+
+fn remove_hop_by_hop(headers: &mut HeaderMap) {
+    let named: Vec<String> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect();
+    for name in HOP_BY_HOP.iter().copied().chain(named.iter().map(String::as_str)) {
+        headers.remove(name);
+    }
+}
+
+fn bad_gateway() -> (StatusCode, String) {
+    (StatusCode::BAD_GATEWAY, "Upstream unavailable".into())
+}
+
+fn gateway_timeout() -> (StatusCode, String) {
+    (StatusCode::GATEWAY_TIMEOUT, "Upstream timed out".into())
+}
+
+fn payload_too_large() -> (StatusCode, String) {
+    (StatusCode::PAYLOAD_TOO_LARGE, "Request body too large".into())
+}
+
+fn circuit_open(route: &Route) -> (StatusCode, String) {
+    debug!("every circuit of {} is open", route.label());
+    (StatusCode::SERVICE_UNAVAILABLE, "Upstream unavailable".into())
+}
+
+/// An upstream of proxy routes.
+#[derive(Serialize, ToSchema)]
+pub(crate) struct Upstream {
+    url: String,
+    /// Labels of the routes balancing over it
+    routes: Vec<String>,
+    /// Requests in flight, their response bodies included
+    in_flight: usize,
+    /// Unset while health checks are off or haven't checked it yet
+    #[serde(skip_serializing_if = "Option::is_none")]
+    health: Option<UpstreamHealth>,
+    /// Whether its circuit breaker stopped sending requests to it
+    circuit_open: bool,
+}
+
+pub fn upstream_routes() -> Router<AppState> {
+    Router::new().route("/api/upstreams", get(list_upstreams))
+}
+
+/// The upstreams of the proxy routes the caller can see.
+#[utoipa::path(
+    get,
+    path = "/api/upstreams",
+    tag = "upstreams",
+    responses((status = 200, description = "Every upstream, by URL", body = [Upstream]))
+)]
+async fn list_upstreams(
+    principal: Principal,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Upstream>>, (StatusCode, String)> {
+    principal.require(Scope::RoutesRead)?;
+
+    let routes = state.store.list().await.map_err(internal_error)?;
+    let mut upstreams: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for route in routes {
+        if route.mode != RouteMode::Proxy || !principal.can_access(route.tenant.as_deref()) {
+            continue;
+        }
+        for upstream in route.proxy.all_upstreams() {
+            upstreams.entry(upstream.clone()).or_default().push(route.label());
+        }
+    }
+
+    let upstreams = upstreams
+        .into_iter()
+        .map(|(url, routes)| Upstream {
+            in_flight: state.proxy.in_flight(&url),
+            health: state.proxy.health(&url),
+            circuit_open: state.proxy.is_circuit_open(&url),
+            url,
+            routes,
+        })
+        .collect();
+    Ok(Json(upstreams))
+}
@@ -0,0 +1,148 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use axum::extract::ConnectInfo;
+use axum_server::accept::Accept;
+use futures::future::BoxFuture;
+use hyper::server::conn::AddrStream;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tower_http::add_extension::AddExtension;
+
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+/// Longest v1 line allowed by the spec, CRLF included
+const V1_MAX_LEN: usize = 107;
+
+/// Adds the client address to every request as `ConnectInfo`. With
+/// `proxy_protocol` it comes from the PROXY header the load balancer sends
+/// ahead of the connection, which is then required.
+#[derive(Clone)]
+pub struct Acceptor<A> {
+    inner: A,
+    proxy_protocol: bool,
+}
+
+impl<A> Acceptor<A> {
+    pub fn new(inner: A, proxy_protocol: bool) -> Self {
+        Self {
+            inner,
+            proxy_protocol,
+        }
+    }
+}
+
+impl<A, S> Accept<AddrStream, S> for Acceptor<A>
+    where
+        A: Accept<AddrStream, AddExtension<S, ConnectInfo<SocketAddr>>> + Clone + Send + 'static,
+        A::Future: Send,
+        S: Send + 'static,
+{
+    type Stream = A::Stream;
+    type Service = A::Service;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, mut stream: AddrStream, service: S) -> Self::Future {
+        let acceptor = self.clone();
+
+        Box::pin(async move {
+            let mut remote = stream.remote_addr();
+            if acceptor.proxy_protocol {
+                let header = tokio::time::timeout(HEADER_TIMEOUT, read_header(&mut stream))
+                    .await
+                    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no PROXY header"))??;
+                // LOCAL and UNKNOWN headers come from the balancer itself
+                remote = header.unwrap_or(remote);
+            }
+
+            acceptor
+                .inner
+                .accept(stream, AddExtension::new(service, ConnectInfo(remote)))
+                .await
+        })
+    }
+}
+
+/// Reads a v1 or v2 header, leaving the stream at the first byte of the
+/// proxied connection. Returns the source address, if the header has one.
+async fn read_header<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Option<SocketAddr>> {
+    // both versions are at least this long
+    let mut start = [0u8; 12];
+    stream.read_exact(&mut start).await?;
+
+    if &start == V2_SIGNATURE {
+        return read_v2(stream).await;
+    }
+    if !start.starts_with(b"PROXY ") {
+        return Err(invalid("missing PROXY header"));
+    }
+
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(invalid("PROXY v1 header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+
+    parse_v1(&line[..line.len() - 2])
+}
+
+fn parse_v1(line: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let line = std::str::from_utf8(line).map_err(|_| invalid("PROXY v1 header is not ASCII"))?;
+
+    match line.split(' ').collect::<Vec<_>>().as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", source, _, source_port, _] => {
+            let ip: IpAddr = source.parse().map_err(|_| invalid("bad PROXY source address"))?;
+            let port: u16 = source_port.parse().map_err(|_| invalid("bad PROXY source port"))?;
+
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("malformed PROXY v1 header")),
+    }
+}
+
+async fn read_v2<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Option<SocketAddr>> {
+    let mut head = [0u8; 4];
+    stream.read_exact(&mut head).await?;
+    let [version_command, family, len_hi, len_lo] = head;
+
+    let mut payload = vec![0u8; u16::from_be_bytes([len_hi, len_lo]) as usize];
+    stream.read_exact(&mut payload).await?;
+
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+    // LOCAL, e.g. the balancer's own health checks
+    if version_command & 0x0f == 0 {
+        return Ok(None);
+    }
+
+    match family >> 4 {
+        // AF_INET: source, destination, source port, destination port
+        0x1 if payload.len() >= 12 => {
+            let mut ip = [0u8; 4];
+            ip.copy_from_slice(&payload[..4]);
+            let port = u16::from_be_bytes([payload[8], payload[9]]);
+
+            Ok(Some(SocketAddr::new(Ipv4Addr::from(ip).into(), port)))
+        }
+        // AF_INET6, same layout
+        0x2 if payload.len() >= 36 => {
+            let mut ip = [0u8; 16];
+            ip.copy_from_slice(&payload[..16]);
+            let port = u16::from_be_bytes([payload[32], payload[33]]);
+
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port)))
+        }
+        // AF_UNIX and AF_UNSPEC have no address worth reporting
+        _ => Ok(None),
+    }
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,142 @@
+use axum::{
+    extract::{Host, Query},
+    http::{header, HeaderMap, Uri},
+    response::{IntoResponse, Response},
+};
+use hyper::StatusCode;
+use qrcode::{render::svg, Color, EcLevel, QrCode};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::{Principal, Scope},
+    config::ErrorCorrection,
+    router::{self, AppState, internal_error},
+    shorten,
+    store::MatchType,
+};
+
+/// Largest image side served, in pixels
+pub const MAX_SIZE: u32 = 4096;
+/// Blank modules around the code, as the spec asks for
+const QUIET_ZONE: usize = 4;
+
+/// `?host=&format=&size=&ec=` of `/api/routes/{slug}/qr`.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct QrParams {
+    /// Hostname the route is scoped to
+    host: Option<String>,
+    #[serde(default)]
+    format: Format,
+    /// Image side in pixels, `qr.size` by default
+    size: Option<u32>,
+    /// `qr.error_correction` by default
+    ec: Option<ErrorCorrection>,
+}
+
+#[derive(Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    #[default]
+    Png,
+    Svg,
+}
+
+/// A QR code of the short URL of an exact route.
+#[utoipa::path(
+    get,
+    path = "/api/routes/{slug}/qr",
+    tag = "routes",
+    params(("slug" = String, Path, description = "Slug of the route, `/` included"), QrParams),
+    responses(
+        (status = 200, description = "The QR code", content_type = ["image/png", "image/svg+xml"]),
+        (status = 400, description = "Not an exact route, or an invalid size", body = String),
+        (status = 404, description = "Route not found", body = String),
+    )
+)]
+pub async fn route_qr(
+    principal: Principal,
+    state: AppState,
+    slug: &str,
+    uri: &Uri,
+    host: Option<Host>,
+    headers: &HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    principal.require(Scope::RoutesRead)?;
+
+    let Query(params) = Query::<QrParams>::try_from_uri(uri)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+    let size = params.size.unwrap_or(state.qr.size);
+    if size == 0 || size > MAX_SIZE {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("size must be 1 to {} pixels", MAX_SIZE),
+        ));
+    }
+
+    let route_host = params.host.as_deref().map(router::normalize_host);
+    let route = router::get_accessible(&state, &principal, route_host.as_deref(), slug).await?;
+    if route.match_type != MatchType::Exact {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Only exact routes have a short URL".into(),
+        ));
+    }
+
+    let url = shorten::short_url(&state.shorten, &route, host, headers);
+    let ec = params.ec.unwrap_or(state.qr.error_correction);
+    let code = QrCode::with_error_correction_level(&url, ec_level(ec))
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("Invalid QR code: {}", err)))?;
+
+    match params.format {
+        Format::Png => {
+            let png = render_png(&code, size).map_err(internal_error)?;
+            Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response())
+        }
+        Format::Svg => {
+            let svg = code
+                .render::<svg::Color>()
+                .min_dimensions(size, size)
+                .build();
+            Ok(([(header::CONTENT_TYPE, "image/svg+xml")], svg).into_response())
+        }
+    }
+}
+
+fn ec_level(ec: ErrorCorrection) -> EcLevel {
+    match ec {
+        ErrorCorrection::L => EcLevel::L,
+        ErrorCorrection::M => EcLevel::M,
+        ErrorCorrection::Q => EcLevel::Q,
+        ErrorCorrection::H => EcLevel::H,
+    }
+}
+
+/// Grayscale PNG of `code`, modules scaled to whole pixels so the image is
+/// `size` wide or slightly wider.
+fn render_png(code: &QrCode, size: u32) -> Result<Vec<u8>, png::EncodingError> {
+    let modules = code.width() + 2 * QUIET_ZONE;
+    let scale = (size as usize).div_ceil(modules).max(1);
+    let side = modules * scale;
+
+    let colors = code.to_colors();
+    let mut pixels = vec![0xff; side * side];
+    for (i, color) in colors.iter().enumerate() {
+        if *color != Color::Dark {
+            continue;
+        }
+        let (x, y) = (i % code.width() + QUIET_ZONE, i / code.width() + QUIET_ZONE);
+        for row in y * scale..(y + 1) * scale {
+            pixels[row * side + x * scale..row * side + (x + 1) * scale].fill(0);
+        }
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, side as u32, side as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&pixels)?;
+
+    Ok(png)
+}
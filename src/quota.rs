@@ -0,0 +1,295 @@
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    Json,
+    response::{IntoResponse, Response},
+    Router, routing::get,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::{self, Principal, Scope},
+    config::{QuotaConfig, QuotaLimits},
+    router::{AppState, internal_error},
+    store::{Store, StoreError},
+};
+
+/// Quotas are per UTC day
+const WINDOW: i64 = 24 * 3600;
+
+/// Usage subject of what a tenant's callers do together
+const TENANT_PREFIX: &str = "tenant:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaKind {
+    Requests,
+    Creates,
+}
+
+impl QuotaKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Requests => "requests",
+            Self::Creates => "creates",
+        }
+    }
+}
+
+/// Counts what each API caller does per day and refuses what goes over its
+/// limits. Usage is counted even when unlimited, to be looked at.
+pub struct Quotas {
+    config: QuotaConfig,
+}
+
+impl Quotas {
+    pub fn new(config: &QuotaConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    /// 0 when unlimited. Tenants only have the limits set for them.
+    fn limit(&self, subject: &str, kind: QuotaKind) -> u64 {
+        let pick = |limits: &QuotaLimits| match kind {
+            QuotaKind::Requests => limits.requests_per_day,
+            QuotaKind::Creates => limits.creates_per_day,
+        };
+        if let Some(tenant) = subject.strip_prefix(TENANT_PREFIX) {
+            return self.config.tenants.get(tenant).and_then(pick).unwrap_or_default();
+        }
+
+        let default = match kind {
+            QuotaKind::Requests => self.config.requests_per_day,
+            QuotaKind::Creates => self.config.creates_per_day,
+        };
+        self.config.keys.get(subject).and_then(pick).unwrap_or(default)
+    }
+
+    /// Counts `n` uses of `kind` by `principal`, against its own limit and
+    /// its tenant's shared one. Returns the subject whose limit that would
+    /// go over, counting nothing then.
+    pub async fn consume(
+        &self,
+        store: &Store,
+        principal: &Principal,
+        kind: QuotaKind,
+        n: u64,
+    ) -> Result<Option<String>, StoreError> {
+        let window = window_start();
+        let subjects = subjects(principal);
+        for (i, subject) in subjects.iter().enumerate() {
+            let used = store
+                .add_usage(subject, kind.as_str(), window, n as i64)
+                .await?;
+            let limit = self.limit(subject, kind);
+            if limit > 0 && used > limit as i64 {
+                // give back the uses so the counters stay at their limits
+                for counted in &subjects[..=i] {
+                    store
+                        .add_usage(counted, kind.as_str(), window, -(n as i64))
+                        .await?;
+                }
+                return Ok(Some(subject.clone()));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Gives back `n` uses that didn't happen after all.
+    pub async fn release(
+        &self,
+        store: &Store,
+        principal: &Principal,
+        kind: QuotaKind,
+        n: u64,
+    ) -> Result<(), StoreError> {
+        let window = window_start();
+        for subject in subjects(principal) {
+            store
+                .add_usage(&subject, kind.as_str(), window, -(n as i64))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// The message refusing `subject` more `kind`.
+    pub fn exceeded(&self, subject: &str, kind: QuotaKind) -> (StatusCode, String) {
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            format!(
+                "Daily quota of {} {} used up, resets at {}",
+                self.limit(subject, kind),
+                kind.as_str(),
+                window_start() + WINDOW
+            ),
+        )
+    }
+
+    /// `exceeded` with a `Retry-After` until the quota resets.
+    pub fn exceeded_response(&self, subject: &str, kind: QuotaKind) -> Response {
+        let retry_after = window_start() + WINDOW - auth::now();
+
+        (
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            self.exceeded(subject, kind),
+        )
+            .into_response()
+    }
+}
+
+/// Counts a route about to be created by `principal`, refusing it over
+/// quota.
+pub async fn reserve_create(
+    state: &AppState,
+    principal: &Principal,
+) -> Result<(), (StatusCode, String)> {
+    let Some(quotas) = &state.quotas else {
+        return Ok(());
+    };
+
+    let exceeded = quotas
+        .consume(&state.store, principal, QuotaKind::Creates, 1)
+        .await
+        .map_err(internal_error)?;
+    if let Some(subject) = exceeded {
+        return Err(quotas.exceeded(&subject, QuotaKind::Creates));
+    }
+
+    Ok(())
+}
+
+/// Gives back `reserve_create` for a route that wasn't created after all.
+pub async fn release_create(
+    state: &AppState,
+    principal: &Principal,
+) -> Result<(), (StatusCode, String)> {
+    if let Some(quotas) = &state.quotas {
+        quotas
+            .release(&state.store, principal, QuotaKind::Creates, 1)
+            .await
+            .map_err(internal_error)?;
+    }
+
+    Ok(())
+}
+
+/// What `principal` is counted as: itself, and its tenant when it has one.
+fn subjects(principal: &Principal) -> Vec<String> {
+    let mut subjects = vec![principal.subject.clone()];
+    if let Some(tenant) = &principal.tenant {
+        subjects.push(format!("{}{}", TENANT_PREFIX, tenant));
+    }
+    subjects
+}
+
+fn window_start() -> i64 {
+    let now = auth::now();
+    now - now.rem_euclid(WINDOW)
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct Usage {
+    subject: String,
+    /// Unix timestamps of the current window
+    window_start: i64,
+    resets_at: i64,
+    requests: KindUsage,
+    creates: KindUsage,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct KindUsage {
+    used: i64,
+    /// Unset when unlimited
+    limit: Option<u64>,
+    remaining: Option<u64>,
+}
+
+/// `?subject=` of `/api/usage`, the caller's own usage by default.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct UsageParams {
+    /// API key id, JWT subject or `tenant:<id>` for a tenant's shared usage
+    subject: Option<String>,
+}
+
+pub fn usage_routes() -> Router<AppState> {
+    Router::new().route("/api/usage", get(read_usage))
+}
+
+/// Looking at someone else's usage takes `keys:admin` and an admin, tenant
+/// callers only seeing their tenant and its keys.
+#[utoipa::path(
+    get,
+    path = "/api/usage",
+    tag = "usage",
+    params(UsageParams),
+    responses(
+        (status = 200, description = "Usage of the current day", body = Usage),
+        (status = 404, description = "Quotas aren't enabled", body = String),
+    )
+)]
+async fn read_usage(
+    principal: Principal,
+    State(state): State<AppState>,
+    Query(params): Query<UsageParams>,
+) -> Result<Json<Usage>, (StatusCode, String)> {
+    let subject = params.subject.unwrap_or_else(|| principal.subject.clone());
+    if subject != principal.subject {
+        principal.require(Scope::KeysAdmin)?;
+        principal.require_admin()?;
+        if !can_view(&state.store, &principal, &subject).await? {
+            return Err((StatusCode::FORBIDDEN, "Not your tenant's usage".into()));
+        }
+    }
+    let Some(quotas) = &state.quotas else {
+        return Err((StatusCode::NOT_FOUND, "Quotas aren't enabled".into()));
+    };
+
+    let window = window_start();
+    let usage = state
+        .store
+        .usage(&subject, window)
+        .await
+        .map_err(internal_error)?;
+    let kind_usage = |kind: QuotaKind| {
+        let used = usage.get(kind.as_str()).copied().unwrap_or_default();
+        let limit = Some(quotas.limit(&subject, kind)).filter(|&limit| limit > 0);
+        KindUsage {
+            used,
+            limit,
+            remaining: limit.map(|limit| limit.saturating_sub(used.max(0) as u64)),
+        }
+    };
+
+    Ok(Json(Usage {
+        requests: kind_usage(QuotaKind::Requests),
+        creates: kind_usage(QuotaKind::Creates),
+        subject,
+        window_start: window,
+        resets_at: window + WINDOW,
+    }))
+}
+
+/// Whether tenant callers may look at the usage of `subject`, their
+/// tenant's or one of its keys.
+async fn can_view(
+    store: &Store,
+    principal: &Principal,
+    subject: &str,
+) -> Result<bool, (StatusCode, String)> {
+    let Some(tenant) = &principal.tenant else {
+        return Ok(true);
+    };
+    if subject.strip_prefix(TENANT_PREFIX) == Some(tenant) {
+        return Ok(true);
+    }
+
+    let keys = store.list_keys().await.map_err(internal_error)?;
+    Ok(keys
+        .iter()
+        .any(|key| key.id == subject && key.tenant.as_ref() == Some(tenant)))
+}
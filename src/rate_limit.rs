@@ -0,0 +1,150 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header, HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use moka::future::Cache;
+
+use crate::config::{IpRange, RateConfig, RateLimitConfig};
+
+/// Paths never limited, so probes and scrapers of the service itself keep
+/// working under load
+const EXEMPT_PATHS: [&str; 4] = ["/ping", "/healthz", "/readyz", "/metrics"];
+
+/// Limits requests per client IP with a token bucket for redirects and
+/// another for the admin API.
+pub struct RateLimiter {
+    redirects: RateConfig,
+    api: RateConfig,
+    trusted_proxies: Vec<IpRange>,
+    buckets: Cache<(IpAddr, Class), Arc<Mutex<Bucket>>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Class {
+    Redirects,
+    Api,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        // an idle client's bucket is full again by then, forgetting it is free
+        let refill = [&config.redirects, &config.api]
+            .iter()
+            .map(|rate| rate.burst as f64 / rate.rate)
+            .fold(1.0, f64::max);
+
+        Self {
+            redirects: config.redirects.clone(),
+            api: config.api.clone(),
+            trusted_proxies: config.trusted_proxies.clone(),
+            buckets: Cache::builder()
+                .max_capacity(config.capacity)
+                .time_to_idle(Duration::from_secs_f64(refill.ceil()))
+                .build(),
+        }
+    }
+
+    /// Takes a token for `client`, or returns how long until one is
+    /// available.
+    async fn take(&self, client: IpAddr, class: Class) -> Result<(), Duration> {
+        let rate = match class {
+            Class::Redirects => &self.redirects,
+            Class::Api => &self.api,
+        };
+        let bucket = self
+            .buckets
+            .get_with((client, class), async {
+                Arc::new(Mutex::new(Bucket {
+                    tokens: rate.burst as f64,
+                    updated: Instant::now(),
+                }))
+            })
+            .await;
+
+        let mut bucket = bucket.lock().expect("rate limit buckets aren't poisoned");
+        let now = Instant::now();
+        let refilled = now.duration_since(bucket.updated).as_secs_f64() * rate.rate;
+        bucket.tokens = (bucket.tokens + refilled).min(rate.burst as f64);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate.rate));
+        }
+
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+
+    /// The peer address, or when the peer is a trusted proxy the last
+    /// `X-Forwarded-For` address that isn't one.
+    fn client(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let trusted = |ip: &IpAddr| self.trusted_proxies.iter().any(|range| range.contains(*ip));
+        if !trusted(&peer) {
+            return peer;
+        }
+
+        let forwarded: Vec<IpAddr> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|addr| addr.trim().parse().ok())
+            .collect();
+        forwarded
+            .iter()
+            .rev()
+            .find(|ip| !trusted(ip))
+            .or(forwarded.first())
+            .copied()
+            .unwrap_or(peer)
+    }
+}
+
+/// Answers `429 Too Many Requests` with a `Retry-After` once a client runs
+/// out of tokens. Requests without a client address, over Unix sockets,
+/// aren't limited.
+pub async fn limit_requests<B>(
+    State(limiter): State<Arc<RateLimiter>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let path = req.uri().path();
+    let Some(ConnectInfo(peer)) = req.extensions().get::<ConnectInfo<SocketAddr>>().copied() else {
+        return next.run(req).await;
+    };
+    if EXEMPT_PATHS.contains(&path) {
+        return next.run(req).await;
+    }
+
+    let class = if path == "/api" || path.starts_with("/api/") {
+        Class::Api
+    } else {
+        Class::Redirects
+    };
+    let client = limiter.client(peer.ip(), req.headers());
+    if let Err(wait) = limiter.take(client, class).await {
+        metrics::counter!("roads_rate_limited_total").increment(1);
+        let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            "Too many requests",
+        )
+            .into_response();
+    }
+
+    next.run(req).await
+}
@@ -0,0 +1,128 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        RwLock,
+    },
+    time::Duration,
+};
+
+use tracing::warn;
+
+use crate::{
+    auth,
+    config::DatabaseConfig,
+    store::{self, Route, Store, StoreError},
+};
+
+/// Longest a lookup waits on the replica before asking the primary.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(1);
+/// Seconds lookups skip the replica after it failed.
+const RETRY_AFTER: i64 = 10;
+
+/// A copy of the database that route lookups read from, taking them off
+/// the primary. They go to the primary while the replica fails, and when it
+/// misses since it may lag behind.
+pub struct ReadReplica {
+    url: String,
+    config: DatabaseConfig,
+    /// Connected on first use, and again after failing to
+    store: RwLock<Option<Store>>,
+    /// Unix timestamp before which the replica isn't tried again
+    retry_at: AtomicI64,
+}
+
+impl ReadReplica {
+    pub fn new(url: &str, config: &DatabaseConfig) -> Self {
+        Self {
+            url: url.to_owned(),
+            config: config.clone(),
+            store: RwLock::new(None),
+            retry_at: AtomicI64::new(0),
+        }
+    }
+
+    pub async fn get(
+        &self,
+        host: Option<&str>,
+        slug: &str,
+        primary: &Store,
+    ) -> Result<Option<Route>, StoreError> {
+        if let Some(replica) = self.replica().await {
+            if let Some(route) = self.read(replica.get(host, slug)).await.flatten() {
+                record_lookup("replica");
+                return Ok(Some(route));
+            }
+        }
+        record_lookup("primary");
+
+        primary.get(host, slug).await
+    }
+
+    pub async fn list_patterns(&self, primary: &Store) -> Result<Vec<Route>, StoreError> {
+        if let Some(replica) = self.replica().await {
+            if let Some(routes) = self.read(replica.list_patterns()).await {
+                record_lookup("replica");
+                return Ok(routes);
+            }
+        }
+        record_lookup("primary");
+
+        primary.list_patterns().await
+    }
+
+    /// The replica, unless it failed lately.
+    async fn replica(&self) -> Option<Store> {
+        if auth::now() < self.retry_at.load(Ordering::Relaxed) {
+            return None;
+        }
+        if let Some(store) = self.store.read().expect("replica lock poisoned").clone() {
+            return Some(store);
+        }
+
+        match tokio::time::timeout(LOOKUP_TIMEOUT, store::connect(&self.url, &self.config)).await {
+            Ok(Ok(store)) => {
+                *self.store.write().expect("replica lock poisoned") = Some(store.clone());
+                Some(store)
+            }
+            Ok(Err(err)) => {
+                self.failed(&err.to_string());
+                None
+            }
+            Err(_) => {
+                self.failed("timed out connecting");
+                None
+            }
+        }
+    }
+
+    /// What `lookup` returned, `None` when it failed.
+    async fn read<T>(&self, lookup: impl Future<Output = Result<T, StoreError>>) -> Option<T> {
+        match tokio::time::timeout(LOOKUP_TIMEOUT, lookup).await {
+            Ok(Ok(found)) => Some(found),
+            Ok(Err(err)) => {
+                self.failed(&err.to_string());
+                None
+            }
+            Err(_) => {
+                self.failed("timed out");
+                None
+            }
+        }
+    }
+
+    fn failed(&self, err: &str) {
+        warn!(
+            "read replica failed, using the primary for {}s: {}",
+            RETRY_AFTER, err
+        );
+        metrics::counter!("roads_replica_failures_total").increment(1);
+        // its connection may not come back on its own
+        *self.store.write().expect("replica lock poisoned") = None;
+        self.retry_at.store(auth::now() + RETRY_AFTER, Ordering::Relaxed);
+    }
+}
+
+fn record_lookup(source: &'static str) {
+    metrics::counter!("roads_replica_lookups_total", "source" => source).increment(1);
+}
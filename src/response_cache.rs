@@ -0,0 +1,303 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{extract::Query, http::Uri};
+use hyper::{
+    body::Bytes,
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    Body, Method, Response, StatusCode,
+};
+use moka::{future::Cache, Expiry};
+use serde::Deserialize;
+use tracing::{debug, warn};
+use utoipa::IntoParams;
+
+use crate::{
+    auth::{Principal, Scope},
+    config::ResponseCacheConfig,
+    proxy,
+    router::{self, AppState},
+    store::Route,
+};
+
+/// A proxied response, kept for `ttl`.
+pub struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    /// Request headers the upstream named in `Vary`, with their values
+    vary: Vec<(HeaderName, Option<HeaderValue>)>,
+    stored_at: Instant,
+    ttl: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    host: Option<String>,
+    slug: String,
+    target: String,
+}
+
+struct TtlExpiry;
+
+impl Expiry<CacheKey, Arc<CachedResponse>> for TtlExpiry {
+    fn expire_after_create(
+        &self,
+        _key: &CacheKey,
+        value: &Arc<CachedResponse>,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Some(value.ttl)
+    }
+}
+
+/// Keeps GET responses of proxy routes for as long as their upstream's
+/// `Cache-Control` says, or the route's `cache_ttl`.
+pub struct ResponseCache {
+    entries: Cache<CacheKey, Arc<CachedResponse>>,
+    max_size: u64,
+}
+
+impl ResponseCache {
+    pub fn new(config: &ResponseCacheConfig) -> Self {
+        let entries = Cache::builder()
+            .max_capacity(config.capacity * 1024 * 1024)
+            .weigher(|_, response: &Arc<CachedResponse>| {
+                u32::try_from(response.body.len()).unwrap_or(u32::MAX)
+            })
+            .expire_after(TtlExpiry)
+            .support_invalidation_closures()
+            .build();
+
+        Self {
+            entries,
+            max_size: config.max_size,
+        }
+    }
+
+    /// Whether a request with `method` and `headers` may be answered from the
+    /// cache or fill it. Requests with credentials never are.
+    pub fn accepts(route: &Route, method: &Method, headers: &HeaderMap) -> bool {
+        matches!(*method, Method::GET | Method::HEAD)
+            && route.proxy.cache_ttl != Some(0)
+            && !headers.contains_key(header::AUTHORIZATION)
+            && !directives(headers).iter().any(|directive| directive == "no-store")
+    }
+
+    /// The response for `target` of `route` if there's a fresh one matching
+    /// the `Vary` headers, a 304 when `If-None-Match` has its ETag.
+    pub async fn lookup(
+        &self,
+        route: &Route,
+        target: &str,
+        method: &Method,
+        headers: &HeaderMap,
+    ) -> Option<Response<Body>> {
+        if directives(headers).iter().any(|directive| directive == "no-cache") {
+            return None;
+        }
+        let cached = self.entries.get(&key(route, target)).await?;
+        if cached.vary.iter().any(|(name, value)| headers.get(name) != value.as_ref()) {
+            return None;
+        }
+        metrics::counter!("roads_proxy_cache_hits_total").increment(1);
+
+        let etag = cached.headers.get(header::ETAG);
+        let not_modified = headers
+            .get(header::IF_NONE_MATCH)
+            .zip(etag)
+            .is_some_and(|(if_none_match, etag)| matches_etag(if_none_match, etag));
+        let mut response = if not_modified {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+            for name in [header::ETAG, header::CACHE_CONTROL, header::VARY] {
+                if let Some(value) = cached.headers.get(&name) {
+                    response.headers_mut().insert(name, value.clone());
+                }
+            }
+            response
+        } else {
+            let body = match *method {
+                Method::HEAD => Body::empty(),
+                _ => Body::from(cached.body.clone()),
+            };
+            let mut response = Response::new(body);
+            *response.status_mut() = cached.status;
+            *response.headers_mut() = cached.headers.clone();
+            response
+        };
+        let age = cached.stored_at.elapsed().as_secs();
+        response.headers_mut().insert(header::AGE, HeaderValue::from(age));
+        response
+            .headers_mut()
+            .insert("x-cache", HeaderValue::from_static("HIT"));
+
+        Some(response)
+    }
+
+    /// How long `response` can be kept: `route.proxy.cache_ttl`, or else the
+    /// `s-maxage` or `max-age` of the upstream. `None` for responses that
+    /// aren't 200, set cookies, or are `private`, `no-store` or `no-cache`.
+    pub fn ttl(route: &Route, response: &Response<Body>) -> Option<Duration> {
+        let headers = response.headers();
+        if response.status() != StatusCode::OK || headers.contains_key(header::SET_COOKIE) {
+            return None;
+        }
+        let directives = directives(headers);
+        let uncacheable = ["private", "no-store", "no-cache"];
+        if directives.iter().any(|directive| uncacheable.contains(&directive.as_str()))
+            || headers.get(header::VARY).is_some_and(|vary| vary == "*")
+        {
+            return None;
+        }
+        if let Some(ttl) = route.proxy.cache_ttl {
+            return Some(Duration::from_secs(ttl));
+        }
+
+        let max_age = |name: &str| {
+            directives
+                .iter()
+                .find_map(|directive| directive.strip_prefix(name)?.strip_prefix('='))
+                .and_then(|secs| secs.trim_matches('"').parse::<u64>().ok())
+        };
+        max_age("s-maxage")
+            .or_else(|| max_age("max-age"))
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    /// Reads the body of `response` to keep it for `ttl`, handing back an
+    /// equal response. Those without a length, or bigger than `max_size`,
+    /// go through untouched.
+    pub async fn store(
+        &self,
+        route: &Route,
+        target: &str,
+        request_headers: &HeaderMap,
+        response: Response<Body>,
+        ttl: Duration,
+    ) -> Result<Response<Body>, (StatusCode, String)> {
+        let size = response
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+        if size.is_none_or(|size| size > self.max_size) {
+            return Ok(response);
+        }
+
+        let (mut parts, body) = response.into_parts();
+        let body = hyper::body::to_bytes(body).await.map_err(|err| {
+            warn!("reading the response of {} failed: {}", target, err);
+            proxy::upstream_error(&err)
+        })?;
+        let vary = parts
+            .headers
+            .get_all(header::VARY)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+            .map(|name| {
+                let value = request_headers.get(&name).cloned();
+                (name, value)
+            })
+            .collect();
+        let cached = CachedResponse {
+            status: parts.status,
+            headers: parts.headers.clone(),
+            body: body.clone(),
+            vary,
+            stored_at: Instant::now(),
+            ttl,
+        };
+        debug!("caching {} for {:?}", target, ttl);
+        self.entries.insert(key(route, target), Arc::new(cached)).await;
+
+        parts
+            .headers
+            .insert("x-cache", HeaderValue::from_static("MISS"));
+        Ok(Response::from_parts(parts, Body::from(body)))
+    }
+
+    /// Drops the responses of the route of `host` and `slug`.
+    pub fn purge(&self, host: Option<&str>, slug: &str) {
+        let (host, slug) = (host.map(String::from), slug.to_owned());
+        let purged = self
+            .entries
+            .invalidate_entries_if(move |key, _| key.host == host && key.slug == slug);
+        if let Err(err) = purged {
+            warn!("failed to purge cached responses: {}", err);
+        }
+    }
+}
+
+fn key(route: &Route, target: &str) -> CacheKey {
+    CacheKey {
+        host: route.host.clone(),
+        slug: route.slug.clone(),
+        target: target.to_owned(),
+    }
+}
+
+/// The `Cache-Control` directives of `headers`, lowercased.
+fn directives(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.trim().to_ascii_lowercase())
+        .collect()
+}
+
+/// Whether `If-None-Match` lists `etag`, weakly compared.
+fn matches_etag(if_none_match: &HeaderValue, etag: &HeaderValue) -> bool {
+    let weak = |tag: &str| tag.trim().trim_start_matches("W/").to_owned();
+    let (Ok(if_none_match), Ok(etag)) = (if_none_match.to_str(), etag.to_str()) else {
+        return false;
+    };
+
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || weak(tag) == weak(etag))
+}
+
+/// `?host=` of `/api/routes/{slug}/cache`.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PurgeParams {
+    /// Hostname the route is scoped to
+    host: Option<String>,
+}
+
+/// Drops the cached responses of a proxy route.
+#[utoipa::path(
+    delete,
+    path = "/api/routes/{slug}/cache",
+    tag = "routes",
+    params(("slug" = String, Path, description = "Slug of the route, `/` included"), PurgeParams),
+    responses(
+        (status = 204, description = "Purged"),
+        (status = 404, description = "Route not found", body = String),
+    )
+)]
+pub async fn purge_route(
+    principal: Principal,
+    state: AppState,
+    slug: &str,
+    uri: &Uri,
+) -> Result<StatusCode, (StatusCode, String)> {
+    principal.require(Scope::RoutesWrite)?;
+
+    let Query(params) = Query::<PurgeParams>::try_from_uri(uri)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+    let host = params.host.as_deref().map(router::normalize_host);
+    router::get_accessible(&state, &principal, host.as_deref(), slug).await?;
+    state.proxy.purge_cache(host.as_deref(), slug);
+
+    debug!("purged cached responses of {} by {}", slug, &principal.subject);
+    Ok(StatusCode::NO_CONTENT)
+}
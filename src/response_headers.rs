@@ -0,0 +1,47 @@
+use std::collections::BTreeMap;
+
+use hyper::{
+    header::{HeaderName, HeaderValue},
+    Body, Response,
+};
+
+use crate::store::Route;
+
+/// Adds the global `[response_headers]` to `response` where it doesn't have
+/// them yet, like `Strict-Transport-Security` or `Content-Security-Policy`,
+/// then the route's own over any of the same name, an empty value dropping a
+/// global one.
+pub fn add(global: &BTreeMap<String, String>, route: &Route, response: &mut Response<Body>) {
+    let headers = response.headers_mut();
+    for (name, value) in global {
+        if route.response_headers.contains_key(name) {
+            continue;
+        }
+        if let Some((name, value)) = parse(name, value) {
+            headers.entry(name).or_insert(value);
+        }
+    }
+    for (name, value) in &route.response_headers {
+        if let Some((name, value)) = parse(name, value).filter(|_| !value.is_empty()) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+/// Whether every name and value of `headers` makes a valid header.
+pub fn check(headers: &BTreeMap<String, String>) -> Result<(), String> {
+    for (name, value) in headers {
+        if parse(name, value).is_none() {
+            return Err(format!("invalid response header {}: {}", name, value));
+        }
+    }
+
+    Ok(())
+}
+
+fn parse(name: &str, value: &str) -> Option<(HeaderName, HeaderValue)> {
+    let name = HeaderName::from_bytes(name.as_bytes()).ok()?;
+    let value = HeaderValue::from_str(value).ok()?;
+
+    Some((name, value))
+}
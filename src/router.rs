@@ -1,68 +1,1377 @@
-use std::sync::Arc;
+use std::{collections::BTreeMap, net::SocketAddr, ops::ControlFlow, sync::Arc};
 
 use axum::{
-    extract::{Path, State},
+    body::StreamBody,
+    extract::{ConnectInfo, DefaultBodyLimit, Form, FromRequest, Host, Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, Method, Request},
     Json,
+    middleware,
     response::{IntoResponse, Response},
-    Router, routing::get,
+    Router, routing::{any, get, post},
 };
-use hyper::{Body, StatusCode};
-use redis::{Commands, Connection};
+use hyper::{Body, StatusCode, Uri};
+use jsonwebtoken::DecodingKey;
+use metrics_exporter_prometheus::PrometheusHandle;
+use rand::Rng;
 use serde::Deserialize;
-use tokio::sync::Mutex;
-use tracing::log::debug;
+use tower_cookies::{cookie::time::Duration, Cookie, CookieManagerLayer, Cookies};
+use tower_http::cors::CorsLayer;
+use tracing::debug;
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Deserialize)]
-struct Route {
+use crate::{
+    audit,
+    auth::{self, Principal, Scope},
+    cache::RouteCache,
+    compression::{self, RouteCompression},
+    config::{CompressionConfig, ExpiredConfig, QrConfig, Service, ShortenConfig, SlugConfig},
+    dashboard,
+    device,
+    error_pages::{ErrorContext, ErrorPages},
+    events::{Event, EventSink},
+    fallback::Fallback,
+    export,
+    geoip::{GeoIp, Location},
+    language,
+    health, history,
+    maintenance::{self, MaintenanceMode},
+    hooks::{self, RoadsMiddleware, RouteRequest},
+    import::{self, Conflict, Format, ImportReport},
+    invalidation::Invalidations,
+    oidc::{self, Oidc},
+    openapi,
+    password::{self, Unlocker},
+    patterns::{self, PatternRoutes},
+    preview, proxy::{self, Proxy}, qr, quota::{self, Quotas},
+    store::{
+        self, DeviceTarget, GeoTarget, Hit, LanguageTarget, MatchType, RevisionAction, Route,
+        ProxyOptions, RouteMode, SplitTarget, Store, StoreError,
+    },
+    shorten,
+    signing::{self, SignatureError, Signer},
+    slug, stats, telemetry, tenants,
+    tracking::ClickTracker,
+    response_cache, response_headers, users, utm,
+    webhooks::Webhooks,
+};
+
+#[derive(Clone)]
+pub struct AppState {
+    pub store: Store,
+    pub cache: Arc<RouteCache>,
+    /// Set when `cache.pubsub` is enabled
+    pub invalidations: Option<Arc<Invalidations>>,
+    pub patterns: Arc<PatternRoutes>,
+    /// Set when JWT authentication is configured
+    pub jwt_key: Option<Arc<DecodingKey>>,
+    /// Set when the `/metrics` endpoint is enabled
+    pub metrics: Option<PrometheusHandle>,
+    pub expired: Arc<ExpiredPage>,
+    pub maintenance: Arc<MaintenanceMode>,
+    pub error_pages: Arc<ErrorPages>,
+    pub fallback: Arc<Fallback>,
+    /// Set when click tracking is enabled
+    pub tracker: Option<Arc<ClickTracker>>,
+    /// Set when events are published to NATS
+    pub events: Option<Arc<EventSink>>,
+    /// Set when a GeoIP database is configured
+    pub geoip: Option<Arc<GeoIp>>,
+    /// The global `[utm]` parameters
+    pub utm: Arc<BTreeMap<String, String>>,
+    /// The global `[response_headers]`
+    pub response_headers: Arc<BTreeMap<String, String>>,
+    pub compression: Arc<CompressionConfig>,
+    /// Bytes of admin API request bodies
+    pub api_body_limit: usize,
+    pub shorten: Arc<ShortenConfig>,
+    pub slugs: Arc<SlugConfig>,
+    pub qr: Arc<QrConfig>,
+    pub unlocker: Arc<Unlocker>,
+    /// Set when `signing.secret` is
+    pub signer: Option<Arc<Signer>>,
+    /// Set when webhook endpoints are configured
+    pub webhooks: Option<Arc<Webhooks>>,
+    /// Set when quotas are enabled
+    pub quotas: Option<Arc<Quotas>>,
+    /// Set when CORS origins are configured, applied to `/api` only
+    pub cors: Option<CorsLayer>,
+    /// Serve the OpenAPI spec and Swagger UI
+    pub api_docs: bool,
+    /// Serve the web dashboard
+    pub dashboard: bool,
+    /// Set when OpenID Connect sign-in is configured
+    pub oidc: Option<Arc<Oidc>>,
+    /// Forwards requests of proxy-mode routes
+    pub proxy: Arc<Proxy>,
+    /// Hooks registered through `RoadsServerBuilder::middleware`, in order
+    pub middleware: Arc<[Box<dyn RoadsMiddleware>]>,
+}
+
+/// What routes past their `expires_at` answer with.
+pub struct ExpiredPage {
+    pub status: StatusCode,
+    /// HTML from `expired.page`
+    pub html: Option<String>,
+}
+
+impl ExpiredPage {
+    pub async fn load(config: &ExpiredConfig) -> std::io::Result<Self> {
+        let html = match &config.page {
+            Some(path) => Some(tokio::fs::read_to_string(path).await?),
+            None => None,
+        };
+
+        Ok(Self {
+            status: StatusCode::from_u16(config.status).unwrap_or(StatusCode::GONE),
+            html,
+        })
+    }
+
+    /// An error when there's no page, for `[error_pages]` to fill in.
+    fn response(&self) -> Result<Response<Body>, (StatusCode, String)> {
+        let Some(html) = &self.html else {
+            return Err((self.status, "Route expired".into()));
+        };
+
+        Response::builder()
+            .status(self.status)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(Body::from(html.clone()))
+            .map_err(internal_error)
+    }
+}
+
+/// `?host=` on the admin endpoints, selecting a host-scoped route.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct HostQuery {
+    /// Hostname the route is scoped to
+    pub(crate) host: Option<String>,
+}
+
+impl HostQuery {
+    fn host(&self) -> Option<String> {
+        self.host.as_deref().map(normalize_host)
+    }
+}
+
+/// A route as put to `/api/routes/{slug}`, replacing it but its hits.
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct RouteUpdate {
     redirect_to: String,
+    #[serde(default)]
+    mode: RouteMode,
+    #[serde(default)]
+    proxy: ProxyOptions,
+    #[serde(default)]
+    match_type: MatchType,
+    #[serde(default)]
+    preserve_query: bool,
+    #[serde(default)]
+    preserve_path: bool,
+    #[serde(default = "store::default_status_code")]
+    status_code: u16,
+    #[serde(default)]
+    expires_at: Option<i64>,
+    #[serde(default)]
+    max_hits: Option<i64>,
+    #[serde(default)]
+    geo_targets: Vec<GeoTarget>,
+    #[serde(default)]
+    device_targets: Vec<DeviceTarget>,
+    #[serde(default)]
+    language_targets: Vec<LanguageTarget>,
+    #[serde(default)]
+    split_targets: Vec<SplitTarget>,
+    #[serde(default)]
+    sticky_split: bool,
+    #[serde(default)]
+    utm: BTreeMap<String, String>,
+    #[serde(default)]
+    response_headers: BTreeMap<String, String>,
+    #[serde(default)]
+    preview: bool,
+    /// Replaces `password_hash` when given
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    password_hash: Option<String>,
+    #[serde(default)]
+    signed: bool,
 }
 
-type Db = Arc<Mutex<Connection>>;
+impl RouteUpdate {
+    /// The update replacing a route by `route`, but for its host, slug, hits,
+    /// tenant, owner and deletion.
+    pub(crate) fn new(route: Route, password: Option<String>) -> Self {
+        Self {
+            redirect_to: route.redirect_to,
+            mode: route.mode,
+            proxy: route.proxy,
+            match_type: route.match_type,
+            preserve_query: route.preserve_query,
+            preserve_path: route.preserve_path,
+            status_code: route.status_code,
+            expires_at: route.expires_at,
+            max_hits: route.max_hits,
+            geo_targets: route.geo_targets,
+            device_targets: route.device_targets,
+            language_targets: route.language_targets,
+            split_targets: route.split_targets,
+            sticky_split: route.sticky_split,
+            utm: route.utm,
+            response_headers: route.response_headers,
+            preview: route.preview,
+            password,
+            password_hash: route.password_hash,
+            signed: route.signed,
+        }
+    }
+}
+
+/// The routes for `services`, redirects last since they catch every path.
+pub fn path_routes(state: AppState, services: &[Service]) -> Router {
+    let mut router = Router::new();
+    if services.contains(&Service::Admin) {
+        let mut api = Router::new()
+            .route("/api/routes", get(list_routes).post(add_route))
+            .route("/api/routes/import", post(import_routes))
+            .route("/api/routes/export", get(export_routes))
+            .route(
+                "/api/routes/*slug",
+                get(read_route)
+                    .post(route_action)
+                    .put(update_route)
+                    .delete(delete_route),
+            )
+            .merge(shorten::shorten_routes())
+            .merge(auth::key_routes())
+            .merge(tenants::tenant_routes())
+            .merge(users::user_routes())
+            .merge(audit::audit_routes())
+            .merge(quota::usage_routes())
+            .merge(proxy::upstream_routes())
+            .merge(maintenance::maintenance_routes());
+        if state.oidc.is_some() {
+            api = api.merge(oidc::exchange_routes());
+        }
+        if state.api_docs {
+            api = api.merge(openapi::docs_routes());
+        }
+        api = api.layer(DefaultBodyLimit::max(state.api_body_limit));
+        if let Some(cors) = state.cors.clone() {
+            api = api.layer(cors);
+        }
+        router = router.merge(api);
+        if state.dashboard {
+            router = router.merge(dashboard::dashboard_routes().layer(
+                middleware::from_fn_with_state(
+                    state.compression.clone(),
+                    compression::compress_responses,
+                ),
+            ));
+            if state.oidc.is_some() {
+                router = router.merge(oidc::login_routes());
+            }
+        }
+    }
+    if services.contains(&Service::Health) {
+        router = router.merge(health::health_routes());
+    }
+    if services.contains(&Service::Metrics) && state.metrics.is_some() {
+        router = router.route("/metrics", get(telemetry::render_metrics));
+    }
+    if services.contains(&Service::Redirects) {
+        router = router.route("/", get(get_root)).route(
+            "/*custom_path",
+            any(get_route)
+                .layer(CookieManagerLayer::new())
+                .layer(middleware::from_fn_with_state(
+                    state.compression.clone(),
+                    compression::compress_responses,
+                )),
+        );
+    }
 
-// FIXME: add Patch, List and Delete actions
-pub fn path_routes(con: Connection) -> Router {
-    Router::new()
-        .route("/*custom_path", get(get_route).post(add_route))
-        .with_state(Arc::new(Mutex::new(con)))
+    router.with_state(state)
 }
 
-async fn get_route(State(con): State<Db>, Path(user_path): Path<String>) -> impl IntoResponse {
-    debug!("Getting key from route: {}", &user_path);
-    let val: Result<String, _> = con.lock().await.get(user_path);
+/// Answers every method on route paths. Redirect routes take GET, and
+/// POST for their password form, proxy routes anything once unlocked.
+/// Slugs without a route get the `[fallback]` answer, and errors the page
+/// of their status from `[error_pages]`, if any.
+async fn get_route(
+    State(state): State<AppState>,
+    host: Option<Host>,
+    Path(user_path): Path<String>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    cookies: Cookies,
+    request: Request<Body>,
+) -> Result<Response<Body>, (StatusCode, String)> {
+    let host = host.map(|Host(host)| normalize_host(&host));
+    let path = request.uri().path().to_owned();
+    let result = serve_route(&state, host.clone(), &user_path, client, cookies, request).await;
 
-    // TODO: set this to show `val` correctly
-    debug!("got value from route: {:#?}", &val);
-    if val.is_err() {
-        return Response::builder()
+    let context = ErrorContext {
+        slug: &user_path,
+        host: host.as_deref(),
+        path: &path,
+    };
+    let result = result.or_else(|err| error_page(&state, err, &context));
+    if state.middleware.is_empty() {
+        return result;
+    }
+    let mut response = result.unwrap_or_else(|(status, message)| {
+        let mut response = Response::new(Body::from(message));
+        *response.status_mut() = status;
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; charset=utf-8"),
+        );
+        response
+    });
+    hooks::on_response(&state.middleware, host.as_deref(), &path, &mut response).await;
+    Ok(response)
+}
+
+/// Answers `/` as `[fallback]` says for its host.
+async fn get_root(
+    State(state): State<AppState>,
+    host: Option<Host>,
+) -> Result<Response<Body>, (StatusCode, String)> {
+    let host = host.map(|Host(host)| normalize_host(&host));
+    let result = if state.maintenance.covers(None) {
+        state.maintenance.response()
+    } else {
+        state.fallback.response(host.as_deref(), true)
+    };
+
+    let context = ErrorContext {
+        slug: "",
+        host: host.as_deref(),
+        path: "/",
+    };
+    result.or_else(|err| error_page(&state, err, &context))
+}
+
+/// The page of `[error_pages]` for the status of `(status, message)`, the
+/// error itself when there's none.
+fn error_page(
+    state: &AppState,
+    (status, message): (StatusCode, String),
+    context: &ErrorContext,
+) -> Result<Response<Body>, (StatusCode, String)> {
+    state
+        .error_pages
+        .render(status, &message, context)
+        .ok_or((status, message))
+}
+
+async fn serve_route(
+    state: &AppState,
+    host: Option<String>,
+    user_path: &str,
+    client: Option<ConnectInfo<SocketAddr>>,
+    cookies: Cookies,
+    request: Request<Body>,
+) -> Result<Response<Body>, (StatusCode, String)> {
+    if state.maintenance.covers(None) {
+        return state.maintenance.response();
+    }
+    let client = client.map(|ConnectInfo(addr)| addr.ip());
+    let (mut parts, body) = request.into_parts();
+    let mut request = RouteRequest {
+        host: host.as_deref(),
+        client,
+        parts: &mut parts,
+    };
+    if let ControlFlow::Break(response) =
+        hooks::before_lookup(&state.middleware, &mut request).await
+    {
+        return Ok(response);
+    }
+    let uri = parts.uri.clone();
+    debug!("Getting key from route: {:?} {}", &host, &user_path);
+
+    let (signed, path, raw_path) = match signed_path(state, user_path, &uri) {
+        Ok(paths) => paths,
+        Err(SignatureError::Invalid) => {
+            debug!("invalid signature for: {}", &user_path);
+            return state.fallback.response(host.as_deref(), false);
+        }
+        Err(SignatureError::Expired) => {
+            debug!("signed link expired: {}", &user_path);
+            metrics::counter!("roads_route_expired_total").increment(1);
+            return state.expired.response();
+        }
+    };
+    // signed routes can't be reached without a signature and vice versa
+    let Some((route, extra_path)) = lookup_route(state, host.as_deref(), path, raw_path)
+        .await?
+        .filter(|(route, _)| route.signed == signed)
+    else {
+        debug!("no route found for: {}", &user_path);
+        metrics::counter!("roads_route_misses_total").increment(1);
+        return state.fallback.response(host.as_deref(), false);
+    };
+    if state.maintenance.covers(Some(&route)) {
+        debug!("route under maintenance: {}", route.label());
+        return state.maintenance.response();
+    }
+    if route.is_expired() {
+        debug!("route expired: {}", route.label());
+        metrics::counter!("roads_route_expired_total").increment(1);
+        return state.expired.response();
+    }
+    let unlocked = state.unlocker.is_unlocked(&route, &cookies);
+    if parts.method == Method::POST && (route.mode == RouteMode::Redirect || !unlocked) {
+        return unlock_route(state, &route, Request::from_parts(parts, body), &cookies).await;
+    }
+    if !unlocked {
+        return password::form(StatusCode::OK, false);
+    }
+    if route.mode == RouteMode::Redirect && !matches!(parts.method, Method::GET | Method::HEAD) {
+        return Err((StatusCode::METHOD_NOT_ALLOWED, "Method not allowed".into()));
+    }
+    if route.max_hits.is_some()
+        && !state
+            .store
+            .record_hit(route.host.as_deref(), &route.slug)
+            .await
+            .map_err(internal_error)?
+    {
+        debug!("route used up its hits: {}", route.label());
+        metrics::counter!("roads_route_expired_total").increment(1);
+        return state.expired.response();
+    }
+
+    let headers = &parts.headers;
+    let location = client
+        .zip(state.geoip.as_ref())
+        .and_then(|(ip, geoip)| geoip.lookup(ip));
+    let (target, variant) = pick_target(&route, headers, location.as_ref(), &cookies);
+
+    if state.tracker.is_some() || state.events.is_some() {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+        let hit = Hit {
+            host: route.host.clone(),
+            slug: route.slug.clone(),
+            at: auth::now(),
+            referrer: header(header::REFERER),
+            user_agent: header(header::USER_AGENT),
+            ip_hash: client
+                .zip(state.tracker.as_ref())
+                .map(|(ip, tracker)| tracker.hash_ip(ip)),
+            country: location.as_ref().map(|location| location.country.clone()),
+            variant: variant.map(|variant| variant as u32),
+        };
+        if let Some(events) = &state.events {
+            events.publish(Event::Hit(hit.clone()));
+        }
+        if let Some(tracker) = &state.tracker {
+            tracker.record(hit);
+        }
+    }
+
+    let mut target = target.to_owned();
+    let rewrite = route.proxy.rewrite.as_ref().filter(|_| route.mode == RouteMode::Proxy);
+    if let Some(rewrite) = rewrite {
+        let path = state.proxy.rewrite_path(rewrite, uri.path()).await?;
+        target = append_path(&target, &path);
+    } else if !extra_path.is_empty() {
+        target = append_path(&target, extra_path);
+    }
+    if route.mode == RouteMode::Proxy {
+        // upstreams get the whole query, only the signature is Roads'
+        let query = uri
+            .query()
+            .map(|query| if signed { signing::strip(query) } else { query.to_owned() })
+            .filter(|query| !query.is_empty());
+        if let Some(query) = query {
+            target = append_query(&target, &query);
+        }
+        let mut request = RouteRequest {
+            host: host.as_deref(),
+            client,
+            parts: &mut parts,
+        };
+        if let ControlFlow::Break(response) =
+            hooks::after_resolve(&state.middleware, &route, &mut target, &mut request).await
+        {
+            return Ok(response);
+        }
+        let request = Request::from_parts(parts, body);
+        let mut response = state.proxy.forward(&route, &target, request, client).await?;
+        response_headers::add(&state.response_headers, &route, &mut response);
+        if let Some(enabled) = route.proxy.compression {
+            response.extensions_mut().insert(RouteCompression(enabled));
+        }
+        return Ok(response);
+    }
+    metrics::counter!("roads_redirects_total").increment(1);
+
+    let status = StatusCode::from_u16(route.status_code).map_err(internal_error)?;
+    let query = uri
+        .query()
+        .filter(|_| route.preserve_query)
+        .map(preview::strip)
+        .map(|query| if signed { signing::strip(&query) } else { query })
+        .filter(|query| !query.is_empty());
+    if let Some(query) = query {
+        target = append_query(&target, &query);
+    }
+    if let Some(query) = utm::query(&state.utm, &route, host.as_deref(), &target) {
+        target = append_query(&target, &query);
+    }
+
+    let mut request = RouteRequest {
+        host: host.as_deref(),
+        client,
+        parts: &mut parts,
+    };
+    if let ControlFlow::Break(response) =
+        hooks::after_resolve(&state.middleware, &route, &mut target, &mut request).await
+    {
+        return Ok(response);
+    }
+
+    debug!("got value from route: {}", &target);
+    let mut response = if route.preview || uri.query().is_some_and(preview::requested) {
+        preview::page(&target)?
+    } else {
+        Response::builder()
+            .status(status)
+            .header("Location", target)
             .body(Body::empty())
-            .map_err(internal_error);
+            .map_err(internal_error)?
+    };
+    response_headers::add(&state.response_headers, &route, &mut response);
+    Ok(response)
+}
+
+#[derive(Deserialize)]
+struct UnlockForm {
+    password: String,
+}
+
+/// Takes the password form of a protected route, redirecting back to the
+/// route once the password is right.
+async fn unlock_route(
+    state: &AppState,
+    route: &Route,
+    request: Request<Body>,
+    cookies: &Cookies,
+) -> Result<Response<Body>, (StatusCode, String)> {
+    let location = request
+        .uri()
+        .path_and_query()
+        .map_or("/", |path| path.as_str())
+        .to_owned();
+
+    if let Some(hash) = route.password_hash.clone() {
+        let Form(form) = Form::<UnlockForm>::from_request(request, state)
+            .await
+            .map_err(|rejection| (rejection.status(), rejection.body_text()))?;
+        let valid = tokio::task::spawn_blocking(move || password::verify(&hash, &form.password))
+            .await
+            .map_err(internal_error)?;
+        if !valid {
+            debug!("wrong password for: {}", route.label());
+            return password::form(StatusCode::UNAUTHORIZED, true);
+        }
+        state.unlocker.unlock(route, cookies);
     }
 
     Response::builder()
-        .status(StatusCode::PERMANENT_REDIRECT)
-        .header("Location", val.unwrap())
+        .status(StatusCode::SEE_OTHER)
+        .header("Location", location)
         .body(Body::empty())
         .map_err(internal_error)
 }
 
-async fn add_route(
-    State(con): State<Db>,
-    Path(custom_path): Path<String>,
-    Json(req): Json<Route>,
-) -> impl IntoResponse {
-    let val: Result<String, _> = con.lock().await.set(&custom_path, req.redirect_to);
+/// Whether `path` is a signed `/s/{slug}` link, along with `path` and the
+/// raw path of `uri` without that prefix. Signed links are only recognized
+/// when `signing.secret` is set.
+fn signed_path<'a>(
+    state: &AppState,
+    path: &'a str,
+    uri: &'a Uri,
+) -> Result<(bool, &'a str, &'a str), SignatureError> {
+    let raw_path = &uri.path()[1..];
+    let Some(signer) = &state.signer else {
+        return Ok((false, path, raw_path));
+    };
+    let Some(slug) = path.strip_prefix(signing::PREFIX) else {
+        return Ok((false, path, raw_path));
+    };
+
+    signer.verify(slug, uri.query())?;
+    Ok((true, slug, &raw_path[signing::PREFIX.len()..]))
+}
+
+/// `find_route` for the request host, then for host-agnostic routes, since
+/// routes scoped to the request host win over the others.
+async fn lookup_route<'a>(
+    state: &AppState,
+    host: Option<&str>,
+    path: &str,
+    raw_path: &'a str,
+) -> Result<Option<(Route, &'a str)>, (StatusCode, String)> {
+    let mut val = find_route(state, host, path, raw_path).await?;
+    if val.is_none() && host.is_some() {
+        val = find_route(state, None, path, raw_path).await?;
+    }
+    // lowercased slugs only match once the path is lowercased too
+    if val.is_none() && state.slugs.lowercase && path.bytes().any(|b| b.is_ascii_uppercase()) {
+        let lowered = path.to_ascii_lowercase();
+        val = find_route(state, host, &lowered, raw_path).await?;
+        if val.is_none() && host.is_some() {
+            val = find_route(state, None, &lowered, raw_path).await?;
+        }
+    }
+
+    Ok(val)
+}
+
+/// The exact route for `path`, then the pattern and regex routes, then the
+/// closest parent of `path` with a route preserving the extra path. The
+/// extra path is returned along with the route, still percent-encoded from
+/// `raw_path`, and is empty for the other matches.
+async fn find_route<'a>(
+    state: &AppState,
+    host: Option<&str>,
+    path: &str,
+    raw_path: &'a str,
+) -> Result<Option<(Route, &'a str)>, (StatusCode, String)> {
+    let exact = state
+        .cache
+        .get(host, path, &state.store)
+        .await
+        .map_err(lookup_error)?
+        .filter(|route| route.match_type == MatchType::Exact);
+    if let Some(route) = exact {
+        return Ok(Some((route, "")));
+    }
+
+    let matched = state
+        .patterns
+        .find(host, path, &state.store)
+        .await
+        .map_err(lookup_error)?;
+    if let Some(route) = matched {
+        return Ok(Some((route, "")));
+    }
+
+    // only the parents known to be `preserve_path` routes are looked up
+    let prefixes = state
+        .patterns
+        .prefixes(host, path, &state.store)
+        .await
+        .map_err(lookup_error)?;
+    for prefix in prefixes {
+        let route = state
+            .cache
+            .get(host, prefix, &state.store)
+            .await
+            .map_err(lookup_error)?
+            .filter(|route| route.match_type == MatchType::Exact && route.preserve_path);
+        if let Some(route) = route {
+            let depth = path[prefix.len()..].matches('/').count();
+            let head = raw_path.rsplitn(depth + 1, '/').last().unwrap_or_default();
+            return Ok(Some((route, &raw_path[head.len()..])));
+        }
+    }
+
+    Ok(None)
+}
+
+/// The first device target matching the client, then the target for its
+/// most preferred language, then the first country target, then a split
+/// target or `redirect_to`. The index of the split target is returned with
+/// it.
+fn pick_target<'a>(
+    route: &'a Route,
+    headers: &HeaderMap,
+    location: Option<&Location>,
+    cookies: &Cookies,
+) -> (&'a str, Option<usize>) {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+    };
+
+    if !route.device_targets.is_empty() {
+        let devices = device::classify(header(header::USER_AGENT));
+        let target = route
+            .device_targets
+            .iter()
+            .find(|target| target.matches(&devices));
+        if let Some(target) = target {
+            return (&target.redirect_to, None);
+        }
+    }
+
+    if let Some(target) = language::pick(&route.language_targets, header(header::ACCEPT_LANGUAGE)) {
+        return (&target.redirect_to, None);
+    }
+
+    let geo_target = location.and_then(|location| {
+        route
+            .geo_targets
+            .iter()
+            .find(|target| target.matches(location))
+    });
+    if let Some(target) = geo_target {
+        return (&target.redirect_to, None);
+    }
+
+    if route.split_targets.is_empty() {
+        return (&route.redirect_to, None);
+    }
+    let variant = pick_variant(route, cookies);
+    (&route.split_targets[variant].redirect_to, Some(variant))
+}
+
+/// A split target index drawn by weight, or the one named by the route's
+/// cookie for sticky splits.
+fn pick_variant(route: &Route, cookies: &Cookies) -> usize {
+    let cookie = format!("roads_variant_{}", &auth::hash_token(&route.label())[..12]);
+    let sticky = route
+        .sticky_split
+        .then(|| cookies.get(&cookie)?.value().parse::<usize>().ok())
+        .flatten()
+        .filter(|&variant| variant < route.split_targets.len());
+    if let Some(variant) = sticky {
+        return variant;
+    }
+
+    let total: u64 = route
+        .split_targets
+        .iter()
+        .map(|target| u64::from(target.weight))
+        .sum();
+    let mut draw = rand::thread_rng().gen_range(0..total.max(1));
+    let variant = route
+        .split_targets
+        .iter()
+        .position(|target| {
+            let weight = u64::from(target.weight);
+            if draw < weight {
+                return true;
+            }
+            draw -= weight;
+            false
+        })
+        .unwrap_or_default();
 
-    if val.is_err() {
-        return internal_error(val.err().unwrap());
+    if route.sticky_split {
+        let mut cookie = Cookie::new(cookie, variant.to_string());
+        cookie.set_path("/");
+        cookie.set_http_only(true);
+        cookie.set_max_age(Duration::days(30));
+        cookies.add(cookie);
     }
 
-    debug!("inserted route: {}", &custom_path);
-    (StatusCode::OK, "Route inserted".into())
+    variant
+}
+
+/// Inserts `rest`, starting with `/`, after the path of `target`.
+fn append_path(target: &str, rest: &str) -> String {
+    let (base, tail) = target.split_at(target.find(['?', '#']).unwrap_or(target.len()));
+
+    format!("{}{}{}", base.trim_end_matches('/'), rest, tail)
+}
+
+/// Adds `query` to the query string of `target`, keeping its fragment last.
+fn append_query(target: &str, query: &str) -> String {
+    let (base, fragment) = target.split_at(target.find('#').unwrap_or(target.len()));
+    let separator = if base.contains('?') { '&' } else { '?' };
+
+    format!("{}{}{}{}", base, separator, query, fragment)
+}
+
+/// `?deleted=` of `/api/routes`.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct ListQuery {
+    /// List the deleted routes instead
+    #[serde(default)]
+    pub(crate) deleted: bool,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/routes",
+    tag = "routes",
+    params(ListQuery),
+    responses(
+        (status = 200, description = "Every route", body = [Route]),
+    )
+)]
+pub(crate) async fn list_routes(
+    principal: Principal,
+    State(state): State<AppState>,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<Vec<Route>>, (StatusCode, String)> {
+    principal.require(Scope::RoutesRead)?;
+
+    let mut routes = if query.deleted {
+        state.store.list_deleted().await
+    } else {
+        state.store.list().await
+    }
+    .map_err(internal_error)?;
+    routes.retain(|route| principal.can_access(route.tenant.as_deref()));
+
+    Ok(Json(routes))
+}
+
+/// Answers `POST {slug}/restore`, which undoes a deletion, and
+/// `POST {slug}/rollback`.
+#[utoipa::path(
+    post,
+    path = "/api/routes/{slug}/restore",
+    tag = "routes",
+    params(("slug" = String, Path, description = "Slug of the route, `/` included"), HostQuery),
+    responses(
+        (status = 200, description = "The restored route", body = Route),
+        (status = 404, description = "No deleted route found", body = String),
+    )
+)]
+async fn route_action(
+    principal: Principal,
+    State(state): State<AppState>,
+    Path(slug): Path<String>,
+    Query(query): Query<HostQuery>,
+    uri: Uri,
+) -> Result<Json<Route>, (StatusCode, String)> {
+    if let Some(slug) = slug.strip_suffix("/rollback") {
+        return history::rollback(principal, state, slug, &uri).await;
+    }
+    let Some(slug) = slug.strip_suffix("/restore") else {
+        return Err(route_not_found());
+    };
+    principal.require(Scope::RoutesWrite)?;
+
+    let host = query.host();
+    let deleted = find_any(&state, &principal, host.as_deref(), slug).await?;
+    principal.require_owner(deleted.owner.as_deref())?;
+    if !state
+        .store
+        .restore(host.as_deref(), slug)
+        .await
+        .map_err(internal_error)?
+    {
+        return Err((StatusCode::NOT_FOUND, "No deleted route found".into()));
+    }
+    invalidate(&state, host.as_deref(), slug).await?;
+
+    let route = state
+        .store
+        .get(host.as_deref(), slug)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(route_not_found)?;
+    record_change(&state, &principal, RevisionAction::Restore, None, Some(&route)).await?;
+
+    debug!("restored route: {} by {}", route.label(), &principal.subject);
+    Ok(Json(route))
+}
+
+/// Also answers `{slug}/stats`, `{slug}/qr` and `{slug}/history`, slugs may
+/// contain `/` so these can't have routes of their own.
+#[utoipa::path(
+    get,
+    path = "/api/routes/{slug}",
+    tag = "routes",
+    params(("slug" = String, Path, description = "Slug of the route, `/` included"), HostQuery),
+    responses(
+        (status = 200, description = "The route", body = Route),
+        (status = 404, description = "Route not found", body = String),
+    )
+)]
+async fn read_route(
+    principal: Principal,
+    State(state): State<AppState>,
+    Path(slug): Path<String>,
+    Query(query): Query<HostQuery>,
+    uri: Uri,
+    host: Option<Host>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    if let Some(slug) = slug.strip_suffix("/stats") {
+        let stats = stats::route_stats(principal, state, slug, &uri).await?;
+        return Ok(stats.into_response());
+    }
+    if let Some(slug) = slug.strip_suffix("/qr") {
+        return qr::route_qr(principal, state, slug, &uri, host, &headers).await;
+    }
+    if let Some(slug) = slug.strip_suffix("/history") {
+        let history = history::route_history(principal, state, slug, &uri).await?;
+        return Ok(history.into_response());
+    }
+    principal.require(Scope::RoutesRead)?;
+
+    let host = query.host();
+    let route = get_accessible(&state, &principal, host.as_deref(), &slug).await?;
+
+    Ok(Json(route).into_response())
+}
+
+/// A route as posted to `/api/routes`.
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct NewRoute {
+    #[serde(flatten)]
+    pub(crate) route: Route,
+    /// Replaces `password_hash` when given
+    pub(crate) password: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/routes",
+    tag = "routes",
+    request_body = NewRoute,
+    responses(
+        (status = 201, description = "The created route", body = Route),
+        (status = 400, description = "Invalid route", body = String),
+        (status = 409, description = "Route already exists", body = String),
+        (status = 429, description = "Daily quota of creates used up", body = String),
+    )
+)]
+pub(crate) async fn add_route(
+    principal: Principal,
+    State(state): State<AppState>,
+    Json(new): Json<NewRoute>,
+) -> Result<(StatusCode, Json<Route>), (StatusCode, String)> {
+    principal.require(Scope::RoutesWrite)?;
+    let mut req = new.route;
+    if let Some(password) = new.password {
+        req.password_hash = Some(hash_password(password).await?);
+    }
+    req.host = req.host.as_deref().map(normalize_host);
+    req.tenant = tenants::assign(&state.store, &principal, req.tenant.take()).await?;
+    req.owner = principal.user.clone();
+    slug::normalize(&state.slugs, &mut req);
+    slug::validate(&state.slugs, &req)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("Invalid slug: {}", err)))?;
+    validate_route(&req)?;
+
+    quota::reserve_create(&state, &principal).await?;
+    let inserted = state.store.insert(&req).await.map_err(internal_error)?;
+
+    if !inserted {
+        quota::release_create(&state, &principal).await?;
+        return Err((StatusCode::CONFLICT, "Route already exists".into()));
+    }
+    invalidate(&state, req.host.as_deref(), &req.slug).await?;
+    record_change(&state, &principal, RevisionAction::Create, None, Some(&req)).await?;
+
+    debug!("inserted route: {} by {}", req.label(), &principal.subject);
+    Ok((StatusCode::CREATED, Json(req)))
+}
+
+/// `?on_conflict=` of `/api/routes/import`.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ImportQuery {
+    #[serde(default)]
+    on_conflict: Conflict,
+}
+
+/// Takes a CSV body when sent as `text/csv`, a JSON array otherwise.
+#[utoipa::path(
+    post,
+    path = "/api/routes/import",
+    tag = "routes",
+    params(ImportQuery),
+    request_body(
+        content = [NewRoute],
+        description = "Routes as `POST /api/routes` takes them, or CSV as `text/csv`",
+    ),
+    responses(
+        (status = 200, description = "What was done with each row", body = ImportReport),
+        (status = 400, description = "Not CSV with the required columns or a JSON array",
+            body = String),
+    )
+)]
+async fn import_routes(
+    principal: Principal,
+    State(state): State<AppState>,
+    Query(query): Query<ImportQuery>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Json<ImportReport>, (StatusCode, String)> {
+    principal.require(Scope::RoutesWrite)?;
+
+    let csv = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/csv"));
+    let format = if csv { Format::Csv } else { Format::Json };
+    let rows = import::parse(&body, format)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("Invalid import: {}", err)))?;
+
+    let report = import::import(
+        &state.store,
+        &state.slugs,
+        &principal,
+        rows,
+        query.on_conflict,
+        Some(&state),
+    )
+    .await
+    .map_err(internal_error)?;
+
+    debug!(
+        "imported routes: {} created, {} updated, {} skipped, {} failed by {}",
+        report.created,
+        report.updated,
+        report.skipped,
+        report.failed.len(),
+        &principal.subject
+    );
+    Ok(Json(report))
+}
+
+/// `?format=&stats=` of `/api/routes/export`.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ExportQuery {
+    #[serde(default)]
+    format: Format,
+    /// Add each route's all-time click count
+    #[serde(default)]
+    stats: bool,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/routes/export",
+    tag = "routes",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "Every route, as a CSV or JSON attachment",
+            body = [Route], content_type = ["application/json", "text/csv"]),
+    )
+)]
+async fn export_routes(
+    principal: Principal,
+    State(state): State<AppState>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    principal.require(Scope::RoutesRead)?;
+    if query.stats {
+        principal.require(Scope::StatsRead)?;
+    }
+
+    let mut routes = state.store.list().await.map_err(internal_error)?;
+    routes.retain(|route| principal.can_access(route.tenant.as_deref()));
+    debug!("exporting {} routes for {}", routes.len(), &principal.subject);
+
+    let (content_type, file) = match query.format {
+        Format::Csv => ("text/csv; charset=utf-8", "routes.csv"),
+        Format::Json => ("application/json", "routes.json"),
+    };
+    let chunks = export::chunks(state.store.clone(), routes, query.format, query.stats);
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", file),
+            ),
+        ],
+        StreamBody::new(chunks),
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/routes/{slug}",
+    tag = "routes",
+    params(("slug" = String, Path, description = "Slug of the route, `/` included"), HostQuery),
+    request_body = RouteUpdate,
+    responses(
+        (status = 200, description = "The updated route", body = Route),
+        (status = 400, description = "Invalid route", body = String),
+        (status = 404, description = "Route not found", body = String),
+    )
+)]
+pub(crate) async fn update_route(
+    principal: Principal,
+    State(state): State<AppState>,
+    Path(slug): Path<String>,
+    Query(query): Query<HostQuery>,
+    Json(req): Json<RouteUpdate>,
+) -> Result<Json<Route>, (StatusCode, String)> {
+    principal.require(Scope::RoutesWrite)?;
+
+    let password_hash = match req.password {
+        Some(password) => Some(hash_password(password).await?),
+        None => req.password_hash,
+    };
+    let mut route = Route {
+        host: query.host(),
+        slug,
+        redirect_to: req.redirect_to,
+        mode: req.mode,
+        proxy: req.proxy,
+        match_type: req.match_type,
+        preserve_query: req.preserve_query,
+        preserve_path: req.preserve_path,
+        status_code: req.status_code,
+        expires_at: req.expires_at,
+        max_hits: req.max_hits,
+        hits: 0,
+        geo_targets: req.geo_targets,
+        device_targets: req.device_targets,
+        language_targets: req.language_targets,
+        split_targets: req.split_targets,
+        sticky_split: req.sticky_split,
+        utm: req.utm,
+        response_headers: req.response_headers,
+        preview: req.preview,
+        password_hash,
+        signed: req.signed,
+        deleted_at: None,
+        tenant: None,
+        owner: None,
+    };
+    validate_route(&route)?;
+
+    let old = get_accessible(&state, &principal, route.host.as_deref(), &route.slug).await?;
+    principal.require_owner(old.owner.as_deref())?;
+    route.tenant = old.tenant.clone();
+    route.owner = old.owner.clone();
+    if !state.store.update(&route).await.map_err(internal_error)? {
+        return Err(route_not_found());
+    }
+    invalidate(&state, route.host.as_deref(), &route.slug).await?;
+    record_change(&state, &principal, RevisionAction::Update, Some(&old), Some(&route)).await?;
+
+    debug!("updated route: {} by {}", route.label(), &principal.subject);
+    Ok(Json(route))
+}
+
+/// Also answers `{slug}/cache`, purging the route's cached responses.
+#[utoipa::path(
+    delete,
+    path = "/api/routes/{slug}",
+    tag = "routes",
+    params(("slug" = String, Path, description = "Slug of the route, `/` included"), HostQuery),
+    responses(
+        (status = 204, description = "Deleted, restorable until purged"),
+        (status = 404, description = "Route not found", body = String),
+    )
+)]
+async fn delete_route(
+    principal: Principal,
+    State(state): State<AppState>,
+    Path(slug): Path<String>,
+    Query(query): Query<HostQuery>,
+    uri: Uri,
+) -> Result<StatusCode, (StatusCode, String)> {
+    if let Some(slug) = slug.strip_suffix("/cache") {
+        return response_cache::purge_route(principal, state, slug, &uri).await;
+    }
+
+    remove_route(&principal, &state, query.host().as_deref(), &slug).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Deletes the route of `host` and `slug`, restorable until purged.
+pub(crate) async fn remove_route(
+    principal: &Principal,
+    state: &AppState,
+    host: Option<&str>,
+    slug: &str,
+) -> Result<(), (StatusCode, String)> {
+    principal.require(Scope::RoutesWrite)?;
+
+    let old = get_accessible(state, principal, host, slug).await?;
+    principal.require_owner(old.owner.as_deref())?;
+    if !state.store.delete(host, slug).await.map_err(internal_error)? {
+        return Err(route_not_found());
+    }
+    invalidate(state, host, slug).await?;
+    record_change(state, principal, RevisionAction::Delete, Some(&old), None).await?;
+
+    debug!("deleted route: {} by {}", slug, &principal.subject);
+    Ok(())
+}
+
+/// The route of `host` and `slug`, other tenants' routes being as missing
+/// to `principal` as ones that don't exist.
+pub(crate) async fn get_accessible(
+    state: &AppState,
+    principal: &Principal,
+    host: Option<&str>,
+    slug: &str,
+) -> Result<Route, (StatusCode, String)> {
+    state
+        .store
+        .get(host, slug)
+        .await
+        .map_err(internal_error)?
+        .filter(|route| principal.can_access(route.tenant.as_deref()))
+        .ok_or_else(route_not_found)
+}
+
+/// Refuses tenant callers what's about a route, current or deleted, of
+/// another tenant.
+pub(crate) async fn check_access(
+    state: &AppState,
+    principal: &Principal,
+    host: Option<&str>,
+    slug: &str,
+) -> Result<(), (StatusCode, String)> {
+    if principal.tenant.is_none() {
+        return Ok(());
+    }
+
+    find_any(state, principal, host, slug).await.map(drop)
+}
+
+/// `get_accessible`, falling back to the deleted routes.
+async fn find_any(
+    state: &AppState,
+    principal: &Principal,
+    host: Option<&str>,
+    slug: &str,
+) -> Result<Route, (StatusCode, String)> {
+    let current = state.store.get(host, slug).await.map_err(internal_error)?;
+    let route = match current {
+        Some(route) => Some(route),
+        None => {
+            let deleted = state.store.list_deleted().await.map_err(internal_error)?;
+            deleted
+                .into_iter()
+                .find(|route| route.host.as_deref() == host && route.slug == slug)
+        }
+    };
+
+    route
+        .filter(|route| principal.can_access(route.tenant.as_deref()))
+        .ok_or_else(route_not_found)
+}
+
+fn validate_route(route: &Route) -> Result<(), (StatusCode, String)> {
+    check_route(route).map_err(|err| (StatusCode::BAD_REQUEST, err))
+}
+
+/// Checks the slug syntax and the targets of `route`, new slugs also going
+/// through `slug::validate`.
+pub(crate) fn check_route(route: &Route) -> Result<(), String> {
+    patterns::validate(route)
+        .map_err(|err| format!("Invalid {} slug: {}", route.match_type.as_str(), err))?;
+
+    route
+        .check_mode()
+        .and_then(|()| route.check_geo_targets())
+        .and_then(|()| route.check_split_targets())
+        .and_then(|()| route.check_utm())
+        .and_then(|()| route.check_response_headers())
+        .and_then(|()| route.check_status())
+        .map_err(|err| format!("Invalid redirect: {}", err))
+}
+
+/// Hashes `password` off the async runtime, Argon2 being slow on purpose.
+async fn hash_password(password: String) -> Result<String, (StatusCode, String)> {
+    if password.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Invalid password: empty".into()));
+    }
+
+    tokio::task::spawn_blocking(move || password::hash(&password))
+        .await
+        .map_err(internal_error)?
+        .map_err(|err| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to hash password: {}", err),
+            )
+        })
+}
+
+/// Records a change to a route in its history and the audit log, and tells
+/// the webhooks about it.
+pub(crate) async fn record_change(
+    state: &AppState,
+    principal: &Principal,
+    action: RevisionAction,
+    old: Option<&Route>,
+    new: Option<&Route>,
+) -> Result<(), (StatusCode, String)> {
+    history::record(&state.store, principal, action, old, new)
+        .await
+        .map_err(internal_error)?;
+    if let Some(webhooks) = &state.webhooks {
+        webhooks.changed(&principal.subject, action, old, new);
+    }
+
+    Ok(())
+}
+
+/// Drops what the lookups cached about `slug`, patterns are reloaded as a
+/// whole since any of them may be affected. Other instances are told to do
+/// the same when `cache.pubsub` is enabled.
+pub(crate) async fn invalidate(
+    state: &AppState,
+    host: Option<&str>,
+    slug: &str,
+) -> Result<(), (StatusCode, String)> {
+    invalidate_local(state, host, slug).await?;
+    if let Some(invalidations) = &state.invalidations {
+        invalidations.publish(host, slug).await;
+    }
+
+    Ok(())
+}
+
+/// `invalidate` on this instance only.
+pub(crate) async fn invalidate_local(
+    state: &AppState,
+    host: Option<&str>,
+    slug: &str,
+) -> Result<(), (StatusCode, String)> {
+    state
+        .cache
+        .invalidate(host, slug)
+        .await
+        .map_err(internal_error)?;
+    state.patterns.invalidate().await;
+    state.proxy.purge_cache(host, slug);
+
+    Ok(())
+}
+
+/// Lowercases `host` and drops the port and a trailing dot, so
+/// `Go.Example.com.:8080` finds the routes of `go.example.com`.
+pub(crate) fn normalize_host(host: &str) -> String {
+    let name = if host.starts_with('[') {
+        host.split_inclusive(']').next().unwrap_or(host)
+    } else {
+        host.split(':').next().unwrap_or(host)
+    };
+
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+pub(crate) fn route_not_found() -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, "Route not found".into())
 }
 
 /// Utility function for mapping any error into a `500 Internal Server Error`
 /// response.
-fn internal_error<E>(_err: E) -> (StatusCode, String)
+pub(crate) fn internal_error<E>(_err: E) -> (StatusCode, String)
     where
         E: std::error::Error,
 {
@@ -72,3 +1381,14 @@ fn internal_error<E>(_err: E) -> (StatusCode, String)
         "unknown error has been reported".into(),
     )
 }
+
+/// Like `internal_error`, but 503 while the store is left alone after failing.
+fn lookup_error(err: StoreError) -> (StatusCode, String) {
+    match err {
+        StoreError::Unavailable(_) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "storage unavailable, try again later".into(),
+        ),
+        err => internal_error(err),
+    }
+}
@@ -0,0 +1,465 @@
+use std::{convert::Infallible, future::Future, net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware,
+    response::IntoResponse,
+    routing::Route,
+    Router,
+};
+use futures::future::{self, BoxFuture};
+use hyper::Body;
+use jsonwebtoken::DecodingKey;
+use tokio::sync::watch;
+use tower::{Layer, Service as TowerService};
+use tower_http::{set_header::SetResponseHeaderLayer, trace::TraceLayer};
+
+use crate::{
+    access_log::{self, AccessLog},
+    cache::RouteCache,
+    config::{Config, ListenAddr, ListenerConfig, Service},
+    cors,
+    dns::UpstreamConnector,
+    error_pages::ErrorPages,
+    events::EventSink,
+    fallback::Fallback,
+    geoip::GeoIp,
+    grpc,
+    health_checks::HealthChecks,
+    hooks::RoadsMiddleware,
+    invalidation::Invalidations,
+    maintenance::MaintenanceMode,
+    oidc::Oidc,
+    password::Unlocker,
+    patterns::PatternRoutes,
+    plain,
+    proxy::Proxy,
+    quota::Quotas,
+    rate_limit::{self, RateLimiter},
+    replica::ReadReplica,
+    router::{AppState, ExpiredPage, path_routes},
+    signing::Signer,
+    store::{self, Store},
+    telemetry, tls,
+    tracking::ClickTracker,
+    trash,
+    webhooks::Webhooks,
+    ServerError,
+};
+
+/// Middleware added through `RoadsServerBuilder::layer`.
+type AddLayer = Arc<dyn Fn(Router) -> Router + Send + Sync>;
+
+/// Roads with its background tasks started, ready to `serve` its listeners
+/// or to hand its `router` to another app.
+pub struct RoadsServer {
+    config: Config,
+    state: AppState,
+    access_log: Option<Arc<AccessLog>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    tracker: Option<Arc<ClickTracker>>,
+    layers: Vec<AddLayer>,
+    shutdown: BoxFuture<'static, ()>,
+}
+
+/// Settings of a `RoadsServer`, the `roads.toml` defaults unless given.
+pub struct RoadsServerBuilder {
+    config: Config,
+    store: Option<Store>,
+    layers: Vec<AddLayer>,
+    middleware: Vec<Box<dyn RoadsMiddleware>>,
+    shutdown: Option<BoxFuture<'static, ()>>,
+}
+
+impl RoadsServer {
+    pub fn builder() -> RoadsServerBuilder {
+        RoadsServerBuilder {
+            config: Config::default(),
+            store: None,
+            layers: Vec::new(),
+            middleware: Vec::new(),
+            shutdown: None,
+        }
+    }
+
+    /// The state of the handlers, for `path_routes` with other services.
+    pub fn state(&self) -> &AppState {
+        &self.state
+    }
+
+    /// Every service with the shared middleware, to nest or merge into
+    /// another app. Nothing is bound, `serve` isn't needed.
+    pub fn router(&self) -> Router {
+        let listener = ListenerConfig {
+            addr: ListenAddr::Tcp(([127, 0, 0, 1], self.config.port).into()),
+            tls: false,
+            mode: None,
+            proxy_protocol: false,
+            serve: Service::all(),
+        };
+        self.app(&listener)
+    }
+
+    /// Serves every listener, and gRPC when enabled, until the shutdown
+    /// signal, then drains their connections for up to `drain_timeout`.
+    pub async fn serve(self) -> Result<(), ServerError> {
+        let config = &self.config;
+        let (stop_tx, stop_rx) = watch::channel(());
+        let mut servers: Vec<BoxFuture<Result<(), ServerError>>> = Vec::new();
+        let mut tls_handles = Vec::new();
+        for listener in config.listeners() {
+            let app = self.app(&listener);
+
+            let stop = stopped(stop_rx.clone());
+            match listener.addr {
+                ListenAddr::Tcp(addr) if listener.tls => {
+                    let handle = axum_server::Handle::new();
+                    tls_handles.push(handle.clone());
+                    let tls = config.tls.clone();
+                    let http2 = config.http2;
+                    servers.push(Box::pin(async move {
+                        tls::serve(&tls, http2, listener.proxy_protocol, addr, app, handle)
+                            .await
+                            .map_err(ServerError::Tls)
+                    }));
+                }
+                ListenAddr::Tcp(addr) => {
+                    servers.push(Box::pin(plain::serve_tcp(
+                        addr,
+                        config.http2,
+                        listener.proxy_protocol,
+                        app,
+                        stop,
+                    )));
+                }
+                #[cfg(unix)]
+                ListenAddr::Unix(path) => {
+                    let http2 = config.http2;
+                    servers.push(Box::pin(async move {
+                        plain::serve_unix(&path, listener.mode, http2, app, stop).await
+                    }));
+                }
+                #[cfg(not(unix))]
+                ListenAddr::Unix(path) => return Err(ServerError::UnixUnsupported(path)),
+            }
+        }
+
+        if config.grpc.enabled {
+            let addr = SocketAddr::from((config.host, config.grpc.port));
+            let state = self.state.clone();
+            let stop = stopped(stop_rx.clone());
+            servers.push(Box::pin(async move {
+                grpc::serve(state, addr, stop).await.map_err(ServerError::Grpc)
+            }));
+        }
+
+        tokio::spawn(async move {
+            self.shutdown.await;
+            tracing::info!("shutting down, draining open connections");
+            let _ = stop_tx.send(());
+            for handle in tls_handles {
+                handle.graceful_shutdown(None);
+            }
+        });
+
+        let drain = Duration::from_secs(config.drain_timeout);
+        tokio::select! {
+            result = future::try_join_all(servers) => {
+                result?;
+            }
+            _ = async {
+                stopped(stop_rx).await;
+                tokio::time::sleep(drain).await;
+            } => tracing::warn!("drain timeout elapsed, dropping remaining connections"),
+        }
+
+        if let Some(tracker) = &self.tracker {
+            tracker.flush().await;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the hits still queued, for apps stopping without `serve`.
+    pub async fn flush(&self) {
+        if let Some(tracker) = &self.tracker {
+            tracker.flush().await;
+        }
+    }
+
+    /// The part of the app `listener` serves, with the shared middleware.
+    fn app(&self, listener: &ListenerConfig) -> Router {
+        let config = &self.config;
+        let mut app = path_routes(self.state.clone(), &listener.serve).fallback(route_not_found);
+        for layer in &self.layers {
+            app = layer(app);
+        }
+        if let Some(rate_limiter) = &self.rate_limiter {
+            app = app.layer(middleware::from_fn_with_state(
+                rate_limiter.clone(),
+                rate_limit::limit_requests,
+            ));
+        }
+        app = app.layer(middleware::from_fn(telemetry::track_requests));
+
+        if let Some(access_log) = &self.access_log {
+            app = app.layer(middleware::from_fn_with_state(
+                access_log.clone(),
+                access_log::log_requests,
+            ));
+        }
+        let http3 = listener.tls && config.tls.http3 && cfg!(feature = "http3");
+        if let (ListenAddr::Tcp(addr), true) = (&listener.addr, http3) {
+            let alt_svc = format!("h3=\":{}\"; ma=86400", addr.port());
+            app = app.layer(SetResponseHeaderLayer::if_not_present(
+                header::ALT_SVC,
+                HeaderValue::from_str(&alt_svc).expect("Alt-Svc is a valid header value"),
+            ));
+        }
+
+        app.layer(TraceLayer::new_for_http())
+    }
+}
+
+impl RoadsServerBuilder {
+    /// Settings of everything the other methods don't set.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Storage to use instead of connecting to `database_url`. It's taken
+    /// as migrated already.
+    pub fn store(mut self, store: Store) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Adds a listener, replacing those of `host`, `port` and `tls.port`.
+    pub fn listener(mut self, listener: ListenerConfig) -> Self {
+        self.config.listeners.push(listener);
+        self
+    }
+
+    /// Middleware wrapping every route, inside the rate limit, metrics and
+    /// access log. The first added runs last.
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + Sync + 'static,
+        L::Service: TowerService<Request<Body>> + Clone + Send + 'static,
+        <L::Service as TowerService<Request<Body>>>::Response: IntoResponse + 'static,
+        <L::Service as TowerService<Request<Body>>>::Error: Into<Infallible> + 'static,
+        <L::Service as TowerService<Request<Body>>>::Future: Send + 'static,
+    {
+        self.layers.push(Arc::new(move |router: Router| router.layer(layer.clone())));
+        self
+    }
+
+    /// Hooks into the requests of routes, run after those added before.
+    pub fn middleware(mut self, middleware: impl RoadsMiddleware + 'static) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Serve Prometheus metrics on `/metrics`
+    pub fn metrics(mut self, enabled: bool) -> Self {
+        self.config.metrics = enabled;
+        self
+    }
+
+    /// Serve the OpenAPI spec and Swagger UI on `/api/docs`
+    pub fn api_docs(mut self, enabled: bool) -> Self {
+        self.config.api_docs = enabled;
+        self
+    }
+
+    /// Serve the web dashboard on `/admin`
+    pub fn dashboard(mut self, enabled: bool) -> Self {
+        self.config.dashboard = enabled;
+        self
+    }
+
+    /// Record every redirect in the `hits` table
+    pub fn tracking(mut self, enabled: bool) -> Self {
+        self.config.tracking.enabled = enabled;
+        self
+    }
+
+    /// Resolves when `serve` is to stop, Ctrl+C or SIGTERM by default.
+    pub fn shutdown(mut self, signal: impl Future<Output = ()> + Send + 'static) -> Self {
+        self.shutdown = Some(Box::pin(signal));
+        self
+    }
+
+    /// Checks the settings, connects to the storage unless one was given and
+    /// starts the background tasks.
+    pub async fn build(self) -> Result<RoadsServer, ServerError> {
+        let config = self.config;
+        config.validate()?;
+        let store = match self.store {
+            Some(store) => store,
+            None => {
+                let store = store::connect(&config.database_url, &config.database).await?;
+                if config.auto_migrate {
+                    store::migrate(&store).await?;
+                }
+                store
+            }
+        };
+
+        trash::start_purge(&config.trash, store.clone());
+        let webhooks = Webhooks::new(&config.webhooks).map(Arc::new);
+        if let Some(webhooks) = &webhooks {
+            let interval = Duration::from_secs(config.webhooks.expiry_interval);
+            webhooks.start_expiry_watch(store.clone(), interval);
+        }
+        let tracker = config
+            .tracking
+            .enabled
+            .then(|| Arc::new(ClickTracker::start(&config.tracking, store.clone())));
+        let replica = config
+            .database_replica_url
+            .as_deref()
+            .map(|url| Arc::new(ReadReplica::new(url, &config.database)));
+        let events = EventSink::start(&config.events).map(Arc::new);
+        let upstreams = UpstreamConnector::new(&config.proxy);
+        let checks = config.health_checks.enabled.then(|| {
+            HealthChecks::start(&config.health_checks, store.clone(), upstreams.clone())
+        });
+        let maintenance = MaintenanceMode::start(&config.maintenance, store.clone())
+            .await
+            .map_err(ServerError::MaintenancePage)?;
+        let state = AppState {
+            store,
+            cache: Arc::new(
+                RouteCache::new(&config.cache, &config.database, replica.clone()).await?,
+            ),
+            invalidations: Invalidations::new(&config.cache).await?.map(Arc::new),
+            patterns: Arc::new(PatternRoutes::new(
+                Duration::from_secs(config.cache.ttl),
+                replica,
+            )),
+            jwt_key: config
+                .auth
+                .jwt_secret
+                .as_ref()
+                .map(|secret| Arc::new(DecodingKey::from_secret(secret.as_bytes()))),
+            metrics: config
+                .metrics
+                .then(telemetry::install_metrics)
+                .transpose()?,
+            expired: Arc::new(
+                ExpiredPage::load(&config.expired)
+                    .await
+                    .map_err(ServerError::ExpiredPage)?,
+            ),
+            maintenance,
+            error_pages: Arc::new(
+                ErrorPages::load(&config.error_pages)
+                    .await
+                    .map_err(ServerError::ErrorPage)?,
+            ),
+            fallback: Arc::new(
+                Fallback::load(&config.fallback)
+                    .await
+                    .map_err(ServerError::FallbackPage)?,
+            ),
+            tracker: tracker.clone(),
+            events: events.clone(),
+            geoip: config
+                .geoip
+                .database
+                .as_deref()
+                .map(GeoIp::open)
+                .transpose()?
+                .map(Arc::new),
+            utm: Arc::new(config.utm.clone()),
+            response_headers: Arc::new(config.response_headers.clone()),
+            compression: Arc::new(config.compression.clone()),
+            api_body_limit: config.body_limits.api,
+            shorten: Arc::new(config.shorten.clone()),
+            slugs: Arc::new(config.slugs.clone()),
+            qr: Arc::new(config.qr.clone()),
+            unlocker: Arc::new(Unlocker::new(&config.passwords)),
+            signer: config
+                .signing
+                .secret
+                .as_deref()
+                .map(|secret| Arc::new(Signer::new(secret))),
+            webhooks,
+            quotas: config
+                .quotas
+                .enabled
+                .then(|| Arc::new(Quotas::new(&config.quotas))),
+            cors: cors::layer(&config.cors),
+            api_docs: config.api_docs,
+            dashboard: config.dashboard,
+            oidc: Oidc::new(&config.oidc, config.auth.jwt_secret.as_deref()).map(Arc::new),
+            proxy: Arc::new(Proxy::new(
+                &config.proxy,
+                upstreams,
+                config.body_limits.proxy,
+                checks,
+                events,
+            )),
+            middleware: self.middleware.into(),
+        };
+        if let Some(invalidations) = &state.invalidations {
+            invalidations.subscribe(state.clone());
+        }
+        let access_log = if config.access_log.enabled {
+            Some(Arc::new(AccessLog::open(&config.access_log).await?))
+        } else {
+            None
+        };
+        let rate_limiter = config
+            .rate_limit
+            .enabled
+            .then(|| Arc::new(RateLimiter::new(&config.rate_limit)));
+
+        Ok(RoadsServer {
+            config,
+            state,
+            access_log,
+            rate_limiter,
+            tracker,
+            layers: self.layers,
+            shutdown: self.shutdown.unwrap_or_else(|| Box::pin(shutdown_signal())),
+        })
+    }
+}
+
+async fn stopped(mut stop_rx: watch::Receiver<()>) {
+    let _ = stop_rx.changed().await;
+}
+
+/// Resolves on Ctrl+C or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+async fn route_not_found() -> (StatusCode, String) {
+    (
+        StatusCode::NOT_FOUND,
+        "Ops, this route doesn't exist!".to_string(),
+    )
+}
@@ -0,0 +1,134 @@
+use axum::{
+    extract::{Host, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+    Router, routing::post,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{Principal, Scope},
+    config::ShortenConfig,
+    quota,
+    router::{self, AppState, internal_error},
+    slug,
+    store::{RevisionAction, Route},
+    tenants,
+};
+
+/// Slugs tried before giving up, collisions only get likely once most of
+/// the slug space is taken.
+const ATTEMPTS: usize = 8;
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct ShortenRequest {
+    redirect_to: String,
+    /// Scope the generated route to this hostname
+    host: Option<String>,
+    /// Tenant the route belongs to, the caller's own for tenant callers
+    tenant: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct Shortened {
+    short_url: String,
+    #[serde(flatten)]
+    route: Route,
+}
+
+pub fn shorten_routes() -> Router<AppState> {
+    Router::new().route("/api/shorten", post(shorten))
+}
+
+/// Creates a route for `redirect_to` under a random slug.
+#[utoipa::path(
+    post,
+    path = "/api/shorten",
+    tag = "routes",
+    request_body = ShortenRequest,
+    responses(
+        (status = 201, description = "The created route and its short URL", body = Shortened),
+        (status = 429, description = "Daily quota of creates used up", body = String),
+        (status = 503, description = "No free slug found", body = String),
+    )
+)]
+async fn shorten(
+    principal: Principal,
+    State(state): State<AppState>,
+    host: Option<Host>,
+    headers: HeaderMap,
+    Json(req): Json<ShortenRequest>,
+) -> Result<(StatusCode, Json<Shortened>), (StatusCode, String)> {
+    principal.require(Scope::RoutesWrite)?;
+
+    let config = &state.shorten;
+    let mut route = Route::new(String::new(), req.redirect_to);
+    route.host = req.host.as_deref().map(router::normalize_host);
+    route.tenant = tenants::assign(&state.store, &principal, req.tenant).await?;
+    route.owner = principal.user.clone();
+    quota::reserve_create(&state, &principal).await?;
+    for _ in 0..ATTEMPTS {
+        route.slug = generate_slug(config);
+        slug::normalize(&state.slugs, &mut route);
+        if slug::validate(&state.slugs, &route).is_err() {
+            continue;
+        }
+        if state.store.insert(&route).await.map_err(internal_error)? {
+            router::invalidate(&state, route.host.as_deref(), &route.slug).await?;
+            router::record_change(&state, &principal, RevisionAction::Create, None, Some(&route))
+                .await?;
+            debug!("shortened route: {} by {}", route.label(), &principal.subject);
+
+            return Ok((
+                StatusCode::CREATED,
+                Json(Shortened {
+                    short_url: short_url(config, &route, host, &headers),
+                    route,
+                }),
+            ));
+        }
+    }
+
+    quota::release_create(&state, &principal).await?;
+    Err((
+        StatusCode::SERVICE_UNAVAILABLE,
+        "No free slug found, increase shorten.length".into(),
+    ))
+}
+
+fn generate_slug(config: &ShortenConfig) -> String {
+    let alphabet: Vec<char> = config.alphabet.chars().collect();
+    let mut rng = rand::thread_rng();
+
+    (0..config.length)
+        .map(|_| alphabet[rng.gen_range(0..alphabet.len())])
+        .collect()
+}
+
+/// The public URL of `route`, under `shorten.base_url` or else the route's
+/// host or the host the request was sent to.
+pub(crate) fn short_url(
+    config: &ShortenConfig,
+    route: &Route,
+    host: Option<Host>,
+    headers: &HeaderMap,
+) -> String {
+    if let Some(base_url) = &config.base_url {
+        return format!("{}/{}", base_url.trim_end_matches('/'), route.slug);
+    }
+
+    let scheme = headers
+        .get("x-forwarded-proto")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("http");
+    let host = route
+        .host
+        .clone()
+        .or(host.map(|Host(host)| host))
+        .unwrap_or_else(|| "localhost".into());
+
+    format!("{}://{}/{}", scheme, host, route.slug)
+}
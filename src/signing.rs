@@ -0,0 +1,79 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::auth;
+
+/// Path prefix of signed links, `/s/{slug}?exp=...&sig=...`
+pub const PREFIX: &str = "s/";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Signs and checks links to routes marked `signed`. The signature is the
+/// hex HMAC-SHA256 of `{exp}:{slug}` under `signing.secret`, `exp` being a
+/// Unix timestamp, so links can be minted offline with the secret alone.
+pub struct Signer {
+    secret: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum SignatureError {
+    /// Missing, malformed or wrong `exp`/`sig`
+    Invalid,
+    Expired,
+}
+
+impl Signer {
+    pub fn new(secret: &str) -> Self {
+        Self {
+            secret: secret.as_bytes().to_vec(),
+        }
+    }
+
+    /// The `exp=...&sig=...` query of a link to `slug` valid until `exp`.
+    pub fn sign(&self, slug: &str, exp: i64) -> String {
+        let sig = hex::encode(self.mac(slug, exp).finalize().into_bytes());
+
+        format!("exp={}&sig={}", exp, sig)
+    }
+
+    /// Checks the `exp` and `sig` parameters of `query` for `slug`.
+    pub fn verify(&self, slug: &str, query: Option<&str>) -> Result<(), SignatureError> {
+        let param = |name| {
+            query?
+                .split('&')
+                .find_map(|param| param.strip_prefix(name)?.strip_prefix('='))
+        };
+        let exp = param("exp")
+            .and_then(|exp| exp.parse::<i64>().ok())
+            .ok_or(SignatureError::Invalid)?;
+        let sig = param("sig")
+            .and_then(|sig| hex::decode(sig).ok())
+            .ok_or(SignatureError::Invalid)?;
+
+        // verify_slice compares in constant time
+        self.mac(slug, exp)
+            .verify_slice(&sig)
+            .map_err(|_| SignatureError::Invalid)?;
+        if exp <= auth::now() {
+            return Err(SignatureError::Expired);
+        }
+
+        Ok(())
+    }
+
+    fn mac(&self, slug: &str, exp: i64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).expect("HMAC takes any key size");
+        mac.update(format!("{}:{}", exp, slug).as_bytes());
+        mac
+    }
+}
+
+/// `query` without `exp` and `sig`, so they aren't passed on to the
+/// target.
+pub fn strip(query: &str) -> String {
+    query
+        .split('&')
+        .filter(|param| !matches!(param.split('=').next(), Some("exp" | "sig")))
+        .collect::<Vec<_>>()
+        .join("&")
+}
@@ -0,0 +1,106 @@
+use tracing::warn;
+
+use crate::{
+    config::SlugConfig,
+    store::{MatchType, Route, Store, StoreError},
+};
+
+/// Last segments the admin API reads as an action on the route before them,
+/// `/api/routes/{slug}/stats` being the stats of `slug`.
+const RESERVED_SUFFIXES: [&str; 6] = ["stats", "qr", "history", "rollback", "restore", "cache"];
+
+/// Characters user-chosen slugs are made of, `/` separating segments.
+pub fn is_slug_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '~' | '/')
+}
+
+/// Lowercases exact slugs when `[slugs].lowercase` is set, patterns and
+/// regexes are left alone since their syntax is case-sensitive.
+pub fn normalize(config: &SlugConfig, route: &mut Route) {
+    if config.lowercase && route.match_type == MatchType::Exact {
+        route.slug = route.slug.to_ascii_lowercase();
+    }
+}
+
+/// Checks a new route's slug against `[slugs]`. Only exact slugs are held
+/// to the charset, but no route may start with a reserved segment, which
+/// would shadow a system endpoint such as `/api/routes`, nor end with one
+/// of the admin API's route actions, which would leave it unmanageable.
+pub fn validate(config: &SlugConfig, route: &Route) -> Result<(), String> {
+    if route.match_type == MatchType::Exact {
+        let len = route.slug.chars().count();
+        if len < config.min_length || len > config.max_length {
+            return Err(format!(
+                "must be {} to {} characters long",
+                config.min_length, config.max_length
+            ));
+        }
+        if let Some(c) = route.slug.chars().find(|&c| !is_slug_char(c)) {
+            return Err(format!("`{}` is not allowed", c));
+        }
+        if route.slug.starts_with('/') || route.slug.ends_with('/') || route.slug.contains("//") {
+            return Err("segments can't be empty".into());
+        }
+    }
+
+    if route.match_type != MatchType::Regex {
+        let first = route.slug.split('/').next().unwrap_or_default();
+        if config.reserved.iter().any(|word| word.eq_ignore_ascii_case(first)) {
+            return Err(format!("`{}` is reserved", first));
+        }
+    }
+    if let Some(last) = reserved_suffix(&route.slug) {
+        return Err(format!("`/{}` is reserved as a last segment", last));
+    }
+
+    Ok(())
+}
+
+fn reserved_suffix(slug: &str) -> Option<&str> {
+    let (_, last) = slug.rsplit_once('/')?;
+    RESERVED_SUFFIXES.contains(&last).then_some(last)
+}
+
+/// Warns about routes stored before their last segment was reserved, which
+/// the admin API can't reach but the `route` commands can.
+pub async fn warn_reserved_suffixes(store: &Store) -> Result<(), StoreError> {
+    for route in store.list().await? {
+        if let Some(last) = reserved_suffix(&route.slug) {
+            warn!(
+                "route {}{} ends with the reserved `/{}`, the admin API can't manage it, \
+                 `roads route rm` can",
+                route.host.as_deref().map_or(String::new(), |host| format!("{} ", host)),
+                route.slug,
+                last
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(slug: &str) -> Route {
+        serde_json::from_value(serde_json::json!({
+            "slug": slug,
+            "redirect_to": "https://example.com",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn rejects_admin_action_suffixes() {
+        let config = SlugConfig::default();
+        for slug in ["promo/stats", "promo/qr", "a/b/history", "promo/rollback", "x/restore"] {
+            assert!(validate(&config, &route(slug)).is_err(), "{}", slug);
+        }
+        assert!(validate(&config, &route("promo/cache")).is_err());
+
+        for slug in ["stats", "promo/statistics", "promo/stats/2024", "qr-codes/promo"] {
+            assert!(validate(&config, &route(slug)).is_ok(), "{}", slug);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use axum::{extract::Query, http::Uri, Json};
+use hyper::StatusCode;
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::{self, Principal, Scope},
+    router::{self, AppState, internal_error},
+    store::{HitStats, StatsQuery},
+};
+
+const DEFAULT_RANGE: i64 = 30 * 24 * 3600;
+const MAX_RANGE: i64 = 366 * 24 * 3600;
+const DEFAULT_TOP: u32 = 10;
+
+/// `?host=&from=&to=&bucket=&top=` of `/api/routes/{slug}/stats`.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct StatsParams {
+    /// Hostname the route is scoped to
+    host: Option<String>,
+    /// Unix timestamps, the last 30 days by default and a year at most
+    from: Option<i64>,
+    to: Option<i64>,
+    #[serde(default)]
+    bucket: Granularity,
+    top: Option<u32>,
+}
+
+#[derive(Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum Granularity {
+    Hour,
+    #[default]
+    Day,
+}
+
+impl Granularity {
+    fn seconds(&self) -> i64 {
+        match self {
+            Self::Hour => 3600,
+            Self::Day => 24 * 3600,
+        }
+    }
+}
+
+/// Hits of a route over time, with its top referrers and user agents.
+#[utoipa::path(
+    get,
+    path = "/api/routes/{slug}/stats",
+    tag = "stats",
+    params(("slug" = String, Path, description = "Slug of the route, `/` included"), StatsParams),
+    responses(
+        (status = 200, description = "Hits of the route", body = HitStats),
+        (status = 400, description = "Invalid or too wide range", body = String),
+        (status = 404, description = "Route not found", body = String),
+    )
+)]
+pub async fn route_stats(
+    principal: Principal,
+    state: AppState,
+    slug: &str,
+    uri: &Uri,
+) -> Result<Json<HitStats>, (StatusCode, String)> {
+    principal.require(Scope::StatsRead)?;
+
+    let Query(params) = Query::<StatsParams>::try_from_uri(uri)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+    let host = params.host.as_deref().map(router::normalize_host);
+    router::get_accessible(&state, &principal, host.as_deref(), slug).await?;
+
+    let to = params.to.unwrap_or_else(|| auth::now() + 1);
+    let from = params.from.unwrap_or(to.saturating_sub(DEFAULT_RANGE));
+    if from >= to {
+        return Err((StatusCode::BAD_REQUEST, "from must be before to".into()));
+    }
+    if to.saturating_sub(from) > MAX_RANGE {
+        return Err((StatusCode::BAD_REQUEST, "Stats span a year at most".into()));
+    }
+
+    let query = StatsQuery {
+        from,
+        to,
+        bucket: params.bucket.seconds(),
+        top: params.top.unwrap_or(DEFAULT_TOP),
+    };
+    let stats = state
+        .store
+        .hit_stats(host.as_deref(), slug, &query)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(stats))
+}
@@ -0,0 +1,1187 @@
+use std::{collections::BTreeMap, str::FromStr, sync::Arc};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::{config::DatabaseConfig, device::Device, geoip::Location, response_headers, slug};
+
+pub use self::{
+    mysql::MySqlStore,
+    redis::{RedisConnection, RedisStore},
+    sqlite::SqliteStore,
+};
+
+mod mysql;
+mod redis;
+mod sqlite;
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct Route {
+    /// Only answer requests for this hostname, any host when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    pub slug: String,
+    pub redirect_to: String,
+    /// Redirect to the target or proxy requests to it
+    #[serde(default)]
+    pub mode: RouteMode,
+    /// How proxy-mode routes reach their upstreams
+    #[serde(default, skip_serializing_if = "ProxyOptions::is_default")]
+    pub proxy: ProxyOptions,
+    /// How `slug` is compared to the request path
+    #[serde(default)]
+    pub match_type: MatchType,
+    /// Append the request's query string to `redirect_to`
+    #[serde(default)]
+    pub preserve_query: bool,
+    /// Also redirect paths below an exact `slug`, appending the rest of the
+    /// path to `redirect_to`
+    #[serde(default)]
+    pub preserve_path: bool,
+    /// One of `REDIRECT_STATUSES`, permanent by default
+    #[serde(default = "default_status_code")]
+    pub status_code: u16,
+    /// Unix timestamp after which the route answers with the expired page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    /// Redirects allowed before the route answers like an expired one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_hits: Option<i64>,
+    /// Redirects counted so far, only kept for routes with `max_hits`
+    #[serde(default)]
+    pub hits: i64,
+    /// Targets for visitors from given countries, the first match wins and
+    /// `redirect_to` is the fallback
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub geo_targets: Vec<GeoTarget>,
+    /// Targets for given device classes, tried before `geo_targets`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub device_targets: Vec<DeviceTarget>,
+    /// Targets picked from `Accept-Language`, tried after `device_targets`
+    /// and before `geo_targets`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub language_targets: Vec<LanguageTarget>,
+    /// Weighted variants replacing `redirect_to` when no other target
+    /// matched, one is picked per request
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub split_targets: Vec<SplitTarget>,
+    /// Keep serving a client the variant it got first, through a cookie
+    #[serde(default)]
+    pub sticky_split: bool,
+    /// UTM parameters added to the target on top of the global `[utm]`
+    /// ones, an empty value removing a global one
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub utm: BTreeMap<String, String>,
+    /// Headers of the redirect or proxied response on top of the global
+    /// `[response_headers]`, overriding them, an empty value dropping one
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub response_headers: BTreeMap<String, String>,
+    /// Answer with a page showing the target and a continue link instead
+    /// of redirecting, also done for any route with `?preview`
+    #[serde(default)]
+    pub preview: bool,
+    /// Argon2 PHC string of the password asked before redirecting, set
+    /// through the write-only `password` of the admin API
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
+    /// Only redirect through signed `/s/{slug}` links, see `signing`
+    #[serde(default)]
+    pub signed: bool,
+    /// Unix timestamp of the deletion, deleted routes are left out of
+    /// lookups until restored or purged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<i64>,
+    /// Id of the tenant owning the route, unset for routes outside any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Id of the user who created the route, who may change it besides
+    /// admins. Unset for routes only admins may change
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub struct ProxyOptions {
+    /// Base URLs standing in for the origin of the target, one picked per
+    /// request. The target alone is used when empty
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub upstreams: Vec<String>,
+    pub balance: Balance,
+    /// Keep each client on the upstream it first got through a cookie, as
+    /// long as that one is up
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub sticky_sessions: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub canary: Option<Canary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<Mirror>,
+    #[serde(skip_serializing_if = "Retries::is_off")]
+    pub retries: Retries,
+    #[serde(skip_serializing_if = "CircuitBreaker::is_off")]
+    pub circuit_breaker: CircuitBreaker,
+    /// Applied after those of the `[proxy]` config
+    #[serde(skip_serializing_if = "HeaderRules::is_empty")]
+    pub request_headers: HeaderRules,
+    /// Makes the path upstreams get out of the whole request path, instead
+    /// of the target's path followed by what's past the slug
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rewrite: Option<PathRewrite>,
+    /// Gzip responses or not, whatever `compression.enabled` says
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<bool>,
+    /// Seconds responses stay in the `[proxy.cache]` whatever the upstream
+    /// says, 0 keeping them out of it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_ttl: Option<u64>,
+    #[serde(skip_serializing_if = "Timeouts::is_unset")]
+    pub timeouts: Timeouts,
+}
+
+impl ProxyOptions {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The upstreams, the canary's included.
+    pub fn all_upstreams(&self) -> impl Iterator<Item = &String> {
+        self.upstreams
+            .iter()
+            .chain(self.canary.as_ref().map(|canary| &canary.upstream))
+    }
+}
+
+/// An upstream getting a share of the requests, like a new version being
+/// rolled out, the others getting the rest.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, ToSchema)]
+pub struct Canary {
+    /// Base URL of the upstream
+    pub upstream: String,
+    /// Percent of the requests it gets
+    pub percent: f64,
+    /// Request header sending a request to the canary when `true` or `1`,
+    /// or away from it when `false` or `0`, for testers
+    #[serde(default = "default_canary_header")]
+    pub header: String,
+}
+
+pub fn default_canary_header() -> String {
+    "x-roads-canary".into()
+}
+
+/// An upstream getting copies of the requests, its responses dropped, like
+/// a new backend version load-tested with real traffic. Only requests with
+/// small bodies of known size are copied.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, ToSchema)]
+pub struct Mirror {
+    /// Base URL of the upstream
+    pub upstream: String,
+    /// Percent of the requests copied
+    #[serde(default = "default_mirror_percent")]
+    pub percent: f64,
+}
+
+pub fn default_mirror_percent() -> f64 {
+    100.0
+}
+
+/// Retries of idempotent requests failing to connect or answered 502 or
+/// 503, on another upstream when there's one.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub struct Retries {
+    /// Retries per request, none by default
+    pub attempts: u32,
+    /// Milliseconds before the first retry, doubling for each next one
+    pub backoff: u64,
+    /// Milliseconds the backoff stops growing at
+    pub max_backoff: u64,
+    /// Retries each request adds to the route's budget, so that retries
+    /// stay a share of the traffic when an upstream is down
+    pub budget: f64,
+}
+
+impl Default for Retries {
+    fn default() -> Self {
+        Self {
+            attempts: 0,
+            backoff: 50,
+            max_backoff: 1000,
+            budget: 0.2,
+        }
+    }
+}
+
+impl Retries {
+    pub fn is_off(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Delay before retry `attempt`, counting from 1, jittered between half
+    /// and all of the backoff.
+    pub fn backoff(&self, attempt: u32) -> std::time::Duration {
+        let backoff = self
+            .backoff
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(self.max_backoff);
+        let jittered = rand::Rng::gen_range(&mut rand::thread_rng(), backoff / 2..=backoff);
+
+        std::time::Duration::from_millis(jittered)
+    }
+}
+
+/// Stops sending requests to an upstream failing too many of them for a
+/// while, the first request after `cooldown` trying it again.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub struct CircuitBreaker {
+    /// Share of requests answered 5xx or failing to connect that opens the
+    /// circuit, off at 0
+    pub error_rate: f64,
+    /// Requests in the window before the error rate is looked at
+    pub min_requests: u32,
+    /// Seconds the error rate is measured over
+    pub window: u64,
+    /// Seconds an open circuit lets no request through
+    pub cooldown: u64,
+    /// Base URL standing in while every circuit is open, instead of a 503
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<String>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {
+            error_rate: 0.0,
+            min_requests: 10,
+            window: 30,
+            cooldown: 30,
+            fallback: None,
+        }
+    }
+}
+
+impl CircuitBreaker {
+    pub fn is_off(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.error_rate > 0.0
+    }
+}
+
+/// A regex replacement over the request path, like `^/svc/foo` to `` for
+/// stripping a prefix or `^/svc/(\w+)` to `/v2/$1`. The result goes after
+/// the target's path.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct PathRewrite {
+    pub pattern: String,
+    /// What matches of `pattern` become, `$1` or `$name` filling in groups
+    pub replacement: String,
+}
+
+/// Seconds a proxied request may take before it's answered 504, those unset
+/// being the `[proxy]` ones and 0 meaning no limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub struct Timeouts {
+    /// Without hearing from the upstream, for the response headers or
+    /// between two chunks of the body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read: Option<u64>,
+    /// For the whole exchange, retries and the response body included
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+}
+
+impl Timeouts {
+    pub fn is_unset(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Changes to the headers of a proxied request, made once the
+/// `X-Forwarded-*` ones are set: `remove` first, then `set`, then `add`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub struct HeaderRules {
+    /// Headers replacing those of the same name, `Host` included
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub set: BTreeMap<String, String>,
+    /// Headers sent besides those of the same name
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub add: BTreeMap<String, String>,
+    /// Names of the headers not passed on, like `cookie`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub remove: Vec<String>,
+}
+
+impl HeaderRules {
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty() && self.add.is_empty() && self.remove.is_empty()
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        let names = self.set.keys().chain(self.add.keys()).chain(&self.remove);
+        for name in names {
+            if hyper::header::HeaderName::from_bytes(name.as_bytes()).is_err() {
+                return Err(format!("invalid header name: {}", name));
+            }
+        }
+        for value in self.set.values().chain(self.add.values()) {
+            if hyper::header::HeaderValue::from_str(value).is_err() {
+                return Err(format!("invalid header value: {}", value));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Makes the changes to `headers`, skipping names and values `check`
+    /// turns down.
+    pub fn apply(&self, headers: &mut hyper::HeaderMap) {
+        use hyper::header::{HeaderName, HeaderValue};
+
+        for name in &self.remove {
+            headers.remove(name.as_str());
+        }
+        for (name, value) in &self.set {
+            if let (Ok(name), Ok(value)) =
+                (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value))
+            {
+                headers.insert(name, value);
+            }
+        }
+        for (name, value) in &self.add {
+            if let (Ok(name), Ok(value)) =
+                (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value))
+            {
+                headers.append(name, value);
+            }
+        }
+    }
+}
+
+/// How a request picks one of the `upstreams`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Balance {
+    /// Each upstream in turn
+    #[default]
+    RoundRobin,
+    /// The upstream with the fewest requests in flight
+    LeastConnections,
+}
+
+impl Balance {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::RoundRobin => "round_robin",
+            Self::LeastConnections => "least_connections",
+        }
+    }
+}
+
+impl FromStr for Balance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round_robin" => Ok(Self::RoundRobin),
+            "least_connections" => Ok(Self::LeastConnections),
+            _ => Err(format!("unknown balance strategy: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct SplitTarget {
+    pub weight: u32,
+    pub redirect_to: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct LanguageTarget {
+    /// Language tags like `pt-BR`, or `pt` for every variant
+    pub languages: Vec<String>,
+    pub redirect_to: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct DeviceTarget {
+    pub devices: Vec<Device>,
+    pub redirect_to: String,
+}
+
+impl DeviceTarget {
+    pub fn matches(&self, devices: &[Device]) -> bool {
+        self.devices.iter().any(|device| devices.contains(device))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct GeoTarget {
+    /// ISO 3166-1 alpha-2 codes, `EU` standing for every member state
+    pub countries: Vec<String>,
+    pub redirect_to: String,
+}
+
+impl GeoTarget {
+    pub fn matches(&self, location: &Location) -> bool {
+        self.countries
+            .iter()
+            .any(|code| *code == location.country || (code == "EU" && location.in_eu))
+    }
+}
+
+/// Statuses a route may redirect with, 301/308 for permanent moves and
+/// 302/307 for temporary ones.
+pub const REDIRECT_STATUSES: [u16; 4] = [301, 302, 307, 308];
+
+pub fn default_status_code() -> u16 {
+    308
+}
+
+impl Route {
+    /// An exact, host-agnostic route with every option left at its default.
+    pub fn new(slug: String, redirect_to: String) -> Self {
+        Self {
+            host: None,
+            slug,
+            redirect_to,
+            mode: RouteMode::Redirect,
+            proxy: ProxyOptions::default(),
+            match_type: MatchType::Exact,
+            preserve_query: false,
+            preserve_path: false,
+            status_code: default_status_code(),
+            expires_at: None,
+            max_hits: None,
+            hits: 0,
+            geo_targets: Vec::new(),
+            device_targets: Vec::new(),
+            language_targets: Vec::new(),
+            split_targets: Vec::new(),
+            sticky_split: false,
+            utm: BTreeMap::new(),
+            response_headers: BTreeMap::new(),
+            preview: false,
+            password_hash: None,
+            signed: false,
+            deleted_at: None,
+            tenant: None,
+            owner: None,
+        }
+    }
+
+    /// `host/slug` for host-scoped routes, the bare slug otherwise.
+    pub fn label(&self) -> String {
+        match &self.host {
+            Some(host) => format!("{}/{}", host, self.slug),
+            None => self.slug.clone(),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= crate::auth::now())
+    }
+
+    /// Country codes have to be two uppercase letters.
+    pub fn check_geo_targets(&self) -> Result<(), String> {
+        let invalid = self
+            .geo_targets
+            .iter()
+            .flat_map(|target| &target.countries)
+            .find(|code| code.len() != 2 || !code.bytes().all(|b| b.is_ascii_uppercase()));
+
+        match invalid {
+            Some(code) => Err(format!("{:?} is not an ISO 3166-1 alpha-2 country code", code)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn check_split_targets(&self) -> Result<(), String> {
+        let total: u64 = self
+            .split_targets
+            .iter()
+            .map(|target| u64::from(target.weight))
+            .sum();
+        if self.split_targets.is_empty() || total > 0 {
+            Ok(())
+        } else {
+            Err("split target weights add up to 0".into())
+        }
+    }
+
+    pub fn check_utm(&self) -> Result<(), String> {
+        match self.utm.keys().find(|name| !name.starts_with("utm_")) {
+            Some(name) => Err(format!("{} is not a UTM parameter", name)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn check_response_headers(&self) -> Result<(), String> {
+        response_headers::check(&self.response_headers)
+    }
+
+    pub fn check_status(&self) -> Result<(), String> {
+        if REDIRECT_STATUSES.contains(&self.status_code) {
+            Ok(())
+        } else {
+            Err(format!(
+                "status {} is not one of 301, 302, 307 or 308",
+                self.status_code
+            ))
+        }
+    }
+
+    /// Proxied targets and upstreams have to be HTTP URLs, and there's no
+    /// target page to preview.
+    pub fn check_mode(&self) -> Result<(), String> {
+        if self.mode != RouteMode::Proxy {
+            return match self.proxy.is_default() {
+                true => Ok(()),
+                false => Err("proxy options need the proxy mode".into()),
+            };
+        }
+        if self.preview {
+            return Err("proxy routes can't have a preview".into());
+        }
+
+        let targets = std::iter::once(&self.redirect_to)
+            .chain(self.geo_targets.iter().map(|target| &target.redirect_to))
+            .chain(self.device_targets.iter().map(|target| &target.redirect_to))
+            .chain(self.language_targets.iter().map(|target| &target.redirect_to))
+            .chain(self.split_targets.iter().map(|target| &target.redirect_to));
+        for target in targets {
+            if !target.starts_with("http://") && !target.starts_with("https://") {
+                return Err(format!("proxy target {} is not an http or https URL", target));
+            }
+        }
+        let retries = &self.proxy.retries;
+        if retries.max_backoff < retries.backoff
+            || !retries.budget.is_finite()
+            || retries.budget < 0.0
+        {
+            return Err("retries need a max_backoff of at least backoff, and a budget of 0 or \
+                        more"
+                .into());
+        }
+        let breaker = &self.proxy.circuit_breaker;
+        if !(0.0..=1.0).contains(&breaker.error_rate)
+            || breaker.window == 0
+            || breaker.cooldown == 0
+        {
+            return Err("the circuit breaker needs an error_rate between 0 and 1, and a window and \
+                        cooldown of at least a second"
+                .into());
+        }
+        if breaker.fallback.is_some() && !breaker.is_enabled() {
+            return Err("a circuit breaker fallback needs an error_rate".into());
+        }
+        if self.proxy.sticky_sessions && self.proxy.upstreams.is_empty() {
+            return Err("sticky sessions need upstreams".into());
+        }
+        if let Some(canary) = &self.proxy.canary {
+            if !(0.0..=100.0).contains(&canary.percent) {
+                return Err("the canary needs a percent between 0 and 100".into());
+            }
+            if hyper::header::HeaderName::from_bytes(canary.header.as_bytes()).is_err() {
+                return Err(format!("invalid header name: {}", canary.header));
+            }
+        }
+        let mirror = self.proxy.mirror.as_ref();
+        if mirror.is_some_and(|mirror| !(0.0..=100.0).contains(&mirror.percent)) {
+            return Err("the mirror needs a percent between 0 and 100".into());
+        }
+        self.proxy.request_headers.check()?;
+        if let Some(rewrite) = &self.proxy.rewrite {
+            regex::Regex::new(&rewrite.pattern)
+                .map_err(|err| format!("invalid rewrite pattern: {}", err))?;
+        }
+        let mirror = mirror.map(|mirror| &mirror.upstream);
+        for upstream in self.proxy.all_upstreams().chain(&breaker.fallback).chain(mirror) {
+            let valid = upstream
+                .parse::<hyper::Uri>()
+                .is_ok_and(|uri| {
+                    matches!(uri.scheme_str(), Some("http" | "https")) && uri.query().is_none()
+                });
+            if !valid {
+                return Err(format!("upstream {} is not an http or https base URL", upstream));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchType {
+    #[default]
+    Exact,
+    /// `u/:name` or `docs/*rest`, the captures fill `{name}` and `{rest}`
+    /// in `redirect_to`
+    Pattern,
+    /// A regex over the whole path, tried after the patterns. Groups fill
+    /// `$1` or `$name` in `redirect_to`
+    Regex,
+}
+
+impl MatchType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Exact => "exact",
+            Self::Pattern => "pattern",
+            Self::Regex => "regex",
+        }
+    }
+}
+
+impl FromStr for MatchType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(Self::Exact),
+            "pattern" => Ok(Self::Pattern),
+            "regex" => Ok(Self::Regex),
+            _ => Err(format!("unknown match type: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteMode {
+    #[default]
+    Redirect,
+    /// Fetch the target and stream its response back, the client keeping
+    /// the route's URL
+    Proxy,
+}
+
+impl RouteMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Redirect => "redirect",
+            Self::Proxy => "proxy",
+        }
+    }
+}
+
+impl FromStr for RouteMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "redirect" => Ok(Self::Redirect),
+            "proxy" => Ok(Self::Proxy),
+            _ => Err(format!("unknown route mode: {}", s)),
+        }
+    }
+}
+
+/// Persistence used by the handlers and the CLI. Every backend stores the
+/// same `Route` records, keyed by host and slug. A `None` host is the route
+/// answering for every host.
+#[async_trait]
+pub trait RouteStore: Send + Sync {
+    /// Brings the schema up to date. Schemaless backends have nothing to do.
+    async fn migrate(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Deleted routes are never returned, the same goes for every lookup
+    /// below unless told otherwise.
+    async fn get(&self, host: Option<&str>, slug: &str) -> Result<Option<Route>, StoreError>;
+
+    /// Returns `false` when a route with the same host and slug already
+    /// exists. A deleted one is replaced.
+    async fn insert(&self, route: &Route) -> Result<bool, StoreError>;
+
+    /// Returns `false` when there is no route to update.
+    async fn update(&self, route: &Route) -> Result<bool, StoreError>;
+
+    /// Marks a route deleted. Returns `false` when there is no route to
+    /// delete.
+    async fn delete(&self, host: Option<&str>, slug: &str) -> Result<bool, StoreError>;
+
+    /// Undoes `delete`. Returns `false` when there is no deleted route.
+    async fn restore(&self, host: Option<&str>, slug: &str) -> Result<bool, StoreError>;
+
+    /// Drops the routes deleted before `before` for good, returning how many.
+    async fn purge(&self, before: i64) -> Result<u64, StoreError>;
+
+    /// All routes, host-agnostic ones first, then by host and slug.
+    async fn list(&self) -> Result<Vec<Route>, StoreError>;
+
+    /// Deleted routes, in the same order as `list`.
+    async fn list_deleted(&self) -> Result<Vec<Route>, StoreError>;
+
+    /// Counts a redirect through a route with `max_hits`. Returns `false`,
+    /// without counting, once the route has used up its hits.
+    async fn record_hit(&self, host: Option<&str>, slug: &str) -> Result<bool, StoreError>;
+
+    /// Routes that aren't matched by exact slug, or also match paths below
+    /// it through `preserve_path`.
+    async fn list_patterns(&self) -> Result<Vec<Route>, StoreError> {
+        let mut routes = self.list().await?;
+        routes.retain(|route| route.match_type != MatchType::Exact || route.preserve_path);
+
+        Ok(routes)
+    }
+
+    /// Cheap round trip used by the readiness probe.
+    async fn ping(&self) -> Result<(), StoreError>;
+
+    /// Connection pool usage, for backends that pool connections.
+    fn pool_stats(&self) -> Option<PoolStats> {
+        None
+    }
+}
+
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: usize,
+}
+
+/// An admin API key. Only the SHA-256 hash of the token is ever stored.
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub hash: String,
+    /// Unix timestamp, in seconds
+    pub created_at: i64,
+    /// The tenant the key acts for, keys without one act across tenants
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// The user the key acts as, keys without one act as admins
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+#[async_trait]
+pub trait KeyStore: Send + Sync {
+    async fn insert_key(&self, key: &ApiKey) -> Result<(), StoreError>;
+
+    async fn find_key(&self, hash: &str) -> Result<Option<ApiKey>, StoreError>;
+
+    /// Returns `false` when there is no key with this id.
+    async fn delete_key(&self, id: &str) -> Result<bool, StoreError>;
+
+    /// All keys, oldest first.
+    async fn list_keys(&self) -> Result<Vec<ApiKey>, StoreError>;
+}
+
+/// One redirect served, recorded for click statistics.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Hit {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    pub slug: String,
+    /// Unix timestamp, in seconds
+    pub at: i64,
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
+    /// Salted SHA-256 of the client address
+    pub ip_hash: Option<String>,
+    /// Set when a GeoIP database is configured
+    #[serde(default)]
+    pub country: Option<String>,
+    /// Index of the split target served
+    #[serde(default)]
+    pub variant: Option<u32>,
+}
+
+/// Which hits of a route to aggregate.
+pub struct StatsQuery {
+    /// Unix timestamps, `from` inclusive and `to` exclusive
+    pub from: i64,
+    pub to: i64,
+    /// Width of the time buckets, in seconds
+    pub bucket: i64,
+    /// Entries kept in the top referrer, user agent and country lists
+    pub top: u32,
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct HitStats {
+    pub total: i64,
+    /// Only buckets with hits, oldest first
+    pub buckets: Vec<Bucket>,
+    pub referrers: Vec<Count>,
+    pub user_agents: Vec<Count>,
+    pub countries: Vec<Count>,
+    /// Hits per split target index
+    pub variants: Vec<Count>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Bucket {
+    /// Unix timestamp the bucket starts at
+    pub start: i64,
+    pub hits: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Count {
+    pub value: String,
+    pub hits: i64,
+}
+
+#[async_trait]
+pub trait HitStore: Send + Sync {
+    async fn insert_hits(&self, hits: &[Hit]) -> Result<(), StoreError>;
+
+    async fn hit_stats(
+        &self,
+        host: Option<&str>,
+        slug: &str,
+        query: &StatsQuery,
+    ) -> Result<HitStats, StoreError>;
+}
+
+/// One change to a route, kept in its history.
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct Revision {
+    /// Assigned by the store, increasing across all routes
+    pub id: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    pub slug: String,
+    /// Unix timestamp, in seconds
+    pub at: i64,
+    /// Subject of the API key or JWT, `cli` for the command line
+    pub author: String,
+    pub action: RevisionAction,
+    pub old_target: Option<String>,
+    pub new_target: Option<String>,
+    /// The route as the change left it, what rolling back to this revision
+    /// restores. Unset for deletions
+    pub route: Option<Route>,
+}
+
+impl Revision {
+    /// The change from `old` to `new`, one of which has to be set.
+    pub fn new(
+        author: &str,
+        action: RevisionAction,
+        old: Option<&Route>,
+        new: Option<&Route>,
+    ) -> Self {
+        let route = new.or(old).expect("revisions have a route before or after");
+
+        Self {
+            id: 0,
+            host: route.host.clone(),
+            slug: route.slug.clone(),
+            at: crate::auth::now(),
+            author: author.into(),
+            action,
+            old_target: old.map(|route| route.redirect_to.clone()),
+            new_target: new.map(|route| route.redirect_to.clone()),
+            // counters aren't part of the route's settings
+            route: new.map(|route| Route {
+                hits: 0,
+                ..route.clone()
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RevisionAction {
+    Create,
+    Update,
+    Delete,
+    Restore,
+    Rollback,
+}
+
+impl RevisionAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Create => "create",
+            Self::Update => "update",
+            Self::Delete => "delete",
+            Self::Restore => "restore",
+            Self::Rollback => "rollback",
+        }
+    }
+}
+
+impl FromStr for RevisionAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "create" => Ok(Self::Create),
+            "update" => Ok(Self::Update),
+            "delete" => Ok(Self::Delete),
+            "restore" => Ok(Self::Restore),
+            "rollback" => Ok(Self::Rollback),
+            _ => Err(format!("unknown revision action: {}", s)),
+        }
+    }
+}
+
+#[async_trait]
+pub trait RevisionStore: Send + Sync {
+    /// Stores `revision` under a new id, which is returned.
+    async fn insert_revision(&self, revision: &Revision) -> Result<i64, StoreError>;
+
+    /// The history of a route, oldest first. Outlives the route itself.
+    async fn list_revisions(
+        &self,
+        host: Option<&str>,
+        slug: &str,
+    ) -> Result<Vec<Revision>, StoreError>;
+
+    async fn get_revision(
+        &self,
+        host: Option<&str>,
+        slug: &str,
+        id: i64,
+    ) -> Result<Option<Revision>, StoreError> {
+        let revisions = self.list_revisions(host, slug).await?;
+
+        Ok(revisions.into_iter().find(|revision| revision.id == id))
+    }
+}
+
+/// An admin action, kept in the append-only audit log.
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct AuditEntry {
+    /// Assigned by the store, increasing
+    pub id: i64,
+    /// Unix timestamp, in seconds
+    pub at: i64,
+    /// Subject of the API key or JWT, `cli` for the command line. Unset for
+    /// failed authentications
+    pub actor: Option<String>,
+    /// Client address, unset for the command line
+    pub ip: Option<String>,
+    /// `route.create`, `key.revoke`, `auth.failure`...
+    pub action: String,
+    /// Label of the route or id of the key acted on
+    pub target: Option<String>,
+    /// Changed fields, `{"field": {"old": ..., "new": ...}}`
+    pub diff: Option<serde_json::Value>,
+}
+
+/// Which audit entries to list, every filter optional.
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    /// Unix timestamps, `from` inclusive and `to` exclusive
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub limit: u32,
+}
+
+impl AuditQuery {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        let is = |filter: &Option<String>, value: Option<&str>| {
+            filter.as_deref().is_none_or(|filter| value == Some(filter))
+        };
+
+        is(&self.actor, entry.actor.as_deref())
+            && is(&self.action, Some(&entry.action))
+            && is(&self.target, entry.target.as_deref())
+            && self.from.is_none_or(|from| entry.at >= from)
+            && self.to.is_none_or(|to| entry.at < to)
+    }
+}
+
+#[async_trait]
+pub trait AuditStore: Send + Sync {
+    /// Appends `entry` under a new id. Entries are never changed or removed.
+    async fn insert_audit(&self, entry: &AuditEntry) -> Result<(), StoreError>;
+
+    /// The newest entries matching `query`, newest first.
+    async fn list_audit(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, StoreError>;
+}
+
+/// Counters of what API callers did per time window, for quotas.
+#[async_trait]
+pub trait UsageStore: Send + Sync {
+    /// Adds `by` (possibly negative) to what `subject` used of `kind` in the
+    /// window starting at `window`, returning the new total.
+    async fn add_usage(
+        &self,
+        subject: &str,
+        kind: &str,
+        window: i64,
+        by: i64,
+    ) -> Result<i64, StoreError>;
+
+    /// Everything `subject` used in the window starting at `window`, by kind.
+    async fn usage(&self, subject: &str, window: i64)
+        -> Result<BTreeMap<String, i64>, StoreError>;
+}
+
+/// A namespace of routes and API keys, kept apart from the others.
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct Tenant {
+    /// Lowercase letters, digits and `-`
+    pub id: String,
+    pub name: String,
+    /// Unix timestamp, in seconds
+    pub created_at: i64,
+}
+
+#[async_trait]
+pub trait TenantStore: Send + Sync {
+    /// Returns `false` when a tenant with the same id already exists.
+    async fn insert_tenant(&self, tenant: &Tenant) -> Result<bool, StoreError>;
+
+    /// All tenants, by id.
+    async fn list_tenants(&self) -> Result<Vec<Tenant>, StoreError>;
+
+    async fn get_tenant(&self, id: &str) -> Result<Option<Tenant>, StoreError> {
+        let tenants = self.list_tenants().await?;
+
+        Ok(tenants.into_iter().find(|tenant| tenant.id == id))
+    }
+
+    /// Returns `false` when there is no tenant with this id.
+    async fn delete_tenant(&self, id: &str) -> Result<bool, StoreError>;
+}
+
+/// Someone API keys and tokens act as, owning the routes they create.
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct User {
+    /// Username or email address
+    pub id: String,
+    pub name: String,
+    /// Admins may change every route, users only their own
+    pub admin: bool,
+    /// Unix timestamp, in seconds
+    pub created_at: i64,
+}
+
+#[async_trait]
+pub trait UserStore: Send + Sync {
+    /// Returns `false` when a user with the same id already exists.
+    async fn insert_user(&self, user: &User) -> Result<bool, StoreError>;
+
+    /// All users, by id.
+    async fn list_users(&self) -> Result<Vec<User>, StoreError>;
+
+    async fn get_user(&self, id: &str) -> Result<Option<User>, StoreError>;
+
+    /// Replaces the name and role of `user`, returning `false` when there
+    /// is no user with its id.
+    async fn update_user(&self, user: &User) -> Result<bool, StoreError>;
+
+    /// Returns `false` when there is no user with this id.
+    async fn delete_user(&self, id: &str) -> Result<bool, StoreError>;
+}
+
+/// Planned downtime, the routes it covers answering 503 meanwhile.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub struct Maintenance {
+    /// Every route is down, else only those in `routes`
+    pub all: bool,
+    /// Labels of the routes down, like `docs` or `example.com/docs`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<String>,
+    /// Seconds clients are told to come back after, those of the
+    /// `[maintenance]` config when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+}
+
+impl Maintenance {
+    pub fn is_on(&self) -> bool {
+        self.all || !self.routes.is_empty()
+    }
+
+    /// Whether `route` is down, or any route at all when `None`.
+    pub fn covers(&self, route: Option<&Route>) -> bool {
+        self.all || route.is_some_and(|route| self.routes.contains(&route.label()))
+    }
+}
+
+#[async_trait]
+pub trait MaintenanceStore: Send + Sync {
+    /// The maintenance in effect, off when none was ever set.
+    async fn maintenance(&self) -> Result<Maintenance, StoreError>;
+
+    async fn set_maintenance(&self, maintenance: &Maintenance) -> Result<(), StoreError>;
+}
+
+/// Everything a storage backend has to provide.
+pub trait Backend:
+    RouteStore
+    + KeyStore
+    + HitStore
+    + RevisionStore
+    + AuditStore
+    + UsageStore
+    + TenantStore
+    + UserStore
+    + MaintenanceStore
+{
+}
+
+impl<T> Backend for T where
+    T: RouteStore
+        + KeyStore
+        + HitStore
+        + RevisionStore
+        + AuditStore
+        + UsageStore
+        + TenantStore
+        + UserStore
+        + MaintenanceStore
+{
+}
+
+pub type Store = Arc<dyn Backend>;
+
+/// Picks the backend from the scheme of `url`.
+pub async fn connect(url: &str, config: &DatabaseConfig) -> Result<Store, StoreError> {
+    if url.starts_with("redis://") || url.starts_with("rediss://") {
+        Ok(Arc::new(RedisStore::connect(url).await?))
+    } else if url.starts_with("sqlite:") {
+        Ok(Arc::new(SqliteStore::connect(url, config).await?))
+    } else if url.starts_with("mysql://") || url.starts_with("mariadb://") {
+        Ok(Arc::new(MySqlStore::connect(url, config).await?))
+    } else {
+        Err(StoreError::UnsupportedUrl(url.into()))
+    }
+}
+
+/// Applies pending migrations, then warns about routes left unmanageable by
+/// slug rules that came after them.
+pub async fn migrate(store: &Store) -> Result<(), StoreError> {
+    store.migrate().await?;
+    slug::warn_reserved_suffixes(store).await
+}
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("Redis error: {0}")]
+    Redis(#[from] ::redis::RedisError),
+
+    #[error("SQL error: {0}")]
+    Sql(#[from] sqlx::Error),
+
+    #[error("Migration error: {0}")]
+    Migrate(#[from] sqlx::migrate::MigrateError),
+
+    #[error("Unsupported database URL: {0}")]
+    UnsupportedUrl(String),
+
+    #[error("Storage unavailable, next attempt in {0}s")]
+    Unavailable(u64),
+}
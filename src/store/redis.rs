@@ -0,0 +1,721 @@
+use std::collections::{BTreeMap, HashMap};
+
+use async_trait::async_trait;
+use redis::{
+    aio::{ConnectionLike, MultiplexedConnection},
+    AsyncCommands, Cmd, Pipeline, RedisFuture, RedisResult, Script, Value,
+};
+use tokio::sync::Mutex;
+
+use crate::auth;
+
+use super::{
+    ApiKey, AuditEntry, AuditQuery, AuditStore, Bucket, Count, Hit, HitStats, HitStore, KeyStore,
+    Maintenance, MaintenanceStore, Revision, RevisionStore, Route, RouteStore, StatsQuery,
+    StoreError, Tenant, TenantStore, UsageStore, User, UserStore,
+};
+
+const ROUTES_KEY: &str = "routes";
+const HITS_KEY: &str = "route_hits";
+const HITS_LIST_PREFIX: &str = "hits:";
+const API_KEYS_KEY: &str = "api_keys";
+const REVISIONS_PREFIX: &str = "revisions:";
+const REVISION_IDS_KEY: &str = "revision_ids";
+const AUDIT_KEY: &str = "audit_log";
+const AUDIT_IDS_KEY: &str = "audit_ids";
+const USAGE_PREFIX: &str = "usage:";
+const TENANTS_KEY: &str = "tenants";
+const USERS_KEY: &str = "users";
+const MAINTENANCE_KEY: &str = "maintenance";
+
+/// Replaces the record of route field `ARGV[1]` with `ARGV[3]`, or removes
+/// it when empty, only while it still holds `ARGV[2]`, empty for a missing
+/// one. Its hit counter goes along when `ARGV[4]` is set.
+const SWAP_ROUTE: &str = r"
+local current = redis.call('HGET', KEYS[1], ARGV[1])
+if (current or '') ~= ARGV[2] then
+    return 0
+end
+if ARGV[3] == '' then
+    redis.call('HDEL', KEYS[1], ARGV[1])
+else
+    redis.call('HSET', KEYS[1], ARGV[1], ARGV[3])
+end
+if ARGV[4] == '1' then
+    redis.call('HDEL', KEYS[2], ARGV[1])
+end
+return 1
+";
+
+/// Routes kept in the `routes` hash, slug -> JSON record, and API keys in
+/// the `api_keys` hash, token hash -> JSON record. Routes written before
+/// records were JSON hold the bare target and are read as exact routes, and
+/// those from before the hash, plain `SET slug target` keys, are moved into
+/// it by `migrate`.
+/// Host-scoped routes are stored under `@{host}/{slug}`. Hit counters live
+/// in the `route_hits` hash under the same fields so `HINCRBY` keeps them
+/// atomic, and recorded hits are appended to a `hits:{field}` list per
+/// route. Revisions are appended to a `revisions:{field}` list, their ids
+/// drawn from the `revision_ids` counter. The audit log is the `audit_log`
+/// list, ids from `audit_ids`. Quota usage is counted in a
+/// `usage:{subject}:{window}` hash per caller and window, by kind. Tenants
+/// and users are kept in the `tenants` and `users` hashes, id -> JSON record.
+/// The maintenance in effect is the JSON record at `maintenance`.
+/// Route records are checked and written in one step by the `SWAP_ROUTE`
+/// script, retried when another write got in between.
+pub struct RedisStore {
+    con: RedisConnection,
+    swap_route: Script,
+}
+
+/// A multiplexed connection, shared by concurrent calls without waiting on
+/// each other. An I/O error drops it, the next call opening it again since
+/// it doesn't come back on its own.
+pub struct RedisConnection {
+    client: redis::Client,
+    con: Mutex<Option<MultiplexedConnection>>,
+}
+
+/// The connection as handed to a call, dropping the shared one on I/O
+/// errors.
+pub struct Con<'a> {
+    con: MultiplexedConnection,
+    shared: &'a RedisConnection,
+}
+
+impl RedisConnection {
+    pub async fn open(url: &str) -> RedisResult<Self> {
+        let client = redis::Client::open(url)?;
+
+        Ok(Self {
+            con: Mutex::new(Some(client.get_multiplexed_tokio_connection().await?)),
+            client,
+        })
+    }
+
+    pub async fn get(&self) -> RedisResult<Con<'_>> {
+        let mut con = self.con.lock().await;
+        let con = match &*con {
+            Some(con) => con.clone(),
+            None => con.insert(self.client.get_multiplexed_tokio_connection().await?).clone(),
+        };
+
+        Ok(Con { con, shared: self })
+    }
+}
+
+impl Con<'_> {
+    async fn checked<T>(&self, result: RedisResult<T>) -> RedisResult<T> {
+        if result.as_ref().is_err_and(|err| err.is_io_error()) {
+            *self.shared.con.lock().await = None;
+        }
+
+        result
+    }
+}
+
+impl ConnectionLike for Con<'_> {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        Box::pin(async move {
+            let result = self.con.req_packed_command(cmd).await;
+            self.checked(result).await
+        })
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        pipeline: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        Box::pin(async move {
+            let result = self.con.req_packed_commands(pipeline, offset, count).await;
+            self.checked(result).await
+        })
+    }
+
+    fn get_db(&self) -> i64 {
+        self.con.get_db()
+    }
+}
+
+impl RedisStore {
+    pub async fn connect(url: &str) -> Result<Self, StoreError> {
+        Ok(Self {
+            con: RedisConnection::open(url).await?,
+            swap_route: Script::new(SWAP_ROUTE),
+        })
+    }
+
+    async fn con(&self) -> Result<Con<'_>, StoreError> {
+        Ok(self.con.get().await?)
+    }
+
+    /// Every route, deleted ones included.
+    async fn list_all(&self) -> Result<Vec<Route>, StoreError> {
+        let mut con = self.con().await?;
+        let entries: Vec<(String, String)> = con.hgetall(ROUTES_KEY).await?;
+        let hits: HashMap<String, i64> = con.hgetall(HITS_KEY).await?;
+
+        let mut routes: Vec<Route> = entries
+            .into_iter()
+            .map(|(field, raw)| Route {
+                hits: hits.get(&field).copied().unwrap_or_default(),
+                ..decode_route(field, raw)
+            })
+            .collect();
+        routes.sort_by(|a, b| (&a.host, &a.slug).cmp(&(&b.host, &b.slug)));
+
+        Ok(routes)
+    }
+
+    /// Deletes (`Some`) or restores (`None`) a route, returning `false` when
+    /// it's missing or already in that state.
+    async fn set_deleted_at(
+        &self,
+        host: Option<&str>,
+        slug: &str,
+        deleted_at: Option<i64>,
+    ) -> Result<bool, StoreError> {
+        let field = route_field(host, slug);
+        let mut con = self.con().await?;
+        loop {
+            let raw: Option<String> = con.hget(ROUTES_KEY, &field).await?;
+            let Some(current) = raw else {
+                return Ok(false);
+            };
+            let mut route = decode_route(slug.into(), current.clone());
+            if route.deleted_at.is_some() == deleted_at.is_some() {
+                return Ok(false);
+            }
+
+            route.deleted_at = deleted_at;
+            let new = encode_route(&route);
+            if self.swap(&mut con, &field, Some(&current), Some(&new), false).await? {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Writes `new` as the record of `field`, or removes it when `None`, if
+    /// it still is `current`, returning `false` when it changed meanwhile.
+    async fn swap(
+        &self,
+        con: &mut Con<'_>,
+        field: &str,
+        current: Option<&str>,
+        new: Option<&str>,
+        reset_hits: bool,
+    ) -> Result<bool, StoreError> {
+        let swapped: bool = self
+            .swap_route
+            .key(ROUTES_KEY)
+            .key(HITS_KEY)
+            .arg(field)
+            .arg(current.unwrap_or_default())
+            .arg(new.unwrap_or_default())
+            .arg(if reset_hits { "1" } else { "0" })
+            .invoke_async(con)
+            .await?;
+
+        Ok(swapped)
+    }
+}
+
+fn route_field(host: Option<&str>, slug: &str) -> String {
+    match host {
+        Some(host) => format!("@{}/{}", host, slug),
+        None => slug.into(),
+    }
+}
+
+fn decode_route(slug: String, raw: String) -> Route {
+    serde_json::from_str(&raw).unwrap_or_else(|_| Route::new(slug, raw))
+}
+
+fn encode_route(route: &Route) -> String {
+    serde_json::to_string(route).expect("routes serialize to JSON")
+}
+
+#[async_trait]
+impl RouteStore for RedisStore {
+    /// Moves the routes of plain string keys into the hash, as bare targets.
+    /// Other string keys, told apart by not holding an HTTP URL, are left
+    /// alone, and so are routes already in the hash.
+    async fn migrate(&self) -> Result<(), StoreError> {
+        let mut con = self.con().await?;
+        let mut keys: Vec<String> = Vec::new();
+        let mut scan = redis::cmd("SCAN")
+            .cursor_arg(0)
+            .arg("TYPE")
+            .arg("string")
+            .clone()
+            .iter_async(&mut con)
+            .await?;
+        while let Some(key) = scan.next_item().await {
+            keys.push(key);
+        }
+        drop(scan);
+
+        for key in keys {
+            if [MAINTENANCE_KEY, REVISION_IDS_KEY, AUDIT_IDS_KEY].contains(&key.as_str()) {
+                continue;
+            }
+            let Some(target) = con.get::<_, Option<String>>(&key).await? else {
+                continue;
+            };
+            if !target.starts_with("http://") && !target.starts_with("https://") {
+                continue;
+            }
+
+            let slug = key.trim_start_matches('/').to_owned();
+            con.hset_nx::<_, _, _, ()>(ROUTES_KEY, &slug, target).await?;
+            con.del::<_, ()>(&key).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn get(&self, host: Option<&str>, slug: &str) -> Result<Option<Route>, StoreError> {
+        let field = route_field(host, slug);
+        let mut con = self.con().await?;
+        let val: Option<String> = con.hget(ROUTES_KEY, &field).await?;
+        let hits: Option<i64> = con.hget(HITS_KEY, &field).await?;
+
+        Ok(val
+            .map(|raw| Route {
+                hits: hits.unwrap_or_default(),
+                ..decode_route(slug.into(), raw)
+            })
+            .filter(|route| route.deleted_at.is_none()))
+    }
+
+    async fn insert(&self, route: &Route) -> Result<bool, StoreError> {
+        let field = route_field(route.host.as_deref(), &route.slug);
+        let new = encode_route(route);
+        let mut con = self.con().await?;
+        loop {
+            let current: Option<String> = con.hget(ROUTES_KEY, &field).await?;
+            let live = current
+                .clone()
+                .map(|raw| decode_route(route.slug.clone(), raw))
+                .is_some_and(|existing| existing.deleted_at.is_none());
+            if live {
+                return Ok(false);
+            }
+
+            // a deleted route is replaced along with its counter
+            let reset_hits = current.is_some();
+            if self
+                .swap(&mut con, &field, current.as_deref(), Some(&new), reset_hits)
+                .await?
+            {
+                return Ok(true);
+            }
+        }
+    }
+
+    async fn update(&self, route: &Route) -> Result<bool, StoreError> {
+        let field = route_field(route.host.as_deref(), &route.slug);
+        let new = encode_route(route);
+        let mut con = self.con().await?;
+        loop {
+            let current: Option<String> = con.hget(ROUTES_KEY, &field).await?;
+            let live = current
+                .clone()
+                .map(|raw| decode_route(route.slug.clone(), raw))
+                .is_some_and(|existing| existing.deleted_at.is_none());
+            if !live {
+                return Ok(false);
+            }
+
+            if self
+                .swap(&mut con, &field, current.as_deref(), Some(&new), false)
+                .await?
+            {
+                return Ok(true);
+            }
+        }
+    }
+
+    async fn delete(&self, host: Option<&str>, slug: &str) -> Result<bool, StoreError> {
+        self.set_deleted_at(host, slug, Some(auth::now())).await
+    }
+
+    async fn restore(&self, host: Option<&str>, slug: &str) -> Result<bool, StoreError> {
+        self.set_deleted_at(host, slug, None).await
+    }
+
+    async fn purge(&self, before: i64) -> Result<u64, StoreError> {
+        let mut con = self.con().await?;
+        let entries: Vec<(String, String)> = con.hgetall(ROUTES_KEY).await?;
+        let mut purged = 0;
+        for (field, raw) in entries {
+            let route = decode_route(field.clone(), raw.clone());
+            // skipped when restored or written again since listed
+            if route.deleted_at.is_some_and(|at| at < before)
+                && self.swap(&mut con, &field, Some(&raw), None, true).await?
+            {
+                purged += 1;
+            }
+        }
+
+        Ok(purged)
+    }
+
+    async fn list(&self) -> Result<Vec<Route>, StoreError> {
+        let mut routes = self.list_all().await?;
+        routes.retain(|route| route.deleted_at.is_none());
+
+        Ok(routes)
+    }
+
+    async fn list_deleted(&self) -> Result<Vec<Route>, StoreError> {
+        let mut routes = self.list_all().await?;
+        routes.retain(|route| route.deleted_at.is_some());
+
+        Ok(routes)
+    }
+
+    async fn record_hit(&self, host: Option<&str>, slug: &str) -> Result<bool, StoreError> {
+        let Some(max_hits) = self.get(host, slug).await?.and_then(|route| route.max_hits) else {
+            return Ok(true);
+        };
+
+        let field = route_field(host, slug);
+        let mut con = self.con().await?;
+        let hits: i64 = con.hincr(HITS_KEY, &field, 1).await?;
+        if hits > max_hits {
+            // give back the hit so the counter stays at `max_hits`
+            con.hincr::<_, _, _, ()>(HITS_KEY, &field, -1).await?;
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        redis::cmd("PING").query_async::<_, ()>(&mut self.con().await?).await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl KeyStore for RedisStore {
+    async fn insert_key(&self, key: &ApiKey) -> Result<(), StoreError> {
+        let raw = serde_json::to_string(key).expect("API keys serialize to JSON");
+        self.con()
+            .await?
+            .hset::<_, _, _, ()>(API_KEYS_KEY, &key.hash, raw)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn find_key(&self, hash: &str) -> Result<Option<ApiKey>, StoreError> {
+        let raw: Option<String> = self.con().await?.hget(API_KEYS_KEY, hash).await?;
+
+        Ok(raw.and_then(|raw| serde_json::from_str(&raw).ok()))
+    }
+
+    async fn delete_key(&self, id: &str) -> Result<bool, StoreError> {
+        let Some(key) = self.list_keys().await?.into_iter().find(|key| key.id == id) else {
+            return Ok(false);
+        };
+
+        let removed: usize = self.con().await?.hdel(API_KEYS_KEY, &key.hash).await?;
+        Ok(removed > 0)
+    }
+
+    async fn list_keys(&self) -> Result<Vec<ApiKey>, StoreError> {
+        let raw: Vec<String> = self.con().await?.hvals(API_KEYS_KEY).await?;
+
+        let mut keys: Vec<ApiKey> = raw
+            .iter()
+            .filter_map(|raw| serde_json::from_str(raw).ok())
+            .collect();
+        keys.sort_by_key(|key| key.created_at);
+
+        Ok(keys)
+    }
+}
+
+#[async_trait]
+impl TenantStore for RedisStore {
+    async fn insert_tenant(&self, tenant: &Tenant) -> Result<bool, StoreError> {
+        let raw = serde_json::to_string(tenant).expect("tenants serialize to JSON");
+        let inserted: bool = self
+            .con()
+            .await?
+            .hset_nx(TENANTS_KEY, &tenant.id, raw)
+            .await?;
+
+        Ok(inserted)
+    }
+
+    async fn list_tenants(&self) -> Result<Vec<Tenant>, StoreError> {
+        let raw: Vec<String> = self.con().await?.hvals(TENANTS_KEY).await?;
+
+        let mut tenants: Vec<Tenant> = raw
+            .iter()
+            .filter_map(|raw| serde_json::from_str(raw).ok())
+            .collect();
+        tenants.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Ok(tenants)
+    }
+
+    async fn get_tenant(&self, id: &str) -> Result<Option<Tenant>, StoreError> {
+        let raw: Option<String> = self.con().await?.hget(TENANTS_KEY, id).await?;
+
+        Ok(raw.and_then(|raw| serde_json::from_str(&raw).ok()))
+    }
+
+    async fn delete_tenant(&self, id: &str) -> Result<bool, StoreError> {
+        let removed: usize = self.con().await?.hdel(TENANTS_KEY, id).await?;
+
+        Ok(removed > 0)
+    }
+}
+
+#[async_trait]
+impl MaintenanceStore for RedisStore {
+    async fn maintenance(&self) -> Result<Maintenance, StoreError> {
+        let raw: Option<String> = self.con().await?.get(MAINTENANCE_KEY).await?;
+
+        Ok(raw
+            .and_then(|raw| serde_json::from_str(&raw).ok())
+            .unwrap_or_default())
+    }
+
+    async fn set_maintenance(&self, maintenance: &Maintenance) -> Result<(), StoreError> {
+        let raw = serde_json::to_string(maintenance).expect("maintenance serializes to JSON");
+        self.con().await?.set::<_, _, ()>(MAINTENANCE_KEY, raw).await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UserStore for RedisStore {
+    async fn insert_user(&self, user: &User) -> Result<bool, StoreError> {
+        let raw = serde_json::to_string(user).expect("users serialize to JSON");
+        let inserted: bool = self.con().await?.hset_nx(USERS_KEY, &user.id, raw).await?;
+
+        Ok(inserted)
+    }
+
+    async fn list_users(&self) -> Result<Vec<User>, StoreError> {
+        let raw: Vec<String> = self.con().await?.hvals(USERS_KEY).await?;
+
+        let mut users: Vec<User> = raw
+            .iter()
+            .filter_map(|raw| serde_json::from_str(raw).ok())
+            .collect();
+        users.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Ok(users)
+    }
+
+    async fn get_user(&self, id: &str) -> Result<Option<User>, StoreError> {
+        let raw: Option<String> = self.con().await?.hget(USERS_KEY, id).await?;
+
+        Ok(raw.and_then(|raw| serde_json::from_str(&raw).ok()))
+    }
+
+    async fn update_user(&self, user: &User) -> Result<bool, StoreError> {
+        let mut con = self.con().await?;
+        let Some(raw) = con.hget::<_, _, Option<String>>(USERS_KEY, &user.id).await? else {
+            return Ok(false);
+        };
+        // keeps created_at as first stored
+        let created_at = serde_json::from_str::<User>(&raw)
+            .map_or(user.created_at, |old| old.created_at);
+        let updated = User {
+            created_at,
+            ..user.clone()
+        };
+        let raw = serde_json::to_string(&updated).expect("users serialize to JSON");
+        con.hset::<_, _, _, ()>(USERS_KEY, &user.id, raw).await?;
+
+        Ok(true)
+    }
+
+    async fn delete_user(&self, id: &str) -> Result<bool, StoreError> {
+        let removed: usize = self.con().await?.hdel(USERS_KEY, id).await?;
+
+        Ok(removed > 0)
+    }
+}
+
+#[async_trait]
+impl RevisionStore for RedisStore {
+    async fn insert_revision(&self, revision: &Revision) -> Result<i64, StoreError> {
+        let key = format!(
+            "{}{}",
+            REVISIONS_PREFIX,
+            route_field(revision.host.as_deref(), &revision.slug)
+        );
+        let mut con = self.con().await?;
+        let id: i64 = con.incr(REVISION_IDS_KEY, 1).await?;
+        let raw = serde_json::to_string(&Revision {
+            id,
+            ..revision.clone()
+        })
+        .expect("revisions serialize to JSON");
+        con.rpush::<_, _, ()>(key, raw).await?;
+
+        Ok(id)
+    }
+
+    async fn list_revisions(
+        &self,
+        host: Option<&str>,
+        slug: &str,
+    ) -> Result<Vec<Revision>, StoreError> {
+        let key = format!("{}{}", REVISIONS_PREFIX, route_field(host, slug));
+        let raw: Vec<String> = self.con().await?.lrange(key, 0, -1).await?;
+
+        Ok(raw
+            .iter()
+            .filter_map(|raw| serde_json::from_str(raw).ok())
+            .collect())
+    }
+}
+
+#[async_trait]
+impl AuditStore for RedisStore {
+    async fn insert_audit(&self, entry: &AuditEntry) -> Result<(), StoreError> {
+        let mut con = self.con().await?;
+        let id: i64 = con.incr(AUDIT_IDS_KEY, 1).await?;
+        let raw = serde_json::to_string(&AuditEntry {
+            id,
+            ..entry.clone()
+        })
+        .expect("audit entries serialize to JSON");
+        con.rpush::<_, _, ()>(AUDIT_KEY, raw).await?;
+
+        Ok(())
+    }
+
+    /// Filtered here from the whole list, newest entries first.
+    async fn list_audit(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, StoreError> {
+        let raw: Vec<String> = self.con().await?.lrange(AUDIT_KEY, 0, -1).await?;
+
+        Ok(raw
+            .iter()
+            .rev()
+            .filter_map(|raw| serde_json::from_str::<AuditEntry>(raw).ok())
+            .filter(|entry| query.matches(entry))
+            .take(query.limit as usize)
+            .collect())
+    }
+}
+
+#[async_trait]
+impl UsageStore for RedisStore {
+    async fn add_usage(
+        &self,
+        subject: &str,
+        kind: &str,
+        window: i64,
+        by: i64,
+    ) -> Result<i64, StoreError> {
+        let key = format!("{}{}:{}", USAGE_PREFIX, subject, window);
+        let mut con = self.con().await?;
+        let count: i64 = con.hincr(&key, kind, by).await?;
+        // windows are at most a day, keep them a little longer to be read
+        con.expire::<_, ()>(&key, 2 * 24 * 3600).await?;
+
+        Ok(count)
+    }
+
+    async fn usage(
+        &self,
+        subject: &str,
+        window: i64,
+    ) -> Result<BTreeMap<String, i64>, StoreError> {
+        let key = format!("{}{}:{}", USAGE_PREFIX, subject, window);
+        let usage: BTreeMap<String, i64> = self.con().await?.hgetall(key).await?;
+
+        Ok(usage)
+    }
+}
+
+#[async_trait]
+impl HitStore for RedisStore {
+    async fn insert_hits(&self, hits: &[Hit]) -> Result<(), StoreError> {
+        let mut pipe = redis::pipe();
+        for hit in hits {
+            let field = route_field(hit.host.as_deref(), &hit.slug);
+            let key = format!("{}{}", HITS_LIST_PREFIX, field);
+            let raw = serde_json::to_string(hit).expect("hits serialize to JSON");
+            pipe.rpush(key, raw).ignore();
+        }
+        pipe.query_async::<_, ()>(&mut self.con().await?).await?;
+
+        Ok(())
+    }
+
+    /// Aggregated here from the route's whole hit list, Redis has no way to
+    /// group them server side.
+    async fn hit_stats(
+        &self,
+        host: Option<&str>,
+        slug: &str,
+        query: &StatsQuery,
+    ) -> Result<HitStats, StoreError> {
+        let key = format!("{}{}", HITS_LIST_PREFIX, route_field(host, slug));
+        let raw: Vec<String> = self.con().await?.lrange(key, 0, -1).await?;
+
+        let mut buckets: BTreeMap<i64, i64> = BTreeMap::new();
+        let mut referrers: HashMap<String, i64> = HashMap::new();
+        let mut user_agents: HashMap<String, i64> = HashMap::new();
+        let mut countries: HashMap<String, i64> = HashMap::new();
+        let mut variants: HashMap<String, i64> = HashMap::new();
+        let hits = raw
+            .iter()
+            .filter_map(|raw| serde_json::from_str::<Hit>(raw).ok())
+            .filter(|hit| hit.at >= query.from && hit.at < query.to);
+        for hit in hits {
+            *buckets.entry(hit.at - hit.at % query.bucket).or_default() += 1;
+            if let Some(referrer) = hit.referrer {
+                *referrers.entry(referrer).or_default() += 1;
+            }
+            if let Some(user_agent) = hit.user_agent {
+                *user_agents.entry(user_agent).or_default() += 1;
+            }
+            if let Some(country) = hit.country {
+                *countries.entry(country).or_default() += 1;
+            }
+            if let Some(variant) = hit.variant {
+                *variants.entry(variant.to_string()).or_default() += 1;
+            }
+        }
+
+        Ok(HitStats {
+            total: buckets.values().sum(),
+            buckets: buckets
+                .into_iter()
+                .map(|(start, hits)| Bucket { start, hits })
+                .collect(),
+            referrers: top(referrers, query.top),
+            user_agents: top(user_agents, query.top),
+            countries: top(countries, query.top),
+            variants: top(variants, query.top),
+        })
+    }
+}
+
+/// The `n` most frequent values, ties broken by value like the SQLite store.
+fn top(counts: HashMap<String, i64>, n: u32) -> Vec<Count> {
+    let mut counts: Vec<Count> = counts
+        .into_iter()
+        .map(|(value, hits)| Count { value, hits })
+        .collect();
+    counts.sort_by(|a, b| b.hits.cmp(&a.hits).then_with(|| a.value.cmp(&b.value)));
+    counts.truncate(n as usize);
+
+    counts
+}
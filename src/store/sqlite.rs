@@ -0,0 +1,763 @@
+use std::{collections::BTreeMap, str::FromStr, time::Duration};
+
+use async_trait::async_trait;
+use sqlx::{
+    QueryBuilder, Row,
+    sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions, SqliteRow},
+};
+
+use crate::{auth, config::DatabaseConfig};
+
+use super::{
+    ApiKey, AuditEntry, AuditQuery, AuditStore, Bucket, Count, Hit, HitStats, HitStore, KeyStore,
+    Maintenance, MaintenanceStore, PoolStats, Revision, RevisionAction, RevisionStore, Route,
+    RouteStore, StatsQuery, StoreError, Tenant, TenantStore, UsageStore, User, UserStore,
+};
+
+/// Routes kept in a local SQLite database, for deployments that don't want
+/// to run a separate database server.
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    pub async fn connect(url: &str, config: &DatabaseConfig) -> Result<Self, StoreError> {
+        let options = SqliteConnectOptions::from_str(url)?
+            .create_if_missing(true)
+            .busy_timeout(Duration::from_secs(config.statement_timeout));
+        let pool = SqlitePoolOptions::new()
+            .max_connections(config.max_connections)
+            .min_connections(config.min_connections)
+            .acquire_timeout(Duration::from_secs(config.acquire_timeout))
+            .idle_timeout(Some(Duration::from_secs(config.idle_timeout)))
+            .connect_with(options)
+            .await?;
+
+        Ok(Self { pool })
+    }
+}
+
+const ROUTE_COLUMNS: &str = "host, slug, redirect_to, mode, proxy, match_type, \
+                             preserve_query, preserve_path, status_code, expires_at, max_hits, \
+                             hits, geo_targets, device_targets, language_targets, \
+                             split_targets, sticky_split, utm, response_headers, preview, \
+                             password_hash, signed, deleted_at, tenant, owner";
+
+fn route_from_row(row: SqliteRow) -> Route {
+    let host: String = row.get("host");
+
+    Route {
+        host: (!host.is_empty()).then_some(host),
+        slug: row.get("slug"),
+        redirect_to: row.get("redirect_to"),
+        mode: row.get::<String, _>("mode").parse().unwrap_or_default(),
+        proxy: serde_json::from_str(row.get("proxy")).unwrap_or_default(),
+        match_type: row
+            .get::<String, _>("match_type")
+            .parse()
+            .unwrap_or_default(),
+        preserve_query: row.get("preserve_query"),
+        preserve_path: row.get("preserve_path"),
+        status_code: row.get("status_code"),
+        expires_at: row.get("expires_at"),
+        max_hits: row.get("max_hits"),
+        hits: row.get("hits"),
+        geo_targets: serde_json::from_str(row.get("geo_targets")).unwrap_or_default(),
+        device_targets: serde_json::from_str(row.get("device_targets")).unwrap_or_default(),
+        language_targets: serde_json::from_str(row.get("language_targets")).unwrap_or_default(),
+        split_targets: serde_json::from_str(row.get("split_targets")).unwrap_or_default(),
+        sticky_split: row.get("sticky_split"),
+        utm: serde_json::from_str(row.get("utm")).unwrap_or_default(),
+        response_headers: serde_json::from_str(row.get("response_headers")).unwrap_or_default(),
+        preview: row.get("preview"),
+        password_hash: row.get("password_hash"),
+        signed: row.get("signed"),
+        deleted_at: row.get("deleted_at"),
+        tenant: row.get("tenant"),
+        owner: row.get("owner"),
+    }
+}
+
+fn encode_json<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("route fields serialize to JSON")
+}
+
+const KEY_COLUMNS: &str = "id, name, hash, created_at, tenant, owner";
+
+fn key_from_row(row: SqliteRow) -> ApiKey {
+    ApiKey {
+        id: row.get("id"),
+        name: row.get("name"),
+        hash: row.get("hash"),
+        created_at: row.get("created_at"),
+        tenant: row.get("tenant"),
+        owner: row.get("owner"),
+    }
+}
+
+const REVISION_COLUMNS: &str = "id, host, slug, at, author, action, old_target, new_target, route";
+
+fn revision_from_row(row: SqliteRow) -> Revision {
+    let host: String = row.get("host");
+
+    Revision {
+        id: row.get("id"),
+        host: (!host.is_empty()).then_some(host),
+        slug: row.get("slug"),
+        at: row.get("at"),
+        author: row.get("author"),
+        action: row
+            .get::<String, _>("action")
+            .parse()
+            .unwrap_or(RevisionAction::Update),
+        old_target: row.get("old_target"),
+        new_target: row.get("new_target"),
+        route: row
+            .get::<Option<String>, _>("route")
+            .and_then(|raw| serde_json::from_str(&raw).ok()),
+    }
+}
+
+#[async_trait]
+impl RouteStore for SqliteStore {
+    async fn migrate(&self) -> Result<(), StoreError> {
+        sqlx::migrate!("migrations/sqlite").run(&self.pool).await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, host: Option<&str>, slug: &str) -> Result<Option<Route>, StoreError> {
+        let row = sqlx::query(&format!(
+            "SELECT {} FROM routes WHERE host = ? AND slug = ? AND deleted_at IS NULL",
+            ROUTE_COLUMNS
+        ))
+        .bind(host.unwrap_or_default())
+        .bind(slug)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(route_from_row))
+    }
+
+    async fn insert(&self, route: &Route) -> Result<bool, StoreError> {
+        let result = sqlx::query(
+            "INSERT INTO routes (host, slug, redirect_to, mode, proxy, match_type, \
+             preserve_query, preserve_path, status_code, expires_at, max_hits, geo_targets, \
+             device_targets, language_targets, split_targets, sticky_split, utm, \
+             response_headers, preview, password_hash, signed, tenant, owner) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) \
+             ON CONFLICT (host, slug) DO UPDATE SET redirect_to = excluded.redirect_to, \
+             mode = excluded.mode, proxy = excluded.proxy, match_type = excluded.match_type, \
+             preserve_query = excluded.preserve_query, \
+             preserve_path = excluded.preserve_path, status_code = excluded.status_code, \
+             expires_at = excluded.expires_at, max_hits = excluded.max_hits, hits = 0, \
+             geo_targets = excluded.geo_targets, device_targets = excluded.device_targets, \
+             language_targets = excluded.language_targets, \
+             split_targets = excluded.split_targets, sticky_split = excluded.sticky_split, \
+             utm = excluded.utm, response_headers = excluded.response_headers, \
+             preview = excluded.preview, \
+             password_hash = excluded.password_hash, signed = excluded.signed, \
+             deleted_at = NULL, tenant = excluded.tenant, owner = excluded.owner \
+             WHERE routes.deleted_at IS NOT NULL",
+        )
+        .bind(route.host.as_deref().unwrap_or_default())
+        .bind(&route.slug)
+        .bind(&route.redirect_to)
+        .bind(route.mode.as_str())
+        .bind(encode_json(&route.proxy))
+        .bind(route.match_type.as_str())
+        .bind(route.preserve_query)
+        .bind(route.preserve_path)
+        .bind(route.status_code)
+        .bind(route.expires_at)
+        .bind(route.max_hits)
+        .bind(encode_json(&route.geo_targets))
+        .bind(encode_json(&route.device_targets))
+        .bind(encode_json(&route.language_targets))
+        .bind(encode_json(&route.split_targets))
+        .bind(route.sticky_split)
+        .bind(encode_json(&route.utm))
+        .bind(encode_json(&route.response_headers))
+        .bind(route.preview)
+        .bind(&route.password_hash)
+        .bind(route.signed)
+        .bind(&route.tenant)
+        .bind(&route.owner)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn update(&self, route: &Route) -> Result<bool, StoreError> {
+        let result = sqlx::query(
+            "UPDATE routes SET redirect_to = ?, mode = ?, proxy = ?, match_type = ?, \
+             preserve_query = ?, preserve_path = ?, status_code = ?, expires_at = ?, \
+             max_hits = ?, geo_targets = ?, device_targets = ?, language_targets = ?, \
+             split_targets = ?, sticky_split = ?, utm = ?, response_headers = ?, preview = ?, \
+             password_hash = ?, signed = ?, tenant = ?, owner = ? \
+             WHERE host = ? AND slug = ? AND deleted_at IS NULL",
+        )
+        .bind(&route.redirect_to)
+        .bind(route.mode.as_str())
+        .bind(encode_json(&route.proxy))
+        .bind(route.match_type.as_str())
+        .bind(route.preserve_query)
+        .bind(route.preserve_path)
+        .bind(route.status_code)
+        .bind(route.expires_at)
+        .bind(route.max_hits)
+        .bind(encode_json(&route.geo_targets))
+        .bind(encode_json(&route.device_targets))
+        .bind(encode_json(&route.language_targets))
+        .bind(encode_json(&route.split_targets))
+        .bind(route.sticky_split)
+        .bind(encode_json(&route.utm))
+        .bind(encode_json(&route.response_headers))
+        .bind(route.preview)
+        .bind(&route.password_hash)
+        .bind(route.signed)
+        .bind(&route.tenant)
+        .bind(&route.owner)
+        .bind(route.host.as_deref().unwrap_or_default())
+        .bind(&route.slug)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete(&self, host: Option<&str>, slug: &str) -> Result<bool, StoreError> {
+        let result = sqlx::query(
+            "UPDATE routes SET deleted_at = ? \
+             WHERE host = ? AND slug = ? AND deleted_at IS NULL",
+        )
+        .bind(auth::now())
+        .bind(host.unwrap_or_default())
+        .bind(slug)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn restore(&self, host: Option<&str>, slug: &str) -> Result<bool, StoreError> {
+        let result = sqlx::query(
+            "UPDATE routes SET deleted_at = NULL \
+             WHERE host = ? AND slug = ? AND deleted_at IS NOT NULL",
+        )
+        .bind(host.unwrap_or_default())
+        .bind(slug)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn purge(&self, before: i64) -> Result<u64, StoreError> {
+        let result = sqlx::query("DELETE FROM routes WHERE deleted_at < ?")
+            .bind(before)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn list(&self) -> Result<Vec<Route>, StoreError> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM routes WHERE deleted_at IS NULL ORDER BY host, slug",
+            ROUTE_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(route_from_row).collect())
+    }
+
+    async fn list_deleted(&self) -> Result<Vec<Route>, StoreError> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM routes WHERE deleted_at IS NOT NULL ORDER BY host, slug",
+            ROUTE_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(route_from_row).collect())
+    }
+
+    async fn record_hit(&self, host: Option<&str>, slug: &str) -> Result<bool, StoreError> {
+        let result = sqlx::query(
+            "UPDATE routes SET hits = hits + 1 \
+             WHERE host = ? AND slug = ? AND deleted_at IS NULL \
+             AND (max_hits IS NULL OR hits < max_hits)",
+        )
+        .bind(host.unwrap_or_default())
+        .bind(slug)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_patterns(&self) -> Result<Vec<Route>, StoreError> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM routes \
+             WHERE (match_type != 'exact' OR preserve_path) AND deleted_at IS NULL \
+             ORDER BY host, slug",
+            ROUTE_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(route_from_row).collect())
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+
+        Ok(())
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        Some(PoolStats {
+            size: self.pool.size(),
+            idle: self.pool.num_idle(),
+        })
+    }
+}
+
+#[async_trait]
+impl KeyStore for SqliteStore {
+    async fn insert_key(&self, key: &ApiKey) -> Result<(), StoreError> {
+        sqlx::query(
+            "INSERT INTO api_keys (id, name, hash, created_at, tenant, owner) \
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&key.id)
+        .bind(&key.name)
+        .bind(&key.hash)
+        .bind(key.created_at)
+        .bind(&key.tenant)
+        .bind(&key.owner)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn find_key(&self, hash: &str) -> Result<Option<ApiKey>, StoreError> {
+        let row = sqlx::query(&format!("SELECT {} FROM api_keys WHERE hash = ?", KEY_COLUMNS))
+            .bind(hash)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(key_from_row))
+    }
+
+    async fn delete_key(&self, id: &str) -> Result<bool, StoreError> {
+        let result = sqlx::query("DELETE FROM api_keys WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_keys(&self) -> Result<Vec<ApiKey>, StoreError> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM api_keys ORDER BY created_at",
+            KEY_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(key_from_row).collect())
+    }
+}
+
+#[async_trait]
+impl TenantStore for SqliteStore {
+    async fn insert_tenant(&self, tenant: &Tenant) -> Result<bool, StoreError> {
+        let result = sqlx::query(
+            "INSERT INTO tenants (id, name, created_at) VALUES (?, ?, ?) \
+             ON CONFLICT (id) DO NOTHING",
+        )
+        .bind(&tenant.id)
+        .bind(&tenant.name)
+        .bind(tenant.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_tenants(&self) -> Result<Vec<Tenant>, StoreError> {
+        let rows = sqlx::query("SELECT id, name, created_at FROM tenants ORDER BY id")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| Tenant {
+                id: row.get("id"),
+                name: row.get("name"),
+                created_at: row.get("created_at"),
+            })
+            .collect())
+    }
+
+    async fn delete_tenant(&self, id: &str) -> Result<bool, StoreError> {
+        let result = sqlx::query("DELETE FROM tenants WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl MaintenanceStore for SqliteStore {
+    async fn maintenance(&self) -> Result<Maintenance, StoreError> {
+        let state: Option<String> =
+            sqlx::query_scalar("SELECT state FROM maintenance WHERE id = 1")
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(state
+            .and_then(|state| serde_json::from_str(&state).ok())
+            .unwrap_or_default())
+    }
+
+    async fn set_maintenance(&self, maintenance: &Maintenance) -> Result<(), StoreError> {
+        let state = serde_json::to_string(maintenance).expect("maintenance serializes to JSON");
+        sqlx::query(
+            "INSERT INTO maintenance (id, state) VALUES (1, ?) \
+             ON CONFLICT (id) DO UPDATE SET state = excluded.state",
+        )
+        .bind(state)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RevisionStore for SqliteStore {
+    async fn insert_revision(&self, revision: &Revision) -> Result<i64, StoreError> {
+        let result = sqlx::query(
+            "INSERT INTO route_revisions (host, slug, at, author, action, old_target, \
+             new_target, route) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(revision.host.as_deref().unwrap_or_default())
+        .bind(&revision.slug)
+        .bind(revision.at)
+        .bind(&revision.author)
+        .bind(revision.action.as_str())
+        .bind(&revision.old_target)
+        .bind(&revision.new_target)
+        .bind(revision.route.as_ref().map(encode_json))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    async fn list_revisions(
+        &self,
+        host: Option<&str>,
+        slug: &str,
+    ) -> Result<Vec<Revision>, StoreError> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM route_revisions WHERE host = ? AND slug = ? ORDER BY id",
+            REVISION_COLUMNS
+        ))
+        .bind(host.unwrap_or_default())
+        .bind(slug)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(revision_from_row).collect())
+    }
+
+    async fn get_revision(
+        &self,
+        host: Option<&str>,
+        slug: &str,
+        id: i64,
+    ) -> Result<Option<Revision>, StoreError> {
+        let row = sqlx::query(&format!(
+            "SELECT {} FROM route_revisions WHERE host = ? AND slug = ? AND id = ?",
+            REVISION_COLUMNS
+        ))
+        .bind(host.unwrap_or_default())
+        .bind(slug)
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(revision_from_row))
+    }
+}
+
+#[async_trait]
+impl AuditStore for SqliteStore {
+    async fn insert_audit(&self, entry: &AuditEntry) -> Result<(), StoreError> {
+        sqlx::query(
+            "INSERT INTO audit_log (at, actor, ip, action, target, diff) \
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(entry.at)
+        .bind(&entry.actor)
+        .bind(&entry.ip)
+        .bind(&entry.action)
+        .bind(&entry.target)
+        .bind(entry.diff.as_ref().map(encode_json))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn list_audit(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, StoreError> {
+        let mut builder = QueryBuilder::new(
+            "SELECT id, at, actor, ip, action, target, diff FROM audit_log WHERE 1 = 1",
+        );
+        if let Some(actor) = &query.actor {
+            builder.push(" AND actor = ").push_bind(actor);
+        }
+        if let Some(action) = &query.action {
+            builder.push(" AND action = ").push_bind(action);
+        }
+        if let Some(target) = &query.target {
+            builder.push(" AND target = ").push_bind(target);
+        }
+        if let Some(from) = query.from {
+            builder.push(" AND at >= ").push_bind(from);
+        }
+        if let Some(to) = query.to {
+            builder.push(" AND at < ").push_bind(to);
+        }
+        builder.push(" ORDER BY id DESC LIMIT ").push_bind(query.limit);
+        let rows = builder.build().fetch_all(&self.pool).await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| AuditEntry {
+                id: row.get("id"),
+                at: row.get("at"),
+                actor: row.get("actor"),
+                ip: row.get("ip"),
+                action: row.get("action"),
+                target: row.get("target"),
+                diff: row
+                    .get::<Option<String>, _>("diff")
+                    .and_then(|raw| serde_json::from_str(&raw).ok()),
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl UsageStore for SqliteStore {
+    async fn add_usage(
+        &self,
+        subject: &str,
+        kind: &str,
+        window: i64,
+        by: i64,
+    ) -> Result<i64, StoreError> {
+        let count = sqlx::query_scalar(
+            "INSERT INTO api_usage (subject, kind, window_start, count) VALUES (?, ?, ?, ?) \
+             ON CONFLICT (subject, kind, window_start) \
+             DO UPDATE SET count = count + excluded.count RETURNING count",
+        )
+        .bind(subject)
+        .bind(kind)
+        .bind(window)
+        .bind(by)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count)
+    }
+
+    async fn usage(
+        &self,
+        subject: &str,
+        window: i64,
+    ) -> Result<BTreeMap<String, i64>, StoreError> {
+        let rows = sqlx::query(
+            "SELECT kind, count FROM api_usage WHERE subject = ? AND window_start = ?",
+        )
+        .bind(subject)
+        .bind(window)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.get("kind"), row.get("count")))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl HitStore for SqliteStore {
+    async fn insert_hits(&self, hits: &[Hit]) -> Result<(), StoreError> {
+        // SQLite caps bound parameters at 32766, eight per hit
+        for chunk in hits.chunks(4000) {
+            QueryBuilder::new(
+                "INSERT INTO hits (host, slug, at, referrer, user_agent, ip_hash, country, \
+                 variant) ",
+            )
+                .push_values(chunk, |mut row, hit| {
+                    row.push_bind(hit.host.as_deref().unwrap_or_default())
+                        .push_bind(&hit.slug)
+                        .push_bind(hit.at)
+                        .push_bind(&hit.referrer)
+                        .push_bind(&hit.user_agent)
+                        .push_bind(&hit.ip_hash)
+                        .push_bind(&hit.country)
+                        .push_bind(hit.variant);
+                })
+                .build()
+                .execute(&self.pool)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn hit_stats(
+        &self,
+        host: Option<&str>,
+        slug: &str,
+        query: &StatsQuery,
+    ) -> Result<HitStats, StoreError> {
+        let buckets: Vec<Bucket> = sqlx::query(
+            "SELECT at - at % ? AS start, COUNT(*) AS hits FROM hits \
+             WHERE host = ? AND slug = ? AND at >= ? AND at < ? GROUP BY start ORDER BY start",
+        )
+        .bind(query.bucket)
+        .bind(host.unwrap_or_default())
+        .bind(slug)
+        .bind(query.from)
+        .bind(query.to)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| Bucket {
+            start: row.get("start"),
+            hits: row.get("hits"),
+        })
+        .collect();
+
+        Ok(HitStats {
+            total: buckets.iter().map(|bucket| bucket.hits).sum(),
+            buckets,
+            referrers: self.top_values("referrer", host, slug, query).await?,
+            user_agents: self.top_values("user_agent", host, slug, query).await?,
+            countries: self.top_values("country", host, slug, query).await?,
+            variants: self.top_values("variant", host, slug, query).await?,
+        })
+    }
+}
+
+impl SqliteStore {
+    /// The most frequent values of `column` among the hits of a route.
+    async fn top_values(
+        &self,
+        column: &str,
+        host: Option<&str>,
+        slug: &str,
+        query: &StatsQuery,
+    ) -> Result<Vec<Count>, StoreError> {
+        let rows = sqlx::query(&format!(
+            "SELECT CAST({0} AS TEXT) AS value, COUNT(*) AS hits FROM hits \
+             WHERE host = ? AND slug = ? AND at >= ? AND at < ? AND {0} IS NOT NULL \
+             GROUP BY {0} ORDER BY hits DESC, value LIMIT ?",
+            column
+        ))
+        .bind(host.unwrap_or_default())
+        .bind(slug)
+        .bind(query.from)
+        .bind(query.to)
+        .bind(query.top)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| Count {
+                value: row.get("value"),
+                hits: row.get("hits"),
+            })
+            .collect())
+    }
+}
+
+fn user_from_row(row: SqliteRow) -> User {
+    User {
+        id: row.get("id"),
+        name: row.get("name"),
+        admin: row.get("admin"),
+        created_at: row.get("created_at"),
+    }
+}
+
+#[async_trait]
+impl UserStore for SqliteStore {
+    async fn insert_user(&self, user: &User) -> Result<bool, StoreError> {
+        let result = sqlx::query(
+            "INSERT INTO users (id, name, admin, created_at) VALUES (?, ?, ?, ?) \
+             ON CONFLICT (id) DO NOTHING",
+        )
+        .bind(&user.id)
+        .bind(&user.name)
+        .bind(user.admin)
+        .bind(user.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_users(&self) -> Result<Vec<User>, StoreError> {
+        let rows = sqlx::query("SELECT id, name, admin, created_at FROM users ORDER BY id")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().map(user_from_row).collect())
+    }
+
+    async fn get_user(&self, id: &str) -> Result<Option<User>, StoreError> {
+        let row = sqlx::query("SELECT id, name, admin, created_at FROM users WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(user_from_row))
+    }
+
+    async fn update_user(&self, user: &User) -> Result<bool, StoreError> {
+        let result = sqlx::query("UPDATE users SET name = ?, admin = ? WHERE id = ?")
+            .bind(&user.name)
+            .bind(user.admin)
+            .bind(&user.id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_user(&self, id: &str) -> Result<bool, StoreError> {
+        let result = sqlx::query("DELETE FROM users WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
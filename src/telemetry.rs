@@ -0,0 +1,61 @@
+use std::time::Instant;
+
+use axum::{extract::State, http::Request, middleware::Next, response::Response};
+use metrics_exporter_prometheus::{BuildError, PrometheusBuilder, PrometheusHandle};
+use opentelemetry::{KeyValue, trace::TraceError};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{Resource, runtime, trace::{self, Tracer}};
+
+use crate::router::AppState;
+
+/// Exports spans over OTLP/gRPC to `endpoint`, batched on the tokio runtime.
+pub fn otlp_tracer(endpoint: &str, service_name: &str) -> Result<Tracer, TraceError> {
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(trace::config().with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            service_name.to_owned(),
+        )])))
+        .install_batch(runtime::Tokio)
+}
+
+pub fn install_metrics() -> Result<PrometheusHandle, BuildError> {
+    PrometheusBuilder::new().install_recorder()
+}
+
+/// Counts every request and records its latency, labelled by method and
+/// status. Paths are left out on purpose: slugs are unbounded.
+pub async fn track_requests<B>(req: Request<B>, next: Next<B>) -> Response {
+    let start = Instant::now();
+    let method = req.method().to_string();
+
+    let response = next.run(req).await;
+
+    let labels = [
+        ("method", method),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    metrics::counter!("roads_http_requests_total", &labels).increment(1);
+    metrics::histogram!("roads_http_request_duration_seconds", &labels)
+        .record(start.elapsed().as_secs_f64());
+
+    response
+}
+
+pub async fn render_metrics(State(state): State<AppState>) -> String {
+    if let Some(stats) = state.store.pool_stats() {
+        metrics::gauge!("roads_db_pool_connections").set(stats.size as f64);
+        metrics::gauge!("roads_db_pool_idle_connections").set(stats.idle as f64);
+    }
+
+    state
+        .metrics
+        .as_ref()
+        .map(|handle| handle.render())
+        .unwrap_or_default()
+}
@@ -0,0 +1,193 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+    Router, routing::{delete, get},
+};
+use serde::Deserialize;
+use tracing::debug;
+use utoipa::ToSchema;
+
+use crate::{
+    audit,
+    auth::{self, Principal, Scope},
+    router::{AppState, internal_error},
+    store::{Store, StoreError, Tenant},
+};
+
+const MAX_ID_LEN: usize = 64;
+
+/// Tenant ids end up in labels and usage keys, so they're kept simple.
+pub fn check_id(id: &str) -> Result<(), String> {
+    if id.is_empty() || id.len() > MAX_ID_LEN {
+        return Err(format!("tenant ids are 1 to {} characters", MAX_ID_LEN));
+    }
+    if !id
+        .bytes()
+        .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+    {
+        return Err(format!("{:?} has more than lowercase letters, digits and -", id));
+    }
+
+    Ok(())
+}
+
+/// Whether routes, deleted ones included, or API keys still belong to
+/// tenant `id`.
+pub async fn in_use(store: &Store, id: &str) -> Result<bool, StoreError> {
+    let owned = |tenant: &Option<String>| tenant.as_deref() == Some(id);
+    let routes = store.list().await?;
+    let deleted = store.list_deleted().await?;
+    let keys = store.list_keys().await?;
+
+    Ok(routes.iter().chain(&deleted).any(|route| owned(&route.tenant))
+        || keys.iter().any(|key| owned(&key.tenant)))
+}
+
+/// The tenant something created by `principal` belongs to: its own for
+/// tenant callers, which can't pick another, and `requested` otherwise,
+/// which has to exist.
+pub async fn assign(
+    store: &Store,
+    principal: &Principal,
+    requested: Option<String>,
+) -> Result<Option<String>, (StatusCode, String)> {
+    let exists = match (&principal.tenant, &requested) {
+        (None, Some(id)) => store.get_tenant(id).await.map_err(internal_error)?.is_some(),
+        _ => true,
+    };
+
+    choose(principal, requested, |_| exists)
+}
+
+/// `assign` with `exists` telling which tenants there are.
+pub fn choose(
+    principal: &Principal,
+    requested: Option<String>,
+    exists: impl FnOnce(&str) -> bool,
+) -> Result<Option<String>, (StatusCode, String)> {
+    match (&principal.tenant, requested) {
+        (Some(own), Some(requested)) if &requested != own => Err((
+            StatusCode::FORBIDDEN,
+            "Can't act for another tenant".into(),
+        )),
+        (Some(own), _) => Ok(Some(own.clone())),
+        (None, Some(id)) if !exists(&id) => {
+            Err((StatusCode::BAD_REQUEST, format!("Unknown tenant {}", id)))
+        }
+        (None, requested) => Ok(requested),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct NewTenant {
+    id: String,
+    name: String,
+}
+
+pub fn tenant_routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/tenants", get(list_tenants).post(create_tenant))
+        .route("/api/tenants/:id", delete(delete_tenant))
+}
+
+/// Only admins outside any tenant manage tenants.
+fn require_admin(principal: &Principal) -> Result<(), (StatusCode, String)> {
+    principal.require(Scope::TenantsAdmin)?;
+    principal.require_admin()?;
+    principal.require_global()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tenants",
+    tag = "tenants",
+    responses((status = 200, description = "Every tenant, by id", body = [Tenant]))
+)]
+async fn list_tenants(
+    principal: Principal,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Tenant>>, (StatusCode, String)> {
+    require_admin(&principal)?;
+
+    let tenants = state.store.list_tenants().await.map_err(internal_error)?;
+
+    Ok(Json(tenants))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/tenants",
+    tag = "tenants",
+    request_body = NewTenant,
+    responses(
+        (status = 201, description = "The new tenant", body = Tenant),
+        (status = 400, description = "Invalid tenant id", body = String),
+        (status = 409, description = "Tenant already exists", body = String),
+    )
+)]
+async fn create_tenant(
+    principal: Principal,
+    State(state): State<AppState>,
+    Json(req): Json<NewTenant>,
+) -> Result<(StatusCode, Json<Tenant>), (StatusCode, String)> {
+    require_admin(&principal)?;
+    check_id(&req.id).map_err(|err| (StatusCode::BAD_REQUEST, format!("Invalid id: {}", err)))?;
+
+    let tenant = Tenant {
+        id: req.id,
+        name: req.name,
+        created_at: auth::now(),
+    };
+    if !state
+        .store
+        .insert_tenant(&tenant)
+        .await
+        .map_err(internal_error)?
+    {
+        return Err((StatusCode::CONFLICT, "Tenant already exists".into()));
+    }
+    let diff = audit::diff(None, Some(&tenant));
+    audit::record(&state.store, &principal, "tenant.create", Some(tenant.id.clone()), diff)
+        .await
+        .map_err(internal_error)?;
+
+    debug!("created tenant: {} by {}", &tenant.id, &principal.subject);
+    Ok((StatusCode::CREATED, Json(tenant)))
+}
+
+/// Refused while routes or keys still belong to the tenant.
+#[utoipa::path(
+    delete,
+    path = "/api/tenants/{id}",
+    tag = "tenants",
+    params(("id" = String, Path, description = "Id of the tenant")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 404, description = "Tenant not found", body = String),
+        (status = 409, description = "Routes or keys still belong to it", body = String),
+    )
+)]
+async fn delete_tenant(
+    principal: Principal,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    require_admin(&principal)?;
+
+    if in_use(&state.store, &id).await.map_err(internal_error)? {
+        return Err((
+            StatusCode::CONFLICT,
+            "Routes or keys still belong to this tenant".into(),
+        ));
+    }
+    if !state.store.delete_tenant(&id).await.map_err(internal_error)? {
+        return Err((StatusCode::NOT_FOUND, "Tenant not found".into()));
+    }
+    audit::record(&state.store, &principal, "tenant.delete", Some(id.clone()), None)
+        .await
+        .map_err(internal_error)?;
+
+    debug!("deleted tenant: {} by {}", &id, &principal.subject);
+    Ok(StatusCode::NO_CONTENT)
+}
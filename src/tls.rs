@@ -0,0 +1,108 @@
+use std::{io, net::SocketAddr, sync::Arc};
+
+use axum::Router;
+use axum_server::{
+    Handle,
+    HttpConfig,
+    tls_rustls::{RustlsAcceptor, RustlsConfig},
+};
+use futures::StreamExt;
+use rustls::ServerConfig;
+use rustls_acme::{AcmeConfig, caches::DirCache};
+use tracing::{debug, info, warn};
+
+use crate::{config::TlsConfig, plain, proxy_protocol};
+
+/// Serves `app` over HTTPS on `addr`, with the certificate either read from
+/// disk or provisioned through ACME. HTTP/2 is negotiated through ALPN.
+pub async fn serve(
+    config: &TlsConfig,
+    http2: bool,
+    proxy_protocol: bool,
+    addr: SocketAddr,
+    app: Router,
+    handle: Handle,
+) -> io::Result<()> {
+    let make_service = app.clone().into_make_service();
+    let server = axum_server::from_tcp(plain::bind(addr)?)
+        .handle(handle)
+        .http_config(HttpConfig::new().http1_only(!http2).build());
+    debug!("listening on {} (TLS)", addr);
+
+    if !config.acme.enabled {
+        let pem = RustlsConfig::from_pem_file(&config.cert, &config.key).await?;
+        let mut tls_config = (*pem.get_inner()).clone();
+        start_http3(config, addr, &tls_config, app)?;
+        tls_config.alpn_protocols = alpn_protocols(http2);
+
+        let acceptor = RustlsAcceptor::new(RustlsConfig::from_config(Arc::new(tls_config)));
+        return server
+            .acceptor(proxy_protocol::Acceptor::new(acceptor, proxy_protocol))
+            .serve(make_service)
+            .await;
+    }
+
+    let acme = &config.acme;
+    let mut state = AcmeConfig::new(&acme.domains)
+        .contact(acme.contact.iter().map(|email| format!("mailto:{}", email)))
+        .cache(DirCache::new(acme.cache_dir.clone()))
+        .directory_lets_encrypt(acme.production)
+        .state();
+    let mut tls_config = (*state.default_rustls_config()).clone();
+    start_http3(config, addr, &tls_config, app)?;
+    tls_config.alpn_protocols = alpn_protocols(http2);
+    let acceptor = state.axum_acceptor(Arc::new(tls_config));
+
+    // drives ordering and renewal, certificates are swapped in as they land
+    tokio::spawn(async move {
+        while let Some(event) = state.next().await {
+            match event {
+                Ok(event) => info!("acme: {:?}", event),
+                Err(err) => warn!("acme: {:?}", err),
+            }
+        }
+    });
+
+    server
+        .acceptor(proxy_protocol::Acceptor::new(acceptor, proxy_protocol))
+        .serve(make_service)
+        .await
+}
+
+#[cfg(feature = "http3")]
+fn start_http3(
+    config: &TlsConfig,
+    addr: SocketAddr,
+    tls_config: &ServerConfig,
+    app: Router,
+) -> io::Result<()> {
+    if config.http3 {
+        crate::http3::spawn(addr, tls_config.clone(), app)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "http3"))]
+fn start_http3(
+    config: &TlsConfig,
+    _addr: SocketAddr,
+    _tls_config: &ServerConfig,
+    _app: Router,
+) -> io::Result<()> {
+    if config.http3 {
+        warn!("tls.http3 is set but Roads was built without the `http3` feature");
+    }
+
+    Ok(())
+}
+
+fn alpn_protocols(http2: bool) -> Vec<Vec<u8>> {
+    let mut protocols = Vec::new();
+    if http2 {
+        protocols.push(b"h2".to_vec());
+    }
+    protocols.push(b"http/1.1".to_vec());
+
+    protocols
+}
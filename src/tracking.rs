@@ -0,0 +1,108 @@
+use std::{net::IpAddr, time::Duration};
+
+use sha2::{Digest, Sha256};
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+use crate::{
+    config::TrackingConfig,
+    store::{Hit, Store},
+};
+
+/// Records redirect hits off the request path. Hits are queued on a bounded
+/// channel and a background task writes them to the store in batches, every
+/// `batch_size` hits or `flush_interval` seconds. When the queue is full
+/// new hits are dropped rather than slowing redirects down.
+pub struct ClickTracker {
+    tx: mpsc::Sender<Message>,
+    ip_salt: String,
+}
+
+enum Message {
+    Hit(Hit),
+    /// Write out what is buffered and report back
+    Flush(oneshot::Sender<()>),
+}
+
+impl ClickTracker {
+    pub fn start(config: &TrackingConfig, store: Store) -> Self {
+        let (tx, rx) = mpsc::channel(config.queue_size);
+        tokio::spawn(write_batches(
+            store,
+            rx,
+            config.batch_size,
+            Duration::from_secs(config.flush_interval),
+        ));
+
+        Self {
+            tx,
+            ip_salt: config.ip_salt.clone(),
+        }
+    }
+
+    pub fn record(&self, hit: Hit) {
+        if self.tx.try_send(Message::Hit(hit)).is_err() {
+            metrics::counter!("roads_hits_dropped_total").increment(1);
+        }
+    }
+
+    /// Salted SHA-256 of `ip`, so visitors can be told apart without
+    /// keeping their address.
+    pub fn hash_ip(&self, ip: IpAddr) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.ip_salt.as_bytes());
+        hasher.update(ip.to_string().as_bytes());
+
+        hex::encode(hasher.finalize())
+    }
+
+    /// Waits until every hit recorded so far is written.
+    pub async fn flush(&self) {
+        let (done, wait) = oneshot::channel();
+        if self.tx.send(Message::Flush(done)).await.is_ok() {
+            let _ = wait.await;
+        }
+    }
+}
+
+async fn write_batches(
+    store: Store,
+    mut rx: mpsc::Receiver<Message>,
+    batch_size: usize,
+    flush_interval: Duration,
+) {
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut ticks = tokio::time::interval(flush_interval);
+    loop {
+        tokio::select! {
+            message = rx.recv() => match message {
+                Some(Message::Hit(hit)) => {
+                    batch.push(hit);
+                    if batch.len() >= batch_size {
+                        write(&store, &mut batch).await;
+                    }
+                }
+                Some(Message::Flush(done)) => {
+                    write(&store, &mut batch).await;
+                    let _ = done.send(());
+                }
+                None => break,
+            },
+            _ = ticks.tick() => write(&store, &mut batch).await,
+        }
+    }
+
+    write(&store, &mut batch).await;
+}
+
+async fn write(store: &Store, batch: &mut Vec<Hit>) {
+    if batch.is_empty() {
+        return;
+    }
+
+    if let Err(err) = store.insert_hits(batch).await {
+        warn!("dropping {} hits: {}", batch.len(), err);
+        metrics::counter!("roads_hits_dropped_total").increment(batch.len() as u64);
+    }
+    batch.clear();
+}
@@ -0,0 +1,27 @@
+use std::time::Duration;
+
+use tracing::{debug, warn};
+
+use crate::{
+    auth,
+    config::TrashConfig,
+    store::Store,
+};
+
+/// Purges routes deleted more than `retention` seconds ago, every
+/// `purge_interval` seconds, for as long as the server runs.
+pub fn start_purge(config: &TrashConfig, store: Store) {
+    let retention = config.retention as i64;
+    let mut ticks = tokio::time::interval(Duration::from_secs(config.purge_interval));
+
+    tokio::spawn(async move {
+        loop {
+            ticks.tick().await;
+            match store.purge(auth::now() - retention).await {
+                Ok(0) => {}
+                Ok(purged) => debug!("purged {} deleted routes", purged),
+                Err(err) => warn!("failed to purge deleted routes: {}", err),
+            }
+        }
+    });
+}
@@ -0,0 +1,174 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+    Router, routing::{delete, get},
+};
+use serde::Deserialize;
+use tracing::debug;
+use utoipa::ToSchema;
+
+use crate::{
+    audit,
+    auth::{self, Principal, Scope},
+    router::{AppState, internal_error},
+    store::{Store, StoreError, User},
+};
+
+const MAX_ID_LEN: usize = 254;
+
+/// User ids are usernames or email addresses, without spaces.
+pub fn check_id(id: &str) -> Result<(), String> {
+    if id.is_empty() || id.len() > MAX_ID_LEN {
+        return Err(format!("user ids are 1 to {} characters", MAX_ID_LEN));
+    }
+    if id.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(format!("{:?} has spaces or control characters", id));
+    }
+
+    Ok(())
+}
+
+/// Whether API keys still act as user `id`.
+pub async fn has_keys(store: &Store, id: &str) -> Result<bool, StoreError> {
+    let keys = store.list_keys().await?;
+
+    Ok(keys.iter().any(|key| key.owner.as_deref() == Some(id)))
+}
+
+/// The user a key minted by `principal` acts as: the caller itself for
+/// users that aren't admins, which can't pick another, and `requested`
+/// otherwise, which has to exist.
+pub async fn assign(
+    store: &Store,
+    principal: &Principal,
+    requested: Option<String>,
+) -> Result<Option<String>, (StatusCode, String)> {
+    if !principal.is_admin() {
+        if requested.is_some() && requested != principal.user {
+            return Err((StatusCode::FORBIDDEN, "Can't act as another user".into()));
+        }
+        return Ok(principal.user.clone());
+    }
+
+    if let Some(id) = &requested {
+        if store.get_user(id).await.map_err(internal_error)?.is_none() {
+            return Err((StatusCode::BAD_REQUEST, format!("Unknown user {}", id)));
+        }
+    }
+
+    Ok(requested)
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct NewUser {
+    id: String,
+    /// The id when left out
+    name: Option<String>,
+    #[serde(default)]
+    admin: bool,
+}
+
+pub fn user_routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/users", get(list_users).post(create_user))
+        .route("/api/users/:id", delete(delete_user))
+}
+
+/// Only admins outside any tenant manage users.
+fn require_admin(principal: &Principal) -> Result<(), (StatusCode, String)> {
+    principal.require(Scope::UsersAdmin)?;
+    principal.require_admin()?;
+    principal.require_global()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/users",
+    tag = "users",
+    responses((status = 200, description = "Every user, by id", body = [User]))
+)]
+async fn list_users(
+    principal: Principal,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<User>>, (StatusCode, String)> {
+    require_admin(&principal)?;
+
+    let users = state.store.list_users().await.map_err(internal_error)?;
+
+    Ok(Json(users))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/users",
+    tag = "users",
+    request_body = NewUser,
+    responses(
+        (status = 201, description = "The new user", body = User),
+        (status = 400, description = "Invalid user id", body = String),
+        (status = 409, description = "User already exists", body = String),
+    )
+)]
+async fn create_user(
+    principal: Principal,
+    State(state): State<AppState>,
+    Json(req): Json<NewUser>,
+) -> Result<(StatusCode, Json<User>), (StatusCode, String)> {
+    require_admin(&principal)?;
+    check_id(&req.id).map_err(|err| (StatusCode::BAD_REQUEST, format!("Invalid id: {}", err)))?;
+
+    let user = User {
+        name: req.name.unwrap_or_else(|| req.id.clone()),
+        id: req.id,
+        admin: req.admin,
+        created_at: auth::now(),
+    };
+    if !state.store.insert_user(&user).await.map_err(internal_error)? {
+        return Err((StatusCode::CONFLICT, "User already exists".into()));
+    }
+    let diff = audit::diff(None, Some(&user));
+    audit::record(&state.store, &principal, "user.create", Some(user.id.clone()), diff)
+        .await
+        .map_err(internal_error)?;
+
+    debug!("created user: {} by {}", &user.id, &principal.subject);
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+/// Refused while API keys still act as the user. Its routes keep it as
+/// owner, only admins changing them from then on.
+#[utoipa::path(
+    delete,
+    path = "/api/users/{id}",
+    tag = "users",
+    params(("id" = String, Path, description = "Id of the user")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 404, description = "User not found", body = String),
+        (status = 409, description = "API keys still act as the user", body = String),
+    )
+)]
+async fn delete_user(
+    principal: Principal,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    require_admin(&principal)?;
+
+    if has_keys(&state.store, &id).await.map_err(internal_error)? {
+        return Err((
+            StatusCode::CONFLICT,
+            "API keys still act as this user, revoke them first".into(),
+        ));
+    }
+    if !state.store.delete_user(&id).await.map_err(internal_error)? {
+        return Err((StatusCode::NOT_FOUND, "User not found".into()));
+    }
+    audit::record(&state.store, &principal, "user.delete", Some(id.clone()), None)
+        .await
+        .map_err(internal_error)?;
+
+    debug!("deleted user: {} by {}", &id, &principal.subject);
+    Ok(StatusCode::NO_CONTENT)
+}
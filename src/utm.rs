@@ -0,0 +1,60 @@
+use std::collections::BTreeMap;
+
+use crate::store::Route;
+
+/// The UTM query string for a redirect to `target`: the global `[utm]`
+/// parameters overridden by the route's own, an empty value dropping a
+/// parameter. `{slug}` and `{host}` in values are replaced by the route
+/// slug and the request host. Parameters `target` already has are kept
+/// as they are.
+pub fn query(
+    global: &BTreeMap<String, String>,
+    route: &Route,
+    host: Option<&str>,
+    target: &str,
+) -> Option<String> {
+    let mut params = global.clone();
+    params.extend(route.utm.clone());
+
+    let existing = existing_params(target);
+    let query: Vec<String> = params
+        .iter()
+        .filter(|(name, value)| !value.is_empty() && !existing.contains(&name.as_str()))
+        .map(|(name, value)| {
+            let value = value
+                .replace("{slug}", &route.slug)
+                .replace("{host}", host.unwrap_or_default());
+            format!("{}={}", encode(name), encode(&value))
+        })
+        .collect();
+
+    (!query.is_empty()).then(|| query.join("&"))
+}
+
+/// Names of the query parameters of `target`.
+fn existing_params(target: &str) -> Vec<&str> {
+    let target = target.split('#').next().unwrap_or_default();
+    let Some((_, query)) = target.split_once('?') else {
+        return Vec::new();
+    };
+
+    query
+        .split('&')
+        .map(|param| param.split('=').next().unwrap_or_default())
+        .collect()
+}
+
+/// Percent-encodes everything but RFC 3986 unreserved characters.
+fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
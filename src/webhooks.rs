@@ -0,0 +1,220 @@
+use std::{sync::Arc, time::Duration};
+
+use hmac::{Hmac, Mac};
+use hyper::{
+    client::HttpConnector,
+    header, Body, Client, Request,
+};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use serde::Serialize;
+use sha2::Sha256;
+use tracing::{debug, warn};
+
+use crate::{
+    auth,
+    config::{WebhookEndpoint, WebhookEvent, WebhooksConfig},
+    store::{RevisionAction, Route, Store},
+};
+
+/// Posts route events to the configured endpoints. Deliveries run in the
+/// background, a slow or failing endpoint never holds up the API.
+pub struct Webhooks {
+    endpoints: Vec<WebhookEndpoint>,
+    client: Client<HttpsConnector<HttpConnector>>,
+    timeout: Duration,
+    attempts: u32,
+}
+
+/// Body of every delivery.
+#[derive(Serialize)]
+struct Payload<'a> {
+    event: &'static str,
+    /// Unix timestamp, in seconds
+    at: i64,
+    /// Unset for expirations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    actor: Option<&'a str>,
+    /// The route after the change, as it was for deletions
+    route: &'a Route,
+    /// The route before an update
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous: Option<&'a Route>,
+}
+
+impl Webhooks {
+    /// `None` when there are no endpoints.
+    pub fn new(config: &WebhooksConfig) -> Option<Self> {
+        if config.endpoints.is_empty() {
+            return None;
+        }
+
+        let connector = HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+
+        Some(Self {
+            endpoints: config.endpoints.clone(),
+            client: Client::builder().build(connector),
+            timeout: Duration::from_secs(config.timeout),
+            attempts: config.attempts,
+        })
+    }
+
+    /// Tells the endpoints about a change by `actor`, recorded as `action`.
+    pub fn changed(
+        &self,
+        actor: &str,
+        action: RevisionAction,
+        old: Option<&Route>,
+        new: Option<&Route>,
+    ) {
+        let event = match action {
+            RevisionAction::Create => WebhookEvent::Created,
+            RevisionAction::Update | RevisionAction::Rollback => WebhookEvent::Updated,
+            RevisionAction::Delete => WebhookEvent::Deleted,
+            RevisionAction::Restore => WebhookEvent::Restored,
+        };
+        let Some(route) = new.or(old) else {
+            return;
+        };
+
+        self.send(event, Some(actor), route, new.and(old));
+    }
+
+    /// Checks for routes past their `expires_at` every `interval`, for as
+    /// long as the server runs. Routes expiring while it's down are missed.
+    pub fn start_expiry_watch(self: &Arc<Self>, store: Store, interval: Duration) {
+        let wanted = self
+            .endpoints
+            .iter()
+            .any(|endpoint| endpoint.events.contains(&WebhookEvent::Expired));
+        if !wanted {
+            return;
+        }
+
+        let webhooks = self.clone();
+        let mut ticks = tokio::time::interval(interval);
+        tokio::spawn(async move {
+            let mut since = auth::now();
+            loop {
+                ticks.tick().await;
+                let now = auth::now();
+                let routes = match store.list().await {
+                    Ok(routes) => routes,
+                    Err(err) => {
+                        warn!("failed to check for expired routes: {}", err);
+                        continue;
+                    }
+                };
+
+                let expired = routes.iter().filter(|route| {
+                    route
+                        .expires_at
+                        .is_some_and(|expires_at| (since..now).contains(&expires_at))
+                });
+                for route in expired {
+                    webhooks.send(WebhookEvent::Expired, None, route, None);
+                }
+                since = now;
+            }
+        });
+    }
+
+    fn send(
+        &self,
+        event: WebhookEvent,
+        actor: Option<&str>,
+        route: &Route,
+        previous: Option<&Route>,
+    ) {
+        let body = serde_json::to_vec(&Payload {
+            event: event.as_str(),
+            at: auth::now(),
+            actor,
+            route,
+            previous,
+        })
+        .expect("webhook payloads serialize to JSON");
+
+        let endpoints = self
+            .endpoints
+            .iter()
+            .filter(|endpoint| endpoint.events.contains(&event));
+        for endpoint in endpoints {
+            let delivery = Delivery {
+                client: self.client.clone(),
+                url: endpoint.url.clone(),
+                signature: endpoint.secret.as_deref().map(|secret| sign(secret, &body)),
+                event,
+                body: body.clone(),
+                timeout: self.timeout,
+                attempts: self.attempts,
+            };
+            tokio::spawn(delivery.run());
+        }
+    }
+}
+
+struct Delivery {
+    client: Client<HttpsConnector<HttpConnector>>,
+    url: String,
+    signature: Option<String>,
+    event: WebhookEvent,
+    body: Vec<u8>,
+    timeout: Duration,
+    attempts: u32,
+}
+
+impl Delivery {
+    /// Tries up to `attempts` times, waiting 2, 4, 8... seconds in between.
+    async fn run(self) {
+        for attempt in 1..=self.attempts {
+            let error = match tokio::time::timeout(self.timeout, self.post()).await {
+                Ok(Ok(status)) if status.is_success() => {
+                    debug!("delivered {} to {}", self.event.as_str(), &self.url);
+                    return;
+                }
+                Ok(Ok(status)) => format!("answered {}", status),
+                Ok(Err(err)) => err.to_string(),
+                Err(_) => "timed out".into(),
+            };
+            warn!(
+                "webhook {} for {} failed (attempt {}/{}): {}",
+                &self.url,
+                self.event.as_str(),
+                attempt,
+                self.attempts,
+                error
+            );
+
+            if attempt < self.attempts {
+                tokio::time::sleep(Duration::from_secs(1 << attempt.min(6))).await;
+            }
+        }
+    }
+
+    async fn post(&self) -> Result<hyper::StatusCode, hyper::Error> {
+        let mut request = Request::post(&self.url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("X-Roads-Event", self.event.as_str());
+        if let Some(signature) = &self.signature {
+            request = request.header("X-Roads-Signature", format!("sha256={}", signature));
+        }
+        let request = request
+            .body(Body::from(self.body.clone()))
+            .expect("webhook URLs are validated with the config");
+
+        Ok(self.client.request(request).await?.status())
+    }
+}
+
+/// Hex HMAC-SHA256 of `body` under `secret`.
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes any key size");
+    mac.update(body);
+
+    hex::encode(mac.finalize().into_bytes())
+}